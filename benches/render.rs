@@ -0,0 +1,42 @@
+// scanline renderer throughput, with and without a full line of sprites
+// cargo bench --bench render
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use rustyboy::ppu::Ppu;
+
+const LCDC: u16 = 0xFF40;
+const OAM: u16 = 0xFE00;
+
+// background on, sprites on if `sprites` puts ten of them on line 0
+fn ppu(sprites: bool) -> Ppu {
+    let mut ppu = Ppu::new();
+    // a striped tile for everything to draw with
+    for addr in 0x8000..0x8010 {
+        ppu.write_byte(addr, 0x5A);
+    }
+    if sprites {
+        for sprite in 0..10 {
+            let entry = OAM + sprite * 4;
+            ppu.write_byte(entry, 16);
+            ppu.write_byte(entry + 1, 8 + sprite as u8 * 12);
+            ppu.write_byte(entry + 2, 0);
+            ppu.write_byte(entry + 3, if sprite % 2 == 0 { 0x20 } else { 0 });
+        }
+    }
+    ppu.write_byte(LCDC, 0x93);
+    ppu
+}
+
+fn render(c: &mut Criterion) {
+    let mut background = ppu(false);
+    c.bench_function("render_scanline background", |b| {
+        b.iter(|| background.render_scanline())
+    });
+    let mut sprites = ppu(true);
+    c.bench_function("render_scanline 10 sprites", |b| {
+        b.iter(|| sprites.render_scanline())
+    });
+}
+
+criterion_group!(benches, render);
+criterion_main!(benches);
@@ -0,0 +1,214 @@
+// arithmetic shared by the cpu instructions
+// every helper returns the result together with the complete new F register,
+// so half carry and carry are computed in exactly one place
+
+use crate::register::Flags;
+
+fn flags(zero: bool, negative: bool, half_carry: bool, carry: bool) -> u8 {
+    let mut f = 0;
+    if zero {
+        f |= Flags::Zero as u8;
+    }
+    if negative {
+        f |= Flags::Negative as u8;
+    }
+    if half_carry {
+        f |= Flags::HalfCarry as u8;
+    }
+    if carry {
+        f |= Flags::Carry as u8;
+    }
+    f
+}
+
+// ADD/ADC: a + b + carry
+pub fn add_u8_with_flags(a: u8, b: u8, carry: bool) -> (u8, u8) {
+    let carry = carry as u8;
+    let result = a.wrapping_add(b).wrapping_add(carry);
+    let half_carry = (a & 0x0F) + (b & 0x0F) + carry > 0x0F;
+    let full_carry = a as u16 + b as u16 + carry as u16 > 0xFF;
+
+    (result, flags(result == 0, false, half_carry, full_carry))
+}
+
+// SUB/SBC/CP: a - b - carry
+pub fn sub_u8_with_flags(a: u8, b: u8, carry: bool) -> (u8, u8) {
+    let carry = carry as u8;
+    let result = a.wrapping_sub(b).wrapping_sub(carry);
+    let half_carry = (a & 0x0F) < (b & 0x0F) + carry;
+    let full_carry = (a as u16) < b as u16 + carry as u16;
+
+    (result, flags(result == 0, true, half_carry, full_carry))
+}
+
+// ADD HL,rr: half carry out of bit 11, carry out of bit 15, Z is left as it was
+pub fn add_u16_hl_with_flags(hl: u16, value: u16, f: u8) -> (u16, u8) {
+    let result = hl.wrapping_add(value);
+    let half_carry = (hl & 0x0FFF) + (value & 0x0FFF) > 0x0FFF;
+    let carry = hl as u32 + value as u32 > 0xFFFF;
+    let zero = f & Flags::Zero as u8 != 0;
+
+    (result, flags(zero, false, half_carry, carry))
+}
+
+// ADD SP,e8 and LD HL,SP+e8: flags come from the unsigned add of the low byte
+pub fn add_sp_i8_with_flags(sp: u16, e8: u8) -> (u16, u8) {
+    let value = e8 as i8 as i16 as u16;
+    let result = sp.wrapping_add(value);
+    let half_carry = (sp & 0x000F) + (value & 0x000F) > 0x000F;
+    let carry = (sp & 0x00FF) + (value & 0x00FF) > 0x00FF;
+
+    (result, flags(false, false, half_carry, carry))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const Z: u8 = Flags::Zero as u8;
+    const N: u8 = Flags::Negative as u8;
+    const H: u8 = Flags::HalfCarry as u8;
+    const C: u8 = Flags::Carry as u8;
+
+    // reference implementations derive the flags from the carry bits that
+    // show up when doing the math in a wider type
+
+    fn reference_add(a: u8, b: u8, carry: bool) -> (u8, u8) {
+        let sum = a as u32 + b as u32 + carry as u32;
+        let result = sum as u8;
+        let carries = a as u32 ^ b as u32 ^ sum;
+        let mut f = 0;
+        if result == 0 {
+            f |= Z;
+        }
+        if carries & 0x10 != 0 {
+            f |= H;
+        }
+        if carries & 0x100 != 0 {
+            f |= C;
+        }
+        (result, f)
+    }
+
+    fn reference_sub(a: u8, b: u8, carry: bool) -> (u8, u8) {
+        let difference = a as i32 - b as i32 - carry as i32;
+        let result = difference as u8;
+        let mut f = N;
+        if result == 0 {
+            f |= Z;
+        }
+        if (a as i32 & 0x0F) - (b as i32 & 0x0F) - (carry as i32) < 0 {
+            f |= H;
+        }
+        if difference < 0 {
+            f |= C;
+        }
+        (result, f)
+    }
+
+    fn reference_add_hl(hl: u16, value: u16, f: u8) -> (u16, u8) {
+        let sum = hl as u32 + value as u32;
+        let carries = hl as u32 ^ value as u32 ^ sum;
+        let mut flags = f & Z;
+        if carries & 0x1000 != 0 {
+            flags |= H;
+        }
+        if carries & 0x10000 != 0 {
+            flags |= C;
+        }
+        (sum as u16, flags)
+    }
+
+    fn reference_add_sp(sp: u16, e8: u8) -> (u16, u8) {
+        let result = (sp as i32 + e8 as i8 as i32) as u16;
+        let carries = sp as u32 ^ e8 as i8 as i32 as u32 ^ result as u32;
+        let mut f = 0;
+        if carries & 0x10 != 0 {
+            f |= H;
+        }
+        if carries & 0x100 != 0 {
+            f |= C;
+        }
+        (result, f)
+    }
+
+    // xorshift, good enough to spread samples over the 16-bit operand space
+    fn next(state: &mut u32) -> u32 {
+        *state ^= *state << 13;
+        *state ^= *state >> 17;
+        *state ^= *state << 5;
+        *state
+    }
+
+    #[test]
+    fn test_add_u8_exhaustive() {
+        for a in 0..=0xFF {
+            for b in 0..=0xFF {
+                for carry in [false, true] {
+                    assert_eq!(
+                        reference_add(a, b, carry),
+                        add_u8_with_flags(a, b, carry),
+                        "{a:#X} + {b:#X} + {carry}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_sub_u8_exhaustive() {
+        for a in 0..=0xFF {
+            for b in 0..=0xFF {
+                for carry in [false, true] {
+                    assert_eq!(
+                        reference_sub(a, b, carry),
+                        sub_u8_with_flags(a, b, carry),
+                        "{a:#X} - {b:#X} - {carry}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_add_u16_hl_randomized() {
+        let mut state = 0x1234_5678;
+        for _ in 0..1_000_000 {
+            let hl = next(&mut state) as u16;
+            let value = next(&mut state) as u16;
+            let f = next(&mut state) as u8 & 0xF0;
+            assert_eq!(
+                reference_add_hl(hl, value, f),
+                add_u16_hl_with_flags(hl, value, f),
+                "{hl:#X} + {value:#X}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_add_u16_hl_edges() {
+        assert_eq!((0x1000, H), add_u16_hl_with_flags(0x0FFF, 0x0001, 0));
+        assert_eq!(
+            (0x0000, H | C | Z),
+            add_u16_hl_with_flags(0xFFFF, 0x0001, Z)
+        );
+        assert_eq!((0x0000, C), add_u16_hl_with_flags(0x8000, 0x8000, N));
+        assert_eq!((0x00FF, 0), add_u16_hl_with_flags(0x00F0, 0x000F, H | C));
+    }
+
+    #[test]
+    fn test_add_sp_i8_exhaustive() {
+        for sp in (0..=0xFFFF).step_by(0x0101) {
+            for low in 0..=0xFF {
+                let sp = (sp & 0xFF00) | low;
+                for e8 in 0..=0xFF {
+                    assert_eq!(
+                        reference_add_sp(sp, e8),
+                        add_sp_i8_with_flags(sp, e8),
+                        "{sp:#X} + {e8:#X}"
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,1335 @@
+// audio processing unit
+// two square channels (the first with a frequency sweep), the wave channel and the noise
+// channel are mixed into a left and a right output by NR50/NR51. changes of the output
+// level go into blip_buf, which turns them into band-limited samples at the output
+// sample rate
+
+use blip_buf::BlipBuf;
+
+use crate::audio::DEFAULT_LATENCY;
+use crate::model::Model;
+
+const APU_START: u16 = 0xFF10;
+const NR10: u16 = 0xFF10;
+const NR11: u16 = 0xFF11;
+const NR12: u16 = 0xFF12;
+const NR13: u16 = 0xFF13;
+const NR14: u16 = 0xFF14;
+const NR21: u16 = 0xFF16;
+const NR22: u16 = 0xFF17;
+const NR23: u16 = 0xFF18;
+const NR24: u16 = 0xFF19;
+const NR30: u16 = 0xFF1A;
+const NR31: u16 = 0xFF1B;
+const NR32: u16 = 0xFF1C;
+const NR33: u16 = 0xFF1D;
+const NR34: u16 = 0xFF1E;
+const NR41: u16 = 0xFF20;
+const NR42: u16 = 0xFF21;
+const NR43: u16 = 0xFF22;
+const NR44: u16 = 0xFF23;
+const NR50: u16 = 0xFF24;
+const NR51: u16 = 0xFF25;
+const NR52: u16 = 0xFF26;
+const UNUSED_START: u16 = 0xFF27;
+const UNUSED_END: u16 = 0xFF2F;
+const WAVE_RAM_START: u16 = 0xFF30;
+const WAVE_RAM_END: u16 = 0xFF3F;
+
+// bits of NR10-NR52 that read back as 1, the write-only and unconnected ones
+const READ_MASKS: [u8; 0x17] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10-NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // NR20-NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // NR40-NR44
+    0x00, 0x00, 0x70, // NR50-NR52
+];
+
+const POWER: u8 = 0x80;
+const TRIGGER: u8 = 0x80;
+const LENGTH_ENABLE: u8 = 0x40;
+
+pub const CLOCK_RATE: u32 = 4_194_304;
+// default output rate
+pub const SAMPLE_RATE: u32 = 48_000;
+// blip_buf is emptied about once per frame, it holds 100ms
+const FRAME_CLOCKS: u32 = 70224;
+// a channel outputs 0-15 and NR50 scales each side by 1-8, four channels at full
+// volume stay inside an i16
+const VOLUME_SCALE: i32 = 64;
+
+// share of the output capacitor's charge kept per t-cycle, the CGB's discharges faster
+const DMG_CHARGE_FACTOR: f64 = 0.999958;
+const CGB_CHARGE_FACTOR: f64 = 0.998943;
+
+// t-cycles the wave channel waits after a trigger before its first read of wave ram
+const WAVE_TRIGGER_DELAY: u32 = 6;
+
+// noise timer periods for the NR43 divisor codes, before the clock shift
+const NOISE_DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+// waveforms of the four duty cycles, one bit per step
+const DUTY_PATTERNS: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+
+// volume envelope of NRx2
+#[derive(Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Envelope {
+    initial: u8,
+    increase: bool,
+    period: u8,
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    fn write(&mut self, value: u8) {
+        self.initial = value >> 4;
+        self.increase = value & 0x08 != 0;
+        self.period = value & 0x07;
+    }
+
+    // the DAC is off when the upper five bits are all 0
+    fn dac_enabled(&self) -> bool {
+        self.initial != 0 || self.increase
+    }
+
+    fn trigger(&mut self) {
+        self.volume = self.initial;
+        self.timer = self.period;
+    }
+
+    fn clock(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.increase && self.volume < 15 {
+                self.volume += 1;
+            } else if !self.increase && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+}
+
+// frequency sweep of NR10, only channel 1 has one
+#[derive(Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Sweep {
+    period: u8,
+    negate: bool,
+    shift: u8,
+    timer: u8,
+    enabled: bool,
+    shadow: u16,
+}
+
+impl Sweep {
+    fn write(&mut self, value: u8) {
+        self.period = (value >> 4) & 0x07;
+        self.negate = value & 0x08 != 0;
+        self.shift = value & 0x07;
+    }
+
+    // a period of 0 reloads the timer with 8
+    fn reload(&mut self) {
+        self.timer = if self.period == 0 { 8 } else { self.period };
+    }
+
+    fn next_frequency(&self) -> u16 {
+        let delta = self.shadow >> self.shift;
+        if self.negate {
+            self.shadow - delta
+        } else {
+            self.shadow + delta
+        }
+    }
+}
+
+// counts down at 256 Hz while enabled and stops its channel at 0. the counter is clocked
+// whether the channel plays or not, and keeps its value while length is disabled
+#[derive(Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Length {
+    counter: u16,
+    enabled: bool,
+}
+
+impl Length {
+    fn load(&mut self, max: u16, value: u16) {
+        self.counter = max - value;
+    }
+
+    // true when this clock made it run out
+    fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter == 0;
+        }
+        false
+    }
+
+    // NRx4 write, `extra_clock` is set when the next frame sequencer step won't clock
+    // lengths. enabling length then clocks it once right away, and a trigger reloading an
+    // empty counter loads max - 1 instead of max. true if the channel has to stop
+    fn write_control(&mut self, value: u8, max: u16, extra_clock: bool) -> bool {
+        let was_enabled = self.enabled;
+        self.enabled = value & LENGTH_ENABLE != 0;
+        let mut expired = false;
+        if extra_clock && !was_enabled {
+            expired = self.clock();
+        }
+        if value & TRIGGER == 0 {
+            return expired;
+        }
+        if self.counter == 0 {
+            self.counter = max;
+            if extra_clock {
+                self.clock();
+            }
+        }
+        false
+    }
+}
+
+#[derive(Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Square {
+    enabled: bool,
+    duty: u8,
+    duty_step: u8,
+    length: Length,
+    frequency: u16,
+    timer: u32,
+    envelope: Envelope,
+    sweep: Sweep,
+}
+
+impl Square {
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 4
+    }
+
+    fn write_length(&mut self, value: u8) {
+        self.duty = value >> 6;
+        self.length.load(64, (value & 0x3F) as u16);
+    }
+
+    fn write_envelope(&mut self, value: u8) {
+        self.envelope.write(value);
+        if !self.envelope.dac_enabled() {
+            self.enabled = false;
+        }
+    }
+
+    fn write_frequency_low(&mut self, value: u8) {
+        self.frequency = (self.frequency & 0x700) | value as u16;
+    }
+
+    fn write_control(&mut self, value: u8, extra_clock: bool) {
+        self.frequency = (self.frequency & 0xFF) | ((value & 0x07) as u16) << 8;
+        if self.length.write_control(value, 64, extra_clock) {
+            self.enabled = false;
+        }
+        if value & TRIGGER != 0 {
+            self.trigger();
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.timer = self.period();
+        self.envelope.trigger();
+
+        self.sweep.shadow = self.frequency;
+        self.sweep.reload();
+        self.sweep.enabled = self.sweep.period != 0 || self.sweep.shift != 0;
+        // the overflow check runs right away when there is a shift
+        if self.sweep.shift != 0 && self.sweep.next_frequency() > 2047 {
+            self.enabled = false;
+        }
+    }
+
+    fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    fn clock_sweep(&mut self) {
+        self.sweep.timer = self.sweep.timer.saturating_sub(1);
+        if self.sweep.timer > 0 {
+            return;
+        }
+        self.sweep.reload();
+        if !self.sweep.enabled || self.sweep.period == 0 {
+            return;
+        }
+        let frequency = self.sweep.next_frequency();
+        if frequency > 2047 {
+            self.enabled = false;
+        } else if self.sweep.shift != 0 {
+            self.sweep.shadow = frequency;
+            self.frequency = frequency;
+            // and once more with the new frequency, without using the result
+            if self.sweep.next_frequency() > 2047 {
+                self.enabled = false;
+            }
+        }
+    }
+
+    fn run(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.duty_step = (self.duty_step + 1) & 0x07;
+        }
+        self.timer -= cycles;
+    }
+
+    fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        (DUTY_PATTERNS[self.duty as usize] >> self.duty_step & 1) * self.envelope.volume
+    }
+}
+
+#[derive(Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Wave {
+    enabled: bool,
+    dac_enabled: bool,
+    length: Length,
+    // NR32 output level, 0 mutes and 1-3 shift the samples right by 0-2
+    level: u8,
+    frequency: u16,
+    timer: u32,
+    // the nibble being played, 32 per wave with the high one of a byte first
+    position: u8,
+    sample: u8,
+    // wave ram was read during the last run, the only time a DMG cpu can reach it while
+    // the channel plays
+    just_read: bool,
+    ram: [u8; 16],
+}
+
+impl Wave {
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 2
+    }
+
+    fn write_dac(&mut self, value: u8) {
+        self.dac_enabled = value & 0x80 != 0;
+        if !self.dac_enabled {
+            self.enabled = false;
+        }
+    }
+
+    fn write_frequency_low(&mut self, value: u8) {
+        self.frequency = (self.frequency & 0x700) | value as u16;
+    }
+
+    fn write_control(&mut self, value: u8, extra_clock: bool) {
+        self.frequency = (self.frequency & 0xFF) | ((value & 0x07) as u16) << 8;
+        if self.length.write_control(value, 256, extra_clock) {
+            self.enabled = false;
+        }
+        if value & TRIGGER != 0 {
+            self.enabled = self.dac_enabled;
+            // the sample buffer keeps the old sample until the delayed first read
+            self.timer = self.period() + WAVE_TRIGGER_DELAY;
+            self.position = 0;
+        }
+    }
+
+    // on a DMG, retriggering right as the channel reads wave ram overwrites the start of
+    // wave ram with what it was reading: the one byte if it is in the first four, the
+    // aligned four bytes around it otherwise
+    fn corrupt_on_retrigger(&mut self) {
+        if !self.enabled || self.timer > 2 {
+            return;
+        }
+        let index = ((self.position + 1) & 0x1F) as usize / 2;
+        if index < 4 {
+            self.ram[0] = self.ram[index];
+        } else {
+            let block = index & !3;
+            self.ram.copy_within(block..block + 4, 0);
+        }
+    }
+
+    // while the channel plays the cpu reaches the byte being played instead of the
+    // addressed one, and on a DMG only in the cycle the channel reads it
+    fn ram_index(&self, addr: u16, model: Model) -> Option<usize> {
+        if !self.enabled {
+            Some((addr - WAVE_RAM_START) as usize)
+        } else if model == Model::Cgb || self.just_read {
+            Some(self.position as usize / 2)
+        } else {
+            None
+        }
+    }
+
+    fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    fn run(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        self.just_read = false;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.just_read = true;
+            self.position = (self.position + 1) & 0x1F;
+            let byte = self.ram[self.position as usize / 2];
+            self.sample = if self.position & 1 == 0 {
+                byte >> 4
+            } else {
+                byte & 0x0F
+            };
+        }
+        self.timer -= cycles;
+    }
+
+    fn output(&self) -> u8 {
+        if !self.enabled || self.level == 0 {
+            return 0;
+        }
+        self.sample >> (self.level - 1)
+    }
+}
+
+#[derive(Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Noise {
+    enabled: bool,
+    length: Length,
+    // NR43
+    clock_shift: u8,
+    // 7-bit mode, also feeds the new bit into bit 6
+    short_mode: bool,
+    divisor: u8,
+    timer: u32,
+    // 15-bit linear feedback shift register, bit 0 inverted is the output
+    lfsr: u16,
+    envelope: Envelope,
+}
+
+impl Noise {
+    fn period(&self) -> u32 {
+        NOISE_DIVISORS[self.divisor as usize] << self.clock_shift
+    }
+
+    fn write_length(&mut self, value: u8) {
+        self.length.load(64, (value & 0x3F) as u16);
+    }
+
+    fn write_envelope(&mut self, value: u8) {
+        self.envelope.write(value);
+        if !self.envelope.dac_enabled() {
+            self.enabled = false;
+        }
+    }
+
+    fn write_polynomial(&mut self, value: u8) {
+        self.clock_shift = value >> 4;
+        self.short_mode = value & 0x08 != 0;
+        self.divisor = value & 0x07;
+    }
+
+    fn write_control(&mut self, value: u8, extra_clock: bool) {
+        if self.length.write_control(value, 64, extra_clock) {
+            self.enabled = false;
+        }
+        if value & TRIGGER != 0 {
+            self.enabled = self.envelope.dac_enabled();
+            self.timer = self.period();
+            self.lfsr = 0x7FFF;
+            self.envelope.trigger();
+        }
+    }
+
+    fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    fn run(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            let bit = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+            self.lfsr = (self.lfsr >> 1) | bit << 14;
+            if self.short_mode {
+                self.lfsr = (self.lfsr & !0x40) | bit << 6;
+            }
+        }
+        self.timer -= cycles;
+    }
+
+    fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        (!self.lfsr & 1) as u8 * self.envelope.volume
+    }
+}
+
+// one side's blip_buf, the level it was last moved to and the last sample it gave.
+// savestates keep those instead of the buffer, a restored one starts out at the last
+// sample so playback goes on without a click
+struct Resampler {
+    buffer: BlipBuf,
+    rate: f64,
+    // ms the buffer has room for
+    latency: u32,
+    level: i32,
+    // blip_buf drifts it towards 0 on its own, so it is not just the level
+    output: i16,
+}
+
+impl Resampler {
+    fn new(rate: f64, latency: u32, level: i32, output: i16) -> Self {
+        let mut buffer = BlipBuf::new((rate * latency as f64 / 1000.0) as u32);
+        buffer.set_rates(CLOCK_RATE as f64, rate);
+        // a step comes out spread over the next 16 samples, they are read away so the
+        // first real sample is already at `output`
+        buffer.add_delta(0, output as i32);
+        let clocks = buffer.clocks_needed(16);
+        buffer.end_frame(clocks);
+        buffer.read_samples(&mut [0; 16], false);
+        Self {
+            buffer,
+            rate,
+            latency,
+            level,
+            output,
+        }
+    }
+
+    fn set_rate(&mut self, rate: f64) {
+        self.rate = rate;
+        self.buffer.set_rates(CLOCK_RATE as f64, rate);
+    }
+
+    fn move_to(&mut self, time: u32, level: i32) {
+        if level != self.level {
+            self.buffer.add_delta(time, level - self.level);
+            self.level = level;
+        }
+    }
+
+    // fills every other sample of `samples`
+    fn read(&mut self, samples: &mut [i16]) {
+        let count = self.buffer.read_samples(samples, true);
+        if count > 0 {
+            self.output = samples[(count - 1) * 2];
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Resampler {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (self.rate, self.latency, self.level, self.output).serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Resampler {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (rate, latency, level, output) = <(f64, u32, i32, i16)>::deserialize(deserializer)?;
+        Ok(Self::new(rate, latency, level, output))
+    }
+}
+
+// the capacitor in series with each output, it blocks the DC offset the DACs leave
+// when channels idle at a nonzero level
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct HighPass {
+    // charge kept per output sample
+    factor: f64,
+    capacitor: f64,
+}
+
+impl HighPass {
+    fn new(model: Model, sample_rate: u32) -> Self {
+        Self {
+            factor: Self::charge_factor(model, sample_rate),
+            capacitor: 0.0,
+        }
+    }
+
+    fn charge_factor(model: Model, sample_rate: u32) -> f64 {
+        let factor = match model {
+            Model::Dmg => DMG_CHARGE_FACTOR,
+            Model::Cgb => CGB_CHARGE_FACTOR,
+        };
+        factor.powf(CLOCK_RATE as f64 / sample_rate as f64)
+    }
+
+    fn filter(&mut self, input: i16) -> i16 {
+        let output = input as f64 - self.capacitor;
+        self.capacitor = input as f64 - output * self.factor;
+        output.round().clamp(i16::MIN as f64, i16::MAX as f64) as i16
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Apu {
+    // wave ram access and retriggering differ between DMG and CGB
+    model: Model,
+    powered: bool,
+    // NR10-NR51 as written, the channels keep the decoded values
+    registers: [u8; 0x16],
+    square1: Square,
+    square2: Square,
+    wave: Wave,
+    noise: Noise,
+    // next frame sequencer step, 0-7
+    sequencer_step: u8,
+    // t-cycles since the last flush of the blip buffers
+    #[cfg_attr(feature = "serde", serde(skip))]
+    time: u32,
+    left: Resampler,
+    right: Resampler,
+    sample_rate: u32,
+    // factor on the sample rate the buffers resample to, nudged to match the device
+    rate_adjustment: f64,
+    // interleaved left/right samples waiting for end_frame, a second at most
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pending: Vec<i16>,
+    // left and right
+    high_pass: [HighPass; 2],
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Self {
+            // the boot rom leaves the apu on
+            model: Model::Dmg,
+            powered: true,
+            registers: [0; 0x16],
+            square1: Square::default(),
+            square2: Square::default(),
+            wave: Wave::default(),
+            noise: Noise::default(),
+            sequencer_step: 0,
+            time: 0,
+            left: Resampler::new(SAMPLE_RATE as f64, DEFAULT_LATENCY, 0, 0),
+            right: Resampler::new(SAMPLE_RATE as f64, DEFAULT_LATENCY, 0, 0),
+            sample_rate: SAMPLE_RATE,
+            rate_adjustment: 1.0,
+            pending: Vec::new(),
+            high_pass: [
+                HighPass::new(Model::Dmg, SAMPLE_RATE),
+                HighPass::new(Model::Dmg, SAMPLE_RATE),
+            ],
+        }
+    }
+
+    pub fn set_model(&mut self, model: Model) {
+        self.model = model;
+        for high_pass in &mut self.high_pass {
+            high_pass.factor = HighPass::charge_factor(model, self.sample_rate);
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // samples not yet taken by end_frame are dropped, the output level carries over
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.resize_buffers(self.latency());
+        for high_pass in &mut self.high_pass {
+            high_pass.factor = HighPass::charge_factor(self.model, sample_rate);
+        }
+    }
+
+    // ms of samples the blip buffers hold, at least a frame
+    pub fn latency(&self) -> u32 {
+        self.left.latency
+    }
+
+    // like set_sample_rate, pending samples are dropped
+    pub fn set_latency(&mut self, latency: u32) {
+        self.resize_buffers(latency);
+    }
+
+    fn resize_buffers(&mut self, latency: u32) {
+        let rate = self.sample_rate as f64;
+        self.left = Resampler::new(rate, latency, self.left.level, self.left.output);
+        self.right = Resampler::new(rate, latency, self.right.level, self.right.output);
+        self.time = 0;
+        self.rate_adjustment = 1.0;
+        self.pending.clear();
+    }
+
+    // makes slightly more (> 1.0) or fewer samples than the sample rate from now on,
+    // keeps the output fed when the device's clock runs a bit off
+    pub fn set_rate_adjustment(&mut self, adjustment: f64) {
+        if adjustment == self.rate_adjustment {
+            return;
+        }
+        self.rate_adjustment = adjustment;
+        let rate = self.sample_rate as f64 * adjustment;
+        self.left.set_rate(rate);
+        self.right.set_rate(rate);
+    }
+
+    pub fn read_byte(&self, addr: u16) -> u8 {
+        match addr {
+            NR52 => {
+                READ_MASKS[(NR52 - APU_START) as usize]
+                    | (self.powered as u8) << 7
+                    | self.channel_status()
+            }
+            APU_START..NR52 => {
+                let index = (addr - APU_START) as usize;
+                self.registers[index] | READ_MASKS[index]
+            }
+            UNUSED_START..=UNUSED_END => 0xFF,
+            WAVE_RAM_START..=WAVE_RAM_END => match self.wave.ram_index(addr, self.model) {
+                Some(index) => self.wave.ram[index],
+                None => 0xFF,
+            },
+            // not routed here by the bus, reads as open bus
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write_byte(&mut self, addr: u16, value: u8) {
+        match addr {
+            NR52 => self.set_power(value & POWER != 0),
+            // everything but wave ram is read only while the apu is off
+            APU_START..NR52 if !self.powered => {}
+            APU_START..NR52 => {
+                self.registers[(addr - APU_START) as usize] = value;
+                self.write_register(addr, value);
+            }
+            UNUSED_START..=UNUSED_END => {}
+            WAVE_RAM_START..=WAVE_RAM_END => {
+                if let Some(index) = self.wave.ram_index(addr, self.model) {
+                    self.wave.ram[index] = value;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // debugger write, the trigger bit is dropped so a channel isn't restarted and NR52 only
+    // flips the power bit without clearing the registers
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        match addr {
+            NR52 => self.powered = value & POWER != 0,
+            NR14 | NR24 | NR34 | NR44 => self.write_byte(addr, value & !TRIGGER),
+            _ => self.write_byte(addr, value),
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, value: u8) {
+        // the step about to run is odd, the last one clocked the lengths
+        let extra_clock = self.sequencer_step & 1 == 1;
+        match addr {
+            NR10 => self.square1.sweep.write(value),
+            NR11 => self.square1.write_length(value),
+            NR12 => self.square1.write_envelope(value),
+            NR13 => self.square1.write_frequency_low(value),
+            NR14 => self.square1.write_control(value, extra_clock),
+            NR21 => self.square2.write_length(value),
+            NR22 => self.square2.write_envelope(value),
+            NR23 => self.square2.write_frequency_low(value),
+            NR24 => self.square2.write_control(value, extra_clock),
+            NR30 => self.wave.write_dac(value),
+            NR31 => self.wave.length.load(256, value as u16),
+            NR32 => self.wave.level = (value >> 5) & 0x03,
+            NR33 => self.wave.write_frequency_low(value),
+            NR34 => {
+                if value & TRIGGER != 0 && self.model == Model::Dmg {
+                    self.wave.corrupt_on_retrigger();
+                }
+                self.wave.write_control(value, extra_clock);
+            }
+            NR41 => self.noise.write_length(value),
+            NR42 => self.noise.write_envelope(value),
+            NR43 => self.noise.write_polynomial(value),
+            NR44 => self.noise.write_control(value, extra_clock),
+            // NR50 and NR51 are read back from the registers when mixing
+            _ => {}
+        }
+    }
+
+    // NR52 bits 0-3, set from a trigger until the length runs out or the DAC is turned off
+    fn channel_status(&self) -> u8 {
+        self.square1.enabled as u8
+            | (self.square2.enabled as u8) << 1
+            | (self.wave.enabled as u8) << 2
+            | (self.noise.enabled as u8) << 3
+    }
+
+    // turning the apu off clears every register but wave ram
+    fn set_power(&mut self, on: bool) {
+        if on == self.powered {
+            return;
+        }
+        self.powered = on;
+        if !on {
+            let ram = self.wave.ram;
+            self.registers = [0; 0x16];
+            self.square1 = Square::default();
+            self.square2 = Square::default();
+            self.noise = Noise::default();
+            self.wave = Wave {
+                ram,
+                ..Wave::default()
+            };
+        } else {
+            self.sequencer_step = 0;
+        }
+    }
+
+    pub fn step(&mut self, m_cycles: u8) {
+        self.step_dots(m_cycles as u32 * 4);
+    }
+
+    // `dots` cycles of the 4MHz clock, a cpu in double speed only gives 2 per machine cycle
+    pub fn step_dots(&mut self, dots: u32) {
+        let mut left = dots;
+        while left > 0 {
+            let cycles = left.min(4);
+            self.run(cycles);
+            left -= cycles;
+        }
+    }
+
+    fn run(&mut self, cycles: u32) {
+        if self.powered {
+            if self.square1.enabled {
+                self.square1.run(cycles);
+            }
+            if self.square2.enabled {
+                self.square2.run(cycles);
+            }
+            if self.wave.enabled {
+                self.wave.run(cycles);
+            }
+            if self.noise.enabled {
+                self.noise.run(cycles);
+            }
+        }
+        self.mix();
+        self.time += cycles;
+        if self.time >= FRAME_CLOCKS {
+            self.flush();
+        }
+    }
+
+    // DIV-APU: the frame sequencer moves on when bit 4 of DIV falls, 512 Hz unless DIV
+    // is written. steps 0, 2, 4 and 6 clock the lengths, 2 and 6 the sweep, 7 the envelopes
+    pub fn clock_frame_sequencer(&mut self) {
+        if !self.powered {
+            return;
+        }
+        if self.sequencer_step & 1 == 0 {
+            self.square1.clock_length();
+            self.square2.clock_length();
+            self.wave.clock_length();
+            self.noise.clock_length();
+        }
+        if self.sequencer_step == 2 || self.sequencer_step == 6 {
+            self.square1.clock_sweep();
+        }
+        if self.sequencer_step == 7 {
+            self.square1.envelope.clock();
+            self.square2.envelope.clock();
+            self.noise.envelope.clock();
+        }
+        self.sequencer_step = (self.sequencer_step + 1) & 0x07;
+    }
+
+    fn mix(&mut self) {
+        let nr50 = self.registers[(NR50 - APU_START) as usize];
+        let nr51 = self.registers[(NR51 - APU_START) as usize];
+        let outputs = [
+            self.square1.output(),
+            self.square2.output(),
+            self.wave.output(),
+            self.noise.output(),
+        ];
+        let (mut left, mut right) = (0, 0);
+        for (channel, &output) in outputs.iter().enumerate() {
+            if nr51 & (0x10 << channel) != 0 {
+                left += output as i32;
+            }
+            if nr51 & (0x01 << channel) != 0 {
+                right += output as i32;
+            }
+        }
+        let left = left * (((nr50 >> 4) & 0x07) as i32 + 1) * VOLUME_SCALE;
+        let right = right * ((nr50 & 0x07) as i32 + 1) * VOLUME_SCALE;
+        self.left.move_to(self.time, left);
+        self.right.move_to(self.time, right);
+    }
+
+    // moves what blip_buf has made so far to the pending samples
+    fn flush(&mut self) {
+        self.left.buffer.end_frame(self.time);
+        self.right.buffer.end_frame(self.time);
+        self.time = 0;
+        let count = self.left.buffer.samples_avail() as usize;
+        if count == 0 {
+            return;
+        }
+        let start = self.pending.len();
+        self.pending.resize(start + count * 2, 0);
+        self.left.read(&mut self.pending[start..]);
+        self.right.read(&mut self.pending[start + 1..]);
+        for (i, sample) in self.pending[start..].iter_mut().enumerate() {
+            *sample = self.high_pass[i & 1].filter(*sample);
+        }
+        let max_pending = self.sample_rate as usize * 2;
+        if self.pending.len() > max_pending {
+            let excess = self.pending.len() - max_pending;
+            self.pending.drain(..excess);
+        }
+    }
+
+    // interleaved left/right samples at the sample rate produced since the last call
+    pub fn end_frame(&mut self) -> Vec<i16> {
+        self.flush();
+        std::mem::take(&mut self.pending)
+    }
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a full volume channel 1 tone on both sides
+    fn playing_apu() -> Apu {
+        let mut apu = Apu::new();
+        apu.write_byte(NR50, 0x77);
+        apu.write_byte(NR51, 0x11);
+        apu.write_byte(NR11, 0x80);
+        apu.write_byte(NR12, 0xF0);
+        apu.write_byte(NR13, 0x00);
+        apu.write_byte(NR14, TRIGGER | 0x06);
+        apu
+    }
+
+    #[test]
+    fn test_registers_read_back_with_unused_bits_set() {
+        let mut apu = Apu::new();
+        for addr in APU_START..NR52 {
+            apu.write_byte(addr, 0x00);
+        }
+        assert_eq!(0x80, apu.read_byte(NR10));
+        assert_eq!(0x3F, apu.read_byte(NR11));
+        assert_eq!(0xFF, apu.read_byte(NR13));
+        assert_eq!(0xBF, apu.read_byte(NR14));
+        assert_eq!(0x7F, apu.read_byte(NR30));
+        assert_eq!(0x9F, apu.read_byte(NR32));
+        assert_eq!(0xFF, apu.read_byte(0xFF15));
+        assert_eq!(0xFF, apu.read_byte(UNUSED_START));
+        apu.write_byte(NR12, 0xF3);
+        assert_eq!(0xF3, apu.read_byte(NR12));
+    }
+
+    #[test]
+    fn test_trigger_sets_channel_status() {
+        let mut apu = playing_apu();
+        assert_eq!(0xF1, apu.read_byte(NR52));
+        // turning the DAC off stops the channel
+        apu.write_byte(NR12, 0x00);
+        assert_eq!(0xF0, apu.read_byte(NR52));
+    }
+
+    // clocks the frame sequencer `steps` times, as DIV would
+    fn run_steps(apu: &mut Apu, steps: u32) {
+        for _ in 0..steps {
+            apu.clock_frame_sequencer();
+        }
+    }
+
+    #[test]
+    fn test_length_expiry_stops_channel() {
+        let mut apu = playing_apu();
+        // 2 steps of length left, clocked every other sequencer step
+        apu.write_byte(NR11, 0x3E);
+        apu.write_byte(NR14, TRIGGER | LENGTH_ENABLE);
+        run_steps(&mut apu, 2);
+        assert_eq!(0x01, apu.read_byte(NR52) & 0x01);
+        run_steps(&mut apu, 2);
+        assert_eq!(0x00, apu.read_byte(NR52) & 0x01);
+    }
+
+    #[test]
+    fn test_enabling_length_after_a_length_step_clocks_it() {
+        let mut apu = playing_apu();
+        run_steps(&mut apu, 1);
+        apu.write_byte(NR11, 0x3E);
+        apu.write_byte(NR14, LENGTH_ENABLE);
+        assert_eq!(1, apu.square1.length.counter);
+        assert_eq!(0x01, apu.read_byte(NR52) & 0x01);
+        // enabling it again is no change, no clock
+        apu.write_byte(NR14, LENGTH_ENABLE);
+        assert_eq!(1, apu.square1.length.counter);
+        // clocked to 0 without a trigger stops the channel
+        apu.write_byte(NR14, 0x00);
+        apu.write_byte(NR14, LENGTH_ENABLE);
+        assert_eq!(0x00, apu.read_byte(NR52) & 0x01);
+    }
+
+    #[test]
+    fn test_enabling_length_before_a_length_step_waits_for_it() {
+        let mut apu = playing_apu();
+        run_steps(&mut apu, 2);
+        apu.write_byte(NR11, 0x3E);
+        apu.write_byte(NR14, LENGTH_ENABLE);
+        assert_eq!(2, apu.square1.length.counter);
+    }
+
+    #[test]
+    fn test_trigger_reloads_empty_length() {
+        let mut apu = playing_apu();
+        apu.write_byte(NR31, 0xFF);
+        apu.write_byte(NR30, 0x80);
+        apu.write_byte(NR34, TRIGGER | LENGTH_ENABLE);
+        run_steps(&mut apu, 1);
+        assert_eq!(0, apu.wave.length.counter);
+        // after a length step the reload is clocked once right away
+        apu.write_byte(NR34, TRIGGER | LENGTH_ENABLE);
+        assert_eq!(255, apu.wave.length.counter);
+        assert_eq!(0x04, apu.read_byte(NR52) & 0x04);
+        // and loads the full length before one
+        run_steps(&mut apu, 255 * 2 + 1);
+        assert_eq!(0, apu.wave.length.counter);
+        apu.write_byte(NR34, TRIGGER | LENGTH_ENABLE);
+        assert_eq!(256, apu.wave.length.counter);
+        // a trigger leaves a running counter alone
+        apu.write_byte(NR34, TRIGGER | LENGTH_ENABLE);
+        assert_eq!(256, apu.wave.length.counter);
+    }
+
+    #[test]
+    fn test_disabled_length_is_frozen() {
+        let mut apu = playing_apu();
+        apu.write_byte(NR41, 0x30);
+        apu.write_byte(NR42, 0xF0);
+        apu.write_byte(NR44, TRIGGER);
+        run_steps(&mut apu, 8);
+        assert_eq!(16, apu.noise.length.counter);
+        // and keeps counting while the channel is off
+        apu.write_byte(NR44, LENGTH_ENABLE);
+        apu.write_byte(NR42, 0x00);
+        run_steps(&mut apu, 8);
+        assert_eq!(12, apu.noise.length.counter);
+    }
+
+    // wave channel playing 0x00-0xFF in wave ram at the highest pitch
+    fn playing_wave(model: Model) -> Apu {
+        let mut apu = Apu::new();
+        apu.set_model(model);
+        for i in 0..16 {
+            apu.write_byte(WAVE_RAM_START + i, i as u8 * 0x11);
+        }
+        apu.write_byte(NR30, 0x80);
+        apu.write_byte(NR33, 0xFE);
+        apu.write_byte(NR34, TRIGGER | 0x07);
+        apu
+    }
+
+    #[test]
+    fn test_wave_ram_reads_while_playing() {
+        let mut apu = playing_wave(Model::Dmg);
+        // period 4 plus the trigger delay, the first read is in the third machine cycle
+        apu.step(2);
+        assert_eq!(0xFF, apu.read_byte(WAVE_RAM_START + 5));
+        apu.step(1);
+        // just read the second nibble of byte 0
+        assert_eq!(0x00, apu.read_byte(WAVE_RAM_START + 5));
+        apu.step(1);
+        assert_eq!(0x11, apu.read_byte(WAVE_RAM_START + 5));
+
+        // a CGB always reaches the byte being played
+        let mut apu = playing_wave(Model::Cgb);
+        apu.step(1);
+        assert_eq!(0x00, apu.read_byte(WAVE_RAM_START + 5));
+        apu.write_byte(WAVE_RAM_START + 5, 0x99);
+        assert_eq!(0x99, apu.wave.ram[0]);
+        assert_eq!(0x55, apu.wave.ram[5]);
+        apu.write_byte(NR30, 0x00);
+        assert_eq!(0x55, apu.read_byte(WAVE_RAM_START + 5));
+    }
+
+    #[test]
+    fn test_first_wave_sample_after_trigger_is_delayed() {
+        let mut apu = playing_wave(Model::Dmg);
+        apu.wave.sample = 0x0A;
+        assert_eq!(4 + WAVE_TRIGGER_DELAY, apu.wave.timer);
+        apu.step(2);
+        assert_eq!(0x0A, apu.wave.sample);
+        apu.step(1);
+        // position 0 is skipped, the first read is position 1
+        assert_eq!(1, apu.wave.position);
+        assert_eq!(0x00, apu.wave.sample);
+    }
+
+    #[test]
+    fn test_wave_retrigger_corrupts_wave_ram_on_dmg() {
+        let mut apu = playing_wave(Model::Dmg);
+        // about to read position 10, byte 5
+        apu.wave.position = 9;
+        apu.wave.timer = 2;
+        apu.write_byte(NR34, TRIGGER | 0x07);
+        assert_eq!([0x44, 0x55, 0x66, 0x77, 0x44], apu.wave.ram[..5]);
+
+        // the first four bytes copy only the one being read
+        let mut apu = playing_wave(Model::Dmg);
+        apu.wave.position = 3;
+        apu.wave.timer = 2;
+        apu.write_byte(NR34, TRIGGER | 0x07);
+        assert_eq!([0x22, 0x11, 0x22, 0x33], apu.wave.ram[..4]);
+
+        // not when the read is further away, nor on a CGB
+        let mut apu = playing_wave(Model::Dmg);
+        apu.wave.timer = 3;
+        apu.write_byte(NR34, TRIGGER | 0x07);
+        assert_eq!(0x00, apu.wave.ram[0]);
+        let mut apu = playing_wave(Model::Cgb);
+        apu.wave.position = 9;
+        apu.wave.timer = 2;
+        apu.write_byte(NR34, TRIGGER | 0x07);
+        assert_eq!(0x00, apu.wave.ram[0]);
+    }
+
+    #[test]
+    fn test_status_of_every_channel() {
+        let mut apu = Apu::new();
+        apu.write_byte(NR22, 0xF0);
+        apu.write_byte(NR24, TRIGGER);
+        apu.write_byte(NR30, 0x80);
+        apu.write_byte(NR34, TRIGGER);
+        apu.write_byte(NR42, 0xF0);
+        apu.write_byte(NR44, TRIGGER);
+        assert_eq!(0xFE, apu.read_byte(NR52));
+        // a channel whose DAC is off does not start
+        apu.write_byte(NR30, 0x00);
+        assert_eq!(0xFA, apu.read_byte(NR52));
+        apu.write_byte(NR34, TRIGGER);
+        assert_eq!(0xFA, apu.read_byte(NR52));
+        apu.write_byte(NR42, 0x00);
+        assert_eq!(0xF2, apu.read_byte(NR52));
+    }
+
+    #[test]
+    fn test_noise_length_expiry_clears_status() {
+        let mut apu = Apu::new();
+        apu.write_byte(NR41, 0x3F);
+        apu.write_byte(NR42, 0xF0);
+        apu.write_byte(NR44, TRIGGER | LENGTH_ENABLE);
+        assert_eq!(0x08, apu.read_byte(NR52) & 0x08);
+        run_steps(&mut apu, 2);
+        assert_eq!(0x00, apu.read_byte(NR52) & 0x08);
+    }
+
+    #[test]
+    fn test_noise_is_not_periodic_like_a_square() {
+        let mut noise = Noise::default();
+        noise.write_envelope(0xF0);
+        noise.write_polynomial(0x00);
+        noise.write_control(TRIGGER, false);
+        let outputs: Vec<u8> = (0..64)
+            .map(|_| {
+                noise.run(8);
+                noise.output()
+            })
+            .collect();
+        assert!(outputs.iter().all(|&output| output == 0 || output == 15));
+        assert!(outputs.contains(&0) && outputs.contains(&15));
+        // the register never ends up all zeros
+        assert_ne!(0, noise.lfsr);
+    }
+
+    #[test]
+    fn test_power_off_clears_registers_but_not_wave_ram() {
+        let mut apu = playing_apu();
+        apu.write_byte(WAVE_RAM_START, 0x12);
+        apu.write_byte(NR52, 0x00);
+        assert_eq!(0x70, apu.read_byte(NR52));
+        assert_eq!(0x00, apu.read_byte(NR50));
+        assert_eq!(0x12, apu.read_byte(WAVE_RAM_START));
+        // and ignores writes until it is turned back on
+        apu.write_byte(NR50, 0x77);
+        assert_eq!(0x00, apu.read_byte(NR50));
+        apu.write_byte(NR52, POWER);
+        apu.write_byte(NR50, 0x77);
+        assert_eq!(0x77, apu.read_byte(NR50));
+    }
+
+    #[test]
+    fn test_sweep_overflow_stops_channel() {
+        let mut apu = Apu::new();
+        apu.write_byte(NR10, 0x11);
+        apu.write_byte(NR12, 0xF0);
+        apu.write_byte(NR13, 0xFF);
+        apu.write_byte(NR14, TRIGGER | 0x07);
+        assert_eq!(0x00, apu.read_byte(NR52) & 0x01);
+    }
+
+    #[test]
+    fn test_tone_produces_samples() {
+        let mut apu = playing_apu();
+        apu.step(255);
+        apu.end_frame();
+        for _ in 0..FRAME_CLOCKS / 4 {
+            apu.step(1);
+        }
+        let samples = apu.end_frame();
+        // a frame at 48kHz, left and right
+        assert!((1590..=1614).contains(&samples.len()), "{}", samples.len());
+        assert!(samples.iter().any(|&sample| sample > 1000));
+        assert!(samples.iter().any(|&sample| sample < -1000));
+        assert!(apu.end_frame().is_empty());
+    }
+
+    #[test]
+    fn test_sample_rate() {
+        let mut apu = playing_apu();
+        apu.set_sample_rate(22_050);
+        for _ in 0..FRAME_CLOCKS / 4 {
+            apu.step(1);
+        }
+        let samples = apu.end_frame();
+        assert!((730..=742).contains(&samples.len()), "{}", samples.len());
+    }
+
+    #[test]
+    fn test_high_pass_removes_dc_offset() {
+        let mut dmg = HighPass::new(Model::Dmg, SAMPLE_RATE);
+        let mut cgb = HighPass::new(Model::Cgb, SAMPLE_RATE);
+        assert_eq!(10000, dmg.filter(10000));
+        let dmg_level = (0..100).map(|_| dmg.filter(10000)).last().unwrap();
+        let cgb_level = (0..100).map(|_| cgb.filter(10000)).last().unwrap();
+        // the CGB capacitor charges up faster
+        assert!(cgb_level < dmg_level / 2, "{} {}", cgb_level, dmg_level);
+        let settled = (0..SAMPLE_RATE / 10).map(|_| dmg.filter(10000)).last();
+        assert_eq!(Some(0), settled);
+        // a swing passes, and ends up centered on 0
+        let swing: Vec<i16> = (0..4800).map(|i| dmg.filter(10000 * (i & 1))).collect();
+        let last = &swing[swing.len() - 2..];
+        assert!(
+            last[0].abs_diff(-5000) < 100 && last[1].abs_diff(5000) < 100,
+            "{:?}",
+            last
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_savestate_keeps_playing() {
+        let mut apu = playing_apu();
+        apu.write_byte(WAVE_RAM_START, 0x12);
+        for _ in 0..FRAME_CLOCKS / 4 * 3 {
+            apu.step(1);
+        }
+        apu.end_frame();
+        let json = serde_json::to_string(&apu).unwrap();
+        let mut restored: Apu = serde_json::from_str(&json).unwrap();
+        assert_eq!(apu.square1.timer, restored.square1.timer);
+        assert_eq!(apu.square1.duty_step, restored.square1.duty_step);
+        assert_eq!(0xF1, restored.read_byte(NR52));
+        assert_eq!(0x12, restored.read_byte(WAVE_RAM_START));
+
+        let frame = |apu: &mut Apu| {
+            for _ in 0..FRAME_CLOCKS / 4 {
+                apu.step(1);
+            }
+            apu.end_frame()
+        };
+        let expected = frame(&mut apu);
+        let samples = frame(&mut restored);
+        assert!(expected.len().abs_diff(samples.len()) <= 2);
+        // the resampler restarts at the last sample, only its phase is lost so edges may
+        // land up to a sample apart
+        for i in 2..expected.len().min(samples.len()) - 2 {
+            let near = &expected[i - 2..=i + 2];
+            let low = *near.iter().min().unwrap() - 300;
+            let high = *near.iter().max().unwrap() + 300;
+            assert!(
+                (low..=high).contains(&samples[i]),
+                "sample {}: {} {:?}",
+                i,
+                samples[i],
+                near
+            );
+        }
+    }
+
+    #[test]
+    fn test_shortest_latency_holds_a_frame() {
+        let mut apu = playing_apu();
+        apu.set_latency(*crate::audio::LATENCIES.start());
+        apu.set_rate_adjustment(1.005);
+        for _ in 0..FRAME_CLOCKS / 4 * 2 {
+            apu.step(1);
+        }
+        assert_eq!(20, apu.latency());
+        assert!(apu.end_frame().len() > 1600);
+        // a new sample rate keeps the latency
+        apu.set_sample_rate(44_100);
+        assert_eq!(20, apu.latency());
+    }
+
+    #[test]
+    fn test_rate_adjustment() {
+        let mut apu = playing_apu();
+        let frame_samples = |apu: &mut Apu| {
+            for _ in 0..FRAME_CLOCKS / 4 * 10 {
+                apu.step(1);
+            }
+            apu.end_frame().len() as f64
+        };
+        frame_samples(&mut apu);
+        let normal = frame_samples(&mut apu);
+        apu.set_rate_adjustment(1.005);
+        let more = frame_samples(&mut apu);
+        apu.set_rate_adjustment(0.995);
+        let fewer = frame_samples(&mut apu);
+        assert!((more / normal - 1.005).abs() < 0.001, "{} {}", normal, more);
+        assert!(
+            (fewer / normal - 0.995).abs() < 0.001,
+            "{} {}",
+            normal,
+            fewer
+        );
+    }
+
+    #[test]
+    fn test_silent_without_nr51() {
+        let mut apu = playing_apu();
+        apu.write_byte(NR51, 0x00);
+        for _ in 0..FRAME_CLOCKS / 4 {
+            apu.step(1);
+        }
+        assert!(apu.end_frame().iter().all(|&sample| sample == 0));
+    }
+}
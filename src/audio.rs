@@ -0,0 +1,263 @@
+// audio output
+// the emulation pushes the apu's samples into a lock-free ring buffer that the sound
+// device's callback drains, the callback never waits for the emulation. when the buffer
+// is full new samples are dropped, when it runs dry the device plays silence
+
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicI16, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// how far dynamic rate control may move the sample rate, too little to hear as pitch
+pub const MAX_RATE_ADJUSTMENT: f64 = 0.005;
+
+// milliseconds of sound buffered for the device. more rides out slow frames without
+// crackling, less makes the sound follow the game sooner
+pub const DEFAULT_LATENCY: u32 = 100;
+// the low end still holds a whole frame
+pub const LATENCIES: RangeInclusive<u32> = 20..=500;
+
+// samples buffered for the device, `latency` ms of stereo
+pub fn buffer_len(sample_rate: u32, latency: u32) -> usize {
+    (sample_rate as u64 * latency as u64 / 1000) as usize * 2
+}
+
+struct Shared {
+    samples: Box<[AtomicI16]>,
+    // both only ever count up, their difference is the number of buffered samples
+    read: AtomicUsize,
+    write: AtomicUsize,
+}
+
+// single producer, single consumer queue of interleaved samples
+pub fn ring_buffer(capacity: usize) -> (Producer, Consumer) {
+    let shared = Arc::new(Shared {
+        samples: (0..capacity).map(|_| AtomicI16::new(0)).collect(),
+        read: AtomicUsize::new(0),
+        write: AtomicUsize::new(0),
+    });
+    (
+        Producer {
+            shared: shared.clone(),
+        },
+        Consumer { shared },
+    )
+}
+
+pub struct Producer {
+    shared: Arc<Shared>,
+}
+
+impl Producer {
+    // queues as many samples as fit, returns how many did
+    pub fn push(&mut self, samples: &[i16]) -> usize {
+        let shared = &self.shared;
+        let capacity = shared.samples.len();
+        let write = shared.write.load(Ordering::Relaxed);
+        let read = shared.read.load(Ordering::Acquire);
+        let count = samples.len().min(capacity - write.wrapping_sub(read));
+        for (i, &sample) in samples[..count].iter().enumerate() {
+            shared.samples[write.wrapping_add(i) % capacity].store(sample, Ordering::Relaxed);
+        }
+        shared
+            .write
+            .store(write.wrapping_add(count), Ordering::Release);
+        count
+    }
+
+    pub fn len(&self) -> usize {
+        let write = self.shared.write.load(Ordering::Relaxed);
+        write.wrapping_sub(self.shared.read.load(Ordering::Acquire))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.shared.samples.len()
+    }
+
+    // dynamic rate control: a factor for the apu's sample rate that steers the buffer
+    // towards half full, making more samples while it drains and fewer while it fills
+    pub fn rate_adjustment(&self) -> f64 {
+        let fill = self.len() as f64 / self.capacity() as f64;
+        1.0 + MAX_RATE_ADJUSTMENT * (1.0 - 2.0 * fill)
+    }
+
+    // blocks until `count` samples fit, for pacing the emulation by the device. gives up
+    // after `timeout` in case the device stopped, false then
+    pub fn wait_for_room(&self, count: usize, timeout: Duration) -> bool {
+        let start = Instant::now();
+        while self.capacity() - self.len() < count.min(self.capacity()) {
+            if start.elapsed() >= timeout {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        true
+    }
+}
+
+pub struct Consumer {
+    shared: Arc<Shared>,
+}
+
+impl Consumer {
+    pub fn pop(&mut self) -> Option<i16> {
+        let shared = &self.shared;
+        let read = shared.read.load(Ordering::Relaxed);
+        if read == shared.write.load(Ordering::Acquire) {
+            return None;
+        }
+        let sample = shared.samples[read % shared.samples.len()].load(Ordering::Relaxed);
+        shared.read.store(read.wrapping_add(1), Ordering::Release);
+        Some(sample)
+    }
+}
+
+// a running output stream, sound stops when it is dropped
+#[cfg(feature = "audio")]
+pub struct AudioOutput {
+    _stream: cpal::Stream,
+}
+
+#[cfg(feature = "audio")]
+impl AudioOutput {
+    // names to pick a device by
+    pub fn device_names() -> Vec<String> {
+        use cpal::traits::{DeviceTrait, HostTrait};
+
+        match cpal::default_host().output_devices() {
+            Ok(devices) => devices.filter_map(|device| device.name().ok()).collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    // plays stereo at `sample_rate` on the named device or the default one, buffering
+    // `latency` ms. the returned producer takes the samples
+    pub fn open(
+        device: Option<&str>,
+        sample_rate: u32,
+        latency: u32,
+    ) -> Result<(Self, Producer), String> {
+        use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+        let host = cpal::default_host();
+        let device = match device {
+            Some(name) => host
+                .output_devices()
+                .map_err(|e| e.to_string())?
+                .find(|device| device.name().is_ok_and(|n| n == name))
+                .ok_or_else(|| format!("no audio device named {}", name))?,
+            None => host
+                .default_output_device()
+                .ok_or("no audio output device")?,
+        };
+        let config = cpal::StreamConfig {
+            channels: 2,
+            sample_rate: cpal::SampleRate(sample_rate),
+            buffer_size: cpal::BufferSize::Default,
+        };
+        let (producer, mut consumer) = ring_buffer(buffer_len(sample_rate, latency));
+        let stream = device
+            .build_output_stream(
+                &config,
+                move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                    for sample in data.iter_mut() {
+                        *sample = consumer.pop().map_or(0.0, |s| s as f32 / 32768.0);
+                    }
+                },
+                |e| eprintln!("audio error: {}", e),
+                None,
+            )
+            .map_err(|e| e.to_string())?;
+        stream.play().map_err(|e| e.to_string())?;
+        Ok((Self { _stream: stream }, producer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_samples_come_out_in_order() {
+        let (mut producer, mut consumer) = ring_buffer(4);
+        assert_eq!(3, producer.push(&[1, 2, 3]));
+        assert_eq!(Some(1), consumer.pop());
+        assert_eq!(Some(2), consumer.pop());
+        // wraps around the end
+        assert_eq!(3, producer.push(&[4, 5, 6]));
+        assert_eq!(4, producer.len());
+        for expected in 3..=6 {
+            assert_eq!(Some(expected), consumer.pop());
+        }
+        assert_eq!(None, consumer.pop());
+        assert!(producer.is_empty());
+    }
+
+    #[test]
+    fn test_full_buffer_drops_new_samples() {
+        let (mut producer, mut consumer) = ring_buffer(2);
+        assert_eq!(2, producer.push(&[1, 2, 3]));
+        assert_eq!(0, producer.push(&[4]));
+        assert_eq!(Some(1), consumer.pop());
+        assert_eq!(Some(2), consumer.pop());
+        assert_eq!(None, consumer.pop());
+    }
+
+    #[test]
+    fn test_buffer_len_follows_latency() {
+        assert_eq!(9600, buffer_len(48_000, DEFAULT_LATENCY));
+        assert_eq!(1764, buffer_len(44_100, 20));
+        // a frame still fits at the lowest latency
+        let frame = 48_000.0 / crate::pacing::GAMEBOY_FRAME_RATE;
+        assert!(buffer_len(48_000, *LATENCIES.start()) as f64 > frame * 2.0);
+    }
+
+    #[test]
+    fn test_rate_adjustment_steers_to_half_full() {
+        let (mut producer, _consumer) = ring_buffer(100);
+        assert_eq!(1.0 + MAX_RATE_ADJUSTMENT, producer.rate_adjustment());
+        producer.push(&[0; 50]);
+        assert_eq!(1.0, producer.rate_adjustment());
+        producer.push(&[0; 50]);
+        assert_eq!(1.0 - MAX_RATE_ADJUSTMENT, producer.rate_adjustment());
+    }
+
+    #[test]
+    fn test_wait_for_room() {
+        let (mut producer, mut consumer) = ring_buffer(8);
+        producer.push(&[0; 6]);
+        assert!(producer.wait_for_room(2, Duration::ZERO));
+        assert!(!producer.wait_for_room(3, Duration::from_millis(5)));
+        let reader = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(5));
+            consumer.pop();
+        });
+        assert!(producer.wait_for_room(3, Duration::from_secs(10)));
+        reader.join().unwrap();
+    }
+
+    #[test]
+    fn test_across_threads() {
+        let (mut producer, mut consumer) = ring_buffer(16);
+        let reader = thread::spawn(move || {
+            let mut received = Vec::new();
+            while received.len() < 1000 {
+                if let Some(sample) = consumer.pop() {
+                    received.push(sample);
+                }
+            }
+            received
+        });
+        let samples: Vec<i16> = (0..1000).collect();
+        let mut sent = 0;
+        while sent < samples.len() {
+            sent += producer.push(&samples[sent..]);
+        }
+        assert_eq!(samples, reader.join().unwrap());
+    }
+}
@@ -0,0 +1,178 @@
+// battery saves
+// cartridge ram of games with a battery is kept in <save dir>/<rom name>.sav, followed by
+// the clock on cartridges with one (see rtc.rs). it is written whenever the ram changed and
+// a few seconds have passed, and once more when the game closes.
+// every write goes to a temporary file that replaces the save in one rename, so a crash
+// halfway through leaves the last complete save behind
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::cartridge::Cartridge;
+use crate::rtc::{FOOTER_LEN, SHORT_FOOTER_LEN};
+
+pub const DEFAULT_AUTOSAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+pub struct BatterySave {
+    path: PathBuf,
+    // shortest time between two autosaves, a game writing its ram every frame doesn't
+    // write the disk every frame
+    interval: Duration,
+    last_write: Instant,
+}
+
+impl BatterySave {
+    pub fn new(path: PathBuf, interval: Duration) -> Self {
+        Self {
+            path,
+            interval,
+            last_write: Instant::now(),
+        }
+    }
+
+    // the save next to the other saves in `save_dir`, named after the rom file
+    pub fn for_rom(save_dir: &Path, rom: &Path, interval: Duration) -> Self {
+        let name = rom.file_stem().unwrap_or(rom.as_os_str());
+        Self::new(save_dir.join(name).with_extension("sav"), interval)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // puts the saved ram and clock into `cartridge`, false when there is no save yet. the
+    // clock catches up with the time that passed since the save
+    pub fn load(&self, cartridge: &mut Cartridge) -> io::Result<bool> {
+        let data = match fs::read(&self.path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        let ram_len = cartridge.ram().len();
+        cartridge.load_ram(&data);
+        if let Some(rtc) = cartridge.rtc_mut() {
+            let footer = data.get(ram_len..).unwrap_or_default();
+            if matches!(footer.len(), FOOTER_LEN | SHORT_FOOTER_LEN) {
+                rtc.load_footer(footer, unix_time());
+            }
+        }
+        Ok(true)
+    }
+
+    // call regularly, writes the ram once it changed and the interval is up. returns
+    // whether it wrote
+    pub fn autosave(&mut self, cartridge: &mut Cartridge) -> io::Result<bool> {
+        if self.last_write.elapsed() < self.interval || !cartridge.is_ram_dirty() {
+            return Ok(false);
+        }
+        self.write(cartridge)
+    }
+
+    // writes right away if the ram changed since the last write. a clock always has moved
+    // on, cartridges with one are written every time
+    pub fn flush(&mut self, cartridge: &mut Cartridge) -> io::Result<bool> {
+        if !cartridge.is_ram_dirty() && cartridge.rtc().is_none() {
+            return Ok(false);
+        }
+        self.write(cartridge)
+    }
+
+    fn write(&mut self, cartridge: &mut Cartridge) -> io::Result<bool> {
+        // a failed write is tried again next time
+        self.last_write = Instant::now();
+        let mut data = cartridge.ram().to_vec();
+        if let Some(rtc) = cartridge.rtc() {
+            data.extend_from_slice(&rtc.to_footer(unix_time()));
+        }
+        write_atomic(&self.path, &data)?;
+        cartridge.mark_ram_saved();
+        Ok(true)
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
+// replaces `path` with `data` without a moment where the file is cut short
+pub fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let temporary = PathBuf::from(temporary);
+    let mut file = File::create(&temporary)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(&temporary, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_saves_changed_ram_and_loads_it_back() {
+        let dir = std::env::temp_dir().join(format!("rustyboy-battery-{}", std::process::id()));
+        let mut rom = vec![0; 0x8000];
+        // MBC1+RAM+BATTERY with 8KB
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x02;
+        let mut cartridge = Cartridge::new();
+        cartridge.load_bytes(rom.clone()).unwrap();
+        assert!(cartridge.has_battery());
+        let mut save = BatterySave::for_rom(&dir, Path::new("roms/game.gb"), Duration::ZERO);
+        assert_eq!(dir.join("game.sav"), save.path());
+        assert!(!save.load(&mut cartridge).unwrap());
+
+        // nothing written yet, nothing to save
+        assert!(!save.autosave(&mut cartridge).unwrap());
+        cartridge.write_byte(0x0000, 0x0A);
+        cartridge.write_byte(0xA010, 0x42);
+        assert!(save.autosave(&mut cartridge).unwrap());
+        assert!(!save.flush(&mut cartridge).unwrap());
+        assert!(!dir.join("game.sav.tmp").exists());
+
+        let mut reloaded = Cartridge::new();
+        reloaded.load_bytes(rom).unwrap();
+        assert!(save.load(&mut reloaded).unwrap());
+        reloaded.write_byte(0x0000, 0x0A);
+        assert_eq!(Some(0x42), reloaded.read_byte(0xA010));
+        assert!(!reloaded.is_ram_dirty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_clock_follows_the_ram() {
+        let dir = std::env::temp_dir().join(format!("rustyboy-rtc-{}", std::process::id()));
+        let mut rom = vec![0; 0x8000];
+        // MBC3+TIMER+RAM+BATTERY with 8KB
+        rom[0x147] = 0x10;
+        rom[0x149] = 0x02;
+        let mut cartridge = Cartridge::new();
+        cartridge.load_bytes(rom.clone()).unwrap();
+        cartridge.write_byte(0x0000, 0x0A);
+        // 5 hours on the clock
+        cartridge.write_byte(0x4000, 0x0A);
+        cartridge.write_byte(0xA000, 5);
+        let mut save = BatterySave::for_rom(&dir, Path::new("clock.gbc"), Duration::ZERO);
+        // the ram is clean, but the clock is saved anyway
+        assert!(save.flush(&mut cartridge).unwrap());
+        assert_eq!(0x2000 + FOOTER_LEN, fs::read(save.path()).unwrap().len());
+
+        let mut reloaded = Cartridge::new();
+        reloaded.load_bytes(rom).unwrap();
+        assert!(save.load(&mut reloaded).unwrap());
+        reloaded.write_byte(0x0000, 0x0A);
+        reloaded.write_byte(0x4000, 0x0A);
+        reloaded.write_byte(0x6000, 0x00);
+        reloaded.write_byte(0x6000, 0x01);
+        assert_eq!(Some(0xE0 | 5), reloaded.read_byte(0xA000));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,8 +1,28 @@
 // memory management unit
 
+use std::cmp::Ordering;
+use std::fmt;
 use std::path::Path;
+use std::str::FromStr;
 
-use crate::{cartridge::Cartridge, serial::Serial, timer::Timer};
+use crate::{
+    apu::Apu,
+    cartridge::{Cartridge, CartridgeError},
+    console::{DebugConsole, DEBUG_CONSOLE},
+    device::Device,
+    dma::Dma,
+    hdma::{Hdma, BLOCK_CYCLES, BLOCK_LEN},
+    input::Button,
+    interrupt::Interrupt,
+    io,
+    joypad::Joypad,
+    memory::Memory,
+    model::Model,
+    ppu::{Mode, Ppu},
+    serial::Serial,
+    sgb::Sgb,
+    timer::Timer,
+};
 
 // NOTE: "word" in this context means 16-bit
 
@@ -10,54 +30,494 @@ const ROM_START: u16 = 0x0000;
 const ROM_END: u16 = 0x7FFF;
 const VRAM_START: u16 = 0x8000;
 const VRAM_END: u16 = 0x9FFF;
+const CARTRIDGE_RAM_START: u16 = 0xA000;
+const CARTRIDGE_RAM_END: u16 = 0xBFFF;
 const WRAM_START: u16 = 0xC000;
 const WRAM_END: u16 = 0xDFFF;
+// mirrors 0xC000-0xDDFF
+const ECHO_START: u16 = 0xE000;
+const ECHO_END: u16 = 0xFDFF;
 const SPRITE_OAM_START: u16 = 0xFE00;
 const SPRITE_OAM_END: u16 = 0xFE9F;
+const UNUSED_START: u16 = 0xFEA0;
+const UNUSED_END: u16 = 0xFEFF;
 const JOYPAD: u16 = 0xFF00;
 const SERIAL_START: u16 = 0xFF01;
 const SERIAL_END: u16 = 0xFF02;
 const TIMER_START: u16 = 0xFF04;
+const DIV: u16 = 0xFF04;
 const TIMER_END: u16 = 0xFF07;
 const INTERRUPT_FLAG: u16 = 0xFF0F;
-const SOUND_START: u16 = 0xFF10;
-const SOUND_END: u16 = 0xFF26;
+const APU_START: u16 = 0xFF10;
+const APU_END: u16 = 0xFF3F;
+const LCD_START: u16 = 0xFF40;
+const LCD_END: u16 = 0xFF4B;
+const OAM_DMA: u16 = 0xFF46;
+const KEY1: u16 = 0xFF4D;
+const VRAM_BANK: u16 = 0xFF4F;
+const HDMA_START: u16 = 0xFF51;
+const HDMA_END: u16 = 0xFF55;
+const CGB_PALETTES_START: u16 = 0xFF68;
+const CGB_PALETTES_END: u16 = 0xFF6B;
+const IO_END: u16 = 0xFF7F;
 const HRAM_START: u16 = 0xFF80;
 const HRAM_END: u16 = 0xFFFE;
 const INTERRUPT_ENABLE: u16 = 0xFFFF;
 
-const WRAM_SIZE: u16 = 0x0FFF;
-const VRAM_SIZE: u16 = 0x1FFF;
+// DIV bit whose falling edge clocks the apu's frame sequencer, the next one up in double
+// speed so it keeps ticking at 512 Hz
+const DIV_APU_BIT: u8 = 0x10;
+const DOUBLE_SPEED_DIV_APU_BIT: u8 = 0x20;
+
+// KEY1
+const SPEED_SWITCH_ARMED: u8 = 0x01;
+const DOUBLE_SPEED: u8 = 0x80;
+// machine cycles the cpu stays stopped while the clock changes
+const SPEED_SWITCH_CYCLES: u32 = 2050;
+
+const WRAM_SIZE: usize = 0x2000;
 const HRAM_SIZE: u16 = 0x7E;
 
+// bits of the I/O registers at 0xFF00-0xFF7F that read as 1 whatever was written, from
+// the register registry. CGB registers are left to their owners as far as they differ
+// on a DMG
+const IO_READ_MASKS: [u8; 0x80] = io::read_masks();
+
+// handlers for one range of the memory map
+struct Mapping {
+    start: u16,
+    end: u16,
+    read: fn(&Bus, u16) -> u8,
+    write: fn(&mut Bus, u16, u8),
+}
+
+// everything the bus decodes, sorted by address. addresses missing here (unused I/O)
+// read 0xFF and ignore writes
+static MEMORY_MAP: [Mapping; 22] = [
+    // from cartridge, usually fixed bank
+    Mapping {
+        start: ROM_START,
+        end: ROM_END,
+        read: |bus, addr| bus.read_cartridge(addr),
+        write: |bus, addr, value| bus.write_mbc(addr, value),
+    },
+    Mapping {
+        start: VRAM_START,
+        end: VRAM_END,
+        read: |bus, addr| bus.ppu.read_byte(addr),
+        write: |bus, addr, value| bus.ppu.write_byte(addr, value),
+    },
+    Mapping {
+        start: CARTRIDGE_RAM_START,
+        end: CARTRIDGE_RAM_END,
+        read: |bus, addr| bus.read_cartridge(addr),
+        write: |bus, addr, value| bus.rom.write_byte(addr, value),
+    },
+    Mapping {
+        start: WRAM_START,
+        end: WRAM_END,
+        read: |bus, addr| bus.working_ram[wram_index(addr)],
+        write: |bus, addr, value| bus.working_ram[wram_index(addr)] = value,
+    },
+    Mapping {
+        start: ECHO_START,
+        end: ECHO_END,
+        read: |bus, addr| bus.working_ram[wram_index(addr)],
+        write: |bus, addr, value| bus.working_ram[wram_index(addr)] = value,
+    },
+    // sprite attribute table
+    Mapping {
+        start: SPRITE_OAM_START,
+        end: SPRITE_OAM_END,
+        read: |bus, addr| bus.ppu.read_byte(addr),
+        write: |bus, addr, value| bus.ppu.write_byte(addr, value),
+    },
+    // prohibited area
+    Mapping {
+        start: UNUSED_START,
+        end: UNUSED_END,
+        read: |bus, addr| bus.read_unused(addr),
+        write: |_, _, _| {},
+    },
+    Mapping {
+        start: JOYPAD,
+        end: JOYPAD,
+        read: |bus, _| bus.read_joypad(),
+        write: |bus, _, value| bus.write_joypad(value),
+    },
+    Mapping {
+        start: SERIAL_START,
+        end: SERIAL_END,
+        read: |bus, addr| bus.serial.read_byte(addr),
+        write: |bus, addr, value| bus.serial.write_byte(addr, value),
+    },
+    Mapping {
+        start: TIMER_START,
+        end: TIMER_END,
+        read: |bus, addr| bus.timer.read_byte(addr),
+        write: |bus, addr, value| bus.write_timer(addr, value),
+    },
+    Mapping {
+        start: INTERRUPT_FLAG,
+        end: INTERRUPT_FLAG,
+        read: |bus, _| bus.interrupt_flag,
+        write: |bus, _, value| bus.interrupt_flag = value & 0x1F,
+    },
+    Mapping {
+        start: APU_START,
+        end: APU_END,
+        read: |bus, addr| bus.apu.read_byte(addr),
+        write: |bus, addr, value| bus.apu.write_byte(addr, value),
+    },
+    Mapping {
+        start: LCD_START,
+        end: OAM_DMA - 1,
+        read: |bus, addr| bus.ppu.read_byte(addr),
+        write: |bus, addr, value| bus.ppu.write_byte(addr, value),
+    },
+    Mapping {
+        start: OAM_DMA,
+        end: OAM_DMA,
+        read: |bus, _| bus.dma.read_byte(),
+        write: |bus, _, value| bus.dma.write_byte(value),
+    },
+    Mapping {
+        start: OAM_DMA + 1,
+        end: LCD_END,
+        read: |bus, addr| bus.ppu.read_byte(addr),
+        write: |bus, addr, value| bus.ppu.write_byte(addr, value),
+    },
+    Mapping {
+        start: KEY1,
+        end: KEY1,
+        read: |bus, _| {
+            if !bus.ppu.is_cgb_mode() {
+                return 0xFF;
+            }
+            let speed = if bus.double_speed { DOUBLE_SPEED } else { 0 };
+            speed | bus.speed_switch_armed as u8
+        },
+        write: |bus, _, value| {
+            if bus.ppu.is_cgb_mode() {
+                bus.speed_switch_armed = value & SPEED_SWITCH_ARMED != 0;
+            }
+        },
+    },
+    Mapping {
+        start: VRAM_BANK,
+        end: VRAM_BANK,
+        read: |bus, addr| bus.ppu.read_byte(addr),
+        write: |bus, addr, value| bus.ppu.write_byte(addr, value),
+    },
+    Mapping {
+        start: HDMA_START,
+        end: HDMA_END,
+        read: |bus, addr| {
+            if !bus.ppu.is_cgb_mode() {
+                return 0xFF;
+            }
+            bus.hdma.read_byte(addr)
+        },
+        write: |bus, addr, value| {
+            if bus.ppu.is_cgb_mode() {
+                bus.write_hdma(addr, value);
+            }
+        },
+    },
+    Mapping {
+        start: CGB_PALETTES_START,
+        end: CGB_PALETTES_END,
+        read: |bus, addr| bus.ppu.read_byte(addr),
+        write: |bus, addr, value| bus.ppu.write_byte(addr, value),
+    },
+    // unconnected, unless the homebrew console is on
+    Mapping {
+        start: DEBUG_CONSOLE,
+        end: DEBUG_CONSOLE,
+        read: |_, _| 0xFF,
+        write: |bus, _, value| {
+            if let Some(console) = &mut bus.debug_console {
+                console.write_byte(value);
+            }
+        },
+    },
+    // high ram (HRAM)
+    Mapping {
+        start: HRAM_START,
+        end: HRAM_END,
+        read: |bus, addr| bus.high_ram[(addr - HRAM_START) as usize],
+        write: |bus, addr, value| bus.high_ram[(addr - HRAM_START) as usize] = value,
+    },
+    // interrupts the game wants to be serviced (IE)
+    Mapping {
+        start: INTERRUPT_ENABLE,
+        end: INTERRUPT_ENABLE,
+        read: |bus, _| bus.interrupt_enable,
+        write: |bus, _, value| bus.interrupt_enable = value,
+    },
+];
+
+fn mapping(addr: u16) -> Option<&'static Mapping> {
+    MEMORY_MAP
+        .binary_search_by(|mapping| {
+            if mapping.end < addr {
+                Ordering::Less
+            } else if mapping.start > addr {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        })
+        .ok()
+        .map(|index| &MEMORY_MAP[index])
+}
+
+// a device the user put on the bus
+struct AttachedDevice {
+    start: u16,
+    end: u16,
+    device: Box<dyn Device>,
+}
+
+impl AttachedDevice {
+    fn contains(&self, addr: u16) -> bool {
+        (self.start..=self.end).contains(&addr)
+    }
+}
+
+// the two buses behind the cpu, OAM DMA only takes over the one it reads from
+#[derive(PartialEq, Eq)]
+enum MemoryBus {
+    // cartridge and work ram
+    External,
+    Vram,
+}
+
+fn memory_bus(addr: u16) -> Option<MemoryBus> {
+    match addr {
+        VRAM_START..=VRAM_END => Some(MemoryBus::Vram),
+        ROM_START..=ECHO_END => Some(MemoryBus::External),
+        // OAM, I/O and HRAM sit inside the cpu
+        _ => None,
+    }
+}
+
+// work ram and its echo above it share one decode, the echo is 0x2000 higher
+fn wram_index(addr: u16) -> usize {
+    (addr - WRAM_START) as usize % WRAM_SIZE
+}
+
+// what reads of addresses nothing drives (no cartridge ram, a removed cartridge) see
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OpenBus {
+    // the data lines are pulled up, 0xFF
+    #[default]
+    PullUp,
+    // the lines keep the last value that was on them
+    LastValue,
+}
+
+impl FromStr for OpenBus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ff" => Ok(Self::PullUp),
+            "last" => Ok(Self::LastValue),
+            _ => Err(format!("unknown open bus model {}, expected ff or last", s)),
+        }
+    }
+}
+
+impl fmt::Display for OpenBus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PullUp => write!(f, "ff"),
+            Self::LastValue => write!(f, "last"),
+        }
+    }
+}
+
+// a cpu access that collided with OAM DMA, games doing this rely on bus behaviour we may
+// not emulate. write is the value written, None for reads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusConflict {
+    pub pc: u16,
+    // machine cycles since power on when the instruction started
+    pub cycle: u64,
+    pub addr: u16,
+    pub write: Option<u8>,
+}
+
+// called with the address and value of a cpu access
+pub type AccessHook = Box<dyn FnMut(u16, u8)>;
+
+// called with the new state whenever the cartridge turns its rumble motor on or off
+pub type RumbleHook = Box<dyn FnMut(bool)>;
+
+// tools watching the bus (debugger, coverage, logging who wrote a register) hook in here
+// instead of changing the bus. reads see the value the cpu got, writes the value the cpu
+// wrote even when OAM DMA kept it from landing
+#[derive(Default)]
+pub struct AccessHooks {
+    reads: Vec<AccessHook>,
+    writes: Vec<AccessHook>,
+}
+
+impl AccessHooks {
+    pub fn on_read(&mut self, hook: AccessHook) {
+        self.reads.push(hook);
+    }
+
+    pub fn on_write(&mut self, hook: AccessHook) {
+        self.writes.push(hook);
+    }
+
+    pub fn clear(&mut self) {
+        self.reads.clear();
+        self.writes.clear();
+    }
+
+    fn read(&mut self, addr: u16, value: u8) {
+        for hook in &mut self.reads {
+            hook(addr, value);
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        for hook in &mut self.writes {
+            hook(addr, value);
+        }
+    }
+}
+
 // can be read from or written to by the CPU
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Bus {
     pub timer: Timer,
+    // only the banks, clock and ram of the cartridge are serialized, the rom is moved
+    // over from the running bus after deserializing. older states load without them
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            default,
+            serialize_with = "crate::cartridge::serialize_state",
+            deserialize_with = "crate::cartridge::deserialize_state"
+        )
+    )]
     rom: Cartridge,
     pub serial: Serial, // TODO: make private when done testing
+    // owns VRAM, OAM and the LCD registers
+    pub ppu: Ppu,
+    // sound registers, channels and the samples they produced
+    // states saved before it was part of them load with a fresh one
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub apu: Apu,
+    #[cfg_attr(feature = "serde", serde(default))]
+    joypad: Joypad,
+    dma: Dma,
+    // CGB VRAM DMA
+    #[cfg_attr(feature = "serde", serde(default))]
+    hdma: Hdma,
     // internal ram
     working_ram: Vec<u8>,
-    // stores graphic tiles
-    // OAM stores data that tells the gameboy
-    // which tiles to use to construct moving objects on the screen
-    video_ram: Vec<u8>,
     high_ram: Vec<u8>,
+    // IF, interrupts requested by the hardware
+    interrupt_flag: u8,
+    // IE, interrupts the game wants to be serviced
+    interrupt_enable: u8,
+    model: Model,
+    // CGB double speed, the cpu and what it clocks (timer, serial, OAM DMA) run at 8MHz
+    // while the ppu and apu stay at 4MHz
+    #[cfg_attr(feature = "serde", serde(default))]
+    double_speed: bool,
+    // KEY1 bit 0, the next STOP switches speed
+    #[cfg_attr(feature = "serde", serde(default))]
+    speed_switch_armed: bool,
+    // t-cycles handed to tick that don't make up a machine cycle yet
+    #[cfg_attr(feature = "serde", serde(default))]
+    leftover_t_cycles: u8,
+    // cpu reads and writes of IO registers so far, lets the watchdog tell polling from a hang
+    #[cfg_attr(feature = "serde", serde(skip))]
+    io_accesses: u64,
+    // diagnostic mode, records accesses that depend on unemulated hardware quirks
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub strict: bool,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    conflicts: Vec<BusConflict>,
+    // pc and start cycle of the instruction being executed, for the conflict log
+    #[cfg_attr(feature = "serde", serde(skip))]
+    instruction_pc: u16,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    cycles: u64,
+    // homebrew debug output at 0xFF7F, None leaves the address unconnected
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub debug_console: Option<DebugConsole>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub hooks: AccessHooks,
+    // frontends vibrate the gamepad from here
+    #[cfg_attr(feature = "serde", serde(skip))]
+    rumble_hook: Option<RumbleHook>,
+    // see attach_device
+    #[cfg_attr(feature = "serde", serde(skip))]
+    devices: Vec<AttachedDevice>,
+    // some games probe for cartridge ram by reading it back, set this to match the hardware
+    // they expect
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub open_bus: OpenBus,
+    // last value a cpu access put on the bus
+    #[cfg_attr(feature = "serde", serde(default))]
+    bus_value: u8,
+    // the SNES side of a Super Game Boy, for SGB games on a DMG
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub sgb: Option<Sgb>,
 }
 
 impl Bus {
-    pub fn new(rom_file: &Path) -> Self {
+    pub fn new(rom_file: &Path) -> Result<Self, CartridgeError> {
+        let mut rom = Cartridge::new();
+        rom.load(rom_file)?;
+        Ok(Self::with_cartridge(rom))
+    }
+
+    pub fn from_rom_bytes(data: Vec<u8>) -> Self {
+        let mut rom = Cartridge::new();
+        rom.load_bytes(data).unwrap();
+        Self::with_cartridge(rom)
+    }
+
+    fn with_cartridge(rom: Cartridge) -> Self {
         let mut bus = Self {
             timer: Timer::new(),
             serial: Serial::new(),
-            rom: Cartridge::new(),
-            working_ram: vec![0; WRAM_SIZE as usize + 1],
-            video_ram: vec![0; VRAM_SIZE as usize + 1],
+            rom,
+            ppu: Ppu::new(),
+            apu: Apu::new(),
+            joypad: Joypad::new(),
+            dma: Dma::new(),
+            hdma: Hdma::new(),
+            working_ram: vec![0; WRAM_SIZE],
             high_ram: vec![0; HRAM_SIZE as usize + 1],
+            interrupt_flag: 0,
+            interrupt_enable: 0,
+            model: Model::default(),
+            double_speed: false,
+            speed_switch_armed: false,
+            leftover_t_cycles: 0,
+            io_accesses: 0,
+            strict: false,
+            conflicts: Vec::new(),
+            instruction_pc: 0,
+            cycles: 0,
+            debug_console: None,
+            hooks: AccessHooks::default(),
+            rumble_hook: None,
+            devices: Vec::new(),
+            open_bus: OpenBus::default(),
+            bus_value: 0xFF,
+            sgb: None,
         };
 
-        bus.rom.load(rom_file).unwrap();
-        println!("{}", bus.rom);
-
         // hardware registers
         bus.write_byte(0xFF00, 0xCF);
         bus.write_byte(0xFF01, 0x00);
@@ -94,63 +554,446 @@ impl Bus {
         bus.write_byte(0xFF4A, 0x00);
         bus.write_byte(0xFF4B, 0x00);
 
+        // color games get a color gameboy
+        if bus.rom.supports_cgb() {
+            bus.set_model(Model::Cgb);
+        } else if bus.rom.supports_sgb() {
+            bus.sgb = Some(Sgb::new());
+        }
         bus
     }
 
     pub fn read_byte(&self, addr: u16) -> u8 {
+        if let Some(attached) = self.attached_device(addr) {
+            return attached.device.read_byte(addr);
+        }
+        let value = mapping(addr).map_or(0xFF, |mapping| (mapping.read)(self, addr));
         match addr {
-            // from cartridge, usually fixed bank
-            ROM_START..=ROM_END => self.rom.read_byte(addr),
-            VRAM_START..=VRAM_END => self.video_ram[addr as usize & VRAM_SIZE as usize],
-            0xA000..=0xBFFF => self.rom.read_byte(addr),
-            WRAM_START..=WRAM_END | 0xE000..=0xEFFF | 0xF000..=0xFDFF => {
-                self.working_ram[addr as usize & WRAM_SIZE as usize]
+            // I/O registers, bits that are not connected read as 1
+            JOYPAD..=IO_END => value | IO_READ_MASKS[(addr - JOYPAD) as usize],
+            _ => value,
+        }
+    }
+
+    pub fn write_byte(&mut self, addr: u16, value: u8) {
+        if let Some(index) = self.devices.iter().position(|d| d.contains(addr)) {
+            self.devices[index].device.write_byte(addr, value);
+        } else if let Some(mapping) = mapping(addr) {
+            (mapping.write)(self, addr, value);
+        }
+    }
+
+    // puts `device` on the bus at `start`-`end`, over anything already mapped there.
+    // devices attached later win where ranges overlap
+    pub fn attach_device(&mut self, start: u16, end: u16, device: Box<dyn Device>) {
+        self.devices
+            .insert(0, AttachedDevice { start, end, device });
+    }
+
+    pub fn detach_devices(&mut self) {
+        self.devices.clear();
+    }
+
+    // savestates don't carry devices, a bus loaded from one takes them over from the
+    // running bus
+    pub fn adopt_devices(&mut self, from: &mut Bus) {
+        self.devices = std::mem::take(&mut from.devices);
+    }
+
+    fn attached_device(&self, addr: u16) -> Option<&AttachedDevice> {
+        self.devices.iter().find(|attached| attached.contains(addr))
+    }
+
+    fn read_cartridge(&self, addr: u16) -> u8 {
+        self.rom
+            .read_byte(addr)
+            .unwrap_or_else(|| self.open_bus_value())
+    }
+
+    fn write_mbc(&mut self, addr: u16, value: u8) {
+        let rumble = self.rom.rumble();
+        self.rom.write_byte(addr, value);
+        if self.rom.rumble() != rumble {
+            if let Some(hook) = &mut self.rumble_hook {
+                hook(!rumble);
             }
-            // sprite attribute table
-            SPRITE_OAM_START..=SPRITE_OAM_END => {
-                self.video_ram[addr as usize - SPRITE_OAM_START as usize]
+        }
+    }
+
+    pub fn on_rumble(&mut self, hook: RumbleHook) {
+        self.rumble_hook = Some(hook);
+    }
+
+    // moves the rumble hook over to a bus loaded from a savestate
+    pub fn take_rumble_hook(&mut self) -> Option<RumbleHook> {
+        self.rumble_hook.take()
+    }
+
+    fn read_joypad(&self) -> u8 {
+        self.sgb
+            .as_ref()
+            .and_then(|sgb| sgb.joypad_id())
+            .unwrap_or_else(|| self.joypad.read_byte())
+    }
+
+    fn write_joypad(&mut self, value: u8) {
+        if self.joypad.write_byte(value) {
+            self.request_interrupt(Interrupt::Joypad);
+        }
+        if let Some(sgb) = &mut self.sgb {
+            if let Some(transfer) = sgb.write_joypad(value) {
+                sgb.finish_transfer(transfer, &self.ppu.screen_tile_data());
+            }
+        }
+    }
+
+    fn write_timer(&mut self, addr: u16, value: u8) {
+        let div = self.timer.read_byte(DIV);
+        self.timer.write_byte(addr, value);
+        // resetting DIV can make bit 4 fall early
+        self.clock_div_apu(div);
+    }
+
+    // debugger/frontend read, never has an effect on the emulated hardware
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.read_byte(addr)
+    }
+
+    // bytes from `start` to `end` inclusive as the cpu would see them right now, with the
+    // mapped banks, and 0xFF for VRAM and OAM while the ppu holds them
+    pub fn dump_range(&self, start: u16, end: u16) -> Vec<u8> {
+        (start..=end)
+            .map(|addr| {
+                if self.ppu.is_locked(addr) {
+                    0xFF
+                } else {
+                    self.peek(addr)
+                }
+            })
+            .collect()
+    }
+
+    // debugger/frontend write that only changes the stored byte
+    // rom is patched instead of treated as a bank switch and cartridge ram is written
+    // whether the MBC has it enabled or not. registers whose writes start something (OAM
+    // DMA, DIV, serial transfers, GDMA, channel triggers, APU power, LCD power, palette
+    // index increments, SGB packets) are set without starting it, the other I/O registers
+    // go through the regular write
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        if self.attached_device(addr).is_some() {
+            self.write_byte(addr, value);
+            return;
+        }
+        match addr {
+            ROM_START..=ROM_END => self.rom.poke(addr, value),
+            CARTRIDGE_RAM_START..=CARTRIDGE_RAM_END => self.rom.poke_ram(addr, value),
+            JOYPAD => {
+                self.joypad.write_byte(value);
+            }
+            SERIAL_START..=SERIAL_END => self.serial.poke(addr, value),
+            TIMER_START..=TIMER_END => self.timer.poke(addr, value),
+            APU_START..=APU_END => self.apu.poke(addr, value),
+            OAM_DMA => self.dma.poke(value),
+            LCD_START..=LCD_END | CGB_PALETTES_START..=CGB_PALETTES_END => {
+                self.ppu.poke(addr, value)
             }
-            // prohibited area
-            0xFEA0..=0xFEFF => 0,
-            // I/O registers
-            JOYPAD => 0, // TODO: implement joypad input
-            SERIAL_START..=SERIAL_END => self.serial.read_byte(addr),
-            TIMER_START..=TIMER_END => self.timer.read_byte(addr),
-            INTERRUPT_FLAG => 0,
-            SOUND_START..=SOUND_END => 0,
-            // high ram (HRAM)
-            HRAM_START..=HRAM_END => self.high_ram[addr as usize & HRAM_SIZE as usize],
-            INTERRUPT_ENABLE => 0,
+            HDMA_START..=HDMA_END => self.hdma.poke(addr, value),
+            _ => self.write_byte(addr, value),
+        }
+    }
 
-            _ => 0,
+    // like peek, but reads rom from `bank` instead of the mapped one
+    pub fn peek_banked(&self, bank: u16, addr: u16) -> u8 {
+        match addr {
+            ROM_START..=ROM_END => self.rom.peek_bank(bank, addr),
+            _ => self.peek(addr),
         }
     }
 
-    pub fn write_byte(&mut self, addr: u16, value: u8) {
+    pub fn poke_banked(&mut self, bank: u16, addr: u16, value: u8) {
         match addr {
-            // from cartridge, usually fixed bank
-            ROM_START..=ROM_END => self.rom.write_byte(addr, value),
-            VRAM_START..=VRAM_END => self.video_ram[addr as usize & VRAM_SIZE as usize] = value,
-            0xA000..=0xBFFF => self.rom.write_byte(addr, value),
-            WRAM_START..=WRAM_END => self.working_ram[addr as usize & WRAM_SIZE as usize] = value,
-            // sprite attribute table
-            SPRITE_OAM_START..=SPRITE_OAM_END => {
-                self.video_ram[addr as usize - SPRITE_OAM_START as usize] = value
+            ROM_START..=ROM_END => self.rom.poke_bank(bank, addr, value),
+            _ => self.poke(addr, value),
+        }
+    }
+
+    // machine cycles run since power on, or since the state was loaded
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // bytes of the running OAM DMA already copied, None when idle
+    pub fn dma_progress(&self) -> Option<u8> {
+        self.dma.progress()
+    }
+
+    // accesses that collided with DMA since the last call, only collected in strict mode
+    pub fn take_conflicts(&mut self) -> Vec<BusConflict> {
+        std::mem::take(&mut self.conflicts)
+    }
+
+    // what a cpu access to `addr` sees while OAM DMA runs, None when it goes through. the
+    // dma owns OAM, and on the bus it reads from the cpu gets the byte being copied
+    // instead of the one it asked for. writes there are lost
+    fn dma_conflict(&mut self, addr: u16, write: Option<u8>) -> Option<u8> {
+        let (source, _) = self.dma.next_transfer()?;
+        let value = match addr {
+            SPRITE_OAM_START..=UNUSED_END => 0xFF,
+            _ if memory_bus(addr).is_some() && memory_bus(addr) == memory_bus(source) => {
+                self.read_byte(source)
             }
-            // prohibited area
-            0xFEA0..=0xFEFF => {}
-            // I/O registers
-            JOYPAD => {}
-            SERIAL_START..=SERIAL_END => self.serial.write_byte(addr, value),
-            TIMER_START..=TIMER_END => self.timer.write_byte(addr, value),
-            INTERRUPT_FLAG => {}
-            SOUND_START..=SOUND_END => {}
-            // high ram (HRAM)
-            HRAM_START..=HRAM_END => self.high_ram[addr as usize & HRAM_SIZE as usize] = value,
-            // interrupt enable register (IE)
-            INTERRUPT_ENABLE => {}
-            _ => {}
+            _ => return None,
+        };
+        if self.strict {
+            self.conflicts.push(BusConflict {
+                pc: self.instruction_pc,
+                cycle: self.cycles,
+                addr,
+                write,
+            });
+        }
+        Some(value)
+    }
+
+    fn update_dma(&mut self, m_cycles: u8) {
+        for _ in 0..m_cycles {
+            let Some((source, destination)) = self.dma.next_transfer() else {
+                return;
+            };
+            let value = self.read_byte(source);
+            self.ppu.write_byte(destination, value);
+            self.dma.advance();
+        }
+    }
+
+    // copies the next block of a VRAM DMA transfer
+    fn copy_hdma_block(&mut self) {
+        let (source, destination) = self.hdma.next_block();
+        for i in 0..BLOCK_LEN {
+            let value = self.read_byte(source.wrapping_add(i));
+            self.ppu
+                .write_byte(VRAM_START + ((destination + i) & 0x1FFF), value);
+        }
+        self.hdma.advance();
+    }
+
+    fn write_hdma(&mut self, addr: u16, value: u8) {
+        if self.hdma.write_byte(addr, value) {
+            self.general_dma();
+        }
+    }
+
+    // the whole transfer at once, the rest of the hardware runs on while the cpu waits
+    fn general_dma(&mut self) {
+        let mut blocks = 0;
+        while self.hdma.is_running() {
+            self.copy_hdma_block();
+            blocks += 1;
         }
+        self.stall(blocks * self.hdma_block_cycles());
+    }
+
+    // a block takes the same time in both speeds, twice the machine cycles in double
+    fn hdma_block_cycles(&self) -> u32 {
+        BLOCK_CYCLES << self.double_speed as u32
+    }
+
+    pub fn is_double_speed(&self) -> bool {
+        self.double_speed
+    }
+
+    // STOP with KEY1 armed switches speed, DIV is reset and the cpu waits for the clock
+    fn switch_speed(&mut self) {
+        if !self.ppu.is_cgb_mode() || !self.speed_switch_armed {
+            return;
+        }
+        self.double_speed = !self.double_speed;
+        self.speed_switch_armed = false;
+        let div = self.timer.read_byte(DIV);
+        self.timer.write_byte(DIV, 0);
+        self.clock_div_apu(div);
+        self.stall(SPEED_SWITCH_CYCLES);
+    }
+
+    // advances everything but the cpu
+    fn stall(&mut self, m_cycles: u32) {
+        self.tick(m_cycles * 4);
+    }
+
+    // the one clock of the machine: advances the timer, serial port, OAM and VRAM DMA,
+    // apu and ppu by `t_cycles` and collects the interrupts they raise. everything but
+    // the ppu counts machine cycles, a remainder waits for the next call
+    pub fn tick(&mut self, t_cycles: u32) {
+        let t_cycles = t_cycles + self.leftover_t_cycles as u32;
+        self.leftover_t_cycles = (t_cycles % 4) as u8;
+        let mut m_cycles = t_cycles / 4;
+        while m_cycles > 0 {
+            let step = m_cycles.min(u8::MAX as u32) as u8;
+            self.tick_m_cycles(step);
+            m_cycles -= step as u32;
+        }
+    }
+
+    fn tick_m_cycles(&mut self, m_cycles: u8) {
+        self.cycles += m_cycles as u64;
+        self.update_dma(m_cycles);
+        let div = self.timer.read_byte(DIV);
+        self.timer.update(m_cycles);
+        self.serial.update(m_cycles);
+        self.clock_div_apu(div);
+        // the ppu and apu run on dots, four per machine cycle or two in double speed
+        let dots = (m_cycles as u32 * 4) >> self.double_speed as u32;
+        self.apu.step_dots(dots);
+        self.rom.tick(dots);
+        let hblank = self.ppu.mode() == Mode::HBlank;
+        self.ppu.update_ly(dots);
+        self.interrupt_flag |= std::mem::take(&mut self.ppu.interrupts);
+        if std::mem::take(&mut self.timer.interrupt) {
+            self.request_interrupt(Interrupt::Timer);
+        }
+        if std::mem::take(&mut self.serial.interrupt) {
+            self.request_interrupt(Interrupt::Serial);
+        }
+        // an HBlank transfer copies a block as each HBlank starts
+        if self.hdma.is_hblank_transfer() && !hblank && self.ppu.mode() == Mode::HBlank {
+            self.copy_hdma_block();
+            self.stall(self.hdma_block_cycles());
+        }
+    }
+
+    fn clock_div_apu(&mut self, old_div: u8) {
+        let bit = if self.double_speed {
+            DOUBLE_SPEED_DIV_APU_BIT
+        } else {
+            DIV_APU_BIT
+        };
+        if old_div & bit != 0 && self.timer.read_byte(DIV) & bit == 0 {
+            self.apu.clock_frame_sequencer();
+        }
+    }
+
+    // the buttons the player holds, replacing the last set
+    pub fn set_buttons(&mut self, buttons: &[Button]) {
+        if self.joypad.set_buttons(buttons) {
+            self.request_interrupt(Interrupt::Joypad);
+        }
+    }
+
+    // the buttons held right now
+    pub fn buttons(&self) -> Vec<Button> {
+        self.joypad.buttons()
+    }
+
+    // how far the player tilts the cartridge, see Mbc::set_tilt
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.rom.set_tilt(x, y);
+    }
+
+    pub fn request_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupt_flag |= interrupt.bit();
+    }
+
+    pub fn clear_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupt_flag &= !interrupt.bit();
+    }
+
+    // interrupts that are both requested and enabled
+    pub fn pending_interrupts(&self) -> u8 {
+        self.interrupt_flag & self.interrupt_enable & 0x1F
+    }
+
+    pub fn io_accesses(&self) -> u64 {
+        self.io_accesses
+    }
+
+    fn count_io_access(&mut self, addr: u16) {
+        if (JOYPAD..HRAM_START).contains(&addr) || addr == INTERRUPT_ENABLE {
+            self.io_accesses += 1;
+        }
+    }
+
+    pub fn interrupt_enable(&self) -> u8 {
+        self.interrupt_enable
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    pub fn set_model(&mut self, model: Model) {
+        self.model = model;
+        self.serial.set_fast_clock_available(model == Model::Cgb);
+        self.apu.set_model(model);
+        // DMG games run in compatibility mode on a CGB
+        self.ppu
+            .set_cgb_mode(model == Model::Cgb && self.rom.supports_cgb());
+        // SGB features only light up on a DMG, a CGB ignores the packets
+        if model == Model::Cgb || !self.rom.supports_sgb() {
+            self.sgb = None;
+        } else if self.sgb.is_none() {
+            self.sgb = Some(Sgb::new());
+        }
+    }
+
+    // the area between OAM and the I/O registers is not connected to anything
+    // DMG reads 0x00, or 0xFF while the ppu holds OAM, and cpu accesses during the OAM scan
+    // corrupt OAM like accesses to OAM itself (see oam_bug). CGB repeats the high nibble of
+    // the low address byte (0xFEAx => 0xAA)
+    fn read_unused(&self, addr: u16) -> u8 {
+        match self.model {
+            Model::Dmg => match self.ppu.mode() {
+                Mode::OamScan | Mode::PixelTransfer => 0xFF,
+                _ => 0x00,
+            },
+            Model::Cgb => {
+                let nibble = ((addr >> 4) & 0xF) as u8;
+                nibble << 4 | nibble
+            }
+        }
+    }
+
+    // cpu reads and writes anywhere in 0xFE00-0xFEFF set off the DMG OAM bug. the 16 bit
+    // increments and decrements that also do on hardware are not emulated
+    fn oam_bug(&mut self, addr: u16, write: bool) {
+        if self.model == Model::Dmg && (SPRITE_OAM_START..=UNUSED_END).contains(&addr) {
+            self.ppu.corrupt_oam(write);
+        }
+    }
+
+    fn open_bus_value(&self) -> u8 {
+        match self.open_bus {
+            OpenBus::PullUp => 0xFF,
+            OpenBus::LastValue => self.bus_value,
+        }
+    }
+
+    pub fn cartridge(&self) -> &Cartridge {
+        &self.rom
+    }
+
+    pub fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.rom
+    }
+
+    // savestates don't carry the rom, it is moved over from the running bus
+    pub fn take_cartridge(&mut self) -> Cartridge {
+        std::mem::take(&mut self.rom)
+    }
+
+    pub fn set_cartridge(&mut self, rom: Cartridge) {
+        self.rom = rom;
+    }
+
+    // puts the running cartridge into a bus loaded from a savestate, it takes on the banks,
+    // clock and ram the savestate has
+    pub fn restore_cartridge(&mut self, mut rom: Cartridge) {
+        rom.load_state(self.rom.save_state());
+        self.rom = rom;
+    }
+
+    // rom bank currently mapped at `addr`
+    pub fn rom_bank_at(&self, addr: u16) -> u16 {
+        self.rom.bank_at(addr)
     }
 
     pub fn read_word(&self, addr: u16) -> u16 {
@@ -162,3 +1005,768 @@ impl Bus {
         self.write_byte(addr + 1, (value >> 8) as u8);
     }
 }
+
+impl Memory for Bus {
+    // the cpu path, OAM DMA may get in the way
+    fn read(&mut self, addr: u16) -> u8 {
+        self.count_io_access(addr);
+        self.oam_bug(addr, false);
+        let value = self
+            .dma_conflict(addr, None)
+            .unwrap_or_else(|| self.read_byte(addr));
+        self.bus_value = value;
+        self.hooks.read(addr, value);
+        value
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.count_io_access(addr);
+        self.bus_value = value;
+        self.hooks.write(addr, value);
+        self.oam_bug(addr, true);
+        if self.dma_conflict(addr, Some(value)).is_some() {
+            return;
+        }
+        self.write_byte(addr, value);
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        Bus::peek(self, addr)
+    }
+
+    fn begin_instruction(&mut self, pc: u16) {
+        self.instruction_pc = pc;
+    }
+
+    fn stop(&mut self) {
+        self.switch_speed();
+    }
+
+    fn tick(&mut self, m_cycles: u8) {
+        Bus::tick(self, m_cycles as u32 * 4);
+    }
+
+    fn pending_interrupts(&self) -> u8 {
+        Bus::pending_interrupts(self)
+    }
+
+    fn clear_interrupt(&mut self, interrupt: Interrupt) {
+        Bus::clear_interrupt(self, interrupt);
+    }
+
+    fn rom_bank_at(&self, addr: u16) -> u16 {
+        Bus::rom_bank_at(self, addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dma::DMA_LENGTH;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_every_hram_byte_is_distinct() {
+        let mut bus = Bus::from_rom_bytes(vec![0; 0x8000]);
+        for addr in HRAM_START..=HRAM_END {
+            bus.write_byte(addr, addr as u8);
+        }
+        for addr in HRAM_START..=HRAM_END {
+            assert_eq!(addr as u8, bus.read_byte(addr));
+        }
+    }
+
+    #[test]
+    fn test_work_ram_banks_are_distinct() {
+        let mut bus = Bus::from_rom_bytes(vec![0; 0x8000]);
+        bus.write_byte(0xC123, 0x11);
+        bus.write_byte(0xD123, 0x22);
+        assert_eq!(0x11, bus.read_byte(0xC123));
+        assert_eq!(0x22, bus.read_byte(0xD123));
+        bus.write_byte(WRAM_END, 0x33);
+        assert_eq!(0x33, bus.read_byte(WRAM_END));
+        assert_eq!(0x11, bus.read_byte(0xC123));
+    }
+
+    #[test]
+    fn test_echo_ram_mirrors_work_ram() {
+        let mut bus = Bus::from_rom_bytes(vec![0; 0x8000]);
+        // written through work ram, read through the echo
+        bus.write_byte(0xC000, 0x12);
+        bus.write_byte(0xDDFF, 0x34);
+        assert_eq!(0x12, bus.read_byte(ECHO_START));
+        assert_eq!(0x34, bus.read_byte(ECHO_END));
+        // and the other way around
+        bus.write_byte(0xE456, 0x56);
+        bus.write_byte(0xF800, 0x78);
+        assert_eq!(0x56, bus.read_byte(0xC456));
+        assert_eq!(0x78, bus.read_byte(0xD800));
+        // nothing else moved
+        assert_eq!(0x00, bus.read_byte(0xC457));
+        assert_eq!(0x00, bus.read_byte(0xD456));
+        assert_eq!(0x12, bus.read_byte(0xC000));
+        bus.poke(0xE001, 0x9A);
+        assert_eq!(0x9A, bus.read_byte(0xC001));
+    }
+
+    #[test]
+    fn test_sound_registers_reach_the_apu() {
+        let mut bus = Bus::from_rom_bytes(vec![0; 0x8000]);
+        // left after the boot rom
+        assert_eq!(0xF1, bus.read_byte(0xFF26));
+        assert_eq!(0x77, bus.read_byte(0xFF24));
+        bus.write_byte(0xFF25, 0x5A);
+        assert_eq!(0x5A, bus.read_byte(0xFF25));
+        bus.write_byte(0xFF3F, 0x34);
+        assert_eq!(0x34, bus.read_byte(0xFF3F));
+        Memory::tick(&mut bus, 255);
+        assert!(!bus.apu.end_frame().is_empty());
+    }
+
+    #[test]
+    fn test_access_hooks_see_cpu_accesses() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let mut bus = Bus::from_rom_bytes(vec![0; 0x8000]);
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let reads = log.clone();
+        bus.hooks.on_read(Box::new(move |addr, value| {
+            reads.borrow_mut().push(('r', addr, value))
+        }));
+        let writes = log.clone();
+        bus.hooks.on_write(Box::new(move |addr, value| {
+            writes.borrow_mut().push(('w', addr, value))
+        }));
+        Memory::write(&mut bus, 0xC000, 0x42);
+        assert_eq!(0x42, Memory::read(&mut bus, 0xC000));
+        // debugger peeks are not cpu accesses
+        bus.read_byte(0xC000);
+        assert_eq!(
+            vec![('w', 0xC000, 0x42), ('r', 0xC000, 0x42)],
+            *log.borrow()
+        );
+
+        bus.hooks.clear();
+        Memory::write(&mut bus, 0xC000, 0x43);
+        assert_eq!(2, log.borrow().len());
+    }
+
+    #[test]
+    fn test_unused_io_bits_read_as_one() {
+        let mut bus = Bus::from_rom_bytes(vec![0; 0x8000]);
+        for addr in JOYPAD..=IO_END {
+            if addr != OAM_DMA && !(APU_START..=APU_END).contains(&addr) {
+                bus.write_byte(addr, 0x00);
+            }
+        }
+        assert_eq!(0xCF, bus.read_byte(JOYPAD));
+        assert_eq!(0x7E, bus.read_byte(0xFF02));
+        assert_eq!(0xF8, bus.read_byte(0xFF07));
+        assert_eq!(0xE0, bus.read_byte(INTERRUPT_FLAG));
+        assert_eq!(0x80, bus.read_byte(0xFF41) & 0xF8);
+        assert_eq!(0x00, bus.read_byte(0xFF42));
+        // no registers at these on a DMG
+        for addr in [
+            0xFF03, 0xFF08, 0xFF0E, 0xFF4C, 0xFF4D, 0xFF4F, 0xFF55, 0xFF68, 0xFF7F,
+        ] {
+            assert_eq!(0xFF, bus.read_byte(addr), "{:04X}", addr);
+        }
+    }
+
+    #[test]
+    fn test_tick_keeps_partial_machine_cycles() {
+        let mut bus = Bus::from_rom_bytes(vec![0; 0x8000]);
+        let cycles = bus.cycles;
+        bus.tick(6);
+        assert_eq!(1, bus.cycles - cycles);
+        bus.tick(2);
+        assert_eq!(2, bus.cycles - cycles);
+        // longer than a u8 of machine cycles at once
+        bus.tick(4 * 1000);
+        assert_eq!(1002, bus.cycles - cycles);
+    }
+
+    #[test]
+    fn test_tick_collects_serial_interrupt() {
+        let mut bus = Bus::from_rom_bytes(vec![0; 0x8000]);
+        bus.write_byte(0xFF02, 0x81);
+        bus.tick(8 * bus.serial.bit_period() * 4);
+        assert_eq!(
+            Interrupt::Serial.bit(),
+            bus.interrupt_flag & Interrupt::Serial.bit()
+        );
+    }
+
+    #[test]
+    fn test_tick_collects_timer_interrupt() {
+        let mut bus = Bus::from_rom_bytes(vec![0; 0x8000]);
+        bus.interrupt_flag = 0;
+        bus.write_byte(0xFF04, 0);
+        bus.write_byte(0xFF06, 0x10);
+        bus.write_byte(0xFF05, 0xFF);
+        bus.write_byte(0xFF07, 0x05);
+        // overflows after 4 machine cycles and reloads in the next
+        bus.tick(4 * 4);
+        assert_eq!(0, bus.interrupt_flag & Interrupt::Timer.bit());
+        bus.tick(4);
+        assert_eq!(
+            Interrupt::Timer.bit(),
+            bus.interrupt_flag & Interrupt::Timer.bit()
+        );
+        assert!(!bus.timer.interrupt);
+        assert_eq!(0x10, bus.read_byte(0xFF05));
+    }
+
+    #[test]
+    fn test_joypad_register_and_interrupt() {
+        let mut bus = Bus::from_rom_bytes(vec![0; 0x8000]);
+        // the boot rom leaves both rows deselected
+        assert_eq!(0xCF, bus.read_byte(JOYPAD));
+        bus.write_byte(JOYPAD, 0x10);
+        bus.set_buttons(&[Button::Start]);
+        assert_eq!(0xD7, bus.read_byte(JOYPAD));
+        assert_eq!(Interrupt::Joypad.bit(), bus.interrupt_flag & 0x1F);
+    }
+
+    // machine cycles until a channel 1 tone with two steps of length left goes silent
+    fn cycles_until_length_expires(write_div: bool) -> u32 {
+        let mut bus = Bus::from_rom_bytes(vec![0; 0x8000]);
+        bus.write_byte(0xFF11, 0x3E);
+        bus.write_byte(0xFF12, 0xF0);
+        bus.write_byte(0xFF14, 0xC0);
+        // right after DIV bit 4 went high
+        while bus.read_byte(DIV) & 0x1F != DIV_APU_BIT {
+            Memory::tick(&mut bus, 1);
+        }
+        if write_div {
+            bus.write_byte(DIV, 0);
+        }
+        let mut cycles = 0;
+        while bus.read_byte(0xFF26) & 0x01 != 0 {
+            Memory::tick(&mut bus, 1);
+            cycles += 1;
+        }
+        cycles
+    }
+
+    #[test]
+    fn test_div_write_moves_apu_frame_sequencer() {
+        // DIV increments every 64 machine cycles and bit 4 falls at 32, 64 and 96. the
+        // lengths are clocked on the first and third fall
+        let undisturbed = cycles_until_length_expires(false);
+        assert_eq!(64 * (96 - 16), undisturbed);
+        // the write makes bit 4 fall right away, then DIV counts from 0 again
+        let reset = cycles_until_length_expires(true);
+        assert_eq!(64 * 64, reset);
+    }
+
+    #[test]
+    fn test_unused_area_reads_zero_on_dmg() {
+        let mut bus = Bus::from_rom_bytes(vec![0; 0x8000]);
+        for addr in UNUSED_START..=UNUSED_END {
+            bus.write_byte(addr, 0x55);
+            assert_eq!(0x00, bus.read_byte(addr));
+        }
+    }
+
+    // OAM holding its own offsets, stopped in the OAM scan at `row`
+    fn oam_bug_bus(model: Model, row: usize) -> Bus {
+        let mut bus = Bus::from_rom_bytes(vec![0; 0x8000]);
+        bus.set_model(model);
+        for i in 0..0xA0 {
+            bus.ppu.write_byte(SPRITE_OAM_START + i, i as u8);
+        }
+        while bus.ppu.oam_scan_row() != Some(row) {
+            bus.tick(4);
+        }
+        bus
+    }
+
+    // the first three rows of OAM, with the LCD off so the ppu lets go of it
+    fn oam_rows(bus: &mut Bus) -> Vec<u8> {
+        bus.ppu.write_byte(0xFF40, 0x00);
+        bus.dump_range(SPRITE_OAM_START, SPRITE_OAM_START + 0x17)
+    }
+
+    #[test]
+    fn test_unused_area_accesses_corrupt_oam_on_dmg() {
+        let untouched: Vec<u8> = (0..0x18).collect();
+        // debugger accesses leave it alone
+        let mut bus = oam_bug_bus(Model::Dmg, 2);
+        bus.peek(0xFEA0);
+        bus.poke(0xFEA0, 0x00);
+        assert_eq!(untouched, oam_rows(&mut bus));
+
+        // a = 0x1110, b = 0x0908, c = 0x0D0C
+        let mut bus = oam_bug_bus(Model::Dmg, 2);
+        Memory::read(&mut bus, 0xFEA0);
+        let mut expected = untouched.clone();
+        expected[0x10..0x12].copy_from_slice(&(0x0908u16 | (0x1110 & 0x0D0C)).to_le_bytes());
+        expected.copy_within(0x0A..0x10, 0x12);
+        assert_eq!(expected, oam_rows(&mut bus));
+
+        // writes mix the rows differently
+        let mut bus = oam_bug_bus(Model::Dmg, 1);
+        Memory::write(&mut bus, 0xFEFF, 0x00);
+        let (a, b, c) = (0x0908u16, 0x0100u16, 0x0504u16);
+        let mut expected = untouched.clone();
+        expected[0x08..0x0A].copy_from_slice(&(((a ^ c) & (b ^ c)) ^ c).to_le_bytes());
+        expected.copy_within(0x02..0x08, 0x0A);
+        assert_eq!(expected, oam_rows(&mut bus));
+
+        // the first row is never hit, and CGB hardware doesn't have the bug
+        let mut bus = oam_bug_bus(Model::Dmg, 0);
+        Memory::write(&mut bus, 0xFEFF, 0x00);
+        assert_eq!(untouched, oam_rows(&mut bus));
+        let mut bus = oam_bug_bus(Model::Cgb, 2);
+        Memory::read(&mut bus, 0xFEA0);
+        assert_eq!(untouched, oam_rows(&mut bus));
+    }
+
+    #[test]
+    fn test_unused_area_repeats_high_nibble_on_cgb() {
+        let mut bus = Bus::from_rom_bytes(vec![0; 0x8000]);
+        bus.set_model(Model::Cgb);
+        assert_eq!(0xAA, bus.read_byte(0xFEA0));
+        assert_eq!(0xAA, bus.read_byte(0xFEAF));
+        assert_eq!(0xBB, bus.read_byte(0xFEB7));
+        assert_eq!(0xFF, bus.read_byte(0xFEFF));
+        bus.write_byte(0xFEC3, 0x12);
+        assert_eq!(0xCC, bus.read_byte(0xFEC3));
+    }
+
+    #[test]
+    fn test_open_bus_reads() {
+        // no ram in the header
+        let mut bus = Bus::from_rom_bytes(vec![0; 0x8000]);
+        Memory::write(&mut bus, 0xC000, 0x42);
+        assert_eq!(0xFF, Memory::read(&mut bus, 0xA000));
+        bus.open_bus = OpenBus::LastValue;
+        Memory::write(&mut bus, 0xC000, 0x42);
+        assert_eq!(0x42, Memory::read(&mut bus, 0xBFFF));
+        // a removed cartridge leaves the whole rom area floating
+        bus.take_cartridge();
+        assert_eq!(0x42, Memory::read(&mut bus, 0x0100));
+        assert_eq!(0x42, Memory::read(&mut bus, 0x7FFF));
+        assert_eq!(Ok(OpenBus::LastValue), "last".parse());
+        assert_eq!("ff", OpenBus::PullUp.to_string());
+    }
+
+    #[test]
+    fn test_cartridge_ram_is_mapped() {
+        let mut rom = vec![0; 0x8000];
+        // ROM+RAM with 2KB, repeated over the 8KB window
+        rom[0x147] = 0x08;
+        rom[0x149] = 0x01;
+        let mut bus = Bus::from_rom_bytes(rom);
+        bus.write_byte(0xA123, 0x99);
+        assert_eq!(0x99, bus.read_byte(0xA123));
+        assert_eq!(0x99, bus.read_byte(0xA923));
+        assert_eq!(0x00, bus.read_byte(0xA124));
+    }
+
+    #[test]
+    fn test_poke_only_changes_the_stored_byte() {
+        let mut rom = vec![0; 0x8000];
+        // MBC1+RAM with 8KB, the ram starts disabled
+        rom[0x147] = 0x02;
+        rom[0x149] = 0x02;
+        let mut bus = Bus::from_rom_bytes(rom);
+        bus.poke(0xA010, 0x5A);
+        assert_eq!(0xFF, bus.peek(0xA010));
+        bus.write_byte(0x0000, 0x0A);
+        assert_eq!(0x5A, bus.peek(0xA010));
+
+        bus.tick(0x1234 * 4);
+        let counter = bus.timer.debug_state().counter;
+        bus.poke(DIV, 0x42);
+        assert_eq!(0x42, bus.peek(DIV));
+        assert_eq!(counter & 0x00FF, bus.timer.debug_state().counter & 0x00FF);
+        bus.poke(OAM_DMA, 0xC0);
+        assert_eq!(0xC0, bus.peek(OAM_DMA));
+        assert_eq!(None, bus.dma_progress());
+        bus.poke(0xFF02, 0x81);
+        assert!(!bus.serial.transfer_in_progress());
+        // channel 1 off, then its DAC back on without a trigger
+        bus.write_byte(0xFF12, 0x00);
+        bus.poke(0xFF12, 0xF0);
+        bus.poke(0xFF14, 0x87);
+        assert_eq!(0, bus.peek(0xFF26) & 0x01);
+
+        // powering the apu off would clear its registers
+        bus.poke(0xFF12, 0xA5);
+        bus.poke(0xFF26, 0x00);
+        assert_eq!(0x70, bus.peek(0xFF26));
+        assert_eq!(0xA5, bus.peek(0xFF12));
+        bus.poke(0xFF26, 0x80);
+        // switching the LCD off would reset LY
+        while bus.peek(0xFF44) == 0 {
+            bus.tick(4);
+        }
+        let ly = bus.peek(0xFF44);
+        bus.poke(0xFF40, 0x11);
+        assert_eq!(0x11, bus.peek(0xFF40));
+        assert_eq!(ly, bus.peek(0xFF44));
+
+        let mut bus = cgb_bus();
+        let cycles = bus.cycles;
+        bus.poke(0xFF55, 0x03);
+        assert_eq!(cycles, bus.cycles);
+        assert_eq!(0x83, bus.peek(0xFF55));
+        // palette data keeps its auto incrementing index
+        bus.write_byte(0xFF68, 0x82);
+        bus.poke(0xFF69, 0x1F);
+        assert_eq!(0x82, bus.peek(0xFF68) & 0xBF);
+        assert_eq!(0x1F, bus.peek(0xFF69));
+        bus.write_byte(0xFF6A, 0x84);
+        bus.poke(0xFF6B, 0x2E);
+        assert_eq!(0x84, bus.peek(0xFF6A) & 0xBF);
+        assert_eq!(0x2E, bus.peek(0xFF6B));
+    }
+
+    #[test]
+    fn test_memory_map_is_sorted() {
+        for pair in MEMORY_MAP.windows(2) {
+            assert!(pair[0].start <= pair[0].end);
+            assert!(pair[0].end < pair[1].start, "{:04X}", pair[1].start);
+        }
+        // every address outside I/O has a handler
+        for addr in 0..=u16::MAX {
+            assert!(mapping(addr).is_some() || (JOYPAD..=IO_END).contains(&addr));
+        }
+    }
+
+    // a register that remembers the last write, shared so the test can look at it
+    struct Latch(Rc<Cell<u8>>);
+
+    impl Device for Latch {
+        fn read_byte(&self, addr: u16) -> u8 {
+            self.0.get() ^ addr as u8
+        }
+
+        fn write_byte(&mut self, _addr: u16, value: u8) {
+            self.0.set(value);
+        }
+    }
+
+    #[test]
+    fn test_attached_device_takes_over_its_range() {
+        let mut bus = Bus::from_rom_bytes(vec![0; 0x8000]);
+        let latch = Rc::new(Cell::new(0));
+        bus.attach_device(0xFF70, 0xFF71, Box::new(Latch(latch.clone())));
+        bus.write(0xFF71, 0x5A);
+        assert_eq!(0x5A, latch.get());
+        assert_eq!(0x5A ^ 0x70, bus.read(0xFF70));
+        // I/O read masks don't apply to attached devices
+        assert_eq!(0x5A ^ 0x71, bus.peek(0xFF71));
+        // work ram under a device is left alone
+        bus.write_byte(0xC000, 0x11);
+        bus.attach_device(0xC000, 0xC000, Box::new(Latch(latch.clone())));
+        bus.write_byte(0xC000, 0x22);
+        assert_eq!(0x22, latch.get());
+        bus.detach_devices();
+        assert_eq!(0x11, bus.read_byte(0xC000));
+    }
+
+    #[test]
+    fn test_mbc1_switches_rom_banks() {
+        // every byte of a bank holds its number
+        let mut rom: Vec<u8> = (0..4).flat_map(|bank| vec![bank as u8; 0x4000]).collect();
+        rom[0x147] = 0x01;
+        let mut bus = Bus::from_rom_bytes(rom);
+        assert_eq!(1, bus.read_byte(0x4000));
+        bus.write_byte(0x2000, 0x03);
+        assert_eq!(3, bus.read_byte(0x7FFF));
+        assert_eq!(3, bus.rom_bank_at(0x4000));
+        // past the end of the rom the bank number wraps
+        bus.write_byte(0x2000, 0x06);
+        assert_eq!(2, bus.read_byte(0x4000));
+        assert_eq!(0, bus.read_byte(0x0000));
+    }
+
+    #[test]
+    fn test_rumble_hook_sees_motor_changes() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0x1C;
+        let mut bus = Bus::from_rom_bytes(rom);
+        let motor = Rc::new(Cell::new(Vec::new()));
+        let seen = motor.clone();
+        bus.on_rumble(Box::new(move |on| {
+            let mut changes = seen.take();
+            changes.push(on);
+            seen.set(changes);
+        }));
+        bus.write_byte(0x4000, 0x08);
+        bus.write_byte(0x4000, 0x09);
+        bus.write_byte(0x4000, 0x00);
+        assert_eq!(vec![true, false], motor.take());
+    }
+
+    fn cgb_bus() -> Bus {
+        let mut rom = vec![0; 0x8000];
+        rom[0x143] = 0x80;
+        Bus::from_rom_bytes(rom)
+    }
+
+    #[test]
+    fn test_general_dma_copies_at_once_and_stalls() {
+        let mut bus = cgb_bus();
+        for i in 0..0x20 {
+            bus.write_byte(0xC000 + i, i as u8 + 1);
+        }
+        bus.write_byte(VRAM_BANK, 1);
+        bus.write_byte(0xFF51, 0xC0);
+        bus.write_byte(0xFF52, 0x00);
+        bus.write_byte(0xFF53, 0x08);
+        bus.write_byte(0xFF54, 0x00);
+        let cycles = bus.cycles;
+        bus.write_byte(0xFF55, 0x01);
+        assert_eq!(2 * BLOCK_CYCLES as u64, bus.cycles - cycles);
+        assert_eq!(0xFF, bus.read_byte(0xFF55));
+        assert_eq!(0x01, bus.read_byte(0x8800));
+        assert_eq!(0x20, bus.read_byte(0x881F));
+        bus.write_byte(VRAM_BANK, 0);
+        assert_eq!(0x00, bus.read_byte(0x8800));
+    }
+
+    #[test]
+    fn test_hblank_dma_copies_a_block_per_hblank() {
+        let mut bus = cgb_bus();
+        for i in 0..0x20 {
+            bus.write_byte(0xC000 + i, 0xAA);
+        }
+        bus.write_byte(0xFF51, 0xC0);
+        bus.write_byte(0xFF52, 0x00);
+        bus.write_byte(0xFF53, 0x00);
+        bus.write_byte(0xFF54, 0x00);
+        bus.write_byte(0xFF55, 0x81);
+        assert_eq!(0x00, bus.read_byte(0x8000));
+        let until_hblank = |bus: &mut Bus| {
+            while bus.ppu.mode() == Mode::HBlank {
+                Memory::tick(bus, 1);
+            }
+            while bus.ppu.mode() != Mode::HBlank {
+                Memory::tick(bus, 1);
+            }
+        };
+        until_hblank(&mut bus);
+        assert_eq!(0xAA, bus.read_byte(0x800F));
+        assert_eq!(0x00, bus.read_byte(0x8010));
+        assert_eq!(0x00, bus.read_byte(0xFF55));
+        until_hblank(&mut bus);
+        assert_eq!(0xAA, bus.read_byte(0x801F));
+        assert_eq!(0xFF, bus.read_byte(0xFF55));
+    }
+
+    #[test]
+    fn test_speed_switch() {
+        let mut bus = cgb_bus();
+        assert_eq!(0x7E, bus.read_byte(KEY1));
+        // STOP without arming KEY1 stays at normal speed
+        Memory::stop(&mut bus);
+        assert!(!bus.is_double_speed());
+        bus.write_byte(KEY1, 0xFF);
+        assert_eq!(0x7F, bus.read_byte(KEY1));
+        Memory::stop(&mut bus);
+        assert!(bus.is_double_speed());
+        assert_eq!(0xFE, bus.read_byte(KEY1));
+
+        // the ppu takes twice the machine cycles per line
+        let start = bus.read_byte(0xFF44);
+        while bus.read_byte(0xFF44) == start {
+            Memory::tick(&mut bus, 1);
+        }
+        let line = bus.read_byte(0xFF44);
+        for _ in 0..227 {
+            Memory::tick(&mut bus, 1);
+        }
+        assert_eq!(line, bus.read_byte(0xFF44));
+        Memory::tick(&mut bus, 1);
+        assert_eq!(line + 1, bus.read_byte(0xFF44));
+
+        // a DMG has no KEY1
+        let mut bus = Bus::from_rom_bytes(vec![0; 0x8000]);
+        bus.write_byte(KEY1, 0x01);
+        assert_eq!(0xFF, bus.read_byte(KEY1));
+        Memory::stop(&mut bus);
+        assert!(!bus.is_double_speed());
+    }
+
+    #[test]
+    fn test_cgb_header_selects_color_mode() {
+        let mut bus = cgb_bus();
+        assert_eq!(Model::Cgb, bus.model());
+        assert!(bus.ppu.is_cgb_mode());
+        bus.write_byte(0xFF4F, 1);
+        assert_eq!(0xFF, bus.read_byte(0xFF4F));
+
+        // DMG games keep the DMG path, even on a CGB
+        let mut bus = Bus::from_rom_bytes(vec![0; 0x8000]);
+        bus.set_model(Model::Cgb);
+        assert!(!bus.ppu.is_cgb_mode());
+        assert_eq!(0xFF, bus.read_byte(0xFF68));
+    }
+
+    #[test]
+    fn test_vram_and_oam_are_separate() {
+        let mut bus = Bus::from_rom_bytes(vec![0; 0x8000]);
+        bus.write_byte(VRAM_START, 0x12);
+        bus.write_byte(SPRITE_OAM_START, 0x34);
+        assert_eq!(0x12, bus.read_byte(VRAM_START));
+        assert_eq!(0x34, bus.read_byte(SPRITE_OAM_START));
+    }
+
+    #[test]
+    fn test_tick_drives_ly_and_vblank() {
+        let mut bus = Bus::from_rom_bytes(vec![0; 0x8000]);
+        assert_eq!(0x91, bus.read_byte(0xFF40));
+        assert_eq!(0, bus.read_byte(0xFF44));
+        // one scanline is 114 machine cycles
+        Memory::tick(&mut bus, 114);
+        assert_eq!(1, bus.read_byte(0xFF44));
+        for _ in 1..144 {
+            Memory::tick(&mut bus, 114);
+        }
+        assert_eq!(144, bus.read_byte(0xFF44));
+        assert_eq!(
+            Interrupt::VBlank.bit(),
+            bus.read_byte(INTERRUPT_FLAG) & Interrupt::VBlank.bit()
+        );
+    }
+
+    #[test]
+    fn test_dump_range_sees_the_ppu_locks() {
+        let mut bus = Bus::from_rom_bytes(vec![0; 0x8000]);
+        bus.write_byte(VRAM_START, 0x12);
+        bus.write_byte(SPRITE_OAM_START, 0x34);
+        while bus.ppu.mode() != Mode::OamScan {
+            bus.tick(4);
+        }
+        assert_eq!(vec![0x12], bus.dump_range(VRAM_START, VRAM_START));
+        assert_eq!(
+            vec![0xFF],
+            bus.dump_range(SPRITE_OAM_START, SPRITE_OAM_START)
+        );
+        while bus.ppu.mode() != Mode::PixelTransfer {
+            bus.tick(4);
+        }
+        assert_eq!(vec![0xFF, 0xFF], bus.dump_range(VRAM_START, VRAM_START + 1));
+        assert_eq!(
+            vec![0xFF],
+            bus.dump_range(SPRITE_OAM_START, SPRITE_OAM_START)
+        );
+        while bus.ppu.mode() != Mode::HBlank {
+            bus.tick(4);
+        }
+        assert_eq!(vec![0x12], bus.dump_range(VRAM_START, VRAM_START));
+        assert_eq!(
+            vec![0x34],
+            bus.dump_range(SPRITE_OAM_START, SPRITE_OAM_START)
+        );
+    }
+
+    #[test]
+    fn test_oam_dma_copies_each_source_byte() {
+        let mut bus = Bus::from_rom_bytes(vec![0; 0x8000]);
+        for i in 0..0xA0 {
+            bus.write_byte(0xC100 + i, i as u8 ^ 0x5A);
+        }
+        bus.write_byte(OAM_DMA, 0xC1);
+        assert_eq!(0xC1, bus.read_byte(OAM_DMA));
+        assert_eq!(Some(0), bus.dma_progress());
+
+        Memory::tick(&mut bus, 10);
+        assert_eq!(Some(10), bus.dma_progress());
+        Memory::tick(&mut bus, 149);
+        assert_eq!(Some(159), bus.dma_progress());
+        Memory::tick(&mut bus, 1);
+        assert_eq!(None, bus.dma_progress());
+        for i in 0..0xA0 {
+            assert_eq!(i as u8 ^ 0x5A, bus.read_byte(SPRITE_OAM_START + i));
+        }
+    }
+
+    #[test]
+    fn test_cpu_reads_the_dma_byte_on_its_bus() {
+        let mut bus = Bus::from_rom_bytes(vec![0; 0x8000]);
+        bus.write_byte(0xC000, 0x12);
+        bus.write_byte(0xC001, 0x13);
+        bus.write_byte(0xD000, 0x77);
+        bus.write_byte(0x8000, 0x66);
+        bus.write(HRAM_START, 0x34);
+        bus.write(OAM_DMA, 0xC0);
+
+        // work ram and the cartridge share the bus the dma reads from
+        assert_eq!(0x12, bus.read(0xD000));
+        assert_eq!(0x12, bus.read(0x0150));
+        bus.write(0xD000, 0x99);
+        Memory::tick(&mut bus, 1);
+        assert_eq!(0x13, bus.read(0xD000));
+        // OAM belongs to the dma, VRAM, I/O and HRAM are free
+        assert_eq!(0xFF, bus.read(SPRITE_OAM_START));
+        assert_eq!(0x66, bus.read(0x8000));
+        assert_eq!(0xC0, bus.read(OAM_DMA));
+        assert_eq!(0x34, bus.read(HRAM_START));
+        bus.write(HRAM_START, 0x56);
+        assert_eq!(0x56, bus.read(HRAM_START));
+
+        Memory::tick(&mut bus, DMA_LENGTH);
+        assert_eq!(0x77, bus.read(0xD000));
+        assert_eq!(0x12, bus.read(SPRITE_OAM_START));
+    }
+
+    #[test]
+    fn test_strict_mode_logs_accesses_blocked_by_dma() {
+        let mut bus = Bus::from_rom_bytes(vec![0; 0x8000]);
+        bus.write(OAM_DMA, 0xC0);
+        bus.read(0xC000);
+        assert!(bus.take_conflicts().is_empty());
+
+        bus.strict = true;
+        Memory::tick(&mut bus, 10);
+        bus.begin_instruction(0xFF80);
+        bus.read(HRAM_START);
+        bus.read(0xC000);
+        bus.write(0x8000, 0x42);
+        bus.write(0xA000, 0x42);
+        assert_eq!(
+            vec![
+                BusConflict {
+                    pc: 0xFF80,
+                    cycle: 10,
+                    addr: 0xC000,
+                    write: None
+                },
+                BusConflict {
+                    pc: 0xFF80,
+                    cycle: 10,
+                    addr: 0xA000,
+                    write: Some(0x42)
+                },
+            ],
+            bus.take_conflicts()
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_fixture_oam_dma_finishing() {
+        let mut bus = Bus::from_rom_bytes(vec![0; 0x8000]);
+        bus.dma = serde_json::from_str(include_str!("../tests/fixtures/oam_dma_in_progress.json"))
+            .unwrap();
+        bus.write_byte(0xC19E, 0xAA);
+        bus.write_byte(0xC19F, 0xBB);
+        assert_eq!(0xAA, bus.read(0xC000));
+
+        Memory::tick(&mut bus, 1);
+        assert_eq!(Some(159), bus.dma_progress());
+        assert_eq!(0xAA, bus.read_byte(0xFE9E));
+        Memory::tick(&mut bus, 1);
+        assert_eq!(None, bus.dma_progress());
+        assert_eq!(0xBB, bus.read_byte(0xFE9F));
+        // the cpu gets the bus back right after the last byte
+        assert_eq!(0xAA, bus.read(0xC19E));
+    }
+}
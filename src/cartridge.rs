@@ -1,17 +1,103 @@
 use std::fmt;
 use std::fs;
+use std::io;
 use std::path::Path;
 
-const ROM_SIZE: u32 = 0x7FFF;
+use crate::eeprom::EEPROM_LEN;
+use crate::gamedb::{self, GameEntry, Quirk};
+use crate::mbc::{Mbc, Mbc1, Mbc5, RomRegion, ROM_BANK_SIZE};
+use crate::model::Region;
+use crate::rtc::Rtc;
 
+// the header ends at 0x014F, anything shorter can't be a rom
+pub const HEADER_END: usize = 0x150;
+// the Nintendo logo at 0x0104-0x0133, the boot rom refuses to start without it
+const LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+// what a savestate keeps of the cartridge: the bank controller with its clock and the
+// external ram. the rom itself is never part of it
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CartridgeState {
+    mbc: Mbc,
+    ram: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum CartridgeError {
+    Io(io::Error),
+    // the file is shorter than a header, holds its length
+    TooSmall(usize),
+    BadLogo,
+    // the checksums as computed and as the header has them
+    BadHeaderChecksum { computed: u8, header: u8 },
+    BadGlobalChecksum { computed: u16, header: u16 },
+    // the cartridge type at 0x0147 names a controller that isn't emulated
+    UnsupportedMapper(u8),
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::TooSmall(len) => write!(
+                f,
+                "{} bytes is too small for a rom, the header alone is {}",
+                len, HEADER_END
+            ),
+            Self::BadLogo => write!(f, "the Nintendo logo in the header is damaged"),
+            Self::BadHeaderChecksum { computed, header } => write!(
+                f,
+                "header checksum is {:#04X} but the header says {:#04X}",
+                computed, header
+            ),
+            Self::BadGlobalChecksum { computed, header } => write!(
+                f,
+                "global checksum is {:#06X} but the header says {:#06X}",
+                computed, header
+            ),
+            Self::UnsupportedMapper(code) => write!(
+                f,
+                "cartridge type {:#04X} ({}) is not supported",
+                code,
+                cartridge_type_name(*code)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CartridgeError {}
+
+impl From<io::Error> for CartridgeError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+#[derive(Clone)]
 pub struct Cartridge {
     title: String,
     ctype: &'static str,
     rom_size: &'static str,
     ram_size: &'static str,
     rom_version: String,
+    // the whole rom, a whole number of banks
     data: Vec<u8>,
+    rom_bank_count: usize,
+    // external ram, empty when the cartridge has none
+    ram: Vec<u8>,
+    // ram written since the last battery save
+    ram_dirty: bool,
+    mbc: Mbc,
     checksum: u8,
+    // the game database entry for this rom, its quirks are applied
+    game: Option<&'static GameEntry>,
+    // sum of every byte of the image as loaded, before any padding
+    global_checksum: u16,
 }
 
 impl Cartridge {
@@ -22,73 +108,372 @@ impl Cartridge {
             rom_size: "UNKNOWN",
             ram_size: "UNKNOWN",
             rom_version: "".to_string(),
-            data: vec![0; ROM_SIZE as usize],
+            data: Vec::new(),
+            rom_bank_count: 0,
+            ram: Vec::new(),
+            ram_dirty: false,
+            mbc: Mbc::None,
             checksum: 0,
+            game: None,
+            global_checksum: 0,
         }
     }
 
-    // TODO: add the different MBC's here.
-    pub fn load(&mut self, path: &Path) -> Result<(), &str> {
-        self.data = fs::read(path).unwrap();
+    // a rom file, which has to have a whole header
+    pub fn load(&mut self, path: &Path) -> Result<(), CartridgeError> {
+        let data = fs::read(path)?;
+        if data.len() < HEADER_END {
+            return Err(CartridgeError::TooSmall(data.len()));
+        }
+        self.load_bytes(data)?;
         println!("{:?} loaded.", path);
+        Ok(())
+    }
+
+    // load a ROM image that is already in memory, e.g. from a fuzzer or a test
+    // images are padded with 0xFF like unmapped ROM reads to a whole number of banks, and
+    // to the two banks of a 32KB rom at least. controllers that aren't emulated leave the
+    // rom mapped like a ROM ONLY cartridge, which is enough for some bootlegs, validate
+    // reports them
+    pub fn load_bytes(&mut self, data: Vec<u8>) -> Result<(), CartridgeError> {
+        self.global_checksum = global_checksum(&data);
+        let mbc = Mbc::detect(&data);
+        self.rom_bank_count = data.len().div_ceil(ROM_BANK_SIZE).max(2);
+        self.data = data;
+        self.data.resize(self.rom_bank_count * ROM_BANK_SIZE, 0xFF);
+        self.title.clear();
         self.get_title();
         self.get_cartridge_type();
         self.get_rom_size();
         self.get_ram_size();
+        self.mbc = mbc.unwrap_or(Mbc::None);
+        // MBC7 headers list no ram, it holds the EEPROM
+        self.ram = match self.mbc {
+            Mbc::Mbc7(_) => vec![0xFF; EEPROM_LEN],
+            _ => vec![0; ram_bytes(self.data[0x149])],
+        };
         self.get_version();
         self.calculate_and_check_checksum();
+        self.game = gamedb::lookup(self.title(), self.header(0x14D));
+        self.apply_quirks();
         Ok(())
     }
 
-    pub fn read_byte(&self, addr: u16) -> u8 {
-        self.data[addr as usize]
+    fn apply_quirks(&mut self) {
+        let Some(game) = self.game else {
+            return;
+        };
+        for quirk in game.quirks {
+            match *quirk {
+                Quirk::Mbc1Multicart if matches!(self.mbc, Mbc::Mbc1(_)) => {
+                    self.mbc = Mbc::Mbc1(Mbc1::multicart())
+                }
+                Quirk::Rumble if matches!(self.mbc, Mbc::Mbc5(_)) => {
+                    self.mbc = Mbc::Mbc5(Mbc5::new(true))
+                }
+                Quirk::RamSize(len) => self.ram = vec![0; len],
+                _ => {}
+            }
+        }
+    }
+
+    // the game database entry that matched when the rom loaded
+    pub fn game_entry(&self) -> Option<&'static GameEntry> {
+        self.game
+    }
+
+    // None where the cartridge doesn't drive the bus: without a rom (the cartridge was
+    // removed) or without external ram, or with the ram disabled
+    pub fn read_byte(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0xA000..=0xBFFF => self
+                .mbc
+                .read_register(addr)
+                .or_else(|| self.ram_index(addr).map(|index| self.ram[index])),
+            _ => {
+                let (region, offset) = RomRegion::split(addr);
+                self.read_rom(region, offset)
+            }
+        }
     }
 
+    // `offset` into the bank the controller maps in `region`, None without a rom
+    pub fn read_rom(&self, region: RomRegion, offset: u16) -> Option<u8> {
+        let bank = self.mapped_bank(region)?;
+        Some(self.data[bank * ROM_BANK_SIZE + offset as usize])
+    }
+
+    pub fn rom_bank_count(&self) -> usize {
+        self.rom_bank_count
+    }
+
+    // rom writes go to the bank controller
     pub fn write_byte(&mut self, addr: u16, value: u8) {
-        self.data[addr as usize] = value;
+        match addr {
+            0xA000..=0xBFFF => {
+                if self.mbc.write_register(addr, value, &mut self.ram) {
+                    self.ram_dirty |= self.mbc.take_ram_written();
+                    return;
+                }
+                if let Some(index) = self.ram_index(addr) {
+                    self.ram_dirty |= self.ram[index] != value;
+                    self.ram[index] = value;
+                }
+            }
+            _ => self.mbc.write_byte(addr, value),
+        }
+    }
+
+    // cartridge types with a battery that keeps the ram when the power is off
+    pub fn has_battery(&self) -> bool {
+        matches!(
+            self.header(0x147),
+            0x03 | 0x06 | 0x09 | 0x0D | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E | 0x22 | 0xFE | 0xFF
+        )
+    }
+
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    // restores a battery save, files of another size fill what fits
+    pub fn load_ram(&mut self, data: &[u8]) {
+        let len = data.len().min(self.ram.len());
+        self.ram[..len].copy_from_slice(&data[..len]);
+        self.ram_dirty = false;
+    }
+
+    pub fn is_ram_dirty(&self) -> bool {
+        self.ram_dirty
+    }
+
+    pub fn mark_ram_saved(&mut self) {
+        self.ram_dirty = false;
+    }
+
+    // the MBC3 real time clock, None on cartridges without one
+    pub fn rtc(&self) -> Option<&Rtc> {
+        self.mbc.rtc()
+    }
+
+    pub fn rtc_mut(&mut self) -> Option<&mut Rtc> {
+        self.mbc.rtc_mut()
+    }
+
+    // runs the cartridge's clock, `dots` at 4 MHz whatever speed the cpu runs at
+    pub fn tick(&mut self, dots: u32) {
+        if let Some(rtc) = self.mbc.rtc_mut() {
+            rtc.tick(dots);
+        }
+    }
+
+    // the rumble motor of MBC5 rumble cartridges
+    pub fn rumble(&self) -> bool {
+        self.mbc.rumble()
+    }
+
+    pub fn ir_led(&self) -> bool {
+        self.mbc.ir_led()
+    }
+
+    pub fn set_ir_light(&mut self, light: bool) {
+        self.mbc.set_ir_light(light);
+    }
+
+    // see Mbc::set_tilt
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.mbc.set_tilt(x, y);
+    }
+
+    // the first thing wrong with the header. the boot rom checks the logo and the header
+    // checksum before it starts the game, nothing checks the global checksum but a bad one
+    // gives away a bad dump. the emulator starts the game either way, this is for telling
+    // the player or refusing in strict mode
+    pub fn validate(&self) -> Result<(), CartridgeError> {
+        if !self.is_mapper_emulated() {
+            return Err(CartridgeError::UnsupportedMapper(self.header(0x147)));
+        }
+        if self.data.get(0x104..0x134) != Some(&LOGO[..]) {
+            return Err(CartridgeError::BadLogo);
+        }
+        if self.checksum != self.header(0x14D) {
+            return Err(CartridgeError::BadHeaderChecksum {
+                computed: self.checksum,
+                header: self.header(0x14D),
+            });
+        }
+        if self.global_checksum != self.header_global_checksum() {
+            return Err(CartridgeError::BadGlobalChecksum {
+                computed: self.global_checksum,
+                header: self.header_global_checksum(),
+            });
+        }
+        Ok(())
+    }
+
+    fn is_mapper_emulated(&self) -> bool {
+        Mbc::detect(&self.data).is_some()
+    }
+
+    pub fn save_state(&self) -> CartridgeState {
+        CartridgeState {
+            mbc: self.mbc.clone(),
+            ram: self.ram.clone(),
+        }
+    }
+
+    // a state saved from a different kind of cartridge can't change this one's hardware,
+    // the controller and the ram are only taken when they match what is plugged in
+    pub fn load_state(&mut self, state: CartridgeState) {
+        if std::mem::discriminant(&state.mbc) == std::mem::discriminant(&self.mbc) {
+            self.mbc = state.mbc;
+        }
+        if state.ram.len() == self.ram.len() && state.ram != self.ram {
+            self.ram = state.ram;
+            self.ram_dirty = true;
+        }
+    }
+
+    pub fn is_inserted(&self) -> bool {
+        !self.data.is_empty()
+    }
+
+    // bank mapped in `region`, bank numbers past the end of the rom wrap around like the
+    // unconnected bank lines do. None without a rom
+    fn mapped_bank(&self, region: RomRegion) -> Option<usize> {
+        if !self.is_inserted() {
+            return None;
+        }
+        Some(self.mbc.rom_bank(region) as usize % self.rom_bank_count)
+    }
+
+    // smaller rams repeat over 0xA000-0xBFFF
+    fn ram_index(&self, addr: u16) -> Option<usize> {
+        self.ram_index_in(self.mbc.ram_bank()?, addr)
+    }
+
+    fn ram_index_in(&self, bank: u16, addr: u16) -> Option<usize> {
+        if self.ram.is_empty() {
+            return None;
+        }
+        Some((bank as usize * 0x2000 + (addr - 0xA000) as usize) % self.ram.len())
+    }
+
+    // header byte, 0 without a rom
+    fn header(&self, addr: usize) -> u8 {
+        self.data.get(addr).copied().unwrap_or(0)
+    }
+
+    pub fn title(&self) -> &str {
+        self.title.trim_end_matches('\0')
+    }
+
+    // CGB flag at 0x0143, set for games that use color features (0x80) or need them (0xC0)
+    pub fn supports_cgb(&self) -> bool {
+        self.header(0x143) & 0x80 != 0
+    }
+
+    // SGB flag at 0x0146, only honoured with the new licensee code marker at 0x014B
+    pub fn supports_sgb(&self) -> bool {
+        self.header(0x146) == 0x03 && self.header(0x14B) == 0x33
+    }
+
+    pub fn destination(&self) -> Region {
+        Region::from_header(self.header(0x14A))
+    }
+
+    // force the destination code, the header checksum is patched so the header stays valid
+    pub fn set_destination(&mut self, region: Region) {
+        self.data[0x14A] = region.header_code();
+        self.calculate_and_check_checksum();
+        self.data[0x14D] = self.checksum;
+    }
+
+    // header checksum computed over 0x0134-0x014C
+    pub fn checksum(&self) -> u8 {
+        self.checksum
+    }
+
+    // 16 bit sum of the whole rom computed without 0x014E-0x014F
+    pub fn global_checksum(&self) -> u16 {
+        self.global_checksum
+    }
+
+    // the global checksum at 0x014E-0x014F, big endian unlike everything else
+    fn header_global_checksum(&self) -> u16 {
+        u16::from_be_bytes([self.header(0x14E), self.header(0x14F)])
+    }
+
+    // offset into the rom image of `addr` in `bank`, bank 0 is always at 0x0000-0x3FFF
+    fn banked_offset(bank: u16, addr: u16) -> usize {
+        match addr {
+            0x0000..=0x3FFF => addr as usize,
+            _ => bank as usize * ROM_BANK_SIZE + (addr as usize & 0x3FFF),
+        }
+    }
+
+    // read any rom bank regardless of what is mapped, 0xFF past the end of the image
+    pub fn peek_bank(&self, bank: u16, addr: u16) -> u8 {
+        self.data
+            .get(Self::banked_offset(bank, addr))
+            .copied()
+            .unwrap_or(0xFF)
+    }
+
+    // patch the rom image directly, bypassing any bank switching writes
+    pub fn poke_bank(&mut self, bank: u16, addr: u16, value: u8) {
+        if let Some(byte) = self.data.get_mut(Self::banked_offset(bank, addr)) {
+            *byte = value;
+        }
+    }
+
+    // patch the rom byte mapped at `addr`
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        let (region, offset) = RomRegion::split(addr);
+        if let Some(bank) = self.mapped_bank(region) {
+            self.data[bank * ROM_BANK_SIZE + offset as usize] = value;
+        }
+    }
+
+    // patch the ram byte the controller has selected at `addr`, even while the ram is
+    // disabled. registers mapped there instead (clock, EEPROM) are left alone
+    pub fn poke_ram(&mut self, addr: u16, value: u8) {
+        let Some(index) = self
+            .mbc
+            .selected_ram_bank()
+            .and_then(|bank| self.ram_index_in(bank, addr))
+        else {
+            return;
+        };
+        self.ram_dirty |= self.ram[index] != value;
+        self.ram[index] = value;
+    }
+
+    // rom bank visible at `addr`, without an MBC bank 1 is always mapped at 0x4000
+    pub fn bank_at(&self, addr: u16) -> u16 {
+        match addr {
+            0x0000..=0x7FFF => self.mapped_bank(RomRegion::split(addr).0).unwrap_or(0) as u16,
+            _ => 0,
+        }
     }
 
     // title of the game in upper case ascii
+    // on CGB cartridges the last byte is the CGB flag instead
     fn get_title(&mut self) {
-        for addr in 0x134..=0x143 {
-            self.title.push(self.read_byte(addr) as char);
+        let end = if self.data[0x143] & 0x80 != 0 {
+            0x142
+        } else {
+            0x143
+        };
+        for addr in 0x134..=end {
+            self.title.push(self.data[addr] as char);
         }
     }
 
     // Specifices which Memory Bank Controller is used in the cartridge and what other external
     // hardware is available
     fn get_cartridge_type(&mut self) {
-        self.ctype = match self.data[0x147] {
-            0x00 => "ROM ONLY",
-            0x01 => "MBC1",
-            0x02 => "MBC1+RAM",
-            0x03 => "MBC1+RAM+BATTERY",
-            0x05 => "MBC2",
-            0x06 => "MBC2+BATTERY",
-            0x08 => "ROM+RAM",
-            0x09 => "ROM+RAM+BATTERY",
-            0x0B => "MMM01",
-            0x0C => "MMM01+RAM",
-            0x0D => "MMM01+RAM+BATTERY",
-            0x0F => "MBC3+TIMER+BATTERY",
-            0x10 => "MBC3+TIMER+RAM+BATTERY",
-            0x11 => "MBC3",
-            0x12 => "MBC3+RAM",
-            0x13 => "MBC3+RAM+BATTERY",
-            0x19 => "MBC5",
-            0x1A => "MBC5+RAM",
-            0x1B => "MBC5+RAM+BATTERY",
-            0x1C => "MBC5+RUMBLE",
-            0x1D => "MBC5+RUMBLE+RAM",
-            0x1E => "MBC5+RUMBLE+RAM+BATTERY",
-            0x20 => "MBC6",
-            0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
-            0xFC => "POCKET CAMERA",
-            0xFD => "BANDAI TAMA5",
-            0xFE => "HuC3",
-            0xFF => "HuC1+RAM+BATTERY",
-            _ => "",
-        }
+        self.ctype = cartridge_type_name(self.data[0x147]);
     }
 
     // Rom size of the cartridge
@@ -139,6 +524,80 @@ impl Cartridge {
     }
 }
 
+fn cartridge_type_name(code: u8) -> &'static str {
+    match code {
+        0x00 => "ROM ONLY",
+        0x01 => "MBC1",
+        0x02 => "MBC1+RAM",
+        0x03 => "MBC1+RAM+BATTERY",
+        0x05 => "MBC2",
+        0x06 => "MBC2+BATTERY",
+        0x08 => "ROM+RAM",
+        0x09 => "ROM+RAM+BATTERY",
+        0x0B => "MMM01",
+        0x0C => "MMM01+RAM",
+        0x0D => "MMM01+RAM+BATTERY",
+        0x0F => "MBC3+TIMER+BATTERY",
+        0x10 => "MBC3+TIMER+RAM+BATTERY",
+        0x11 => "MBC3",
+        0x12 => "MBC3+RAM",
+        0x13 => "MBC3+RAM+BATTERY",
+        0x19 => "MBC5",
+        0x1A => "MBC5+RAM",
+        0x1B => "MBC5+RAM+BATTERY",
+        0x1C => "MBC5+RUMBLE",
+        0x1D => "MBC5+RUMBLE+RAM",
+        0x1E => "MBC5+RUMBLE+RAM+BATTERY",
+        0x20 => "MBC6",
+        0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+        0xFC => "POCKET CAMERA",
+        0xFD => "BANDAI TAMA5",
+        0xFE => "HuC3",
+        0xFF => "HuC1+RAM+BATTERY",
+        _ => "UNKNOWN",
+    }
+}
+
+// the bus serializes its cartridge as a CartridgeState, what comes back is a cartridge
+// without a rom that only carries the state over to the running one (see load_state)
+#[cfg(feature = "serde")]
+pub fn serialize_state<S: serde::Serializer>(
+    rom: &Cartridge,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serde::Serialize::serialize(&rom.save_state(), serializer)
+}
+
+#[cfg(feature = "serde")]
+pub fn deserialize_state<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Cartridge, D::Error> {
+    let state: CartridgeState = serde::Deserialize::deserialize(deserializer)?;
+    let mut rom = Cartridge::new();
+    rom.mbc = state.mbc;
+    rom.ram = state.ram;
+    Ok(rom)
+}
+
+fn global_checksum(data: &[u8]) -> u16 {
+    data.iter()
+        .enumerate()
+        .filter(|&(addr, _)| addr != 0x14E && addr != 0x14F)
+        .fold(0u16, |sum, (_, &byte)| sum.wrapping_add(byte as u16))
+}
+
+// bytes of external ram for the header's ram size code
+fn ram_bytes(code: u8) -> usize {
+    match code {
+        0x01 => 0x800,
+        0x02 => 0x2000,
+        0x03 => 0x8000,
+        0x04 => 0x20000,
+        0x05 => 0x10000,
+        _ => 0,
+    }
+}
+
 impl Default for Cartridge {
     fn default() -> Self {
         Self::new()
@@ -147,20 +606,153 @@ impl Default for Cartridge {
 
 impl fmt::Display for Cartridge {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let result = |passed: bool| if passed { "PASSED" } else { "FAILED" };
+        let emulated = match self.mbc {
+            _ if !self.is_mapper_emulated() => " (not emulated)",
+            Mbc::WisdomTree(_) => " (Wisdom Tree)",
+            _ => "",
+        };
+        let cgb = match self.header(0x143) {
+            0xC0 => "required",
+            0x80 => "supported",
+            _ => "no",
+        };
+        writeln!(f, "Title: {}", self.title())?;
+        writeln!(f, "Type: {}{}", self.ctype, emulated)?;
+        writeln!(f, "ROM Size: {}", self.rom_size)?;
+        writeln!(f, "Ram Size: {}", self.ram_size)?;
+        writeln!(f, "CGB: {}", cgb)?;
+        writeln!(f, "SGB: {}", if self.supports_sgb() { "yes" } else { "no" })?;
+        writeln!(f, "Destination: {:?}", self.destination())?;
+        writeln!(f, "Version: {}", self.rom_version)?;
         writeln!(
             f,
-            "Title: {}\nType: {}\nROM Size: {}\nRam Size: {}\nVersion: {}\nChecksum: {:#X} {}",
-            self.title,
-            self.ctype,
-            self.rom_size,
-            self.ram_size,
-            self.rom_version,
+            "Logo: {}",
+            result(!matches!(self.validate(), Err(CartridgeError::BadLogo)))
+        )?;
+        writeln!(
+            f,
+            "Checksum: {:#X} {}",
             self.checksum,
-            if self.checksum == self.data[0x14D] {
-                "PASSED"
-            } else {
-                "FAILED"
-            }
+            result(self.checksum == self.header(0x14D))
+        )?;
+        writeln!(
+            f,
+            "Global checksum: {:#06X} {}",
+            self.global_checksum,
+            result(self.global_checksum == self.header_global_checksum())
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_loading_errors() {
+        let path = std::env::temp_dir().join(format!("rustyboy-load-{}.gb", std::process::id()));
+        let load = |data: &[u8]| {
+            fs::write(&path, data).unwrap();
+            Cartridge::new().load(&path)
+        };
+        assert!(matches!(
+            load(&[0; 0x14F]),
+            Err(CartridgeError::TooSmall(0x14F))
+        ));
+        let mut rom = vec![0; 0x8000];
+        // MBC2 runs as ROM ONLY, it only fails validation
+        rom[0x147] = 0x05;
+        assert!(load(&rom).is_ok());
+        let mut cartridge = Cartridge::new();
+        cartridge.load_bytes(rom.clone()).unwrap();
+        assert_eq!(
+            "cartridge type 0x05 (MBC2) is not supported",
+            cartridge.validate().unwrap_err().to_string()
+        );
+        rom[0x147] = 0x00;
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            Cartridge::new().load(&path),
+            Err(CartridgeError::Io(_))
+        ));
+
+        let mut cartridge = Cartridge::new();
+        cartridge.load_bytes(rom.clone()).unwrap();
+        assert!(matches!(cartridge.validate(), Err(CartridgeError::BadLogo)));
+        rom[0x104..0x134].copy_from_slice(&LOGO);
+        cartridge.load_bytes(rom.clone()).unwrap();
+        assert!(matches!(
+            cartridge.validate(),
+            Err(CartridgeError::BadHeaderChecksum {
+                computed: 0xE7,
+                header: 0x00
+            })
+        ));
+        rom[0x14D] = 0xE7;
+        cartridge.load_bytes(rom.clone()).unwrap();
+        let global = cartridge.global_checksum();
+        assert!(matches!(
+            cartridge.validate(),
+            Err(CartridgeError::BadGlobalChecksum { header: 0, .. })
+        ));
+        // the checksum bytes themselves aren't summed
+        rom[0x14E..0x150].copy_from_slice(&global.to_be_bytes());
+        cartridge.load_bytes(rom).unwrap();
+        assert_eq!(global, cartridge.global_checksum());
+        assert_eq!(2, cartridge.rom_bank_count());
+        assert!(cartridge.validate().is_ok());
+    }
+
+    #[test]
+    fn test_rom_is_padded_to_whole_banks() {
+        let mut rom: Vec<u8> = (0..3)
+            .flat_map(|bank| vec![bank as u8; ROM_BANK_SIZE])
+            .collect();
+        rom.truncate(0x9000);
+        // MBC5
+        rom[0x147] = 0x19;
+        let mut cartridge = Cartridge::new();
+        cartridge.load_bytes(rom).unwrap();
+        assert_eq!(3, cartridge.rom_bank_count());
+        cartridge.write_byte(0x2000, 0x05);
+        // bank 5 wraps to bank 2, which is padded past the end of the image
+        assert_eq!(2, cartridge.bank_at(0x4000));
+        assert_eq!(Some(0x02), cartridge.read_rom(RomRegion::High, 0x0FFF));
+        assert_eq!(Some(0xFF), cartridge.read_rom(RomRegion::High, 0x1000));
+        assert_eq!(Some(0x00), cartridge.read_byte(0x0000));
+        assert_eq!(None, Cartridge::new().read_rom(RomRegion::Low, 0));
+    }
+
+    #[test]
+    fn test_header_info() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x134..0x138].copy_from_slice(b"DEMO");
+        rom[0x143] = 0xC0;
+        rom[0x147] = 0x06;
+        rom[0x104..0x134].copy_from_slice(&LOGO);
+        let mut cartridge = Cartridge::new();
+        cartridge.load_bytes(rom).unwrap();
+        let info = cartridge.to_string();
+        assert!(info.starts_with("Title: DEMO\nType: MBC2+BATTERY (not emulated)\n"));
+        assert!(info.contains("CGB: required\nSGB: no\n"));
+        assert!(info.contains("Logo: PASSED\nChecksum: 0xFC FAILED\n"));
+    }
+
+    #[test]
+    fn test_game_database_quirks_apply() {
+        let mut rom: Vec<u8> = (0..64)
+            .flat_map(|bank| vec![bank as u8; ROM_BANK_SIZE])
+            .collect();
+        rom[0x134..0x144].fill(0);
+        rom[0x134..0x13A].copy_from_slice(b"BOMCOL");
+        rom[0x147] = 0x01;
+        let mut cartridge = Cartridge::new();
+        cartridge.load_bytes(rom).unwrap();
+        assert_eq!("BOMCOL", cartridge.game_entry().unwrap().title);
+        // the second game of the multicart starts at bank 0x10
+        cartridge.write_byte(0x4000, 0x01);
+        cartridge.write_byte(0x2000, 0x01);
+        assert_eq!(Some(0x11), cartridge.read_byte(0x4000));
+    }
+}
@@ -0,0 +1,414 @@
+// user configuration
+// one file with the frontend settings and the input bindings, written by the first run
+// wizard and read on every later start, and again when the reload_config hotkey goes down.
+// settings look like "scale = 4". the lines after a [bindings] header are bindings as in
+// input.rs, older files have them mixed in with the settings. # starts a comment
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::audio::{DEFAULT_LATENCY, LATENCIES};
+use crate::battery::DEFAULT_AUTOSAVE_INTERVAL;
+use crate::input::{Action, Bindings, Button, Input};
+
+// window scales minifb can open
+pub const SCALES: [usize; 4] = [1, 2, 4, 8];
+const DEFAULT_SCALE: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    pub bindings: Bindings,
+    // where savestates and battery saves go
+    pub save_dir: PathBuf,
+    pub scale: usize,
+    // ms of sound buffered ahead, see audio.rs
+    pub audio_latency: u32,
+    // seconds between battery saves while the game runs, see battery.rs
+    pub autosave_interval: u64,
+}
+
+impl Config {
+    // $XDG_CONFIG_HOME/rustyboy/config, falling back to ~/.config and then the working
+    // directory
+    pub fn default_path() -> PathBuf {
+        let dir = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+        match dir {
+            Some(dir) => dir.join("rustyboy").join("config"),
+            None => PathBuf::from("rustyboy.config"),
+        }
+    }
+
+    // the config at `path`, None on first run when there is none yet
+    pub fn load(path: &Path) -> io::Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let config = fs::read_to_string(path)?;
+        config
+            .parse()
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.to_string())
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bindings: Bindings::default(),
+            save_dir: PathBuf::from("saves"),
+            scale: DEFAULT_SCALE,
+            audio_latency: DEFAULT_LATENCY,
+            autosave_interval: DEFAULT_AUTOSAVE_INTERVAL.as_secs(),
+        }
+    }
+}
+
+// missing settings keep their defaults, a file without any bindings keeps the default ones
+impl FromStr for Config {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut config = Self::default();
+        let mut bindings = String::new();
+        let mut has_bindings = false;
+        let mut in_bindings = false;
+        for (number, line) in s.lines().enumerate() {
+            let setting = line.split('#').next().unwrap_or("").trim();
+            let invalid = || format!("invalid setting on line {}: {}", number + 1, setting);
+            if setting.starts_with('[') {
+                if setting != "[bindings]" {
+                    return Err(format!(
+                        "unknown section on line {}: {}",
+                        number + 1,
+                        setting
+                    ));
+                }
+                in_bindings = true;
+                bindings.push('\n');
+                continue;
+            }
+            if in_bindings {
+                has_bindings |= !setting.is_empty();
+                bindings.push_str(line);
+                bindings.push('\n');
+                continue;
+            }
+            match setting
+                .split_once('=')
+                .map(|(key, value)| (key.trim(), value.trim()))
+            {
+                Some(("scale", value)) => {
+                    config.scale = value
+                        .parse()
+                        .ok()
+                        .filter(|scale| SCALES.contains(scale))
+                        .ok_or_else(invalid)?;
+                }
+                Some(("audio_latency", value)) => {
+                    config.audio_latency = value
+                        .parse()
+                        .ok()
+                        .filter(|latency| LATENCIES.contains(latency))
+                        .ok_or_else(invalid)?;
+                }
+                Some(("autosave", value)) => {
+                    config.autosave_interval = value.parse().map_err(|_| invalid())?;
+                }
+                Some(("save_dir", value)) if !value.is_empty() => {
+                    config.save_dir = PathBuf::from(value)
+                }
+                // keep line numbers right for binding errors
+                _ => {
+                    has_bindings |= !setting.is_empty();
+                    bindings.push_str(line);
+                }
+            }
+            bindings.push('\n');
+        }
+        if has_bindings {
+            config.bindings = bindings.parse()?;
+        }
+        Ok(config)
+    }
+}
+
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "# rustyboy config")?;
+        writeln!(f, "scale = {}", self.scale)?;
+        writeln!(f, "save_dir = {}", self.save_dir.display())?;
+        writeln!(f, "audio_latency = {}", self.audio_latency)?;
+        writeln!(f, "autosave = {}", self.autosave_interval)?;
+        writeln!(f)?;
+        writeln!(f, "[bindings]")?;
+        let mut bindings: Vec<String> = self
+            .bindings
+            .iter()
+            .map(|(input, action)| format!("{} = {}", input, action))
+            .collect();
+        bindings.sort();
+        for binding in bindings {
+            writeln!(f, "{}", binding)?;
+        }
+        Ok(())
+    }
+}
+
+// first run setup, one question at a time
+// the frontend shows prompt() and feeds every key that goes down into key_pressed()
+pub struct Wizard {
+    config: Config,
+    step: usize,
+    // the save directory typed so far, empty keeps the default
+    save_dir: String,
+}
+
+// joypad buttons the wizard asks for, in order
+const WIZARD_BUTTONS: [Button; 8] = [
+    Button::Up,
+    Button::Down,
+    Button::Left,
+    Button::Right,
+    Button::A,
+    Button::B,
+    Button::Start,
+    Button::Select,
+];
+
+impl Wizard {
+    pub fn new() -> Self {
+        Self {
+            config: Config::default(),
+            step: 0,
+            save_dir: String::new(),
+        }
+    }
+
+    // what to ask the user, None once every question is answered
+    pub fn prompt(&self) -> Option<String> {
+        match self.step {
+            step if step < WIZARD_BUTTONS.len() => Some(format!(
+                "Press the key for {} (Enter keeps {})",
+                Action::Joypad(WIZARD_BUTTONS[step]),
+                self.default_key(WIZARD_BUTTONS[step]),
+            )),
+            step if step == WIZARD_BUTTONS.len() => Some(format!(
+                "Display scale: press 1, 2, 4 or 8 (Enter keeps {})",
+                DEFAULT_SCALE
+            )),
+            step if step == WIZARD_BUTTONS.len() + 1 && self.save_dir.is_empty() => Some(format!(
+                "Type a save directory (Enter keeps {})",
+                self.config.save_dir.display()
+            )),
+            step if step == WIZARD_BUTTONS.len() + 1 => Some(format!(
+                "Saves go to {}, press Enter to finish",
+                self.save_dir
+            )),
+            _ => None,
+        }
+    }
+
+    // `key` is the frontend's key name, e.g. "Z" or "Key4"
+    pub fn key_pressed(&mut self, key: &str) {
+        let keep = key == "Enter";
+        match self.step {
+            step if step < WIZARD_BUTTONS.len() => {
+                if !keep {
+                    let action = Action::Joypad(WIZARD_BUTTONS[step]);
+                    self.config.bindings.unbind_action(action);
+                    self.config.bindings.bind(Input::key(key), action);
+                }
+            }
+            step if step == WIZARD_BUTTONS.len() => {
+                if !keep {
+                    let digit = key.trim_start_matches("Key").trim_start_matches("NumPad");
+                    match digit.parse() {
+                        Ok(scale) if SCALES.contains(&scale) => self.config.scale = scale,
+                        // ask again
+                        _ => return,
+                    }
+                }
+            }
+            step if step == WIZARD_BUTTONS.len() + 1 => {
+                if !keep {
+                    if key == "Backspace" {
+                        self.save_dir.pop();
+                    } else if let Some(c) = path_char(key) {
+                        self.save_dir.push(c);
+                    }
+                    return;
+                }
+                if !self.save_dir.is_empty() {
+                    self.config.save_dir = PathBuf::from(&self.save_dir);
+                }
+            }
+            _ => return,
+        }
+        self.step += 1;
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.prompt().is_none()
+    }
+
+    pub fn finish(self) -> Config {
+        self.config
+    }
+
+    fn default_key(&self, button: Button) -> String {
+        self.config
+            .bindings
+            .iter()
+            .filter(|(_, action)| *action == Action::Joypad(button))
+            .map(|(input, _)| input.to_string())
+            .next()
+            .unwrap_or_else(|| "nothing".to_string())
+    }
+}
+
+// the character a key types into a path, letters come out lowercase
+fn path_char(key: &str) -> Option<char> {
+    let c = match key {
+        "Slash" => '/',
+        "Backslash" => '\\',
+        "Period" => '.',
+        "Minus" => '-',
+        "Space" => ' ',
+        "Semicolon" => ':',
+        _ => {
+            let key = key.trim_start_matches("Key").trim_start_matches("NumPad");
+            let mut chars = key.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) if c.is_ascii_alphanumeric() => c.to_ascii_lowercase(),
+                _ => return None,
+            }
+        }
+    };
+    Some(c)
+}
+
+impl Default for Wizard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_round_trip() {
+        let mut config = Config {
+            scale: 2,
+            save_dir: PathBuf::from("/tmp/saves"),
+            audio_latency: 40,
+            autosave_interval: 30,
+            ..Config::default()
+        };
+        config
+            .bindings
+            .bind(Input::gamepad("South"), Action::Joypad(Button::A));
+        let text = config.to_string();
+        assert!(text.contains("scale = 2\n"), "{text}");
+        assert_eq!(Ok(config), text.parse());
+
+        assert!("scale = 3".parse::<Config>().is_err());
+        assert!("audio_latency = 5".parse::<Config>().is_err());
+        assert!("autosave = -1".parse::<Config>().is_err());
+        // binding errors point at the line in the whole file
+        let error = "scale = 2\nkey:Z".parse::<Config>().unwrap_err();
+        assert!(error.contains("line 2"), "{error}");
+        assert!("[window]".parse::<Config>().is_err());
+    }
+
+    #[test]
+    fn test_bindings_section() {
+        let text = "scale = 2\n\n[bindings]\n# hotkeys\npad:South = a\nkey:F3 = save:2\n";
+        let config: Config = text.parse().unwrap();
+        assert_eq!(2, config.scale);
+        assert_eq!(
+            Some(Action::SaveState(2)),
+            config.bindings.action_for(&Input::key("F3"))
+        );
+        // only the listed bindings, no defaults
+        assert_eq!(None, config.bindings.action_for(&Input::key("Z")));
+        // settings don't belong in the section
+        let error = "[bindings]\nscale = 2".parse::<Config>().unwrap_err();
+        assert!(error.contains("scale"), "{error}");
+        // older files without the header still load
+        let config: Config = "scale = 2\npad:South = a".parse().unwrap();
+        assert_eq!(
+            Some(Action::Joypad(Button::A)),
+            config.bindings.action_for(&Input::gamepad("South"))
+        );
+    }
+
+    #[test]
+    fn test_wizard_builds_config() {
+        let mut wizard = Wizard::new();
+        assert!(wizard.prompt().unwrap().contains("key:Up"));
+        // W for up, defaults for the rest of the buttons
+        wizard.key_pressed("W");
+        for _ in 1..WIZARD_BUTTONS.len() {
+            wizard.key_pressed("Enter");
+        }
+        // 3 is not a scale
+        wizard.key_pressed("Key3");
+        assert!(wizard.prompt().unwrap().starts_with("Display scale"));
+        wizard.key_pressed("Key2");
+        assert!(wizard.prompt().unwrap().contains("Enter keeps saves"));
+        // typed path with a typo, keys that type nothing are skipped
+        for key in [
+            "Slash",
+            "T",
+            "M",
+            "P",
+            "Slash",
+            "G",
+            "B",
+            "X",
+            "Backspace",
+            "LeftShift",
+        ] {
+            wizard.key_pressed(key);
+        }
+        assert!(wizard.prompt().unwrap().contains("/tmp/gb"));
+        assert!(!wizard.is_done());
+        wizard.key_pressed("Enter");
+        assert!(wizard.is_done());
+
+        let config = wizard.finish();
+        assert_eq!(2, config.scale);
+        assert_eq!(PathBuf::from("/tmp/gb"), config.save_dir);
+        let up = Action::Joypad(Button::Up);
+        assert_eq!(Some(up), config.bindings.action_for(&Input::key("W")));
+        assert_eq!(None, config.bindings.action_for(&Input::key("Up")));
+        assert_eq!(
+            Some(Action::Joypad(Button::A)),
+            config.bindings.action_for(&Input::key("Z"))
+        );
+        assert_eq!(Ok(config.clone()), config.to_string().parse());
+        assert_eq!(Ok(Config::default()), "scale = 4".parse());
+
+        // Enter right away keeps the default directory
+        let mut wizard = Wizard::new();
+        while !wizard.is_done() {
+            wizard.key_pressed("Enter");
+        }
+        assert_eq!(Config::default().save_dir, wizard.finish().save_dir);
+    }
+}
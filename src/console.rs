@@ -0,0 +1,85 @@
+// debug console for homebrew
+// an opt-in development device: bytes the game writes to 0xFF7F, an IO address the
+// hardware leaves unconnected, are collected into lines of text for the log. with the
+// console off the address behaves like on hardware, so real games never notice it
+
+pub const DEBUG_CONSOLE: u16 = 0xFF7F;
+// longer lines are broken up, in case a game never writes a newline
+const MAX_LINE_LEN: usize = 256;
+
+#[derive(Debug, Default)]
+pub struct DebugConsole {
+    line: Vec<u8>,
+    lines: Vec<String>,
+}
+
+impl DebugConsole {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // a newline or a 0 byte ends the line, like the end of a C string
+    pub fn write_byte(&mut self, value: u8) {
+        match value {
+            b'\n' | 0 => self.end_line(),
+            b'\r' => {}
+            _ => {
+                self.line.push(value);
+                if self.line.len() == MAX_LINE_LEN {
+                    self.end_line();
+                }
+            }
+        }
+    }
+
+    // lines completed since the last call
+    pub fn take_lines(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.lines)
+    }
+
+    fn end_line(&mut self) {
+        let line = std::mem::take(&mut self.line);
+        self.lines.push(String::from_utf8_lossy(&line).into_owned());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+
+    #[test]
+    fn test_collects_lines_only_when_enabled() {
+        let mut bus = Bus::from_rom_bytes(vec![0; 0x8000]);
+        for &byte in b"off\n" {
+            bus.write_byte(DEBUG_CONSOLE, byte);
+        }
+        assert!(bus.debug_console.is_none());
+
+        bus.debug_console = Some(DebugConsole::new());
+        for &byte in b"hp=3\r\nlevel 2\0partial" {
+            bus.write_byte(DEBUG_CONSOLE, byte);
+        }
+        let console = bus.debug_console.as_mut().unwrap();
+        assert_eq!(vec!["hp=3", "level 2"], console.take_lines());
+        assert!(console.take_lines().is_empty());
+        // the game can't read anything back
+        assert_eq!(
+            bus.read_byte(DEBUG_CONSOLE),
+            Bus::from_rom_bytes(vec![0; 0x8000]).read_byte(DEBUG_CONSOLE)
+        );
+    }
+
+    #[test]
+    fn test_long_lines_are_split() {
+        let mut console = DebugConsole::new();
+        for _ in 0..MAX_LINE_LEN + 1 {
+            console.write_byte(b'x');
+        }
+        console.write_byte(b'\n');
+        let lines = console.take_lines();
+        assert_eq!(2, lines.len());
+        assert_eq!(MAX_LINE_LEN, lines[0].len());
+        assert_eq!("x", lines[1]);
+    }
+}
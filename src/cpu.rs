@@ -1,6 +1,17 @@
 use std::path::Path;
 
-use crate::{bus::Bus, register::Flags, register::Register};
+use crate::{
+    alu,
+    bus::Bus,
+    cartridge::CartridgeError,
+    disasm,
+    interrupt::Interrupt,
+    memory::Memory,
+    model::Model,
+    register::Flags,
+    register::Register,
+    trace::{TraceHistory, TraceRecord, Tracer},
+};
 
 // memory interface can address up to 65536 bytes (16-bit bus)
 // programs are accessed through the same address bus as normal memory
@@ -18,29 +29,121 @@ enum MathOperations {
 
 // TODO: add timing for more accurate emulation
 
-pub struct Cpu {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Cpu<M: Memory = Bus> {
     reg: Register,
-    pub bus: Bus,
+    pub bus: M,
     // clock for last instruction
     m: u8,
     halted: bool,
     should_interrupt: bool,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    tracer: Option<Tracer>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    history: TraceHistory,
 }
 
-impl Cpu {
-    pub fn new(rom_file: &Path) -> Self {
+impl Cpu<Bus> {
+    pub fn new(rom_file: &Path) -> Result<Self, CartridgeError> {
+        Ok(Self::with_bus(Bus::new(rom_file)?))
+    }
+
+    pub fn from_rom_bytes(rom: Vec<u8>) -> Self {
+        Self::with_bus(Bus::from_rom_bytes(rom))
+    }
+
+    fn with_bus(bus: Bus) -> Self {
+        let model = bus.model();
+        let mut cpu = Self::with_memory(bus);
+        cpu.reg = Register::for_model(model);
+        cpu
+    }
+
+    // switch the emulated hardware, registers go back to what that model's boot rom
+    // leaves behind so this belongs before the first instruction runs
+    pub fn set_model(&mut self, model: Model) {
+        self.bus.set_model(model);
+        self.reg = Register::for_model(model);
+    }
+}
+
+impl<M: Memory> Cpu<M> {
+    // run the cpu against any memory, e.g. a FlatRam in tests
+    pub fn with_memory(memory: M) -> Self {
         Self {
             reg: Register::new(),
-            bus: Bus::new(rom_file),
+            bus: memory,
             m: 0,
             halted: false,
             should_interrupt: false,
+            tracer: None,
+            history: TraceHistory::default(),
         }
     }
 
+    pub fn pc(&self) -> u16 {
+        self.reg.pc
+    }
+
+    pub fn registers(&self) -> Register {
+        self.reg
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    // machine cycles taken by the last executed instruction
+    pub fn last_cycles(&self) -> u8 {
+        self.m
+    }
+
+    // call `tracer` with a record of every instruction before it is executed
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    pub fn clear_tracer(&mut self) {
+        self.tracer = None;
+    }
+
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
+    fn trace_record(&self) -> TraceRecord {
+        let bank = self.bus.rom_bank_at(self.reg.pc);
+        self.record_at(bank, self.reg)
+    }
+
+    // peeks the instruction bytes, tracing must not change the run it traces
+    fn record_at(&self, bank: u16, registers: Register) -> TraceRecord {
+        let pc = registers.pc;
+        let length = disasm::instruction_length(self.bus.peek(pc));
+        let bytes: Vec<u8> = (0..length)
+            .map(|offset| self.bus.peek(pc.wrapping_add(offset)))
+            .collect();
+        TraceRecord {
+            pc,
+            bank,
+            mnemonic: disasm::disassemble(pc, &bytes),
+            bytes,
+            registers,
+        }
+    }
+
+    // the last instructions executed, oldest first
+    // they are disassembled from memory as it is now, which may differ from when they ran
+    pub fn recent_instructions(&self) -> Vec<TraceRecord> {
+        self.history
+            .iter()
+            .map(|&(bank, registers)| self.record_at(bank, registers))
+            .collect()
+    }
+
     // --------------------------- UTIL -----------------------------------------------
     fn read_byte(&mut self) -> u8 {
-        let byte = self.bus.read_byte(self.reg.pc);
+        let byte = self.bus.read(self.reg.pc);
         self.reg.pc += 1;
         byte
     }
@@ -72,31 +175,44 @@ impl Cpu {
         result
     }
 
-    fn add16(&mut self, register: u16) {
-        let result = self.reg.get_hl().wrapping_add(register);
-        self.unset_flag(Flags::Negative);
-        self.set_flag_on_if(Flags::Carry, self.reg.get_hl() > 0xFFFF - register);
-        self.set_flag_on_if(
-            Flags::HalfCarry,
-            (self.reg.get_hl() & 0x07FF) + (register & 0x07FF) > 0x07FF,
-        );
+    // add a 16-bit register to HL (ADD HL,rr)
+    // H is the carry out of bit 11, C the carry out of bit 15 and Z is left untouched
+    fn add_hl(&mut self, register: u16) {
+        let (result, flags) = alu::add_u16_hl_with_flags(self.reg.get_hl(), register, self.reg.f);
+        self.reg.f = flags;
         self.reg.set_hl(result);
     }
 
+    // add signed 8-bit immediate to a 16-bit register (ADD SP,e8 and LD HL,SP+e8)
+    // the cpu does this as an 8-bit add on the low byte, so H and C come from
+    // bit 3 and bit 7 no matter what happens to the high byte
     fn add16_imm(&mut self, register: u16) -> u16 {
-        let value = self.read_byte() as i8 as i16 as u16;
-        self.unset_flag(Flags::Negative);
-        self.unset_flag(Flags::Zero);
-        self.set_flag_on_if(
-            Flags::HalfCarry,
-            (register & 0x000F) + (value & 0x000F) > 0x000F,
-        );
-        self.set_flag_on_if(
-            Flags::Carry,
-            (register & 0x00FF) + (value & 0x00FF) > 0x00FF,
-        );
+        let value = self.read_byte();
+        let (result, flags) = alu::add_sp_i8_with_flags(register, value);
+        self.reg.f = flags;
+        result
+    }
 
-        register.wrapping_add(value)
+    // add value to register A, ADC includes the carry flag
+    fn add_a(&mut self, value: u8, with_carry: bool) {
+        let carry = with_carry && self.flag_is_active(Flags::Carry);
+        let (result, flags) = alu::add_u8_with_flags(self.reg.a, value, carry);
+        self.reg.f = flags;
+        self.reg.a = result;
+    }
+
+    // subtract value from register A, SBC includes the carry flag
+    fn sub_a(&mut self, value: u8, with_carry: bool) {
+        let carry = with_carry && self.flag_is_active(Flags::Carry);
+        let (result, flags) = alu::sub_u8_with_flags(self.reg.a, value, carry);
+        self.reg.f = flags;
+        self.reg.a = result;
+    }
+
+    // compare value with register A, like SUB but the result is thrown away
+    fn cp_a(&mut self, value: u8) {
+        let (_, flags) = alu::sub_u8_with_flags(self.reg.a, value, false);
+        self.reg.f = flags;
     }
 
     // STACK OPERATIONS
@@ -112,14 +228,6 @@ impl Cpu {
         value
     }
 
-    fn print_register_data(&self) {
-        println!("A: {:2X} F: {:2X} B: {:2X} C: {:2X} D: {:2X} E: {:2X} H: {:2X} L: {:2X} SP: {:4X} PC: {:4X} ({:2X} {:2X} {:2X} {:2X})",
-        self.reg.a, self.reg.f, self.reg.b, self.reg.c, self.reg.d, self.reg.e, self.reg.h, self.reg.l, self.reg.sp, self.reg.pc,
-        self.bus.read_byte(self.reg.pc),
-        self.bus.read_byte(self.reg.pc + 1),
-        self.bus.read_byte(self.reg.pc + 2),
-        self.bus.read_byte(self.reg.pc + 3));
-    }
     fn reset_flags(&mut self) {
         self.reg.f &= Flags::Zero as u8;
         self.reg.f &= Flags::HalfCarry as u8;
@@ -148,7 +256,7 @@ impl Cpu {
         }
     }
 
-    fn get_src_register(&self, src_register: u8) -> u8 {
+    fn get_src_register(&mut self, src_register: u8) -> u8 {
         match src_register {
             0 => self.reg.b,
             1 => self.reg.c,
@@ -156,7 +264,7 @@ impl Cpu {
             3 => self.reg.e,
             4 => self.reg.h,
             5 => self.reg.l,
-            6 => self.bus.read_byte(self.reg.get_hl()),
+            6 => self.bus.read(self.reg.get_hl()),
             7 => self.reg.a,
             _ => {
                 panic!("SRC REGISTER NOT HERE AARRRRH");
@@ -172,9 +280,10 @@ impl Cpu {
             3 => self.reg.e = self.get_src_register(src_register),
             4 => self.reg.h = self.get_src_register(src_register),
             5 => self.reg.l = self.get_src_register(src_register),
-            6 => self
-                .bus
-                .write_byte(self.reg.get_hl(), self.get_src_register(src_register)),
+            6 => {
+                let value = self.get_src_register(src_register);
+                self.bus.write(self.reg.get_hl(), value);
+            }
             7 => self.reg.a = self.get_src_register(src_register),
             _ => panic!("DEST REGISTER NOT HERE"), //println!("Didnt find a destination register, got: {}", dest_register),
         }
@@ -335,7 +444,7 @@ impl Cpu {
     fn load_bc_a(&mut self) {
         self.m = 2;
 
-        self.bus.write_byte(self.reg.get_bc(), self.reg.a);
+        self.bus.write(self.reg.get_bc(), self.reg.a);
     }
 
     // increment register pair BC
@@ -387,14 +496,14 @@ impl Cpu {
     // add register BC to HL
     fn add_hl_bc(&mut self) {
         self.m = 2;
-        self.add16(self.reg.get_bc());
+        self.add_hl(self.reg.get_bc());
     }
 
     // load contents specified by register BC into register A
     fn ld_a_bc(&mut self) {
         self.m = 2;
 
-        self.reg.a = self.bus.read_byte(self.reg.get_bc());
+        self.reg.a = self.bus.read(self.reg.get_bc());
     }
 
     // decrement register pair BC by 1
@@ -436,8 +545,10 @@ impl Cpu {
     }
 
     // stop system clock and oscillator circuit
+    // only the CGB speed switch is emulated, not the low power mode
     fn stop(&mut self) {
-        self.nop()
+        self.nop();
+        self.bus.stop();
     }
 
     // load 2 bytes of immediate data into register pair DE
@@ -452,7 +563,7 @@ impl Cpu {
     fn ld_a(&mut self) {
         self.m = 2;
 
-        self.bus.write_byte(self.reg.get_de(), self.reg.a);
+        self.bus.write(self.reg.get_de(), self.reg.a);
     }
 
     // increment contents of register pair DE by 1
@@ -496,7 +607,7 @@ impl Cpu {
     // jump s8 steps from current address in the pc
     fn jr(&mut self) {
         self.m = 3;
-        let value = self.bus.read_byte(self.reg.pc) as i8;
+        let value = self.bus.read(self.reg.pc) as i8;
         self.reg.pc = ((self.reg.pc as u32 as i32) + (value as i32)) as u16;
     }
 
@@ -504,14 +615,14 @@ impl Cpu {
     fn add_hl_de(&mut self) {
         self.m = 2;
 
-        self.add16(self.reg.get_de());
+        self.add_hl(self.reg.get_de());
     }
 
     // load 8-bit contents of memory specified by register pair DE into register A
     fn ld_a_de(&mut self) {
         self.m = 2;
 
-        self.reg.a = self.bus.read_byte(self.reg.get_de());
+        self.reg.a = self.bus.read(self.reg.get_de());
     }
 
     // decrement contents of register pair DE by 1
@@ -577,7 +688,7 @@ impl Cpu {
     fn ld_hl_inc_a(&mut self) {
         self.m = 2;
 
-        self.bus.write_byte(self.reg.get_hl(), self.reg.a);
+        self.bus.write(self.reg.get_hl(), self.reg.a);
         self.reg.set_hl(self.reg.get_hl().wrapping_add(1));
     }
 
@@ -657,7 +768,7 @@ impl Cpu {
     fn add_hl_hl(&mut self) {
         self.m = 2;
 
-        self.add16(self.reg.get_hl());
+        self.add_hl(self.reg.get_hl());
     }
 
     // load contents of memory specified by register pair HL into register A and increase
@@ -666,7 +777,7 @@ impl Cpu {
         self.m = 2;
 
         //self.reg.set_hl(self.reg.get_hl().wrapping_add(1));
-        self.reg.a = self.bus.read_byte(self.reg.get_hl());
+        self.reg.a = self.bus.read(self.reg.get_hl());
         self.reg.set_hl(self.reg.get_hl().wrapping_add(1));
     }
 
@@ -732,7 +843,7 @@ impl Cpu {
     fn ld_hlm_a(&mut self) {
         self.m = 2;
 
-        self.bus.write_byte(self.reg.get_hl(), self.reg.a);
+        self.bus.write(self.reg.get_hl(), self.reg.a);
         self.reg.set_hl(self.reg.get_hl().wrapping_sub(1));
     }
 
@@ -747,28 +858,24 @@ impl Cpu {
     fn inc_content_at_hl(&mut self) {
         self.m = 3;
 
-        let value = self.bus.read_byte(self.reg.get_hl()).wrapping_add(1);
+        let current = self.bus.read(self.reg.get_hl());
+        let value = current.wrapping_add(1);
         self.unset_flag(Flags::Negative);
         self.set_flag_on_if(Flags::Zero, value == 0);
-        self.set_flag_on_if(
-            Flags::HalfCarry,
-            (self.bus.read_byte(self.reg.get_hl()) & 0xF) + 1 > 0xF,
-        );
-        self.bus.write_byte(self.reg.get_hl(), value);
+        self.set_flag_on_if(Flags::HalfCarry, (current & 0xF) + 1 > 0xF);
+        self.bus.write(self.reg.get_hl(), value);
     }
 
     // decrement contents of memory specifed by register pair HL by 1
     fn dec_content_at_hl(&mut self) {
         self.m = 3;
 
-        let value = self.bus.read_byte(self.reg.get_hl()).wrapping_sub(1);
+        let current = self.bus.read(self.reg.get_hl());
+        let value = current.wrapping_sub(1);
         self.set_flag(Flags::Negative);
         self.set_flag_on_if(Flags::Zero, value == 0);
-        self.set_flag_on_if(
-            Flags::HalfCarry,
-            self.bus.read_byte(self.reg.get_hl()) & 0xF == 0,
-        );
-        self.bus.write_byte(self.reg.get_hl(), value);
+        self.set_flag_on_if(Flags::HalfCarry, current & 0xF == 0);
+        self.bus.write(self.reg.get_hl(), value);
     }
 
     // store contents of 8-bit immediate operation into memory location
@@ -777,7 +884,7 @@ impl Cpu {
         self.m = 3;
 
         let value = self.read_byte();
-        self.bus.write_byte(self.reg.get_hl(), value);
+        self.bus.write(self.reg.get_hl(), value);
     }
 
     // set the carry flag
@@ -806,7 +913,7 @@ impl Cpu {
     fn add_hl_sp(&mut self) {
         self.m = 2;
 
-        self.add16(self.reg.sp);
+        self.add_hl(self.reg.sp);
     }
 
     // load contents specified by register pair HL into register A
@@ -815,7 +922,7 @@ impl Cpu {
         self.m = 2;
 
         //self.reg.set_hl(self.reg.get_hl().wrapping_sub(1));
-        self.reg.a = self.bus.read_byte(self.reg.get_hl());
+        self.reg.a = self.bus.read(self.reg.get_hl());
         self.reg.set_hl(self.reg.get_hl().wrapping_sub(1));
     }
 
@@ -890,51 +997,16 @@ impl Cpu {
 
         let register = opcode & 0x7;
         let math_operation = (opcode >> 3) & 0x7;
-
-        let carry = if self.flag_is_active(Flags::Carry) {
-            1
-        } else {
-            0
-        };
-
-        if math_operation == MathOperations::Add as u8
-            || math_operation == MathOperations::Adc as u8
-        {
-            let result = self
-                .reg
-                .a
-                .wrapping_add(self.get_src_register(register))
-                .wrapping_add(carry);
-            self.unset_flag(Flags::Negative);
-            self.set_flag_on_if(Flags::Zero, result == 0);
-            self.set_flag_on_if(
-                Flags::HalfCarry,
-                (self.reg.a & 0xF) + (self.get_src_register(register) & 0xF) + carry > 0xF,
-            );
-            self.set_flag_on_if(
-                Flags::Carry,
-                self.reg.a as u16 + self.get_src_register(register) as u16 + carry as u16 > 0xFF,
-            );
-            self.reg.a = result;
-        } else if math_operation == MathOperations::Sub as u8
-            || math_operation == MathOperations::Sbc as u8
-        {
-            let result = self
-                .reg
-                .a
-                .wrapping_sub(self.get_src_register(register))
-                .wrapping_sub(carry);
-            self.set_flag(Flags::Negative);
-            self.set_flag_on_if(Flags::Zero, result == 0);
-            self.set_flag_on_if(
-                Flags::HalfCarry,
-                (self.reg.a & 0x0F) < (self.get_src_register(register) & 0x0F) + carry,
-            );
-            self.set_flag_on_if(
-                Flags::Carry,
-                self.get_src_register(register) + carry > self.reg.a,
-            );
-            self.reg.a = result;
+        let value = self.get_src_register(register);
+
+        if math_operation == MathOperations::Add as u8 {
+            self.add_a(value, false);
+        } else if math_operation == MathOperations::Adc as u8 {
+            self.add_a(value, true);
+        } else if math_operation == MathOperations::Sub as u8 {
+            self.sub_a(value, false);
+        } else if math_operation == MathOperations::Sbc as u8 {
+            self.sub_a(value, true);
         }
     }
 
@@ -978,22 +1050,8 @@ impl Cpu {
         self.m = 1;
 
         let register = opcode & 0x7;
-        let result = self.reg.a.wrapping_sub(self.get_src_register(register));
-        let carry = if self.flag_is_active(Flags::Carry) {
-            1
-        } else {
-            0
-        };
-        self.set_flag(Flags::Negative);
-        self.set_flag_on_if(Flags::Zero, result == 0);
-        self.set_flag_on_if(
-            Flags::HalfCarry,
-            (self.reg.a & 0x0F) < (self.get_src_register(register) & 0x0F) + carry,
-        );
-        self.set_flag_on_if(
-            Flags::Carry,
-            self.get_src_register(register) + carry > self.reg.a,
-        );
+        let value = self.get_src_register(register);
+        self.cp_a(value);
     }
 
     // return from subroutine if nz
@@ -1055,23 +1113,7 @@ impl Cpu {
         self.m = 2;
 
         let value = self.read_byte();
-        let result = self.reg.a.wrapping_add(value);
-        let carry = if self.flag_is_active(Flags::Carry) {
-            1
-        } else {
-            0
-        };
-        self.unset_flag(Flags::Negative);
-        self.set_flag_on_if(Flags::Zero, result == 0);
-        self.set_flag_on_if(
-            Flags::HalfCarry,
-            (self.reg.a & 0xF) + (value & 0xF) + carry > 0xF,
-        );
-        self.set_flag_on_if(
-            Flags::Carry,
-            self.reg.a as u16 + value as u16 + carry as u16 > 0xFF,
-        );
-        self.reg.a = result;
+        self.add_a(value, false);
     }
 
     // call address
@@ -1121,8 +1163,9 @@ impl Cpu {
             0x04 => self.reg.h = self.cb_rlc(self.reg.h),
             0x05 => self.reg.l = self.cb_rlc(self.reg.l),
             0x06 => {
-                let value = self.cb_rlc(self.bus.read_byte(self.reg.get_hl()));
-                self.bus.write_byte(self.reg.get_hl(), value);
+                let value = self.bus.read(self.reg.get_hl());
+                let value = self.cb_rlc(value);
+                self.bus.write(self.reg.get_hl(), value);
             }
             0x07 => self.reg.a = self.cb_rlc(self.reg.a),
             0x08 => self.reg.b = self.cb_rrc(self.reg.b),
@@ -1132,8 +1175,9 @@ impl Cpu {
             0x0C => self.reg.h = self.cb_rrc(self.reg.h),
             0x0D => self.reg.l = self.cb_rrc(self.reg.l),
             0x0E => {
-                let value = self.cb_rrc(self.bus.read_byte(self.reg.get_hl()));
-                self.bus.write_byte(self.reg.get_hl(), value)
+                let value = self.bus.read(self.reg.get_hl());
+                let value = self.cb_rrc(value);
+                self.bus.write(self.reg.get_hl(), value)
             }
             0x0F => self.reg.a = self.cb_rrc(self.reg.a),
             0x10 => self.reg.b = self.cb_rl(self.reg.b),
@@ -1143,8 +1187,9 @@ impl Cpu {
             0x14 => self.reg.h = self.cb_rl(self.reg.h),
             0x15 => self.reg.l = self.cb_rl(self.reg.l),
             0x16 => {
-                let value = self.cb_rl(self.bus.read_byte(self.reg.get_hl()));
-                self.bus.write_byte(self.reg.get_hl(), value);
+                let value = self.bus.read(self.reg.get_hl());
+                let value = self.cb_rl(value);
+                self.bus.write(self.reg.get_hl(), value);
             }
             0x17 => self.reg.a = self.cb_rl(self.reg.a),
             0x18 => self.reg.b = self.cb_rr(self.reg.b),
@@ -1154,8 +1199,9 @@ impl Cpu {
             0x1C => self.reg.h = self.cb_rr(self.reg.h),
             0x1D => self.reg.l = self.cb_rr(self.reg.l),
             0x1E => {
-                let value = self.cb_rr(self.bus.read_byte(self.reg.get_hl()));
-                self.bus.write_byte(self.reg.get_hl(), value);
+                let value = self.bus.read(self.reg.get_hl());
+                let value = self.cb_rr(value);
+                self.bus.write(self.reg.get_hl(), value);
             }
             0x1F => self.reg.a = self.cb_rr(self.reg.a),
             0x20 => self.reg.b = self.cb_sla(self.reg.b),
@@ -1165,8 +1211,9 @@ impl Cpu {
             0x24 => self.reg.h = self.cb_sla(self.reg.h),
             0x25 => self.reg.l = self.cb_sla(self.reg.l),
             0x26 => {
-                let value = self.cb_sla(self.bus.read_byte(self.reg.get_hl()));
-                self.bus.write_byte(self.reg.get_hl(), value);
+                let value = self.bus.read(self.reg.get_hl());
+                let value = self.cb_sla(value);
+                self.bus.write(self.reg.get_hl(), value);
             }
             0x27 => self.reg.a = self.cb_sla(self.reg.a),
             0x28 => self.reg.b = self.cb_sra(self.reg.b),
@@ -1176,8 +1223,9 @@ impl Cpu {
             0x2C => self.reg.h = self.cb_sra(self.reg.h),
             0x2D => self.reg.l = self.cb_sra(self.reg.l),
             0x2E => {
-                let value = self.cb_sra(self.bus.read_byte(self.reg.get_hl()));
-                self.bus.write_byte(self.reg.get_hl(), value);
+                let value = self.bus.read(self.reg.get_hl());
+                let value = self.cb_sra(value);
+                self.bus.write(self.reg.get_hl(), value);
             }
             0x2F => self.reg.a = self.cb_sra(self.reg.a),
             0x30 => self.reg.b = self.cb_swap(self.reg.b),
@@ -1187,8 +1235,9 @@ impl Cpu {
             0x34 => self.reg.h = self.cb_swap(self.reg.h),
             0x35 => self.reg.l = self.cb_swap(self.reg.l),
             0x36 => {
-                let value = self.cb_swap(self.bus.read_byte(self.reg.get_hl()));
-                self.bus.write_byte(self.reg.get_hl(), value);
+                let value = self.bus.read(self.reg.get_hl());
+                let value = self.cb_swap(value);
+                self.bus.write(self.reg.get_hl(), value);
             }
             0x37 => self.reg.a = self.cb_swap(self.reg.a),
             0x38 => self.reg.b = self.cb_srl(self.reg.b),
@@ -1198,8 +1247,9 @@ impl Cpu {
             0x3C => self.reg.h = self.cb_srl(self.reg.h),
             0x3D => self.reg.l = self.cb_srl(self.reg.l),
             0x3E => {
-                let value = self.cb_srl(self.bus.read_byte(self.reg.get_hl()));
-                self.bus.write_byte(self.reg.get_hl(), value);
+                let value = self.bus.read(self.reg.get_hl());
+                let value = self.cb_srl(value);
+                self.bus.write(self.reg.get_hl(), value);
             }
             0x3F => self.reg.a = self.cb_srl(self.reg.a),
             0x40 => self.cb_bit(0, self.reg.b),
@@ -1208,7 +1258,10 @@ impl Cpu {
             0x43 => self.cb_bit(0, self.reg.e),
             0x44 => self.cb_bit(0, self.reg.h),
             0x45 => self.cb_bit(0, self.reg.l),
-            0x46 => self.cb_bit(0, self.bus.read_byte(self.reg.get_hl())),
+            0x46 => {
+                let value = self.bus.read(self.reg.get_hl());
+                self.cb_bit(0, value)
+            }
             0x47 => self.cb_bit(0, self.reg.a),
             0x48 => self.cb_bit(1, self.reg.b),
             0x49 => self.cb_bit(1, self.reg.c),
@@ -1216,7 +1269,10 @@ impl Cpu {
             0x4B => self.cb_bit(1, self.reg.e),
             0x4C => self.cb_bit(1, self.reg.h),
             0x4D => self.cb_bit(1, self.reg.l),
-            0x4E => self.cb_bit(1, self.bus.read_byte(self.reg.get_hl())),
+            0x4E => {
+                let value = self.bus.read(self.reg.get_hl());
+                self.cb_bit(1, value)
+            }
             0x4F => self.cb_bit(1, self.reg.a),
             0x50 => self.cb_bit(2, self.reg.b),
             0x51 => self.cb_bit(2, self.reg.c),
@@ -1224,7 +1280,10 @@ impl Cpu {
             0x53 => self.cb_bit(2, self.reg.e),
             0x54 => self.cb_bit(2, self.reg.h),
             0x55 => self.cb_bit(2, self.reg.l),
-            0x56 => self.cb_bit(2, self.bus.read_byte(self.reg.get_hl())),
+            0x56 => {
+                let value = self.bus.read(self.reg.get_hl());
+                self.cb_bit(2, value)
+            }
             0x57 => self.cb_bit(2, self.reg.a),
             0x58 => self.cb_bit(3, self.reg.b),
             0x59 => self.cb_bit(3, self.reg.c),
@@ -1232,7 +1291,10 @@ impl Cpu {
             0x5B => self.cb_bit(3, self.reg.e),
             0x5C => self.cb_bit(3, self.reg.h),
             0x5D => self.cb_bit(3, self.reg.l),
-            0x5E => self.cb_bit(3, self.bus.read_byte(self.reg.get_hl())),
+            0x5E => {
+                let value = self.bus.read(self.reg.get_hl());
+                self.cb_bit(3, value)
+            }
             0x5F => self.cb_bit(3, self.reg.a),
             0x60 => self.cb_bit(4, self.reg.b),
             0x61 => self.cb_bit(4, self.reg.c),
@@ -1240,7 +1302,10 @@ impl Cpu {
             0x63 => self.cb_bit(4, self.reg.e),
             0x64 => self.cb_bit(4, self.reg.h),
             0x65 => self.cb_bit(4, self.reg.l),
-            0x66 => self.cb_bit(4, self.bus.read_byte(self.reg.get_hl())),
+            0x66 => {
+                let value = self.bus.read(self.reg.get_hl());
+                self.cb_bit(4, value)
+            }
             0x67 => self.cb_bit(4, self.reg.a),
             0x68 => self.cb_bit(5, self.reg.b),
             0x69 => self.cb_bit(5, self.reg.c),
@@ -1248,7 +1313,10 @@ impl Cpu {
             0x6B => self.cb_bit(5, self.reg.e),
             0x6C => self.cb_bit(5, self.reg.h),
             0x6D => self.cb_bit(5, self.reg.l),
-            0x6E => self.cb_bit(5, self.bus.read_byte(self.reg.get_hl())),
+            0x6E => {
+                let value = self.bus.read(self.reg.get_hl());
+                self.cb_bit(5, value)
+            }
             0x6F => self.cb_bit(5, self.reg.a),
             0x70 => self.cb_bit(6, self.reg.b),
             0x71 => self.cb_bit(6, self.reg.c),
@@ -1256,7 +1324,10 @@ impl Cpu {
             0x73 => self.cb_bit(6, self.reg.e),
             0x74 => self.cb_bit(6, self.reg.h),
             0x75 => self.cb_bit(6, self.reg.l),
-            0x76 => self.cb_bit(6, self.bus.read_byte(self.reg.get_hl())),
+            0x76 => {
+                let value = self.bus.read(self.reg.get_hl());
+                self.cb_bit(6, value)
+            }
             0x77 => self.cb_bit(6, self.reg.a),
             0x78 => self.cb_bit(7, self.reg.b),
             0x79 => self.cb_bit(7, self.reg.c),
@@ -1264,7 +1335,10 @@ impl Cpu {
             0x7B => self.cb_bit(7, self.reg.e),
             0x7C => self.cb_bit(7, self.reg.h),
             0x7D => self.cb_bit(7, self.reg.l),
-            0x7E => self.cb_bit(7, self.bus.read_byte(self.reg.get_hl())),
+            0x7E => {
+                let value = self.bus.read(self.reg.get_hl());
+                self.cb_bit(7, value)
+            }
             0x7F => self.cb_bit(7, self.reg.a),
             0x80 => self.reg.b = self.cb_res(0, self.reg.b),
             0x81 => self.reg.c = self.cb_res(0, self.reg.c),
@@ -1273,8 +1347,9 @@ impl Cpu {
             0x84 => self.reg.h = self.cb_res(0, self.reg.h),
             0x85 => self.reg.l = self.cb_res(0, self.reg.l),
             0x86 => {
-                let value = self.cb_res(0, self.bus.read_byte(self.reg.get_hl()));
-                self.bus.write_byte(self.reg.get_hl(), value);
+                let value = self.bus.read(self.reg.get_hl());
+                let value = self.cb_res(0, value);
+                self.bus.write(self.reg.get_hl(), value);
             }
             0x87 => self.reg.a = self.cb_res(0, self.reg.a),
             0x88 => self.reg.b = self.cb_res(1, self.reg.b),
@@ -1284,8 +1359,9 @@ impl Cpu {
             0x8C => self.reg.h = self.cb_res(1, self.reg.h),
             0x8D => self.reg.l = self.cb_res(1, self.reg.l),
             0x8E => {
-                let value = self.cb_res(1, self.bus.read_byte(self.reg.get_hl()));
-                self.bus.write_byte(self.reg.get_hl(), value);
+                let value = self.bus.read(self.reg.get_hl());
+                let value = self.cb_res(1, value);
+                self.bus.write(self.reg.get_hl(), value);
             }
             0x8F => self.reg.a = self.cb_res(1, self.reg.a),
             0x90 => self.reg.b = self.cb_res(2, self.reg.b),
@@ -1295,8 +1371,9 @@ impl Cpu {
             0x94 => self.reg.h = self.cb_res(2, self.reg.h),
             0x95 => self.reg.l = self.cb_res(2, self.reg.l),
             0x96 => {
-                let value = self.cb_res(2, self.bus.read_byte(self.reg.get_hl()));
-                self.bus.write_byte(self.reg.get_hl(), value);
+                let value = self.bus.read(self.reg.get_hl());
+                let value = self.cb_res(2, value);
+                self.bus.write(self.reg.get_hl(), value);
             }
             0x97 => self.reg.a = self.cb_res(2, self.reg.a),
             0x98 => self.reg.b = self.cb_res(3, self.reg.b),
@@ -1306,8 +1383,9 @@ impl Cpu {
             0x9C => self.reg.h = self.cb_res(3, self.reg.h),
             0x9D => self.reg.l = self.cb_res(3, self.reg.l),
             0x9E => {
-                let value = self.cb_res(3, self.bus.read_byte(self.reg.get_hl()));
-                self.bus.write_byte(self.reg.get_hl(), value);
+                let value = self.bus.read(self.reg.get_hl());
+                let value = self.cb_res(3, value);
+                self.bus.write(self.reg.get_hl(), value);
             }
             0x9F => self.reg.a = self.cb_res(3, self.reg.a),
             0xA0 => self.reg.b = self.cb_res(4, self.reg.b),
@@ -1317,8 +1395,9 @@ impl Cpu {
             0xA4 => self.reg.h = self.cb_res(4, self.reg.h),
             0xA5 => self.reg.l = self.cb_res(4, self.reg.l),
             0xA6 => {
-                let value = self.cb_res(4, self.bus.read_byte(self.reg.get_hl()));
-                self.bus.write_byte(self.reg.get_hl(), value);
+                let value = self.bus.read(self.reg.get_hl());
+                let value = self.cb_res(4, value);
+                self.bus.write(self.reg.get_hl(), value);
             }
             0xA7 => self.reg.a = self.cb_res(4, self.reg.a),
             0xA8 => self.reg.b = self.cb_res(5, self.reg.b),
@@ -1328,8 +1407,9 @@ impl Cpu {
             0xAC => self.reg.h = self.cb_res(5, self.reg.h),
             0xAD => self.reg.l = self.cb_res(5, self.reg.l),
             0xAE => {
-                let value = self.cb_res(5, self.bus.read_byte(self.reg.get_hl()));
-                self.bus.write_byte(self.reg.get_hl(), value);
+                let value = self.bus.read(self.reg.get_hl());
+                let value = self.cb_res(5, value);
+                self.bus.write(self.reg.get_hl(), value);
             }
             0xAF => self.reg.a = self.cb_res(5, self.reg.a),
             0xB0 => self.reg.b = self.cb_res(6, self.reg.b),
@@ -1339,8 +1419,9 @@ impl Cpu {
             0xB4 => self.reg.h = self.cb_res(6, self.reg.h),
             0xB5 => self.reg.l = self.cb_res(6, self.reg.l),
             0xB6 => {
-                let value = self.cb_res(6, self.bus.read_byte(self.reg.get_hl()));
-                self.bus.write_byte(self.reg.get_hl(), value);
+                let value = self.bus.read(self.reg.get_hl());
+                let value = self.cb_res(6, value);
+                self.bus.write(self.reg.get_hl(), value);
             }
             0xB7 => self.reg.a = self.cb_res(6, self.reg.a),
             0xB8 => self.reg.b = self.cb_res(7, self.reg.b),
@@ -1350,8 +1431,9 @@ impl Cpu {
             0xBC => self.reg.h = self.cb_res(7, self.reg.h),
             0xBD => self.reg.l = self.cb_res(7, self.reg.l),
             0xBE => {
-                let value = self.cb_res(7, self.bus.read_byte(self.reg.get_hl()));
-                self.bus.write_byte(self.reg.get_hl(), value);
+                let value = self.bus.read(self.reg.get_hl());
+                let value = self.cb_res(7, value);
+                self.bus.write(self.reg.get_hl(), value);
             }
             0xBF => self.reg.a = self.cb_res(7, self.reg.a),
             0xC0 => self.reg.b = self.cb_set(0, self.reg.b),
@@ -1361,8 +1443,9 @@ impl Cpu {
             0xC4 => self.reg.h = self.cb_set(0, self.reg.h),
             0xC5 => self.reg.l = self.cb_set(0, self.reg.l),
             0xC6 => {
-                let value = self.cb_set(0, self.bus.read_byte(self.reg.get_hl()));
-                self.bus.write_byte(self.reg.get_hl(), value);
+                let value = self.bus.read(self.reg.get_hl());
+                let value = self.cb_set(0, value);
+                self.bus.write(self.reg.get_hl(), value);
             }
             0xC7 => self.reg.a = self.cb_set(0, self.reg.a),
             0xC8 => self.reg.b = self.cb_set(1, self.reg.b),
@@ -1372,8 +1455,9 @@ impl Cpu {
             0xCC => self.reg.h = self.cb_set(1, self.reg.h),
             0xCD => self.reg.l = self.cb_set(1, self.reg.l),
             0xCE => {
-                let value = self.cb_set(1, self.bus.read_byte(self.reg.get_hl()));
-                self.bus.write_byte(self.reg.get_hl(), value);
+                let value = self.bus.read(self.reg.get_hl());
+                let value = self.cb_set(1, value);
+                self.bus.write(self.reg.get_hl(), value);
             }
             0xCF => self.reg.a = self.cb_set(1, self.reg.a),
             0xD0 => self.reg.b = self.cb_set(2, self.reg.b),
@@ -1383,8 +1467,9 @@ impl Cpu {
             0xD4 => self.reg.h = self.cb_set(2, self.reg.h),
             0xD5 => self.reg.l = self.cb_set(2, self.reg.l),
             0xD6 => {
-                let value = self.cb_set(2, self.bus.read_byte(self.reg.get_hl()));
-                self.bus.write_byte(self.reg.get_hl(), value);
+                let value = self.bus.read(self.reg.get_hl());
+                let value = self.cb_set(2, value);
+                self.bus.write(self.reg.get_hl(), value);
             }
             0xD7 => self.reg.a = self.cb_set(2, self.reg.a),
             0xD8 => self.reg.b = self.cb_set(3, self.reg.b),
@@ -1394,8 +1479,9 @@ impl Cpu {
             0xDC => self.reg.h = self.cb_set(3, self.reg.h),
             0xDD => self.reg.l = self.cb_set(3, self.reg.l),
             0xDE => {
-                let value = self.cb_set(3, self.bus.read_byte(self.reg.get_hl()));
-                self.bus.write_byte(self.reg.get_hl(), value);
+                let value = self.bus.read(self.reg.get_hl());
+                let value = self.cb_set(3, value);
+                self.bus.write(self.reg.get_hl(), value);
             }
             0xDF => self.reg.a = self.cb_set(3, self.reg.a),
             0xE0 => self.reg.b = self.cb_set(4, self.reg.b),
@@ -1405,8 +1491,9 @@ impl Cpu {
             0xE4 => self.reg.h = self.cb_set(4, self.reg.h),
             0xE5 => self.reg.l = self.cb_set(4, self.reg.l),
             0xE6 => {
-                let value = self.cb_set(4, self.bus.read_byte(self.reg.get_hl()));
-                self.bus.write_byte(self.reg.get_hl(), value);
+                let value = self.bus.read(self.reg.get_hl());
+                let value = self.cb_set(4, value);
+                self.bus.write(self.reg.get_hl(), value);
             }
             0xE7 => self.reg.a = self.cb_set(4, self.reg.a),
             0xE8 => self.reg.b = self.cb_set(5, self.reg.b),
@@ -1416,8 +1503,9 @@ impl Cpu {
             0xEC => self.reg.h = self.cb_set(5, self.reg.h),
             0xED => self.reg.l = self.cb_set(5, self.reg.l),
             0xEE => {
-                let value = self.cb_set(5, self.bus.read_byte(self.reg.get_hl()));
-                self.bus.write_byte(self.reg.get_hl(), value);
+                let value = self.bus.read(self.reg.get_hl());
+                let value = self.cb_set(5, value);
+                self.bus.write(self.reg.get_hl(), value);
             }
             0xEF => self.reg.a = self.cb_set(5, self.reg.a),
             0xF0 => self.reg.b = self.cb_set(6, self.reg.b),
@@ -1427,8 +1515,9 @@ impl Cpu {
             0xF4 => self.reg.h = self.cb_set(6, self.reg.h),
             0xF5 => self.reg.l = self.cb_set(6, self.reg.l),
             0xF6 => {
-                let value = self.cb_set(6, self.bus.read_byte(self.reg.get_hl()));
-                self.bus.write_byte(self.reg.get_hl(), value);
+                let value = self.bus.read(self.reg.get_hl());
+                let value = self.cb_set(6, value);
+                self.bus.write(self.reg.get_hl(), value);
             }
             0xF7 => self.reg.a = self.cb_set(6, self.reg.a),
             0xF8 => self.reg.b = self.cb_set(7, self.reg.b),
@@ -1438,8 +1527,9 @@ impl Cpu {
             0xFC => self.reg.h = self.cb_set(7, self.reg.h),
             0xFD => self.reg.l = self.cb_set(7, self.reg.l),
             0xFE => {
-                let value = self.cb_set(7, self.bus.read_byte(self.reg.get_hl()));
-                self.bus.write_byte(self.reg.get_hl(), value);
+                let value = self.bus.read(self.reg.get_hl());
+                let value = self.cb_set(7, value);
+                self.bus.write(self.reg.get_hl(), value);
             }
             0xFF => self.reg.a = self.cb_set(7, self.reg.a),
         }
@@ -1469,24 +1559,8 @@ impl Cpu {
     fn adc_a(&mut self) {
         self.m = 2;
 
-        let carry = if self.flag_is_active(Flags::Carry) {
-            1
-        } else {
-            0
-        };
         let value = self.read_byte();
-        let result = self.reg.a.wrapping_add(value).wrapping_add(carry);
-        self.unset_flag(Flags::Negative);
-        self.set_flag_on_if(Flags::Zero, result == 0);
-        self.set_flag_on_if(
-            Flags::HalfCarry,
-            (self.reg.a & 0xF) + (value & 0xF) + carry > 0xF,
-        );
-        self.set_flag_on_if(
-            Flags::Carry,
-            self.reg.a as u16 + value as u16 + carry as u16 > 0xFF,
-        );
-        self.reg.a = result;
+        self.add_a(value, true);
     }
 
     // call address
@@ -1549,18 +1623,8 @@ impl Cpu {
     fn sub_imm(&mut self) {
         self.m = 2;
 
-        let carry = if self.flag_is_active(Flags::Carry) {
-            1
-        } else {
-            0
-        };
         let value = self.read_byte();
-        let result = self.reg.a.wrapping_sub(value).wrapping_sub(carry);
-        self.set_flag(Flags::Negative);
-        self.set_flag_on_if(Flags::Zero, result == 0);
-        self.set_flag_on_if(Flags::HalfCarry, (self.reg.a & 0xF) < (value & 0xF) + carry);
-        self.set_flag_on_if(Flags::Carry, self.reg.a < value + carry);
-        self.reg.a = result;
+        self.sub_a(value, false);
     }
 
     // call address
@@ -1616,18 +1680,8 @@ impl Cpu {
     fn sbc_a(&mut self) {
         self.m = 2;
 
-        let carry = if self.flag_is_active(Flags::Carry) {
-            1
-        } else {
-            0
-        };
         let value = self.read_byte();
-        let result = self.reg.a.wrapping_sub(value).wrapping_sub(carry);
-        self.set_flag(Flags::Negative);
-        self.set_flag_on_if(Flags::Zero, result == 0);
-        self.set_flag_on_if(Flags::HalfCarry, (self.reg.a & 0xF) < (value & 0xF) + carry);
-        self.set_flag_on_if(Flags::Carry, self.reg.a < value + carry);
-        self.reg.a = result;
+        self.sub_a(value, true);
     }
 
     // call adress
@@ -1643,7 +1697,7 @@ impl Cpu {
         self.m = 3;
 
         let value = self.read_byte();
-        self.bus.write_byte(0xFF00 | value as u16, self.reg.a);
+        self.bus.write(0xFF00 | value as u16, self.reg.a);
     }
 
     // pop contents from memory stack into register pair HL
@@ -1657,7 +1711,7 @@ impl Cpu {
     // store contents of register A in the internal ram, port register or mode register
     fn ld_addr_c_a(&mut self) {
         self.m = 2;
-        self.bus.write_byte(0xFF00 | self.reg.c as u16, self.reg.a);
+        self.bus.write(0xFF00 | self.reg.c as u16, self.reg.a);
     }
 
     // push contents of register pair HL onto the memory stack
@@ -1691,7 +1745,7 @@ impl Cpu {
     fn add_sp(&mut self) {
         self.m = 4;
 
-        self.add16_imm(self.reg.sp);
+        self.reg.sp = self.add16_imm(self.reg.sp);
     }
 
     // load contents of register pair HL into the pc
@@ -1707,7 +1761,7 @@ impl Cpu {
         self.m = 4;
 
         let address = self.read_word();
-        self.bus.write_byte(address, self.reg.a);
+        self.bus.write(address, self.reg.a);
     }
 
     // bitwise xor a and 8-bit immediate operand
@@ -1736,7 +1790,7 @@ impl Cpu {
         self.m = 3;
 
         let value = 0xFF00 | self.read_byte() as u16;
-        self.reg.a = self.bus.read_byte(value);
+        self.reg.a = self.bus.read(value);
     }
 
     // pop contents of the memory stack into register pair AF
@@ -1750,7 +1804,7 @@ impl Cpu {
     // load into register A the contents of internal ram, port register or mode register
     fn ld_a_c_addr(&mut self) {
         self.m = 2;
-        self.reg.a = self.bus.read_byte(0xFF00 | self.reg.c as u16);
+        self.reg.a = self.bus.read(0xFF00 | self.reg.c as u16);
     }
 
     // reset interrupt master enable(IME) flag and prohibit maskable interrupts
@@ -1807,7 +1861,7 @@ impl Cpu {
         self.m = 4;
 
         let value = self.read_word();
-        self.reg.a = self.bus.read_byte(value);
+        self.reg.a = self.bus.read(value);
     }
 
     // set the interrupt master enable(IME) flag and
@@ -1821,17 +1875,8 @@ impl Cpu {
     fn cp_d8(&mut self) {
         self.m = 2;
 
-        let carry = if self.flag_is_active(Flags::Carry) {
-            1
-        } else {
-            0
-        };
         let value = self.read_byte();
-        let result = self.reg.a.wrapping_sub(value);
-        self.set_flag(Flags::Negative);
-        self.set_flag_on_if(Flags::Zero, result == 0);
-        self.set_flag_on_if(Flags::HalfCarry, (self.reg.a & 0xF) < (value & 0xF) + carry);
-        self.set_flag_on_if(Flags::Carry, self.reg.a < value + carry);
+        self.cp_a(value);
     }
 
     // call address
@@ -1972,21 +2017,65 @@ impl Cpu {
         }
     }
 
+    // service the highest priority pending interrupt, returns true if one was dispatched
+    // any pending interrupt ends HALT, even when IME is off
+    fn handle_interrupts(&mut self) -> bool {
+        if self.bus.pending_interrupts() == 0 {
+            return false;
+        }
+        self.halted = false;
+        if !self.should_interrupt {
+            return false;
+        }
+
+        self.should_interrupt = false;
+        self.m = 5;
+        // the high byte of pc is pushed first, if that write lands on IE and disables
+        // the pending interrupt the dispatch is cancelled and execution continues at 0x0000
+        self.reg.sp = self.reg.sp.wrapping_sub(1);
+        self.bus.write(self.reg.sp, (self.reg.pc >> 8) as u8);
+        let pending = self.bus.pending_interrupts();
+        self.reg.sp = self.reg.sp.wrapping_sub(1);
+        self.bus.write(self.reg.sp, (self.reg.pc & 0xFF) as u8);
+
+        self.reg.pc = match Interrupt::highest_priority(pending) {
+            Some(interrupt) => {
+                self.bus.clear_interrupt(interrupt);
+                interrupt.vector()
+            }
+            None => 0x0000,
+        };
+        true
+    }
+
+    // advance the hardware running alongside the cpu by the cycles of the last instruction
+    fn update_peripherals(&mut self) {
+        self.bus.tick(self.m);
+    }
+
     pub fn run_cycle(&mut self) {
-        if !self.halted {
-            self.print_register_data();
-            self.decode_execute();
-            self.bus.timer.update(self.m);
-            if self.should_interrupt && self.bus.timer.interrupt {
-                println!("TIMER INTERRUPT");
-                println!("TIMER INTERRUPT");
-                println!("TIMER INTERRUPT");
-                println!("TIMER INTERRUPT");
-                println!("TIMER INTERRUPT");
-                self.push_stack(self.reg.pc);
-                self.reg.pc = 0x0040;
+        if self.handle_interrupts() {
+            self.update_peripherals();
+            return;
+        }
+        if self.halted {
+            // the clock keeps running while halted so an interrupt can wake the cpu up
+            self.m = 1;
+            self.update_peripherals();
+            return;
+        }
+
+        self.bus.begin_instruction(self.reg.pc);
+        if self.tracer.is_some() {
+            let record = self.trace_record();
+            if let Some(tracer) = self.tracer.as_mut() {
+                tracer(&record);
             }
         }
+        let bank = self.bus.rom_bank_at(self.reg.pc);
+        self.history.push(bank, self.reg);
+        self.decode_execute();
+        self.update_peripherals();
     }
 }
 
@@ -1994,20 +2083,284 @@ impl Cpu {
 mod tests {
     use super::*;
 
+    // cpu with `program` placed at the 0x0100 entry point
+    fn cpu_with_program(program: &[u8]) -> Cpu {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0100..0x0100 + program.len()].copy_from_slice(program);
+        Cpu::from_rom_bytes(rom)
+    }
+
+    // reads and writes the access hooks saw while running `instructions`
+    fn hooked_accesses(cpu: &mut Cpu, instructions: usize) -> Vec<(char, u16)> {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let reads = log.clone();
+        cpu.bus.hooks.on_read(Box::new(move |addr, _| {
+            reads.borrow_mut().push(('r', addr))
+        }));
+        let writes = log.clone();
+        cpu.bus.hooks.on_write(Box::new(move |addr, _| {
+            writes.borrow_mut().push(('w', addr))
+        }));
+        for _ in 0..instructions {
+            cpu.run_cycle();
+        }
+        cpu.bus.hooks.clear();
+        let accesses = log.borrow().clone();
+        accesses
+    }
+
+    #[test]
+    fn test_access_hooks_see_only_cpu_accesses() {
+        // LD A,(0xC000); LD (0xC001),A
+        let mut cpu = cpu_with_program(&[0xFA, 0x00, 0xC0, 0xEA, 0x01, 0xC0]);
+        let io_accesses = cpu.bus.io_accesses();
+        assert_eq!(
+            vec![
+                ('r', 0x0100),
+                ('r', 0x0101),
+                ('r', 0x0102),
+                ('r', 0xC000),
+                ('r', 0x0103),
+                ('r', 0x0104),
+                ('r', 0x0105),
+                ('w', 0xC001),
+            ],
+            hooked_accesses(&mut cpu, 2)
+        );
+        assert_eq!(io_accesses, cpu.bus.io_accesses());
+    }
+
+    #[test]
+    fn test_tracer_does_not_access_the_bus() {
+        // LD A,(0xC000); LD (0xC001),A
+        let program = [0xFA, 0x00, 0xC0, 0xEA, 0x01, 0xC0];
+        let mut untraced = cpu_with_program(&program);
+        let mut traced = cpu_with_program(&program);
+        let records = std::rc::Rc::new(std::cell::Cell::new(0));
+        let counter = records.clone();
+        traced.set_tracer(Box::new(move |_| counter.set(counter.get() + 1)));
+        assert_eq!(
+            hooked_accesses(&mut untraced, 2),
+            hooked_accesses(&mut traced, 2)
+        );
+        assert_eq!(2, records.get());
+    }
+
+    // run ADD SP,e8 from `sp` and return the resulting sp and flags
+    fn add_sp(sp: u16, e8: u8) -> (u16, u8) {
+        let mut cpu = cpu_with_program(&[0xE8, e8]);
+        cpu.reg.sp = sp;
+        cpu.run_cycle();
+        assert_eq!(4, cpu.m);
+        (cpu.reg.sp, cpu.reg.f)
+    }
+
+    #[test]
+    fn test_add_sp_flags_come_from_low_byte() {
+        // carry out of bit 3 and bit 7, zero result never sets Z
+        assert_eq!((0x0000, 0x30), add_sp(0xFFF8, 0x08));
+        // negative offsets still add 0xFF to the low byte
+        assert_eq!((0x000E, 0x30), add_sp(0x000F, 0xFF));
+        assert_eq!((0x0FFF, 0x00), add_sp(0x1000, 0xFF));
+        // carry without half carry
+        assert_eq!((0x0110, 0x10), add_sp(0x00F0, 0x20));
+        // half carry without carry
+        assert_eq!((0xD011, 0x20), add_sp(0xD00A, 0x07));
+    }
+
+    #[test]
+    fn test_add_and_sub_ignore_carry_unlike_adc_and_sbc() {
+        // ADD A,B; ADC A,B; SUB B; SBC A,B; CP B
+        let mut cpu = cpu_with_program(&[0x80, 0x88, 0x90, 0x98, 0xB8]);
+        cpu.reg.a = 0x0F;
+        cpu.reg.b = 0x01;
+        cpu.set_flag(Flags::Carry);
+        cpu.run_cycle();
+        assert_eq!(0x10, cpu.reg.a);
+        assert_eq!(0x20, cpu.reg.f);
+
+        cpu.set_flag(Flags::Carry);
+        cpu.run_cycle();
+        assert_eq!(0x12, cpu.reg.a);
+        assert_eq!(0x00, cpu.reg.f);
+
+        cpu.set_flag(Flags::Carry);
+        cpu.run_cycle();
+        assert_eq!(0x11, cpu.reg.a);
+        assert_eq!(0x40, cpu.reg.f);
+
+        cpu.set_flag(Flags::Carry);
+        cpu.run_cycle();
+        assert_eq!(0x0F, cpu.reg.a);
+        assert_eq!(0x60, cpu.reg.f);
+
+        cpu.set_flag(Flags::Carry);
+        cpu.run_cycle();
+        assert_eq!(0x0F, cpu.reg.a);
+        assert_eq!(0x40, cpu.reg.f);
+    }
+
+    #[test]
+    fn test_add_hl_rr_flags() {
+        // ADD HL,BC; ADD HL,DE; ADD HL,HL; ADD HL,SP
+        let mut cpu = cpu_with_program(&[0x09, 0x19, 0x29, 0x39]);
+        cpu.reg.f = 0x80;
+        cpu.reg.set_hl(0x0FFF);
+        cpu.reg.set_bc(0x0001);
+        cpu.run_cycle();
+        assert_eq!(0x1000, cpu.reg.get_hl());
+        assert_eq!(0xA0, cpu.reg.f);
+        assert_eq!(2, cpu.m);
+
+        cpu.reg.f = 0x70;
+        cpu.reg.set_de(0x7000);
+        cpu.run_cycle();
+        assert_eq!(0x8000, cpu.reg.get_hl());
+        assert_eq!(0x00, cpu.reg.f);
+
+        cpu.run_cycle();
+        assert_eq!(0x0000, cpu.reg.get_hl());
+        assert_eq!(0x10, cpu.reg.f);
+
+        cpu.reg.set_hl(0x8FFF);
+        cpu.reg.sp = 0x7001;
+        cpu.run_cycle();
+        assert_eq!(0x0000, cpu.reg.get_hl());
+        assert_eq!(0x30, cpu.reg.f);
+    }
+
+    #[test]
+    fn test_simultaneous_interrupts_are_serviced_by_priority() {
+        let mut cpu = cpu_with_program(&[0x00]);
+        cpu.should_interrupt = true;
+        cpu.bus.write_byte(0xFFFF, 0x1F);
+        cpu.bus.request_interrupt(Interrupt::Joypad);
+        cpu.bus.request_interrupt(Interrupt::Timer);
+        cpu.bus.request_interrupt(Interrupt::VBlank);
+
+        cpu.run_cycle();
+        assert_eq!(0x0040, cpu.reg.pc);
+        assert_eq!(5, cpu.m);
+        assert!(!cpu.should_interrupt);
+        // only the serviced bit is acknowledged
+        assert_eq!(0x14, cpu.bus.read_byte(0xFF0F) & 0x1F);
+        assert_eq!(0x0100, cpu.bus.read_word(cpu.reg.sp));
+
+        // RETI re-enables IME, the next interrupt in line is timer
+        cpu.reg.pc = 0x0100;
+        cpu.should_interrupt = true;
+        cpu.run_cycle();
+        assert_eq!(0x0050, cpu.reg.pc);
+        assert_eq!(0x10, cpu.bus.read_byte(0xFF0F) & 0x1F);
+
+        cpu.should_interrupt = true;
+        cpu.run_cycle();
+        assert_eq!(0x0060, cpu.reg.pc);
+        assert_eq!(0x00, cpu.bus.read_byte(0xFF0F) & 0x1F);
+    }
+
+    #[test]
+    fn test_disabled_interrupts_are_not_serviced() {
+        let mut cpu = cpu_with_program(&[0x00, 0x00]);
+        cpu.should_interrupt = true;
+        cpu.bus.write_byte(0xFFFF, Interrupt::Timer.bit());
+        cpu.bus.request_interrupt(Interrupt::VBlank);
+        cpu.bus.request_interrupt(Interrupt::Timer);
+
+        cpu.run_cycle();
+        assert_eq!(0x0050, cpu.reg.pc);
+        assert_eq!(0x01, cpu.bus.read_byte(0xFF0F) & 0x1F);
+
+        // without IME nothing is dispatched
+        cpu.reg.pc = 0x0100;
+        cpu.bus.write_byte(0xFFFF, 0x1F);
+        cpu.run_cycle();
+        assert_eq!(0x0101, cpu.reg.pc);
+        assert_eq!(0x01, cpu.bus.read_byte(0xFF0F) & 0x1F);
+    }
+
+    #[test]
+    fn test_dispatch_is_cancelled_when_push_overwrites_ie() {
+        let mut cpu = cpu_with_program(&[0x00]);
+        cpu.should_interrupt = true;
+        cpu.reg.sp = 0x0000;
+        cpu.bus.write_byte(0xFFFF, Interrupt::Timer.bit());
+        cpu.bus.request_interrupt(Interrupt::Timer);
+        cpu.reg.pc = 0x0100;
+
+        // pushing 0x01 into IE leaves only VBlank enabled, which isn't requested
+        cpu.run_cycle();
+        assert_eq!(0x0000, cpu.reg.pc);
+        assert_eq!(0xFFFE, cpu.reg.sp);
+        assert_eq!(0x01, cpu.bus.read_byte(0xFFFF));
+        assert_eq!(Interrupt::Timer.bit(), cpu.bus.read_byte(0xFF0F) & 0x1F);
+        assert!(!cpu.should_interrupt);
+    }
+
+    #[test]
+    fn test_dispatch_picks_again_after_high_byte_push() {
+        let mut cpu = cpu_with_program(&[0x00]);
+        cpu.should_interrupt = true;
+        cpu.reg.sp = 0x0000;
+        cpu.bus.write_byte(0xFFFF, 0x05);
+        cpu.bus.request_interrupt(Interrupt::VBlank);
+        cpu.bus.request_interrupt(Interrupt::Timer);
+        // pc high byte 0x04 disables VBlank, so timer is serviced instead
+        cpu.reg.pc = 0x0400;
+
+        cpu.run_cycle();
+        assert_eq!(0x0050, cpu.reg.pc);
+        assert_eq!(Interrupt::VBlank.bit(), cpu.bus.read_byte(0xFF0F) & 0x1F);
+    }
+
+    #[test]
+    fn test_pending_interrupt_wakes_halt_without_ime() {
+        // HALT, INC A
+        let mut cpu = cpu_with_program(&[0x76, 0x3C]);
+        cpu.bus.write_byte(0xFFFF, Interrupt::Timer.bit());
+        cpu.run_cycle();
+        assert!(cpu.halted);
+        cpu.run_cycle();
+        assert!(cpu.halted);
+        assert_eq!(1, cpu.m);
+
+        cpu.bus.request_interrupt(Interrupt::Timer);
+        cpu.run_cycle();
+        assert!(!cpu.halted);
+        assert_eq!(0x02, cpu.reg.a);
+        assert_eq!(Interrupt::Timer.bit(), cpu.bus.read_byte(0xFF0F) & 0x1F);
+    }
+
+    #[test]
+    fn test_ld_hl_sp_e8_leaves_sp_untouched() {
+        let mut cpu = cpu_with_program(&[0xF8, 0x02]);
+        cpu.reg.sp = 0xFFF8;
+        cpu.run_cycle();
+        assert_eq!(0xFFFA, cpu.reg.get_hl());
+        assert_eq!(0xFFF8, cpu.reg.sp);
+        assert_eq!(0x00, cpu.reg.f);
+        assert_eq!(3, cpu.m);
+
+        let mut cpu = cpu_with_program(&[0xF8, 0xFE]);
+        cpu.reg.sp = 0x0001;
+        cpu.run_cycle();
+        assert_eq!(0xFFFF, cpu.reg.get_hl());
+        assert_eq!(0x00, cpu.reg.f);
+    }
+
     #[test]
     fn test_correct_resetting_of_flags() {
-        let mut cpu = Cpu::new(Path::new(
-            "gb-test-roms/cpu_instrs/individual/01-special.gb",
-        ));
+        let mut cpu = Cpu::from_rom_bytes(vec![0; 0x8000]);
         cpu.reset_flags();
         assert_eq!(0, cpu.reg.f);
     }
 
     #[test]
     fn test_correct_setting_of_flag() {
-        let mut cpu = Cpu::new(Path::new(
-            "gb-test-roms/cpu_instrs/individual/01-special.gb",
-        ));
+        let mut cpu = Cpu::from_rom_bytes(vec![0; 0x8000]);
         cpu.reset_flags();
         cpu.set_flag(Flags::Carry);
         assert_eq!(0x10, cpu.reg.f);
@@ -2017,20 +2370,31 @@ mod tests {
 
     #[test]
     fn test_correct_unsetting_of_flag() {
-        let mut cpu = Cpu::new(Path::new(
-            "gb-test-roms/cpu_instrs/individual/01-special.gb",
-        ));
+        let mut cpu = Cpu::from_rom_bytes(vec![0; 0x8000]);
         cpu.unset_flag(Flags::Zero);
         assert_eq!(0x30, cpu.reg.f);
     }
 
     #[test]
     fn test_if_flag_is_active() {
-        let cpu = Cpu::new(Path::new(
-            "gb-test-roms/cpu_instrs/individual/01-special.gb",
-        ));
-        assert_eq!(true, cpu.flag_is_active(Flags::Zero));
-        assert_eq!(true, cpu.flag_is_active(Flags::Carry));
-        assert_eq!(true, cpu.flag_is_active(Flags::HalfCarry));
+        let cpu = Cpu::from_rom_bytes(vec![0; 0x8000]);
+        assert!(cpu.flag_is_active(Flags::Zero));
+        assert!(cpu.flag_is_active(Flags::Carry));
+        assert!(cpu.flag_is_active(Flags::HalfCarry));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_roundtrip_keeps_cpu_state() {
+        let mut cpu = Cpu::from_rom_bytes(vec![0; 0x8000]);
+        cpu.reg.set_bc(0xBEEF);
+        cpu.bus.write_byte(0xC123, 0x42);
+        cpu.bus.timer.update(4);
+
+        let json = serde_json::to_string(&cpu).unwrap();
+        let restored: Cpu = serde_json::from_str(&json).unwrap();
+        assert_eq!(cpu.reg, restored.reg);
+        assert_eq!(0x42, restored.bus.read_byte(0xC123));
+        assert_eq!(json, serde_json::to_string(&restored).unwrap());
     }
 }
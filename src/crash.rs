@@ -0,0 +1,141 @@
+// crash reports
+// when the core panics we write everything needed to reproduce the problem to a file,
+// so a bug report can carry more than a backtrace
+
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{register::Register, trace::TraceRecord};
+
+thread_local! {
+    // message and location of the last panic on this thread, filled in by the panic hook
+    static LAST_PANIC: RefCell<Option<String>> = const { RefCell::new(None) };
+    // set inside catch_crash, where a panic ends up in a crash report
+    static REPORTING: Cell<bool> = const { Cell::new(false) };
+}
+
+// panics inside catch_crash are kept for the crash report instead of printed, any other
+// panic still goes to the hook that was there before
+pub fn install_panic_hook() {
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if REPORTING.with(Cell::get) {
+            LAST_PANIC.with(|last| *last.borrow_mut() = Some(info.to_string()));
+        } else {
+            previous(info);
+        }
+    }));
+}
+
+// runs `f`, a panic in it comes back as its message
+pub fn catch_crash<R>(f: impl FnOnce() -> R) -> Result<R, String> {
+    let reporting = REPORTING.with(|reporting| reporting.replace(true));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    REPORTING.with(|flag| flag.set(reporting));
+    result.map_err(|payload| take_panic_message(payload.as_ref()))
+}
+
+// message of the last panic caught by the hook, falling back to the panic payload
+pub fn take_panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    LAST_PANIC
+        .with(|last| last.borrow_mut().take())
+        .or_else(|| payload.downcast_ref::<&str>().map(|s| s.to_string()))
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+pub struct CrashReport {
+    pub message: String,
+    pub title: String,
+    pub checksum: u8,
+    pub registers: Register,
+    // oldest first
+    pub trace: Vec<TraceRecord>,
+    // serialized core state, only available with the serde feature
+    pub savestate: Option<String>,
+}
+
+impl CrashReport {
+    // write the report to a new file in `dir` and return its path
+    pub fn write_to(&self, dir: &Path) -> io::Result<PathBuf> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_millis())
+            .unwrap_or(0);
+        let path = dir.join(format!("rustyboy-crash-{}.txt", timestamp));
+        fs::write(&path, self.to_string())?;
+        Ok(path)
+    }
+}
+
+impl fmt::Display for CrashReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let r = &self.registers;
+        writeln!(f, "rustyboy crash report")?;
+        writeln!(f, "Panic: {}", self.message)?;
+        writeln!(f, "Title: {}", self.title)?;
+        writeln!(f, "Checksum: {:#04X}", self.checksum)?;
+        writeln!(f, "PC: {:04X}", r.pc)?;
+        writeln!(
+            f,
+            "A: {:02X} F: {:02X} B: {:02X} C: {:02X} D: {:02X} E: {:02X} H: {:02X} L: {:02X} SP: {:04X}",
+            r.a, r.f, r.b, r.c, r.d, r.e, r.h, r.l, r.sp
+        )?;
+        writeln!(f)?;
+        writeln!(f, "Last {} instructions:", self.trace.len())?;
+        for record in &self.trace {
+            writeln!(
+                f,
+                "{:02X}:{:04X}  {:<14} AF: {:04X} BC: {:04X} DE: {:04X} HL: {:04X} SP: {:04X}",
+                record.bank,
+                record.pc,
+                record.mnemonic,
+                record.registers.get_af(),
+                record.registers.get_bc(),
+                record.registers.get_de(),
+                record.registers.get_hl(),
+                record.registers.sp
+            )?;
+        }
+        if let Some(savestate) = &self.savestate {
+            writeln!(f)?;
+            writeln!(f, "Savestate:")?;
+            writeln!(f, "{}", savestate)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_panic_message_from_hook_or_payload() {
+        let printed = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let previous = printed.clone();
+        panic::set_hook(Box::new(move |_| {
+            previous.store(true, std::sync::atomic::Ordering::SeqCst)
+        }));
+        install_panic_hook();
+        let message = catch_crash(|| panic!("bad opcode")).unwrap_err();
+        assert!(message.contains("bad opcode"));
+        assert!(message.contains("crash.rs"));
+        assert!(!printed.load(std::sync::atomic::Ordering::SeqCst));
+
+        // panics outside still reach the previous hook
+        assert!(panic::catch_unwind(|| panic!("elsewhere")).is_err());
+        assert!(printed.load(std::sync::atomic::Ordering::SeqCst));
+        assert_eq!(Ok(1), catch_crash(|| 1));
+
+        // without the hook the payload is used
+        let payload: Box<dyn std::any::Any + Send> = Box::new("plain");
+        assert_eq!("plain", take_panic_message(payload.as_ref()));
+        let _ = panic::take_hook();
+    }
+}
@@ -0,0 +1,267 @@
+// breakpoints and other helpers for stepping through a rom
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::bus::Bus;
+
+const SWITCHABLE_ROM_START: u16 = 0x4000;
+const SWITCHABLE_ROM_END: u16 = 0x7FFF;
+
+// work ram and high ram, the places a game keeps its variables
+pub const RAM_RANGES: [(u16, usize); 2] = [(0xC000, 0x2000), (0xFF80, 0x7F)];
+
+// breakpoint on an address, optionally only while a specific rom bank is mapped
+// without a bank a breakpoint in 0x4000-0x7FFF fires in every bank
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub bank: Option<u16>,
+    pub addr: u16,
+}
+
+impl Breakpoint {
+    pub fn new(addr: u16) -> Self {
+        Self { bank: None, addr }
+    }
+
+    pub fn in_bank(bank: u16, addr: u16) -> Self {
+        Self {
+            bank: Some(bank),
+            addr,
+        }
+    }
+
+    // the bank is looked up at fetch time, since the game may switch banks at any point
+    pub fn hit(&self, bus: &Bus, pc: u16) -> bool {
+        if pc != self.addr {
+            return false;
+        }
+        match self.bank {
+            Some(bank) if (SWITCHABLE_ROM_START..=SWITCHABLE_ROM_END).contains(&pc) => {
+                bus.rom_bank_at(pc) == bank
+            }
+            _ => true,
+        }
+    }
+}
+
+// parses "ADDR" or "BANK:ADDR", both in hex, e.g. "0150" or "03:4A10"
+impl FromStr for Breakpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_hex = |value: &str| {
+            u16::from_str_radix(value.trim().trim_start_matches("0x"), 16)
+                .map_err(|_| format!("invalid breakpoint: {}", s))
+        };
+        match s.split_once(':') {
+            Some((bank, addr)) => Ok(Self::in_bank(parse_hex(bank)?, parse_hex(addr)?)),
+            None => Ok(Self::new(parse_hex(s)?)),
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.bank {
+            Some(bank) => write!(f, "{:02X}:{:04X}", bank, self.addr),
+            None => write!(f, "{:04X}", self.addr),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self {
+            breakpoints: Vec::new(),
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        if !self.breakpoints.contains(&breakpoint) {
+            self.breakpoints.push(breakpoint);
+        }
+    }
+
+    pub fn remove_breakpoint(&mut self, breakpoint: Breakpoint) {
+        self.breakpoints.retain(|bp| *bp != breakpoint);
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    // first breakpoint that fires for the instruction about to be fetched at pc
+    pub fn breakpoint_at(&self, bus: &Bus, pc: u16) -> Option<Breakpoint> {
+        self.breakpoints.iter().copied().find(|bp| bp.hit(bus, pc))
+    }
+}
+
+// label names from an assembler symbol file, one "BANK:ADDR NAME" per line in hex
+// (the rgbds .sym format), ; starts a comment
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Symbols {
+    names: HashMap<u16, String>,
+}
+
+impl Symbols {
+    pub fn name(&self, addr: u16) -> Option<&str> {
+        self.names.get(&addr).map(String::as_str)
+    }
+}
+
+impl FromStr for Symbols {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut names = HashMap::new();
+        for (number, line) in s.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = || format!("invalid symbol on line {}: {}", number + 1, line);
+            let (location, name) = line.split_once(char::is_whitespace).ok_or_else(invalid)?;
+            // ram labels are looked up by address alone
+            let addr = location.rsplit(':').next().unwrap_or(location);
+            let addr = u16::from_str_radix(addr, 16).map_err(|_| invalid())?;
+            // the first label at an address is the one that names it
+            names.entry(addr).or_insert_with(|| name.trim().to_string());
+        }
+        Ok(Self { names })
+    }
+}
+
+// an inclusive address range, parsed from "START-END" in hex, e.g. "C000-C0FF"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddressRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl FromStr for AddressRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid address range: {}", s);
+        let parse_hex = |value: &str| {
+            u16::from_str_radix(value.trim().trim_start_matches("0x"), 16).map_err(|_| invalid())
+        };
+        let (start, end) = s.split_once('-').ok_or_else(invalid)?;
+        let (start, end) = (parse_hex(start)?, parse_hex(end)?);
+        if start > end {
+            return Err(invalid());
+        }
+        Ok(Self { start, end })
+    }
+}
+
+// 16 bytes per line, with the address of the first one and the printable ones as text:
+// "C000  48 49 00 ...  HI."
+pub fn hexdump(start: u16, bytes: &[u8]) -> String {
+    let mut dump = String::new();
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        let addr = start.wrapping_add(line as u16 * 16);
+        let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02X}", byte)).collect();
+        let text: String = chunk
+            .iter()
+            .map(|&byte| match byte {
+                0x20..=0x7E => byte as char,
+                _ => '.',
+            })
+            .collect();
+        dump.push_str(&format!("{:04X}  {:<47}  {}\n", addr, hex.join(" "), text));
+    }
+    dump
+}
+
+// a byte that differs between two states of the machine
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RamChange {
+    pub addr: u16,
+    pub old: u8,
+    pub new: u8,
+    pub symbol: Option<String>,
+}
+
+impl fmt::Display for RamChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04X}: {:02X} -> {:02X}", self.addr, self.old, self.new)?;
+        if let Some(symbol) = &self.symbol {
+            write!(f, " {}", symbol)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_breakpoints() {
+        assert_eq!(Ok(Breakpoint::new(0x0150)), "0150".parse());
+        assert_eq!(Ok(Breakpoint::in_bank(3, 0x4A10)), "03:4A10".parse());
+        assert_eq!(Ok(Breakpoint::in_bank(1, 0x7FFF)), "0x1:0x7FFF".parse());
+        assert!("nope".parse::<Breakpoint>().is_err());
+        assert!("1:".parse::<Breakpoint>().is_err());
+        assert_eq!("03:4A10", Breakpoint::in_bank(3, 0x4A10).to_string());
+    }
+
+    #[test]
+    fn test_banked_breakpoint_only_fires_in_mapped_bank() {
+        let bus = Bus::from_rom_bytes(vec![0; 0x8000]);
+        assert!(Breakpoint::in_bank(1, 0x4000).hit(&bus, 0x4000));
+        assert!(!Breakpoint::in_bank(2, 0x4000).hit(&bus, 0x4000));
+        assert!(Breakpoint::new(0x4000).hit(&bus, 0x4000));
+        assert!(!Breakpoint::new(0x4000).hit(&bus, 0x4001));
+        // bank 0 and non-rom addresses are always mapped
+        assert!(Breakpoint::in_bank(5, 0x0150).hit(&bus, 0x0150));
+        assert!(Breakpoint::in_bank(5, 0xC000).hit(&bus, 0xC000));
+    }
+
+    #[test]
+    fn test_hexdump() {
+        assert_eq!(
+            Ok(AddressRange {
+                start: 0xC000,
+                end: 0xC0FF
+            }),
+            "C000-C0FF".parse()
+        );
+        assert!("C0FF-C000".parse::<AddressRange>().is_err());
+        assert!("C000".parse::<AddressRange>().is_err());
+
+        let mut bus = Bus::from_rom_bytes(vec![0; 0x8000]);
+        for (offset, &byte) in b"Hi\n".iter().enumerate() {
+            bus.write_byte(0xC000 + offset as u16, byte);
+        }
+        let bytes = bus.dump_range(0xC000, 0xC010);
+        assert_eq!(17, bytes.len());
+        let dump = hexdump(0xC000, &bytes);
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(
+            "C000  48 69 0A 00 00 00 00 00 00 00 00 00 00 00 00 00  Hi..............",
+            lines[0]
+        );
+        assert!(lines[1].starts_with("C010  00 "), "{dump}");
+    }
+
+    #[test]
+    fn test_parse_symbols() {
+        let symbols: Symbols = "; rgbds\n00:0150 Start\n00:C0A0 wPlayerX ; x\nC0A0 wAlias\n"
+            .parse()
+            .unwrap();
+        assert_eq!(Some("wPlayerX"), symbols.name(0xC0A0));
+        assert_eq!(Some("Start"), symbols.name(0x0150));
+        assert_eq!(None, symbols.name(0xC0A1));
+        assert!("00:ZZZZ nope".parse::<Symbols>().is_err());
+        assert!("C0A0".parse::<Symbols>().is_err());
+    }
+}
@@ -0,0 +1,10 @@
+// devices on the memory bus
+// peripherals the hardware doesn't have (test fixtures, homebrew debug ports, link cable
+// experiments) implement this and are attached to an address range with
+// Bus::attach_device. they see the full address, and take the range over from whatever
+// the bus had there
+
+pub trait Device {
+    fn read_byte(&self, addr: u16) -> u8;
+    fn write_byte(&mut self, addr: u16, value: u8);
+}
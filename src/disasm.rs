@@ -0,0 +1,242 @@
+// disassembler for the sm83 instruction set
+// used by tracers and debugging tools, never by the cpu itself
+
+const REGISTERS: [&str; 8] = ["B", "C", "D", "E", "H", "L", "(HL)", "A"];
+const ALU_OPERATIONS: [&str; 8] = [
+    "ADD A,", "ADC A,", "SUB ", "SBC A,", "AND ", "XOR ", "OR ", "CP ",
+];
+const CB_OPERATIONS: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SWAP", "SRL"];
+
+// mnemonic template for an unprefixed opcode
+// operand placeholders: d8/a8/r8 are one byte, d16/a16 are two bytes
+fn template(opcode: u8) -> &'static str {
+    match opcode {
+        0x00 => "NOP",
+        0x01 => "LD BC,d16",
+        0x02 => "LD (BC),A",
+        0x03 => "INC BC",
+        0x04 => "INC B",
+        0x05 => "DEC B",
+        0x06 => "LD B,d8",
+        0x07 => "RLCA",
+        0x08 => "LD (a16),SP",
+        0x09 => "ADD HL,BC",
+        0x0A => "LD A,(BC)",
+        0x0B => "DEC BC",
+        0x0C => "INC C",
+        0x0D => "DEC C",
+        0x0E => "LD C,d8",
+        0x0F => "RRCA",
+        0x10 => "STOP d8",
+        0x11 => "LD DE,d16",
+        0x12 => "LD (DE),A",
+        0x13 => "INC DE",
+        0x14 => "INC D",
+        0x15 => "DEC D",
+        0x16 => "LD D,d8",
+        0x17 => "RLA",
+        0x18 => "JR r8",
+        0x19 => "ADD HL,DE",
+        0x1A => "LD A,(DE)",
+        0x1B => "DEC DE",
+        0x1C => "INC E",
+        0x1D => "DEC E",
+        0x1E => "LD E,d8",
+        0x1F => "RRA",
+        0x20 => "JR NZ,r8",
+        0x21 => "LD HL,d16",
+        0x22 => "LD (HL+),A",
+        0x23 => "INC HL",
+        0x24 => "INC H",
+        0x25 => "DEC H",
+        0x26 => "LD H,d8",
+        0x27 => "DAA",
+        0x28 => "JR Z,r8",
+        0x29 => "ADD HL,HL",
+        0x2A => "LD A,(HL+)",
+        0x2B => "DEC HL",
+        0x2C => "INC L",
+        0x2D => "DEC L",
+        0x2E => "LD L,d8",
+        0x2F => "CPL",
+        0x30 => "JR NC,r8",
+        0x31 => "LD SP,d16",
+        0x32 => "LD (HL-),A",
+        0x33 => "INC SP",
+        0x34 => "INC (HL)",
+        0x35 => "DEC (HL)",
+        0x36 => "LD (HL),d8",
+        0x37 => "SCF",
+        0x38 => "JR C,r8",
+        0x39 => "ADD HL,SP",
+        0x3A => "LD A,(HL-)",
+        0x3B => "DEC SP",
+        0x3C => "INC A",
+        0x3D => "DEC A",
+        0x3E => "LD A,d8",
+        0x3F => "CCF",
+        0xC0 => "RET NZ",
+        0xC1 => "POP BC",
+        0xC2 => "JP NZ,a16",
+        0xC3 => "JP a16",
+        0xC4 => "CALL NZ,a16",
+        0xC5 => "PUSH BC",
+        0xC6 => "ADD A,d8",
+        0xC7 => "RST 00H",
+        0xC8 => "RET Z",
+        0xC9 => "RET",
+        0xCA => "JP Z,a16",
+        0xCC => "CALL Z,a16",
+        0xCD => "CALL a16",
+        0xCE => "ADC A,d8",
+        0xCF => "RST 08H",
+        0xD0 => "RET NC",
+        0xD1 => "POP DE",
+        0xD2 => "JP NC,a16",
+        0xD4 => "CALL NC,a16",
+        0xD5 => "PUSH DE",
+        0xD6 => "SUB d8",
+        0xD7 => "RST 10H",
+        0xD8 => "RET C",
+        0xD9 => "RETI",
+        0xDA => "JP C,a16",
+        0xDC => "CALL C,a16",
+        0xDE => "SBC A,d8",
+        0xDF => "RST 18H",
+        0xE0 => "LDH (a8),A",
+        0xE1 => "POP HL",
+        0xE2 => "LD (C),A",
+        0xE5 => "PUSH HL",
+        0xE6 => "AND d8",
+        0xE7 => "RST 20H",
+        0xE8 => "ADD SP,r8",
+        0xE9 => "JP (HL)",
+        0xEA => "LD (a16),A",
+        0xEE => "XOR d8",
+        0xEF => "RST 28H",
+        0xF0 => "LDH A,(a8)",
+        0xF1 => "POP AF",
+        0xF2 => "LD A,(C)",
+        0xF3 => "DI",
+        0xF5 => "PUSH AF",
+        0xF6 => "OR d8",
+        0xF7 => "RST 30H",
+        0xF8 => "LD HL,SP+r8",
+        0xF9 => "LD SP,HL",
+        0xFA => "LD A,(a16)",
+        0xFB => "EI",
+        0xFE => "CP d8",
+        0xFF => "RST 38H",
+        // opcodes that don't exist on the gameboy
+        _ => "ILLEGAL",
+    }
+}
+
+// number of bytes the instruction starting with `opcode` occupies, including operands
+pub fn instruction_length(opcode: u8) -> u16 {
+    match opcode {
+        0xCB => 2,
+        0x40..=0xBF => 1,
+        _ => {
+            let template = template(opcode);
+            if template.contains("16") {
+                3
+            } else if template.contains('8') && !template.starts_with("RST") {
+                2
+            } else {
+                1
+            }
+        }
+    }
+}
+
+// disassemble the instruction found at `pc`
+// `bytes` must hold at least `instruction_length(bytes[0])` bytes
+pub fn disassemble(pc: u16, bytes: &[u8]) -> String {
+    let opcode = bytes[0];
+    match opcode {
+        0x76 => "HALT".to_string(),
+        0x40..=0x7F => format!(
+            "LD {},{}",
+            REGISTERS[((opcode >> 3) & 0x7) as usize],
+            REGISTERS[(opcode & 0x7) as usize]
+        ),
+        0x80..=0xBF => format!(
+            "{}{}",
+            ALU_OPERATIONS[((opcode >> 3) & 0x7) as usize],
+            REGISTERS[(opcode & 0x7) as usize]
+        ),
+        0xCB => disassemble_cb(bytes[1]),
+        _ => {
+            let template = template(opcode);
+            if template.contains("d16") || template.contains("a16") {
+                let word = (bytes[1] as u16) | ((bytes[2] as u16) << 8);
+                template
+                    .replace("d16", &format!("${:04X}", word))
+                    .replace("a16", &format!("${:04X}", word))
+            } else if template.contains("JR") {
+                // relative jumps are shown with their absolute target
+                let target = pc.wrapping_add(2).wrapping_add(bytes[1] as i8 as u16);
+                template.replace("r8", &format!("${:04X}", target))
+            } else if template.contains("r8") {
+                template.replace("r8", &format!("{}", bytes[1] as i8))
+            } else if template.contains("a8") {
+                template.replace("a8", &format!("$FF{:02X}", bytes[1]))
+            } else if template.contains("d8") {
+                template.replace("d8", &format!("${:02X}", bytes[1]))
+            } else {
+                template.to_string()
+            }
+        }
+    }
+}
+
+fn disassemble_cb(opcode: u8) -> String {
+    let register = REGISTERS[(opcode & 0x7) as usize];
+    let bit = (opcode >> 3) & 0x7;
+    match opcode {
+        0x00..=0x3F => format!("{} {}", CB_OPERATIONS[bit as usize], register),
+        0x40..=0x7F => format!("BIT {},{}", bit, register),
+        0x80..=0xBF => format!("RES {},{}", bit, register),
+        _ => format!("SET {},{}", bit, register),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instruction_lengths() {
+        assert_eq!(1, instruction_length(0x00));
+        assert_eq!(3, instruction_length(0x01));
+        assert_eq!(2, instruction_length(0x06));
+        assert_eq!(2, instruction_length(0x18));
+        assert_eq!(1, instruction_length(0x7E));
+        assert_eq!(2, instruction_length(0xCB));
+        assert_eq!(1, instruction_length(0xC7));
+        assert_eq!(2, instruction_length(0xE0));
+        assert_eq!(3, instruction_length(0xEA));
+        assert_eq!(2, instruction_length(0xF8));
+    }
+
+    #[test]
+    fn test_disassemble_operands() {
+        assert_eq!("LD BC,$BEEF", disassemble(0x0100, &[0x01, 0xEF, 0xBE]));
+        assert_eq!("JR NZ,$00FE", disassemble(0x0100, &[0x20, 0xFC]));
+        assert_eq!("LDH ($FF44),A", disassemble(0, &[0xE0, 0x44]));
+        assert_eq!("ADD SP,-2", disassemble(0, &[0xE8, 0xFE]));
+        assert_eq!("CP $90", disassemble(0, &[0xFE, 0x90]));
+    }
+
+    #[test]
+    fn test_disassemble_register_blocks() {
+        assert_eq!("LD A,(HL)", disassemble(0, &[0x7E]));
+        assert_eq!("HALT", disassemble(0, &[0x76]));
+        assert_eq!("XOR A", disassemble(0, &[0xAF]));
+        assert_eq!("SBC A,B", disassemble(0, &[0x98]));
+        assert_eq!("SWAP A", disassemble(0, &[0xCB, 0x37]));
+        assert_eq!("BIT 7,H", disassemble(0, &[0xCB, 0x7C]));
+        assert_eq!("SET 0,(HL)", disassemble(0, &[0xCB, 0xC6]));
+    }
+}
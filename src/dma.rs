@@ -0,0 +1,71 @@
+// OAM DMA
+// writing XX to 0xFF46 copies XX00-XX9F into OAM, one byte per machine cycle
+// while it runs the cpu reads the byte being copied from anything on the bus the dma uses,
+// which is why games run the wait loop from HRAM
+
+pub const DMA_LENGTH: u8 = 0xA0;
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Dma {
+    // last value written to 0xFF46
+    register: u8,
+    // bytes copied so far, None when idle
+    progress: Option<u8>,
+}
+
+impl Dma {
+    pub fn new() -> Self {
+        Self {
+            register: 0xFF,
+            progress: None,
+        }
+    }
+
+    pub fn read_byte(&self) -> u8 {
+        self.register
+    }
+
+    // (re)starts a transfer from `value` * 0x100
+    pub fn write_byte(&mut self, value: u8) {
+        self.register = value;
+        self.progress = Some(0);
+    }
+
+    // sets the register without starting a transfer
+    pub fn poke(&mut self, value: u8) {
+        self.register = value;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.progress.is_some()
+    }
+
+    pub fn progress(&self) -> Option<u8> {
+        self.progress
+    }
+
+    // source and destination of the byte to copy in the next machine cycle, if any
+    // sources above 0xDFFF read from the echo of work ram
+    pub fn next_transfer(&self) -> Option<(u16, u16)> {
+        let offset = self.progress? as u16;
+        let mut source = (self.register as u16) << 8;
+        if source >= 0xE000 {
+            source -= 0x2000;
+        }
+        Some((source + offset, 0xFE00 + offset))
+    }
+
+    // call once the byte from next_transfer has been copied
+    pub fn advance(&mut self) {
+        self.progress = match self.progress {
+            Some(copied) if copied + 1 < DMA_LENGTH => Some(copied + 1),
+            _ => None,
+        };
+    }
+}
+
+impl Default for Dma {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,271 @@
+// 93LC56 serial EEPROM on MBC7 cartridges
+// 128 16 bit words the game talks to one bit at a time through a register: it raises chip
+// select, then clocks in a start bit, a 2 bit opcode and an 8 bit address on DI, followed
+// by data for writes. reads come back on DO, a dummy 0 and then the word from the top bit.
+// the words live in the cartridge ram, low byte first, so battery saves keep them
+
+pub const EEPROM_LEN: usize = 256;
+
+// bits of the register at 0xA080
+const CHIP_SELECT: u8 = 0x80;
+const CLOCK: u8 = 0x40;
+const DATA_IN: u8 = 0x02;
+const DATA_OUT: u8 = 0x01;
+
+// start bit, opcode and address
+const COMMAND_BITS: u8 = 11;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+enum State {
+    // waiting for the start bit
+    Idle,
+    // bits of the command so far, starting with the start bit
+    Command {
+        value: u16,
+        bits: u8,
+    },
+    // shifting out a word
+    Read {
+        word: u16,
+        bits_left: u8,
+    },
+    // shifting in a word, for one address or all of them
+    Write {
+        addr: Option<u8>,
+        word: u16,
+        bits: u8,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Eeprom {
+    // the register as last written
+    pins: u8,
+    data_out: bool,
+    write_enabled: bool,
+    state: State,
+    // a word was stored since the last take_written
+    written: bool,
+}
+
+impl Eeprom {
+    pub fn new() -> Self {
+        Self {
+            pins: 0,
+            data_out: true,
+            write_enabled: false,
+            state: State::Idle,
+            written: false,
+        }
+    }
+
+    pub fn read_byte(&self) -> u8 {
+        let data_out = if self.data_out { DATA_OUT } else { 0 };
+        self.pins & (CHIP_SELECT | CLOCK | DATA_IN) | data_out
+    }
+
+    // whether a word was stored since the last call, the ram needs saving then
+    pub fn take_written(&mut self) -> bool {
+        std::mem::take(&mut self.written)
+    }
+
+    // `memory` is the cartridge ram holding the words
+    pub fn write_byte(&mut self, value: u8, memory: &mut [u8]) {
+        let rising = value & CLOCK != 0 && self.pins & CLOCK == 0;
+        self.pins = value;
+        if value & CHIP_SELECT == 0 {
+            self.state = State::Idle;
+            return;
+        }
+        if rising {
+            self.clock_in(value & DATA_IN != 0, memory);
+        }
+    }
+
+    fn clock_in(&mut self, bit: bool, memory: &mut [u8]) {
+        self.state = match std::mem::replace(&mut self.state, State::Idle) {
+            State::Idle if bit => State::Command { value: 1, bits: 1 },
+            State::Idle => State::Idle,
+            State::Command { value, bits } => {
+                let value = value << 1 | bit as u16;
+                if bits + 1 < COMMAND_BITS {
+                    State::Command {
+                        value,
+                        bits: bits + 1,
+                    }
+                } else {
+                    self.execute((value >> 8) as u8 & 0x03, value as u8, memory)
+                }
+            }
+            State::Read { word, bits_left } => {
+                self.data_out = word & 0x8000 != 0;
+                match bits_left {
+                    1 => State::Idle,
+                    _ => State::Read {
+                        word: word << 1,
+                        bits_left: bits_left - 1,
+                    },
+                }
+            }
+            State::Write { addr, word, bits } => {
+                let word = word << 1 | bit as u16;
+                if bits + 1 < 16 {
+                    State::Write {
+                        addr,
+                        word,
+                        bits: bits + 1,
+                    }
+                } else {
+                    match addr {
+                        Some(addr) => self.store(addr, word, memory),
+                        None => (0..0x80).for_each(|addr| self.store(addr, word, memory)),
+                    }
+                    // done right away, DO reports ready
+                    self.data_out = true;
+                    State::Idle
+                }
+            }
+        };
+    }
+
+    fn execute(&mut self, opcode: u8, addr: u8, memory: &mut [u8]) -> State {
+        // the top two address bits pick the command for opcode 0
+        let command = addr >> 6;
+        // the top address bit is not connected
+        let addr = addr & 0x7F;
+        match opcode {
+            // READ
+            0b10 => {
+                self.data_out = false;
+                State::Read {
+                    word: load(addr, memory),
+                    bits_left: 16,
+                }
+            }
+            // WRITE
+            0b01 => State::Write {
+                addr: Some(addr),
+                word: 0,
+                bits: 0,
+            },
+            // ERASE
+            0b11 => {
+                self.store(addr, 0xFFFF, memory);
+                State::Idle
+            }
+            _ => match command {
+                // EWDS
+                0b00 => {
+                    self.write_enabled = false;
+                    State::Idle
+                }
+                // WRAL
+                0b01 => State::Write {
+                    addr: None,
+                    word: 0,
+                    bits: 0,
+                },
+                // ERAL
+                0b10 => {
+                    (0..0x80).for_each(|addr| self.store(addr, 0xFFFF, memory));
+                    State::Idle
+                }
+                // EWEN
+                _ => {
+                    self.write_enabled = true;
+                    State::Idle
+                }
+            },
+        }
+    }
+
+    fn store(&mut self, addr: u8, word: u16, memory: &mut [u8]) {
+        let offset = addr as usize * 2;
+        if self.write_enabled && offset + 1 < memory.len() {
+            memory[offset..offset + 2].copy_from_slice(&word.to_le_bytes());
+            self.written = true;
+        }
+    }
+}
+
+fn load(addr: u8, memory: &[u8]) -> u16 {
+    let offset = addr as usize * 2;
+    memory
+        .get(offset..offset + 2)
+        .map_or(0xFFFF, |word| u16::from_le_bytes([word[0], word[1]]))
+}
+
+impl Default for Eeprom {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // clocks `bits` from the top of `value` in with chip select held
+    fn send(eeprom: &mut Eeprom, memory: &mut [u8], value: u32, bits: u8) {
+        for i in (0..bits).rev() {
+            let bit = if value >> i & 1 != 0 { DATA_IN } else { 0 };
+            eeprom.write_byte(CHIP_SELECT | bit, memory);
+            eeprom.write_byte(CHIP_SELECT | CLOCK | bit, memory);
+        }
+    }
+
+    // start bit, opcode and address
+    fn command(opcode: u32, addr: u32) -> u32 {
+        1 << 10 | opcode << 8 | addr
+    }
+
+    fn receive(eeprom: &mut Eeprom, memory: &mut [u8], bits: u8) -> u32 {
+        let mut value = 0;
+        for _ in 0..bits {
+            eeprom.write_byte(CHIP_SELECT, memory);
+            eeprom.write_byte(CHIP_SELECT | CLOCK, memory);
+            value = value << 1 | (eeprom.read_byte() & DATA_OUT) as u32;
+        }
+        value
+    }
+
+    #[test]
+    fn test_write_then_read_a_word() {
+        let mut memory = vec![0; EEPROM_LEN];
+        let mut eeprom = Eeprom::new();
+        // writes are ignored until EWEN
+        send(
+            &mut eeprom,
+            &mut memory,
+            command(0b01, 0x05) << 16 | 0xBEEF,
+            27,
+        );
+        assert_eq!(0, load(5, &memory));
+        assert!(!eeprom.take_written());
+        eeprom.write_byte(0, &mut memory);
+        send(&mut eeprom, &mut memory, command(0b00, 0xC0), 11);
+        eeprom.write_byte(0, &mut memory);
+        send(
+            &mut eeprom,
+            &mut memory,
+            command(0b01, 0x05) << 16 | 0xBEEF,
+            27,
+        );
+        assert_eq!([0xEF, 0xBE], memory[10..12]);
+        assert!(eeprom.take_written());
+        assert_eq!(DATA_OUT, eeprom.read_byte() & DATA_OUT);
+
+        eeprom.write_byte(0, &mut memory);
+        send(&mut eeprom, &mut memory, command(0b10, 0x85), 11);
+        // a dummy 0 after the address, the top address bit is ignored
+        assert_eq!(0, eeprom.read_byte() & DATA_OUT);
+        assert_eq!(0xBEEF, receive(&mut eeprom, &mut memory, 16));
+
+        // ERAL sets every bit
+        eeprom.write_byte(0, &mut memory);
+        send(&mut eeprom, &mut memory, command(0b00, 0x80), 11);
+        assert!(memory.iter().all(|&byte| byte == 0xFF));
+    }
+}
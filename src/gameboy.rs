@@ -1,38 +1,1330 @@
-use std::path::Path;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use minifb::{Key, Window, WindowOptions};
+use minifb::{KeyRepeat, Scale, Window, WindowOptions};
 
-use crate::cpu::Cpu;
+use crate::{
+    audio::Producer,
+    battery::BatterySave,
+    cartridge::CartridgeError,
+    config::{Config, Wizard},
+    cpu::Cpu,
+    crash::{self, CrashReport},
+    debugger::{Breakpoint, Debugger, RamChange, Symbols, RAM_RANGES},
+    input::{Action, Bindings, Button, Input},
+    model::Model,
+    movie::Movie,
+    pacing::{FramePacer, GAMEBOY_FRAME_RATE},
+    palette::DmgPalette,
+    png,
+    ppu::{SCREEN_HEIGHT, SCREEN_WIDTH},
+    register::Register,
+    sgb::{Sgb, SGB_HEIGHT, SGB_WIDTH},
+    watchdog::{StuckLoop, Watchdog},
+};
 
-const WIDTH: usize = 800;
-const HEIGHT: usize = 600;
+// 154 lines of 456 dots, 4 dots per machine cycle
+const CYCLES_PER_FRAME: u64 = 154 * 456 / 4;
+// frames emulated per presented frame while fast forward is held
+const FAST_FORWARD_FRAMES: u32 = 4;
+// longest wait for the audio device in audio-synced runs, then it is assumed stuck
+const AUDIO_WAIT_LIMIT: Duration = Duration::from_millis(100);
+
+// why a bounded run returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    // the machine cycle cap was reached
+    CycleLimit,
+    // the caller's condition returned true
+    Condition,
+    // the cpu halted with every interrupt disabled in IE, nothing can wake it up
+    Halted,
+    // the next instruction sits on a breakpoint
+    Breakpoint(Breakpoint),
+    // the watchdog caught the cpu spinning in a loop without any IO
+    Stuck(StuckLoop),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunSummary {
+    pub instructions: u64,
+    // machine cycles, same unit as the timer
+    pub cycles: u64,
+    pub reason: StopReason,
+}
+
+// thumbnails are the screen at half size
+pub const THUMBNAIL_WIDTH: usize = SCREEN_WIDTH / 2;
+pub const THUMBNAIL_HEIGHT: usize = SCREEN_HEIGHT / 2;
+
+// what a savestate holds, to show before deciding to load it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatePreview {
+    // the screen when the state was saved, THUMBNAIL_WIDTH x THUMBNAIL_HEIGHT 0xAARRGGBB
+    pub thumbnail: Vec<u32>,
+    pub registers: Register,
+    pub rom_bank: u16,
+    pub model: Model,
+}
+
+enum MovieMode {
+    Recording(Movie),
+    // the next frame to play
+    Playing(Movie, usize),
+}
+
+// called after every completed frame, e.g. to poke cheats into memory
+pub type FrameHook = Box<dyn FnMut(&mut Gameboy)>;
+
+// set from a Ctrl+C handler, the window loops return and flush the battery save
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+// stops a running window frontend at its next update
+pub fn interrupt() {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
 
 pub struct Gameboy {
     pub cpu: Cpu,
+    pub debugger: Debugger,
+    // maps keys of the window frontend to actions
+    pub bindings: Bindings,
+    // breaks out of runs that look hung, None turns it off
+    pub watchdog: Option<Watchdog>,
+    // upscale factor of screenshots taken with the hotkey
+    pub screenshot_scale: usize,
+    // 1, 2, 4 or 8
+    pub window_scale: usize,
+    // takes the apu's samples after every frame, None leaves them in the apu
+    pub audio: Option<Producer>,
+    // run pacing follows the audio device instead of the window's update rate, avoids
+    // pitch drift and crackle on displays that don't refresh at 59.73 Hz
+    pub audio_sync: bool,
+    // where the cartridge ram goes for games with a battery, None keeps it in memory
+    pub battery: Option<BatterySave>,
+    // the config file the bindings came from, reload_config reads it again
+    pub config_path: Option<PathBuf>,
+    // savestate slots are this with the extension ss<slot>, None leaves the slots off
+    pub state_path: Option<PathBuf>,
+    // input movie being recorded or played back, see movie.rs
+    movie: Option<MovieMode>,
+    frame_hooks: Vec<FrameHook>,
 }
 
 impl Gameboy {
-    pub fn new(rom_file: &Path) -> Self {
+    pub fn new(rom_file: &Path) -> Result<Self, CartridgeError> {
+        Ok(Self {
+            cpu: Cpu::new(rom_file)?,
+            debugger: Debugger::new(),
+            bindings: Bindings::default(),
+            watchdog: Some(Watchdog::default()),
+            screenshot_scale: 1,
+            window_scale: 4,
+            audio: None,
+            audio_sync: false,
+            battery: None,
+            config_path: None,
+            state_path: None,
+            movie: None,
+            frame_hooks: Vec::new(),
+        })
+    }
+
+    pub fn from_rom_bytes(rom: Vec<u8>) -> Self {
         Self {
-            cpu: Cpu::new(rom_file),
+            cpu: Cpu::from_rom_bytes(rom),
+            debugger: Debugger::new(),
+            bindings: Bindings::default(),
+            watchdog: Some(Watchdog::default()),
+            screenshot_scale: 1,
+            window_scale: 4,
+            audio: None,
+            audio_sync: false,
+            battery: None,
+            config_path: None,
+            state_path: None,
+            movie: None,
+            frame_hooks: Vec::new(),
         }
     }
 
-    pub fn run(&mut self) {
-        let mut window = Window::new("Rustyboy", WIDTH, HEIGHT, WindowOptions::default())
-            .unwrap_or_else(|e| panic!("{}", e));
-        let buffer = vec![125; WIDTH * HEIGHT];
+    // memory access for tools and scripts, built on the bus peek/poke so reading or
+    // patching game state never disturbs the emulation. addresses wrap at 0xFFFF
+    pub fn read_range(&self, addr: u16, len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|offset| self.cpu.bus.peek(addr.wrapping_add(offset as u16)))
+            .collect()
+    }
+
+    pub fn write_range(&mut self, addr: u16, data: &[u8]) {
+        for (offset, &value) in data.iter().enumerate() {
+            self.cpu.bus.poke(addr.wrapping_add(offset as u16), value);
+        }
+    }
+
+    // like read_range, but rom in 0x4000-0x7FFF comes from `bank` rather than the mapped bank
+    pub fn read_bank_range(&self, bank: u16, addr: u16, len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|offset| {
+                self.cpu
+                    .bus
+                    .peek_banked(bank, addr.wrapping_add(offset as u16))
+            })
+            .collect()
+    }
+
+    pub fn write_bank_range(&mut self, bank: u16, addr: u16, data: &[u8]) {
+        for (offset, &value) in data.iter().enumerate() {
+            self.cpu
+                .bus
+                .poke_banked(bank, addr.wrapping_add(offset as u16), value);
+        }
+    }
+
+    // run without a window for at most `max_cycles` machine cycles
+    pub fn run_for_cycles(&mut self, max_cycles: u64) -> RunSummary {
+        self.run_until(max_cycles, |_| false)
+    }
+
+    // run without a window until `condition` holds, giving up after `max_cycles` machine cycles
+    // the condition and breakpoints are checked before every instruction, breakpoints are
+    // skipped for the first one so a run can resume from the breakpoint it stopped at
+    pub fn run_until<F>(&mut self, max_cycles: u64, mut condition: F) -> RunSummary
+    where
+        F: FnMut(&Gameboy) -> bool,
+    {
+        let mut summary = RunSummary {
+            instructions: 0,
+            cycles: 0,
+            reason: StopReason::CycleLimit,
+        };
+
+        while summary.cycles < max_cycles {
+            if condition(self) {
+                summary.reason = StopReason::Condition;
+                return summary;
+            }
+            if self.cpu.is_halted() && self.cpu.bus.interrupt_enable() & 0x1F == 0 {
+                summary.reason = StopReason::Halted;
+                return summary;
+            }
+            if summary.instructions > 0 {
+                if let Some(breakpoint) = self.debugger.breakpoint_at(&self.cpu.bus, self.cpu.pc())
+                {
+                    summary.reason = StopReason::Breakpoint(breakpoint);
+                    return summary;
+                }
+            }
+            let halted = self.cpu.is_halted();
+            let pc = self.cpu.pc();
+            let bank = self.cpu.bus.rom_bank_at(pc);
+            self.cpu.run_cycle();
+            summary.instructions += 1;
+            summary.cycles += self.cpu.last_cycles() as u64;
+
+            if let Some(watchdog) = self.watchdog.as_mut() {
+                if halted {
+                    // waiting for an interrupt is not a runaway loop
+                    watchdog.reset();
+                } else if let Some(stuck) = watchdog.observe(
+                    bank,
+                    pc,
+                    self.cpu.bus.io_accesses(),
+                    self.cpu.last_cycles() as u64,
+                ) {
+                    summary.reason = StopReason::Stuck(stuck);
+                    return summary;
+                }
+            }
+        }
+
+        // the last instruction may have made the condition true right at the limit
+        if condition(self) {
+            summary.reason = StopReason::Condition;
+        }
+        summary
+    }
+
+    // run until the ppu completes a frame, with the LCD off this gives up after one frame's time
+    pub fn run_frame(&mut self) -> RunSummary {
+        self.step_movie();
+        self.cpu.bus.ppu.frame_ready = false;
+        let summary = self.run_until(CYCLES_PER_FRAME, |gb| gb.cpu.bus.ppu.frame_ready);
+        if self.cpu.bus.ppu.frame_ready {
+            self.run_frame_hooks();
+        }
+        if let Some(audio) = &mut self.audio {
+            audio.push(&self.cpu.bus.apu.end_frame());
+            // audio-synced runs already follow the device's clock
+            if !self.audio_sync {
+                let adjustment = audio.rate_adjustment();
+                self.cpu.bus.apu.set_rate_adjustment(adjustment);
+            }
+        }
+        summary
+    }
+
+    // records the buttons of every frame from here on. the movie starts from a savestate
+    // of this point with the serde feature, without it only from power on
+    pub fn record_movie(&mut self) -> Result<(), String> {
+        #[cfg(feature = "serde")]
+        let start_state = Some(self.save_state()?);
+        #[cfg(not(feature = "serde"))]
+        let start_state = match self.cpu.bus.cycles() {
+            0 => None,
+            _ => return Err("recording after power on needs the serde feature".to_string()),
+        };
+        let checksum = self.cpu.bus.cartridge().global_checksum();
+        self.movie = Some(MovieMode::Recording(Movie::new(checksum, start_state)));
+        Ok(())
+    }
+
+    // plays `movie` into the joypad in place of the player's input, from its savestate or
+    // from the current state when it starts at power on
+    pub fn play_movie(&mut self, movie: Movie) -> Result<(), String> {
+        let checksum = self.cpu.bus.cartridge().global_checksum();
+        if movie.rom_checksum != checksum {
+            return Err(format!(
+                "movie was recorded on rom {:04X}, this is {:04X}",
+                movie.rom_checksum, checksum
+            ));
+        }
+        if let Some(state) = &movie.start_state {
+            #[cfg(feature = "serde")]
+            self.load_state(state)?;
+            #[cfg(not(feature = "serde"))]
+            return Err(format!(
+                "movie starts from a savestate, that needs the serde feature ({} bytes)",
+                state.len()
+            ));
+        }
+        self.movie = Some(MovieMode::Playing(movie, 0));
+        Ok(())
+    }
+
+    // ends recording or playback, returns the movie recorded so far
+    pub fn stop_movie(&mut self) -> Option<Movie> {
+        match self.movie.take() {
+            Some(MovieMode::Recording(movie)) => Some(movie),
+            _ => None,
+        }
+    }
+
+    pub fn is_playing_movie(&self) -> bool {
+        matches!(self.movie, Some(MovieMode::Playing(..)))
+    }
+
+    // at the start of a frame: note the held buttons or replace them with the movie's
+    fn step_movie(&mut self) {
+        match &mut self.movie {
+            Some(MovieMode::Recording(movie)) => movie.record(&self.cpu.bus.buttons()),
+            Some(MovieMode::Playing(movie, frame)) => match movie.frame(*frame) {
+                Some(buttons) => {
+                    *frame += 1;
+                    self.cpu.bus.set_buttons(&buttons);
+                }
+                // the player takes over
+                None => {
+                    self.movie = None;
+                    self.cpu.bus.set_buttons(&[]);
+                    eprintln!("movie finished");
+                }
+            },
+            None => {}
+        }
+    }
+
+    pub fn add_frame_hook(&mut self, hook: FrameHook) {
+        self.frame_hooks.push(hook);
+    }
+
+    fn run_frame_hooks(&mut self) {
+        // hooks get the whole gameboy, so they are moved out while running
+        let mut hooks = std::mem::take(&mut self.frame_hooks);
+        for hook in hooks.iter_mut() {
+            hook(self);
+        }
+        hooks.append(&mut self.frame_hooks);
+        self.frame_hooks = hooks;
+    }
+
+    // whole machine state as json, without the cartridge rom
+    #[cfg(feature = "serde")]
+    pub fn save_state(&self) -> Result<String, String> {
+        serde_json::to_string(&self.cpu).map_err(|e| e.to_string())
+    }
+
+    // restore a state from save_state, the loaded cartridge and frontend settings are kept
+    #[cfg(feature = "serde")]
+    pub fn load_state(&mut self, state: &str) -> Result<(), String> {
+        let mut cpu: Cpu = serde_json::from_str(state).map_err(|e| e.to_string())?;
+        cpu.bus.restore_cartridge(self.cpu.bus.take_cartridge());
+        cpu.bus.strict = self.cpu.bus.strict;
+        cpu.bus.open_bus = self.cpu.bus.open_bus;
+        cpu.bus.debug_console = self.cpu.bus.debug_console.take();
+        cpu.bus.hooks = std::mem::take(&mut self.cpu.bus.hooks);
+        cpu.bus.adopt_devices(&mut self.cpu.bus);
+        if let Some(hook) = self.cpu.bus.take_rumble_hook() {
+            cpu.bus.on_rumble(hook);
+        }
+        if let Some(link) = self.cpu.bus.serial.take_link() {
+            cpu.bus.serial.attach_link(link);
+        }
+        cpu.bus.ppu.set_palette(self.cpu.bus.ppu.palette());
+        cpu.bus
+            .ppu
+            .set_pixel_format(self.cpu.bus.ppu.pixel_format());
+        cpu.bus.ppu.set_ghosting(self.cpu.bus.ppu.ghosting());
+        cpu.bus
+            .ppu
+            .set_color_correction(self.cpu.bus.ppu.color_correction());
+        cpu.bus.apu.set_sample_rate(self.cpu.bus.apu.sample_rate());
+        cpu.bus.apu.set_latency(self.cpu.bus.apu.latency());
+        if let Some(tracer) = self.cpu.take_tracer() {
+            cpu.set_tracer(tracer);
+        }
+        self.cpu = cpu;
+        Ok(())
+    }
+
+    // file of savestate `slot`, next to the battery save
+    #[cfg(feature = "serde")]
+    pub fn state_slot_path(&self, slot: u8) -> Option<PathBuf> {
+        let path = self.state_path.as_ref()?;
+        Some(path.with_extension(format!("ss{}", slot)))
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn save_state_slot(&mut self, slot: u8) -> Result<PathBuf, String> {
+        let path = self.state_slot_path(slot).ok_or("no save directory")?;
+        let state = self.save_state()?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        fs::write(&path, state).map_err(|e| e.to_string())?;
+        Ok(path)
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn load_state_slot(&mut self, slot: u8) -> Result<PathBuf, String> {
+        let path = self.state_slot_path(slot).ok_or("no save directory")?;
+        let state = fs::read_to_string(&path).map_err(|e| e.to_string())?;
+        self.load_state(&state)?;
+        Ok(path)
+    }
+
+    // look inside a state from save_state without touching the running core
+    #[cfg(feature = "serde")]
+    pub fn preview_state(state: &str) -> Result<StatePreview, String> {
+        let cpu: Cpu = serde_json::from_str(state).map_err(|e| e.to_string())?;
+        let frame = cpu.bus.ppu.front_buffer();
+        let format = cpu.bus.ppu.pixel_format();
+        let thumbnail = (0..THUMBNAIL_HEIGHT)
+            .flat_map(|y| {
+                (0..THUMBNAIL_WIDTH)
+                    .map(move |x| format.to_argb(frame[y * 2 * SCREEN_WIDTH + x * 2]))
+            })
+            .collect();
+        let registers = cpu.registers();
+        Ok(StatePreview {
+            thumbnail,
+            registers,
+            rom_bank: cpu.bus.rom_bank_at(registers.pc),
+            model: cpu.bus.model(),
+        })
+    }
+
+    // a second, independent core running `state` with a copy of this cartridge, e.g. to
+    // compare two points in time in the debugger. the running core is left alone
+    #[cfg(feature = "serde")]
+    pub fn load_state_detached(&self, state: &str) -> Result<Gameboy, String> {
+        let mut cpu: Cpu = serde_json::from_str(state).map_err(|e| e.to_string())?;
+        cpu.bus.restore_cartridge(self.cpu.bus.cartridge().clone());
+        cpu.bus.ppu.set_palette(self.cpu.bus.ppu.palette());
+        cpu.bus
+            .ppu
+            .set_pixel_format(self.cpu.bus.ppu.pixel_format());
+        cpu.bus.ppu.set_ghosting(self.cpu.bus.ppu.ghosting());
+        cpu.bus
+            .ppu
+            .set_color_correction(self.cpu.bus.ppu.color_correction());
+        Ok(Gameboy {
+            cpu,
+            debugger: Debugger::new(),
+            bindings: self.bindings.clone(),
+            watchdog: Some(Watchdog::default()),
+            screenshot_scale: self.screenshot_scale,
+            window_scale: self.window_scale,
+            // a detached core has nothing to play to
+            audio: None,
+            audio_sync: false,
+            battery: None,
+            config_path: None,
+            state_path: None,
+            movie: None,
+            frame_hooks: Vec::new(),
+        })
+    }
+
+    // bytes that differ between this core and `other`, as (address, ours, theirs)
+    pub fn diff_range(&self, other: &Gameboy, addr: u16, len: usize) -> Vec<(u16, u8, u8)> {
+        self.read_range(addr, len)
+            .into_iter()
+            .zip(other.read_range(addr, len))
+            .enumerate()
+            .filter(|(_, (ours, theirs))| ours != theirs)
+            .map(|(offset, (ours, theirs))| (addr.wrapping_add(offset as u16), ours, theirs))
+            .collect()
+    }
 
+    // work and high ram bytes that changed from this core to `other`, named from `symbols`
+    pub fn diff_ram(&self, other: &Gameboy, symbols: Option<&Symbols>) -> Vec<RamChange> {
+        RAM_RANGES
+            .iter()
+            .flat_map(|&(addr, len)| self.diff_range(other, addr, len))
+            .map(|(addr, old, new)| RamChange {
+                addr,
+                old,
+                new,
+                symbol: symbols
+                    .and_then(|symbols| symbols.name(addr))
+                    .map(String::from),
+            })
+            .collect()
+    }
+
+    // the last completed frame as a PNG file
+    pub fn screenshot(&self, path: &Path) -> io::Result<()> {
+        self.screenshot_scaled(path, 1)
+    }
+
+    // like screenshot, with every pixel blown up to a `scale` x `scale` square
+    pub fn screenshot_scaled(&self, path: &Path, scale: usize) -> io::Result<()> {
+        let ppu = &self.cpu.bus.ppu;
+        let format = ppu.pixel_format();
+        let frame: Vec<u32> = ppu
+            .frame()
+            .iter()
+            .map(|&pixel| format.to_argb(pixel))
+            .collect();
+        fs::write(
+            path,
+            png::encode(SCREEN_WIDTH, SCREEN_HEIGHT, &frame, scale),
+        )
+    }
+
+    // hotkey screenshot into the working directory, named after the time it was taken
+    fn take_screenshot(&self) -> io::Result<PathBuf> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_millis())
+            .unwrap_or(0);
+        let dir = std::env::current_dir().unwrap_or_else(|_| ".".into());
+        let path = dir.join(format!("rustyboy-{}.png", timestamp));
+        self.screenshot_scaled(&path, self.screenshot_scale)?;
+        Ok(path)
+    }
+
+    // snapshot of the core for a bug report, e.g. after `message` panicked
+    pub fn crash_report(&mut self, message: String) -> CrashReport {
+        #[cfg(feature = "serde")]
+        let savestate = self.save_state().ok();
+        #[cfg(not(feature = "serde"))]
+        let savestate = None;
+
+        let cartridge = self.cpu.bus.cartridge();
+        CrashReport {
+            message,
+            title: cartridge.title().to_string(),
+            checksum: cartridge.checksum(),
+            registers: self.cpu.registers(),
+            trace: self.cpu.recent_instructions(),
+            savestate,
+        }
+    }
+
+    // run a frame for the frontend, a panic in the core is turned into a crash report
+    // returns false once the core has crashed
+    fn run_frame_or_report(&mut self) -> bool {
+        match crash::catch_crash(|| self.run_frame()) {
+            Ok(summary) => {
+                if let StopReason::Stuck(stuck) = summary.reason {
+                    eprintln!("watchdog: {}", stuck);
+                    eprintln!(
+                        "break into the debugger with a breakpoint at {:02X}:{:04X}",
+                        stuck.bank, stuck.start
+                    );
+                }
+                if let Some(console) = &mut self.cpu.bus.debug_console {
+                    for line in console.take_lines() {
+                        eprintln!("console: {}", line);
+                    }
+                }
+                for conflict in self.cpu.bus.take_conflicts() {
+                    match conflict.write {
+                        Some(value) => eprintln!(
+                            "dma conflict: pc {:04X} cycle {} wrote {:02X} to {:04X}",
+                            conflict.pc, conflict.cycle, value, conflict.addr
+                        ),
+                        None => eprintln!(
+                            "dma conflict: pc {:04X} cycle {} read {:04X}",
+                            conflict.pc, conflict.cycle, conflict.addr
+                        ),
+                    }
+                }
+                true
+            }
+            Err(message) => {
+                let report = self.crash_report(message);
+                eprintln!("rustyboy crashed: {}", report.message);
+                let dir = std::env::current_dir().unwrap_or_else(|_| ".".into());
+                match report.write_to(&dir) {
+                    Ok(path) => eprintln!("crash report written to {}", path.display()),
+                    Err(e) => eprintln!("could not write crash report: {}\n{}", e, report),
+                }
+                false
+            }
+        }
+    }
+
+    // actions bound to the keys held down in the window
+    fn held_actions(&self, window: &Window) -> Vec<Action> {
+        let keys: Vec<Input> = window
+            .get_keys()
+            .into_iter()
+            .map(|key| Input::Key(format!("{:?}", key)))
+            .collect();
+        // TODO: the overlay action once the frontend has one
+        self.bindings.actions(&keys)
+    }
+
+    // the bound buttons held down go to the joypad
+    fn apply_joypad(&mut self, actions: &[Action]) {
+        let buttons: Vec<Button> = actions
+            .iter()
+            .filter_map(|action| match action {
+                Action::Joypad(button) => Some(*button),
+                _ => None,
+            })
+            .collect();
+        self.cpu.bus.set_buttons(&buttons);
+        // a held key tilts all the way, opposite keys cancel out
+        let held = |action| actions.contains(&action) as u8 as f32;
+        self.cpu.bus.set_tilt(
+            held(Action::TiltRight) - held(Action::TiltLeft),
+            held(Action::TiltDown) - held(Action::TiltUp),
+        );
+    }
+
+    // one-shot actions fire once when their input goes down, `held` is last update's set
+    fn apply_pressed_actions(&mut self, actions: &[Action], held: &mut Vec<Action>) {
+        for action in actions.iter().filter(|action| !held.contains(action)) {
+            match action {
+                Action::CyclePalette => {
+                    let ppu = &mut self.cpu.bus.ppu;
+                    ppu.set_palette(ppu.palette().next_preset());
+                }
+                Action::ToggleColorCorrection => {
+                    let ppu = &mut self.cpu.bus.ppu;
+                    ppu.set_color_correction(!ppu.color_correction());
+                }
+                Action::ReloadConfig => self.reload_config(),
+                Action::Screenshot => match self.take_screenshot() {
+                    Ok(path) => eprintln!("screenshot saved to {}", path.display()),
+                    Err(e) => eprintln!("could not save screenshot: {}", e),
+                },
+                #[cfg(feature = "serde")]
+                Action::SaveState(slot) => match self.save_state_slot(*slot) {
+                    Ok(path) => eprintln!("state saved to {}", path.display()),
+                    Err(e) => eprintln!("could not save state {}: {}", slot, e),
+                },
+                #[cfg(feature = "serde")]
+                Action::LoadState(slot) => match self.load_state_slot(*slot) {
+                    Ok(path) => eprintln!("state loaded from {}", path.display()),
+                    Err(e) => eprintln!("could not load state {}: {}", slot, e),
+                },
+                _ => {}
+            }
+        }
+        *held = actions.to_vec();
+    }
+
+    // blocks until the audio device has made room for the next frame's samples
+    fn wait_for_audio(&self) {
+        if let Some(audio) = &self.audio {
+            let frame_samples = self.cpu.bus.apu.sample_rate() as f64 / GAMEBOY_FRAME_RATE;
+            audio.wait_for_room(frame_samples.ceil() as usize * 2, AUDIO_WAIT_LIMIT);
+        }
+    }
+
+    // emulated frames to run before presenting the next one
+    fn frames_per_update(actions: &[Action]) -> u32 {
+        if actions.contains(&Action::FastForward) {
+            FAST_FORWARD_FRAMES
+        } else {
+            1
+        }
+    }
+
+    // bindings and window scale from the user's config
+    pub fn apply_config(&mut self, config: &Config) {
+        self.bindings = config.bindings.clone();
+        self.window_scale = config.scale;
+    }
+
+    // new bindings from the config file without restarting, the rest of it needs a restart
+    fn reload_config(&mut self) {
+        let Some(path) = &self.config_path else {
+            return;
+        };
+        match Config::load(path) {
+            Ok(Some(config)) => {
+                self.bindings = config.bindings;
+                eprintln!("bindings reloaded from {}", path.display());
+            }
+            Ok(None) => eprintln!("no config at {}", path.display()),
+            Err(e) => eprintln!("could not reload config {}: {}", path.display(), e),
+        }
+    }
+
+    // ask for the first run settings in a window, the prompts go in the title bar.
+    // None if the window was closed before the end
+    pub fn run_setup_wizard() -> Option<Config> {
+        let mut window = Self::open_window(4, SCREEN_WIDTH, SCREEN_HEIGHT);
         window.limit_update_rate(Some(std::time::Duration::from_micros(16600)));
-        window.set_background_color(125, 125, 125);
+        let screen = vec![0xFFFFFFFF; SCREEN_WIDTH * SCREEN_HEIGHT];
+        let mut wizard = Wizard::new();
+        while window.is_open() {
+            let Some(prompt) = wizard.prompt() else {
+                return Some(wizard.finish());
+            };
+            window.set_title(&format!("Rustyboy setup - {}", prompt));
+            for key in window.get_keys_pressed(KeyRepeat::No) {
+                wizard.key_pressed(&format!("{:?}", key));
+            }
+            window
+                .update_with_buffer(&screen, SCREEN_WIDTH, SCREEN_HEIGHT)
+                .unwrap();
+        }
+        None
+    }
+
+    fn open_window(scale: usize, width: usize, height: usize) -> Window {
+        let scale = match scale {
+            1 => Scale::X1,
+            2 => Scale::X2,
+            8 => Scale::X8,
+            _ => Scale::X4,
+        };
+        let mut window = Window::new(
+            "Rustyboy",
+            width,
+            height,
+            WindowOptions {
+                scale,
+                ..WindowOptions::default()
+            },
+        )
+        .unwrap_or_else(|e| panic!("{}", e));
+        window.limit_update_rate(None);
+        window
+    }
+
+    // the game window, big enough for the border on a Super Game Boy
+    fn open_game_window(&self) -> Window {
+        match self.cpu.bus.sgb {
+            Some(_) => Self::open_window(self.window_scale, SGB_WIDTH, SGB_HEIGHT),
+            None => Self::open_window(self.window_scale, SCREEN_WIDTH, SCREEN_HEIGHT),
+        }
+    }
 
-        while window.is_open() && !window.is_key_down(Key::Escape) {
-            window.update_with_buffer(&buffer, WIDTH, HEIGHT).unwrap();
+    // show `frame`, framed by the SGB border if there is an SGB
+    fn present(window: &mut Window, sgb: Option<&mut Sgb>, palette: &DmgPalette, frame: &[u32]) {
+        let result = match sgb {
+            Some(sgb) => {
+                window.update_with_buffer(&sgb.compose(frame, palette), SGB_WIDTH, SGB_HEIGHT)
+            }
+            None => window.update_with_buffer(frame, SCREEN_WIDTH, SCREEN_HEIGHT),
+        };
+        result.unwrap();
+    }
+
+    // present on every host vsync at `pacer`'s refresh rate instead of forcing 60 Hz
+    pub fn run_paced(&mut self, pacer: FramePacer) {
+        self.run_paced_window(pacer);
+        self.save_battery(true);
+    }
+
+    fn run_paced_window(&mut self, mut pacer: FramePacer) {
+        crash::install_panic_hook();
+        let mut window = self.open_game_window();
+        window.limit_update_rate(Some(std::time::Duration::from_secs_f64(
+            1.0 / pacer.refresh_rate(),
+        )));
+
+        let mut held = Vec::new();
+        while window.is_open() && !interrupted() {
+            let actions = self.held_actions(&window);
+            if actions.contains(&Action::Quit) {
+                return;
+            }
+            self.apply_pressed_actions(&actions, &mut held);
+            self.apply_joypad(&actions);
+            for _ in 0..pacer.frames_due() * Self::frames_per_update(&actions) {
+                if !self.run_frame_or_report() {
+                    return;
+                }
+                pacer.frame_ready(self.cpu.bus.ppu.frame());
+            }
+            let bus = &mut self.cpu.bus;
+            let palette = bus.ppu.palette();
+            Self::present(&mut window, bus.sgb.as_mut(), &palette, pacer.present());
+            self.save_battery(false);
+        }
+    }
+
+    pub fn run(&mut self) {
+        self.run_window();
+        self.save_battery(true);
+    }
+
+    fn run_window(&mut self) {
+        crash::install_panic_hook();
+        let mut window = self.open_game_window();
+        let audio_synced = self.audio_sync && self.audio.is_some();
+        window.limit_update_rate(if audio_synced {
+            None
+        } else {
+            Some(std::time::Duration::from_micros(16600))
+        });
+
+        let mut held = Vec::new();
+        while window.is_open() && !interrupted() {
+            let actions = self.held_actions(&window);
+            if actions.contains(&Action::Quit) {
+                return;
+            }
+            self.apply_pressed_actions(&actions, &mut held);
+            self.apply_joypad(&actions);
             // REMOVE FOR DEBUGGING
             //if window.is_key_pressed(Key::Space, minifb::KeyRepeat::No) {
             //    self.cpu.decode_execute();
             //}
-            self.cpu.run_cycle();
+            let frames = Self::frames_per_update(&actions);
+            // fast forward runs ahead and drops what doesn't fit
+            if audio_synced && frames == 1 {
+                self.wait_for_audio();
+            }
+            for _ in 0..frames {
+                if !self.run_frame_or_report() {
+                    return;
+                }
+            }
+            let bus = &mut self.cpu.bus;
+            let palette = bus.ppu.palette();
+            Self::present(&mut window, bus.sgb.as_mut(), &palette, bus.ppu.frame());
+            self.save_battery(false);
         }
     }
+
+    // writes changed cartridge ram to the battery save, `now` skips the autosave interval
+    fn save_battery(&mut self, now: bool) {
+        let Some(battery) = &mut self.battery else {
+            return;
+        };
+        let cartridge = self.cpu.bus.cartridge_mut();
+        let result = if now {
+            battery.flush(cartridge)
+        } else {
+            battery.autosave(cartridge)
+        };
+        if let Err(e) = result {
+            eprintln!("could not write {}: {}", battery.path().display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{Model, Region};
+
+    // 32KB image with `program` placed at the 0x0100 entry point
+    fn rom_with_program(program: &[u8]) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0100..0x0100 + program.len()].copy_from_slice(program);
+        rom
+    }
+
+    #[test]
+    fn test_movie_plays_back_recorded_buttons() {
+        // JR -2
+        let rom = rom_with_program(&[0x18, 0xFE]);
+        let mut gameboy = Gameboy::from_rom_bytes(rom.clone());
+        gameboy.record_movie().unwrap();
+        for buttons in [&[][..], &[Button::Right, Button::A], &[]] {
+            gameboy.cpu.bus.set_buttons(buttons);
+            gameboy.run_frame();
+        }
+        let movie: Movie = gameboy.stop_movie().unwrap().to_string().parse().unwrap();
+        assert_eq!(3, movie.len());
+
+        let mut replay = Gameboy::from_rom_bytes(rom);
+        replay.play_movie(movie.clone()).unwrap();
+        // d-pad row
+        replay.cpu.bus.write_byte(0xFF00, 0x20);
+        let mut rows = Vec::new();
+        for _ in 0..3 {
+            replay.run_frame();
+            rows.push(replay.cpu.bus.read_byte(0xFF00) & 0x0F);
+        }
+        assert_eq!(vec![0x0F, 0x0E, 0x0F], rows);
+        assert!(replay.is_playing_movie());
+        replay.run_frame();
+        assert!(!replay.is_playing_movie());
+
+        let mut other = Gameboy::from_rom_bytes(rom_with_program(&[0x00, 0x18, 0xFD]));
+        assert!(other.play_movie(movie).is_err());
+    }
+
+    #[cfg(not(feature = "serde"))]
+    #[test]
+    fn test_movie_without_savestates_starts_at_power_on() {
+        let mut gameboy = Gameboy::from_rom_bytes(rom_with_program(&[0x18, 0xFE]));
+        gameboy.run_frame();
+        assert!(gameboy.record_movie().is_err());
+        assert_eq!(None, gameboy.stop_movie());
+
+        let mut gameboy = Gameboy::from_rom_bytes(rom_with_program(&[0x18, 0xFE]));
+        gameboy.record_movie().unwrap();
+        assert_eq!(None, gameboy.stop_movie().unwrap().start_state);
+    }
+
+    #[test]
+    fn test_run_for_cycles_stops_at_cycle_limit() {
+        // JP 0x0100
+        let mut gameboy = Gameboy::from_rom_bytes(rom_with_program(&[0xC3, 0x00, 0x01]));
+        let summary = gameboy.run_for_cycles(1000);
+        assert_eq!(StopReason::CycleLimit, summary.reason);
+        assert_eq!(250, summary.instructions);
+        assert_eq!(1000, summary.cycles);
+    }
+
+    #[test]
+    fn test_run_until_stops_on_condition() {
+        // LD A,0x42; LD (0xC000),A; JP 0x0105
+        let mut gameboy = Gameboy::from_rom_bytes(rom_with_program(&[
+            0x3E, 0x42, 0xEA, 0x00, 0xC0, 0xC3, 0x05, 0x01,
+        ]));
+        let summary = gameboy.run_until(1000, |gb| gb.cpu.bus.read_byte(0xC000) == 0x42);
+        assert_eq!(StopReason::Condition, summary.reason);
+        assert_eq!(2, summary.instructions);
+        assert_eq!(6, summary.cycles);
+    }
+
+    #[test]
+    fn test_run_until_stops_when_halted() {
+        // NOP, HALT
+        let mut gameboy = Gameboy::from_rom_bytes(rom_with_program(&[0x00, 0x76]));
+        let summary = gameboy.run_for_cycles(1000);
+        assert_eq!(StopReason::Halted, summary.reason);
+        assert_eq!(2, summary.instructions);
+        assert_eq!(2, summary.cycles);
+    }
+
+    #[test]
+    fn test_run_until_stops_at_breakpoint_and_resumes() {
+        // NOP, NOP, JP 0x0100
+        let mut gameboy =
+            Gameboy::from_rom_bytes(rom_with_program(&[0x00, 0x00, 0xC3, 0x00, 0x01]));
+        gameboy.debugger.add_breakpoint(Breakpoint::new(0x0102));
+        gameboy
+            .debugger
+            .add_breakpoint(Breakpoint::in_bank(2, 0x4000));
+
+        let summary = gameboy.run_for_cycles(1000);
+        assert_eq!(
+            StopReason::Breakpoint(Breakpoint::new(0x0102)),
+            summary.reason
+        );
+        assert_eq!(2, summary.instructions);
+
+        let summary = gameboy.run_for_cycles(1000);
+        assert_eq!(
+            StopReason::Breakpoint(Breakpoint::new(0x0102)),
+            summary.reason
+        );
+        assert_eq!(3, summary.instructions);
+    }
+
+    #[test]
+    fn test_watchdog_breaks_out_of_loops_without_io() {
+        // JP 0x0100
+        let mut gameboy = Gameboy::from_rom_bytes(rom_with_program(&[0xC3, 0x00, 0x01]));
+        gameboy.watchdog = Some(Watchdog::new(1000));
+        let summary = gameboy.run_for_cycles(10_000);
+        assert_eq!(
+            StopReason::Stuck(StuckLoop {
+                bank: 0,
+                start: 0x0100,
+                end: 0x0100,
+                cycles: 1000
+            }),
+            summary.reason
+        );
+
+        // LDH A,(0x44); JP 0x0100, polling LY is not a hang
+        let mut gameboy =
+            Gameboy::from_rom_bytes(rom_with_program(&[0xF0, 0x44, 0xC3, 0x00, 0x01]));
+        gameboy.watchdog = Some(Watchdog::new(1000));
+        let summary = gameboy.run_for_cycles(10_000);
+        assert_eq!(StopReason::CycleLimit, summary.reason);
+    }
+
+    #[test]
+    fn test_run_frame_queues_audio() {
+        // JP 0x0100
+        let mut gameboy = Gameboy::from_rom_bytes(rom_with_program(&[0xC3, 0x00, 0x01]));
+        let (producer, _consumer) = crate::audio::ring_buffer(1 << 16);
+        gameboy.audio = Some(producer);
+        gameboy.run_frame();
+        gameboy.run_frame();
+        // a frame's worth of stereo samples at 48kHz, the first one runs short
+        let queued = gameboy.audio.as_ref().unwrap().len();
+        assert!((1600..=3300).contains(&queued), "{}", queued);
+    }
+
+    #[test]
+    fn test_run_frame_stops_at_vblank() {
+        // JP 0x0100
+        let mut gameboy = Gameboy::from_rom_bytes(rom_with_program(&[0xC3, 0x00, 0x01]));
+        let summary = gameboy.run_frame();
+        assert_eq!(StopReason::Condition, summary.reason);
+        assert_eq!(144, gameboy.cpu.bus.read_byte(0xFF44));
+
+        // the next frame takes a full frame's time
+        let summary = gameboy.run_frame();
+        assert_eq!(StopReason::Condition, summary.reason);
+        assert!(summary.cycles.abs_diff(CYCLES_PER_FRAME) <= 4);
+    }
+
+    #[test]
+    fn test_crash_report_contains_recent_instructions() {
+        // INC B; JP 0x0100
+        let mut gameboy = Gameboy::from_rom_bytes(rom_with_program(&[0x04, 0xC3, 0x00, 0x01]));
+        gameboy.run_for_cycles(1000);
+        // writing the report doesn't touch the bus
+        let accesses = std::rc::Rc::new(std::cell::Cell::new(0));
+        let counter = accesses.clone();
+        gameboy
+            .cpu
+            .bus
+            .hooks
+            .on_read(Box::new(move |_, _| counter.set(counter.get() + 1)));
+        let io_accesses = gameboy.cpu.bus.io_accesses();
+        let report = gameboy.crash_report("test".to_string());
+        assert_eq!(0, accesses.get());
+        assert_eq!(io_accesses, gameboy.cpu.bus.io_accesses());
+        assert_eq!(crate::trace::HISTORY_LEN, report.trace.len());
+        assert_eq!("JP $0100", report.trace.last().unwrap().mnemonic);
+        assert_eq!(gameboy.cpu.pc(), report.registers.pc);
+
+        let text = report.to_string();
+        assert!(text.contains("Panic: test"));
+        assert!(text.contains("00:0100  INC B"));
+        #[cfg(feature = "serde")]
+        assert!(report.savestate.is_some());
+    }
+
+    #[test]
+    fn test_rom_file_is_mapped_through_the_cartridge() {
+        // LD A,0x42 at the entry point, the rest of the header area filled with a pattern
+        let mut rom = rom_with_program(&[0x3E, 0x42]);
+        rom[..0x0100].fill(0xAA);
+        let path = std::env::temp_dir().join(format!("rustyboy-entry-{}.gb", std::process::id()));
+        std::fs::write(&path, &rom).unwrap();
+        let mut gameboy = Gameboy::new(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(0x0100, gameboy.cpu.pc());
+        gameboy.cpu.run_cycle();
+        assert_eq!(0x42, gameboy.cpu.registers().a);
+        assert_eq!(0x0102, gameboy.cpu.pc());
+        // the rom stays in the cartridge, work ram is untouched
+        assert_eq!(vec![0xAA; 4], gameboy.read_range(0x0000, 4));
+        assert!(gameboy.read_range(0xC000, 0x2000).iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_forced_region_and_model() {
+        let mut rom = rom_with_program(&[0x00]);
+        rom[0x143] = 0x80;
+        rom[0x14A] = 0x01;
+        let mut gameboy = Gameboy::from_rom_bytes(rom);
+        // color games boot as a CGB
+        assert_eq!(0x11, gameboy.cpu.registers().a);
+        assert_eq!(Region::Overseas, gameboy.cpu.bus.cartridge().destination());
+
+        gameboy
+            .cpu
+            .bus
+            .cartridge_mut()
+            .set_destination(Region::Japan);
+        gameboy.cpu.set_model(Model::Dmg);
+        assert_eq!(vec![0x00], gameboy.read_range(0x014A, 1));
+        let cartridge = gameboy.cpu.bus.cartridge();
+        assert_eq!(Region::Japan, cartridge.destination());
+        assert_eq!(gameboy.read_range(0x014D, 1)[0], cartridge.checksum());
+        assert_eq!(0x01, gameboy.cpu.registers().a);
+        assert!(!gameboy.cpu.bus.ppu.is_cgb_mode());
+
+        gameboy.cpu.set_model(Model::Cgb);
+        assert_eq!(0x11, gameboy.cpu.registers().a);
+        assert!(gameboy.cpu.bus.ppu.is_cgb_mode());
+    }
+
+    #[test]
+    fn test_read_and_write_ranges() {
+        let mut rom = rom_with_program(&[0x00]);
+        rom.resize(0x10000, 0);
+        rom[0x4000] = 0x11;
+        rom[0xC000] = 0x33;
+        let mut gameboy = Gameboy::from_rom_bytes(rom);
+
+        gameboy.write_range(0xC000, &[1, 2, 3]);
+        assert_eq!(vec![1, 2, 3], gameboy.read_range(0xC000, 3));
+        // echo ram pokes land in work ram
+        gameboy.write_range(0xE003, &[4]);
+        assert_eq!(vec![4], gameboy.read_range(0xC003, 1));
+
+        // rom is patched in place
+        gameboy.write_range(0x0150, &[0xAB, 0xCD]);
+        assert_eq!(vec![0xAB, 0xCD], gameboy.read_range(0x0150, 2));
+
+        assert_eq!(vec![0x11], gameboy.read_range(0x4000, 1));
+        assert_eq!(vec![0x33], gameboy.read_bank_range(3, 0x4000, 1));
+        gameboy.write_bank_range(3, 0x4001, &[0x44]);
+        assert_eq!(vec![0x33, 0x44], gameboy.read_bank_range(3, 0x4000, 2));
+        // the mapped bank is untouched and unmapped banks read open bus
+        assert_eq!(vec![0x11, 0x00], gameboy.read_range(0x4000, 2));
+        assert_eq!(vec![0xFF], gameboy.read_bank_range(9, 0x4000, 1));
+
+        // addresses wrap around the end of the map
+        assert_eq!(2, gameboy.read_range(0xFFFF, 2).len());
+    }
+
+    #[test]
+    fn test_frame_hooks_run_after_each_frame() {
+        // JP 0x0100
+        let mut gameboy = Gameboy::from_rom_bytes(rom_with_program(&[0xC3, 0x00, 0x01]));
+        gameboy.add_frame_hook(Box::new(|gb| {
+            let frames = gb.read_range(0xC000, 1)[0];
+            gb.write_range(0xC000, &[frames + 1]);
+        }));
+        for _ in 0..3 {
+            gameboy.run_frame();
+        }
+        assert_eq!(vec![3], gameboy.read_range(0xC000, 1));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_load_state_replays_identically() {
+        // INC A; LD (0xC000),A; JP 0x0100
+        let mut gameboy = Gameboy::from_rom_bytes(rom_with_program(&[
+            0x3C, 0xEA, 0x00, 0xC0, 0xC3, 0x00, 0x01,
+        ]));
+        gameboy.run_for_cycles(30_000);
+        let state = gameboy.save_state().unwrap();
+
+        gameboy.run_for_cycles(10_000);
+        let registers = gameboy.cpu.registers();
+        let ly = gameboy.read_range(0xFF44, 1);
+
+        gameboy.load_state(&state).unwrap();
+        assert_ne!(registers, gameboy.cpu.registers());
+        gameboy.run_for_cycles(10_000);
+        assert_eq!(registers, gameboy.cpu.registers());
+        assert_eq!(ly, gameboy.read_range(0xFF44, 1));
+        assert!(gameboy.load_state("{}").is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_state_carries_cartridge_banks_and_ram() {
+        // MBC1+RAM+BATTERY, 4 banks and 8KB ram
+        let mut rom = rom_with_program(&[0x18, 0xFE]);
+        rom.resize(0x10000, 0);
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x02;
+        let mut gameboy = Gameboy::from_rom_bytes(rom);
+        let bus = &mut gameboy.cpu.bus;
+        bus.write_byte(0x0000, 0x0A);
+        bus.write_byte(0x2000, 0x03);
+        bus.write_byte(0xA000, 0x42);
+        let state = gameboy.save_state().unwrap();
+
+        let bus = &mut gameboy.cpu.bus;
+        bus.write_byte(0x2000, 0x02);
+        bus.write_byte(0xA000, 0x00);
+        bus.write_byte(0x0000, 0x00);
+        gameboy.load_state(&state).unwrap();
+        assert_eq!(3, gameboy.cpu.bus.rom_bank_at(0x4000));
+        assert_eq!(vec![0x42], gameboy.read_range(0xA000, 1));
+        assert!(gameboy.cpu.bus.cartridge().is_ram_dirty());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_preview_shows_the_last_complete_frame() {
+        // LD A,0xFF; LDH (0x47),A; JR -2, every shade black
+        let mut gameboy =
+            Gameboy::from_rom_bytes(rom_with_program(&[0x3E, 0xFF, 0xE0, 0x47, 0x18, 0xFE]));
+        gameboy.run_frame();
+        gameboy.run_frame();
+        let black = gameboy.cpu.bus.ppu.frame()[0];
+        assert!(gameboy
+            .cpu
+            .bus
+            .ppu
+            .frame()
+            .iter()
+            .all(|&pixel| pixel == black));
+        // a frame half drawn into the back buffer is not what the state shows
+        gameboy.cpu.bus.ppu.frame_buffer.fill(0xFF123456);
+        let state = gameboy.save_state().unwrap();
+
+        let preview = Gameboy::preview_state(&state).unwrap();
+        assert_eq!(
+            vec![black; THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT],
+            preview.thumbnail
+        );
+        let blank = Gameboy::from_rom_bytes(vec![0; 0x8000]).cpu.bus.ppu.frame()[0];
+        assert_ne!(blank, black);
+
+        // the thumbnail is 0xAARRGGBB whatever format the frontend draws in
+        gameboy
+            .cpu
+            .bus
+            .ppu
+            .set_pixel_format(crate::pixel::PixelFormat::Rgb565);
+        assert_ne!(black, gameboy.cpu.bus.ppu.frame()[0]);
+        let state = gameboy.save_state().unwrap();
+        let preview = Gameboy::preview_state(&state).unwrap();
+        assert!(preview.thumbnail.iter().all(|&pixel| pixel == black));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_state_slot_hotkeys() {
+        // INC A; JR -3
+        let mut gameboy = Gameboy::from_rom_bytes(rom_with_program(&[0x3C, 0x18, 0xFD]));
+        let save = [Action::SaveState(2)];
+        let load = [Action::LoadState(2)];
+        let mut held = Vec::new();
+        // without a save directory there are no slots
+        assert_eq!(None, gameboy.state_slot_path(2));
+        assert!(gameboy.load_state_slot(2).is_err());
+
+        let dir = std::env::temp_dir().join(format!("rustyboy-slots-{}", std::process::id()));
+        gameboy.state_path = Some(dir.join("game"));
+        gameboy.run_for_cycles(1000);
+        let saved = gameboy.cpu.registers();
+        gameboy.apply_pressed_actions(&save, &mut held);
+        assert!(dir.join("game.ss2").exists());
+        gameboy.run_for_cycles(1000);
+        assert_ne!(saved, gameboy.cpu.registers());
+        // held down it only fires once
+        gameboy.apply_pressed_actions(&load, &mut held);
+        gameboy.run_for_cycles(4);
+        gameboy.apply_pressed_actions(&load, &mut held);
+        assert_ne!(saved, gameboy.cpu.registers());
+        held.clear();
+        gameboy.apply_pressed_actions(&load, &mut held);
+        assert_eq!(saved, gameboy.cpu.registers());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_preview_and_compare_states() {
+        // INC A; LD (0xC000),A; JP 0x0100
+        let mut gameboy = Gameboy::from_rom_bytes(rom_with_program(&[
+            0x3C, 0xEA, 0x00, 0xC0, 0xC3, 0x00, 0x01,
+        ]));
+        gameboy.run_frame();
+        let state = gameboy.save_state().unwrap();
+        gameboy.run_for_cycles(10_000);
+
+        let preview = Gameboy::preview_state(&state).unwrap();
+        assert_eq!(THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT, preview.thumbnail.len());
+        assert_eq!(Model::Dmg, preview.model);
+        assert_ne!(gameboy.cpu.registers(), preview.registers);
+
+        let earlier = gameboy.load_state_detached(&state).unwrap();
+        assert_eq!(preview.registers, earlier.cpu.registers());
+        // only the counter in work ram has moved on since the save
+        assert_eq!(
+            vec![(
+                0xC000,
+                gameboy.read_range(0xC000, 1)[0],
+                earlier.read_range(0xC000, 1)[0]
+            )],
+            gameboy.diff_range(&earlier, 0xC000, 0x1000)
+        );
+        assert!(Gameboy::preview_state("{}").is_err());
+    }
+
+    #[test]
+    fn test_diff_ram_names_changed_variables() {
+        let rom = rom_with_program(&[0x18, 0xFE]);
+        let old = Gameboy::from_rom_bytes(rom.clone());
+        let mut new = Gameboy::from_rom_bytes(rom);
+        let before = old.read_range(0xC0A0, 1)[0];
+        new.write_range(0xC0A0, &[before.wrapping_add(1)]);
+        new.write_range(0xFF90, &[0x42]);
+        let symbols: Symbols = "00:C0A0 wPlayerX".parse().unwrap();
+
+        let changes = old.diff_ram(&new, Some(&symbols));
+        let player_x = changes.iter().find(|change| change.addr == 0xC0A0).unwrap();
+        assert_eq!(
+            format!(
+                "C0A0: {:02X} -> {:02X} wPlayerX",
+                before,
+                before.wrapping_add(1)
+            ),
+            player_x.to_string()
+        );
+        assert!(changes
+            .iter()
+            .any(|change| change.addr == 0xFF90 && change.new == 0x42 && change.symbol.is_none()));
+        assert!(old.diff_ram(&old, None).is_empty());
+    }
+
+    #[test]
+    fn test_screenshot_writes_png() {
+        let gameboy = Gameboy::from_rom_bytes(rom_with_program(&[0x18, 0xFE]));
+        let path = std::env::temp_dir().join(format!("rustyboy-test-{}.png", std::process::id()));
+        gameboy.screenshot_scaled(&path, 2).unwrap();
+        let png = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(b"\x89PNG", &png[..4]);
+        // IHDR width and height
+        assert_eq!(320u32.to_be_bytes(), png[16..20]);
+        assert_eq!(288u32.to_be_bytes(), png[20..24]);
+    }
+
+    #[test]
+    fn test_arbitrary_small_rom_does_not_panic_on_load() {
+        let mut gameboy = Gameboy::from_rom_bytes(vec![0x00; 16]);
+        let summary = gameboy.run_for_cycles(100);
+        assert_eq!(StopReason::CycleLimit, summary.reason);
+    }
 }
@@ -0,0 +1,72 @@
+// known games whose header doesn't tell the whole story
+// looked up by title and header checksum when a rom loads, the quirks of the matching
+// entry override what the header says. the checksum tells revisions and regions apart
+// that share a title, entries without one match every rom with the title
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quirk {
+    // MBC1M multicart: BANK2 selects one of the games, 4 bits of BANK1 the bank in it
+    Mbc1Multicart,
+    // the cartridge has a rumble motor the cartridge type doesn't list
+    Rumble,
+    // bytes of ram, for headers that list the wrong size
+    RamSize(usize),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct GameEntry {
+    pub title: &'static str,
+    // header checksum at 0x014D, None for any
+    pub header_checksum: Option<u8>,
+    pub quirks: &'static [Quirk],
+}
+
+// titles as the headers spell them
+static GAMES: [GameEntry; 5] = [
+    GameEntry {
+        title: "BOMCOL",
+        header_checksum: None,
+        quirks: &[Quirk::Mbc1Multicart],
+    },
+    GameEntry {
+        title: "GENCOL",
+        header_checksum: None,
+        quirks: &[Quirk::Mbc1Multicart],
+    },
+    GameEntry {
+        title: "MOMOCOLLECTION",
+        header_checksum: None,
+        quirks: &[Quirk::Mbc1Multicart],
+    },
+    GameEntry {
+        title: "MORTALKOMBATI&II",
+        header_checksum: None,
+        quirks: &[Quirk::Mbc1Multicart],
+    },
+    GameEntry {
+        title: "SUPERCHINESE 123",
+        header_checksum: None,
+        quirks: &[Quirk::Mbc1Multicart],
+    },
+];
+
+// an entry for the exact checksum wins over one for the title alone
+pub fn lookup(title: &str, header_checksum: u8) -> Option<&'static GameEntry> {
+    let matches = || GAMES.iter().filter(move |game| game.title == title);
+    matches()
+        .find(|game| game.header_checksum == Some(header_checksum))
+        .or_else(|| matches().find(|game| game.header_checksum.is_none()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_by_title_and_checksum() {
+        let entry = lookup("BOMCOL", 0x12).unwrap();
+        assert_eq!(&[Quirk::Mbc1Multicart], entry.quirks);
+        assert_eq!(None, lookup("BOMCOL ", 0x12));
+        assert_eq!(None, lookup("TETRIS", 0x0A));
+    }
+}
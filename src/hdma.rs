@@ -0,0 +1,155 @@
+// CGB VRAM DMA (HDMA1-HDMA5, 0xFF51-0xFF55)
+// copies 16 byte blocks from rom or ram into VRAM. a general transfer copies everything
+// at once, an HBlank transfer one block at the start of every HBlank. the cpu is stalled
+// for 8 machine cycles per block either way
+
+pub const BLOCK_LEN: u16 = 0x10;
+// machine cycles the cpu waits per block
+pub const BLOCK_CYCLES: u32 = 8;
+
+const HDMA1: u16 = 0xFF51;
+const HDMA2: u16 = 0xFF52;
+const HDMA3: u16 = 0xFF53;
+const HDMA4: u16 = 0xFF54;
+const HDMA5: u16 = 0xFF55;
+
+const HBLANK_MODE: u8 = 0x80;
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Hdma {
+    source: u16,
+    // offset into VRAM
+    destination: u16,
+    // blocks left minus one, what HDMA5 reads
+    blocks: u8,
+    running: bool,
+    hblank: bool,
+}
+
+impl Hdma {
+    pub fn new() -> Self {
+        Self {
+            source: 0,
+            destination: 0,
+            blocks: 0x7F,
+            running: false,
+            hblank: false,
+        }
+    }
+
+    // only HDMA5 reads back, bit 7 is clear while an HBlank transfer runs
+    pub fn read_byte(&self, addr: u16) -> u8 {
+        match addr {
+            HDMA5 if self.running => self.blocks,
+            HDMA5 => HBLANK_MODE | self.blocks,
+            _ => 0xFF,
+        }
+    }
+
+    // returns true when a general transfer was started, the bus copies it right away
+    pub fn write_byte(&mut self, addr: u16, value: u8) -> bool {
+        match addr {
+            HDMA1 => self.source = (self.source & 0x00FF) | (value as u16) << 8,
+            // the low 4 bits of both addresses are ignored
+            HDMA2 => self.source = (self.source & 0xFF00) | (value & 0xF0) as u16,
+            HDMA3 => self.destination = (self.destination & 0x00FF) | ((value & 0x1F) as u16) << 8,
+            HDMA4 => self.destination = (self.destination & 0xFF00) | (value & 0xF0) as u16,
+            HDMA5 => {
+                // clearing bit 7 during an HBlank transfer stops it
+                if self.running && self.hblank && value & HBLANK_MODE == 0 {
+                    self.running = false;
+                    return false;
+                }
+                self.blocks = value & 0x7F;
+                self.running = true;
+                self.hblank = value & HBLANK_MODE != 0;
+                return !self.hblank;
+            }
+            _ => {}
+        }
+        false
+    }
+
+    // debugger write, HDMA5 only sets the block count and never starts or stops a transfer
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        match addr {
+            HDMA5 => self.blocks = value & 0x7F,
+            _ => {
+                self.write_byte(addr, value);
+            }
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    // an HBlank transfer waits for the next HBlank
+    pub fn is_hblank_transfer(&self) -> bool {
+        self.running && self.hblank
+    }
+
+    // source and VRAM offset of the next block
+    pub fn next_block(&self) -> (u16, u16) {
+        (self.source, self.destination)
+    }
+
+    // call once the block from next_block has been copied. a transfer running past the
+    // end of VRAM stops there
+    pub fn advance(&mut self) {
+        self.source = self.source.wrapping_add(BLOCK_LEN);
+        self.destination = (self.destination + BLOCK_LEN) & 0x1FF0;
+        self.blocks = self.blocks.wrapping_sub(1) & 0x7F;
+        if self.blocks == 0x7F || self.destination == 0 {
+            self.running = false;
+        }
+    }
+}
+
+impl Default for Hdma {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hblank_transfer_counts_down_and_stops() {
+        let mut hdma = Hdma::new();
+        hdma.write_byte(HDMA1, 0x40);
+        hdma.write_byte(HDMA2, 0x1F);
+        hdma.write_byte(HDMA3, 0xE1);
+        hdma.write_byte(HDMA4, 0x2F);
+        assert!(!hdma.write_byte(HDMA5, HBLANK_MODE | 0x02));
+        assert_eq!((0x4010, 0x0120), hdma.next_block());
+        assert_eq!(0x02, hdma.read_byte(HDMA5));
+        hdma.advance();
+        assert_eq!((0x4020, 0x0130), hdma.next_block());
+        assert_eq!(0x01, hdma.read_byte(HDMA5));
+        // stopped early, the blocks left stay readable
+        hdma.write_byte(HDMA5, 0x00);
+        assert!(!hdma.is_running());
+        assert_eq!(0x81, hdma.read_byte(HDMA5));
+        assert_eq!(0xFF, hdma.read_byte(HDMA1));
+    }
+
+    #[test]
+    fn test_general_transfer_ends_after_its_blocks() {
+        let mut hdma = Hdma::new();
+        assert!(hdma.write_byte(HDMA5, 0x01));
+        hdma.advance();
+        assert!(hdma.is_running());
+        hdma.advance();
+        assert!(!hdma.is_running());
+        assert_eq!(0xFF, hdma.read_byte(HDMA5));
+        // the end of VRAM stops it too
+        hdma.write_byte(HDMA3, 0x1F);
+        hdma.write_byte(HDMA4, 0xF0);
+        hdma.write_byte(HDMA5, 0x05);
+        hdma.advance();
+        assert!(!hdma.is_running());
+    }
+}
@@ -0,0 +1,330 @@
+// frontend-agnostic input bindings
+// physical inputs (keyboard keys, gamepad buttons) map to actions through one table,
+// so the keyboard, gamepads and the config file all share the same vocabulary.
+// config lines look like "key:Z = a" or "pad:Start = start", # starts a comment
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    Joypad(Button),
+    // tilt an MBC7 cartridge, see Mbc::set_tilt
+    TiltRight,
+    TiltLeft,
+    TiltUp,
+    TiltDown,
+    SaveState(u8),
+    LoadState(u8),
+    FastForward,
+    Screenshot,
+    ToggleOverlay,
+    CyclePalette,
+    ToggleColorCorrection,
+    // read the config file again and take its bindings
+    ReloadConfig,
+    Quit,
+}
+
+// a physical input by the name the frontend gives it, e.g. Key("Escape")
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Input {
+    Key(String),
+    Gamepad(String),
+}
+
+impl Input {
+    pub fn key(name: &str) -> Self {
+        Self::Key(name.to_string())
+    }
+
+    pub fn gamepad(name: &str) -> Self {
+        Self::Gamepad(name.to_string())
+    }
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_lowercase();
+        let slot = |value: &str| {
+            value
+                .parse::<u8>()
+                .map_err(|_| format!("invalid state slot: {}", value))
+        };
+        Ok(match s.as_str() {
+            "right" => Self::Joypad(Button::Right),
+            "left" => Self::Joypad(Button::Left),
+            "up" => Self::Joypad(Button::Up),
+            "down" => Self::Joypad(Button::Down),
+            "a" => Self::Joypad(Button::A),
+            "b" => Self::Joypad(Button::B),
+            "select" => Self::Joypad(Button::Select),
+            "start" => Self::Joypad(Button::Start),
+            "tilt_right" => Self::TiltRight,
+            "tilt_left" => Self::TiltLeft,
+            "tilt_up" => Self::TiltUp,
+            "tilt_down" => Self::TiltDown,
+            "fast_forward" => Self::FastForward,
+            "screenshot" => Self::Screenshot,
+            "toggle_overlay" => Self::ToggleOverlay,
+            "cycle_palette" => Self::CyclePalette,
+            "toggle_color_correction" => Self::ToggleColorCorrection,
+            "reload_config" => Self::ReloadConfig,
+            "quit" => Self::Quit,
+            _ => match s.split_once(':') {
+                Some(("save", n)) => Self::SaveState(slot(n)?),
+                Some(("load", n)) => Self::LoadState(slot(n)?),
+                _ => return Err(format!("unknown action: {}", s)),
+            },
+        })
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Joypad(button) => write!(f, "{}", format!("{:?}", button).to_lowercase()),
+            Self::TiltRight => write!(f, "tilt_right"),
+            Self::TiltLeft => write!(f, "tilt_left"),
+            Self::TiltUp => write!(f, "tilt_up"),
+            Self::TiltDown => write!(f, "tilt_down"),
+            Self::SaveState(slot) => write!(f, "save:{}", slot),
+            Self::LoadState(slot) => write!(f, "load:{}", slot),
+            Self::FastForward => write!(f, "fast_forward"),
+            Self::Screenshot => write!(f, "screenshot"),
+            Self::ToggleOverlay => write!(f, "toggle_overlay"),
+            Self::CyclePalette => write!(f, "cycle_palette"),
+            Self::ToggleColorCorrection => write!(f, "toggle_color_correction"),
+            Self::ReloadConfig => write!(f, "reload_config"),
+            Self::Quit => write!(f, "quit"),
+        }
+    }
+}
+
+impl FromStr for Input {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().split_once(':') {
+            Some(("key", name)) if !name.is_empty() => Ok(Self::key(name)),
+            Some(("pad", name)) if !name.is_empty() => Ok(Self::gamepad(name)),
+            _ => Err(format!("unknown input: {}", s.trim())),
+        }
+    }
+}
+
+impl fmt::Display for Input {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Key(name) => write!(f, "key:{}", name),
+            Self::Gamepad(name) => write!(f, "pad:{}", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bindings {
+    map: HashMap<Input, Action>,
+}
+
+impl Bindings {
+    pub fn empty() -> Self {
+        Self {
+            map: HashMap::new(),
+        }
+    }
+
+    // an input drives one action, binding it again replaces the old action
+    pub fn bind(&mut self, input: Input, action: Action) {
+        self.map.insert(input, action);
+    }
+
+    pub fn unbind(&mut self, input: &Input) {
+        self.map.remove(input);
+    }
+
+    // remove every input bound to `action`
+    pub fn unbind_action(&mut self, action: Action) {
+        self.map.retain(|_, bound| *bound != action);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Input, Action)> {
+        self.map.iter().map(|(input, &action)| (input, action))
+    }
+
+    pub fn action_for(&self, input: &Input) -> Option<Action> {
+        self.map.get(input).copied()
+    }
+
+    // actions for every input that is currently held
+    pub fn actions<'a>(&'a self, inputs: impl IntoIterator<Item = &'a Input>) -> Vec<Action> {
+        inputs
+            .into_iter()
+            .filter_map(|input| self.action_for(input))
+            .collect()
+    }
+
+    // layer config lines on top of the current bindings
+    pub fn apply_config(&mut self, config: &str) -> Result<(), String> {
+        for (number, line) in config.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (input, action) = line
+                .split_once('=')
+                .ok_or_else(|| format!("invalid binding on line {}: {}", number + 1, line))?;
+            self.bind(input.parse()?, action.parse()?);
+        }
+        Ok(())
+    }
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        let mut bindings = Self::empty();
+        let keys = [
+            ("Right", Action::Joypad(Button::Right)),
+            ("Left", Action::Joypad(Button::Left)),
+            ("Up", Action::Joypad(Button::Up)),
+            ("Down", Action::Joypad(Button::Down)),
+            ("Z", Action::Joypad(Button::A)),
+            ("X", Action::Joypad(Button::B)),
+            ("Backspace", Action::Joypad(Button::Select)),
+            ("LeftShift", Action::Joypad(Button::Select)),
+            ("RightShift", Action::Joypad(Button::Select)),
+            ("Enter", Action::Joypad(Button::Start)),
+            ("L", Action::TiltRight),
+            ("J", Action::TiltLeft),
+            ("I", Action::TiltUp),
+            ("K", Action::TiltDown),
+            ("Space", Action::FastForward),
+            ("F12", Action::Screenshot),
+            ("Tab", Action::ToggleOverlay),
+            ("P", Action::CyclePalette),
+            ("C", Action::ToggleColorCorrection),
+            ("F5", Action::ReloadConfig),
+            ("Escape", Action::Quit),
+        ];
+        for (key, action) in keys {
+            bindings.bind(Input::key(key), action);
+        }
+        // savestates need the serde feature
+        #[cfg(feature = "serde")]
+        {
+            bindings.bind(Input::key("F1"), Action::SaveState(1));
+            bindings.bind(Input::key("F2"), Action::LoadState(1));
+        }
+        bindings
+    }
+}
+
+impl FromStr for Bindings {
+    type Err = String;
+
+    // bindings from a config file alone, without the defaults
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut bindings = Self::empty();
+        bindings.apply_config(s)?;
+        Ok(bindings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_actions_and_inputs_round_trip() {
+        let actions = [
+            Action::Joypad(Button::Select),
+            Action::TiltRight,
+            Action::TiltLeft,
+            Action::TiltUp,
+            Action::TiltDown,
+            Action::SaveState(3),
+            Action::LoadState(0),
+            Action::FastForward,
+            Action::Screenshot,
+            Action::ToggleOverlay,
+            Action::CyclePalette,
+            Action::ToggleColorCorrection,
+            Action::ReloadConfig,
+            Action::Quit,
+        ];
+        for action in actions {
+            assert_eq!(Ok(action), action.to_string().parse());
+        }
+        for input in [Input::key("F5"), Input::gamepad("South")] {
+            assert_eq!(Ok(input.clone()), input.to_string().parse());
+        }
+        assert!("save:x".parse::<Action>().is_err());
+        assert!("jump".parse::<Action>().is_err());
+        assert!("mouse:Left".parse::<Input>().is_err());
+    }
+
+    #[test]
+    fn test_default_keyboard_joypad() {
+        let bindings = Bindings::default();
+        let layout = [
+            ("Right", Button::Right),
+            ("Left", Button::Left),
+            ("Up", Button::Up),
+            ("Down", Button::Down),
+            ("Z", Button::A),
+            ("X", Button::B),
+            ("Enter", Button::Start),
+            ("LeftShift", Button::Select),
+            ("RightShift", Button::Select),
+            ("Backspace", Button::Select),
+        ];
+        for (key, button) in layout {
+            assert_eq!(
+                Some(Action::Joypad(button)),
+                bindings.action_for(&Input::key(key))
+            );
+        }
+    }
+
+    #[test]
+    fn test_config_overrides_defaults() {
+        let mut bindings = Bindings::default();
+        assert_eq!(
+            Some(Action::Quit),
+            bindings.action_for(&Input::key("Escape"))
+        );
+
+        bindings
+            .apply_config("# remap\nkey:Escape = start\npad:South = a\n\nkey:F3 = save:2 # slot 2")
+            .unwrap();
+        assert_eq!(
+            Some(Action::Joypad(Button::Start)),
+            bindings.action_for(&Input::key("Escape"))
+        );
+        assert_eq!(
+            Some(Action::Joypad(Button::A)),
+            bindings.action_for(&Input::gamepad("South"))
+        );
+        let held = [Input::key("F3"), Input::key("Q"), Input::key("Z")];
+        assert_eq!(
+            vec![Action::SaveState(2), Action::Joypad(Button::A)],
+            bindings.actions(&held)
+        );
+        assert!(bindings.apply_config("key:Z").is_err());
+    }
+}
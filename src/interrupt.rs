@@ -0,0 +1,63 @@
+// interrupt sources, in priority order
+// the discriminant is the bit in IF/IE and picks the handler address
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    VBlank = 0,
+    LcdStat = 1,
+    Timer = 2,
+    Serial = 3,
+    Joypad = 4,
+}
+
+impl Interrupt {
+    // highest priority first
+    pub const ALL: [Interrupt; 5] = [
+        Interrupt::VBlank,
+        Interrupt::LcdStat,
+        Interrupt::Timer,
+        Interrupt::Serial,
+        Interrupt::Joypad,
+    ];
+
+    pub fn bit(self) -> u8 {
+        1 << self as u8
+    }
+
+    // address the cpu jumps to when servicing the interrupt
+    pub fn vector(self) -> u16 {
+        0x0040 + 8 * self as u16
+    }
+
+    // when several interrupts are pending the lowest bit wins
+    pub fn highest_priority(pending: u8) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|interrupt| pending & interrupt.bit() != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bits_and_vectors() {
+        assert_eq!(0x01, Interrupt::VBlank.bit());
+        assert_eq!(0x10, Interrupt::Joypad.bit());
+        assert_eq!(0x0040, Interrupt::VBlank.vector());
+        assert_eq!(0x0048, Interrupt::LcdStat.vector());
+        assert_eq!(0x0050, Interrupt::Timer.vector());
+        assert_eq!(0x0058, Interrupt::Serial.vector());
+        assert_eq!(0x0060, Interrupt::Joypad.vector());
+    }
+
+    #[test]
+    fn test_lowest_pending_bit_wins() {
+        assert_eq!(None, Interrupt::highest_priority(0x00));
+        assert_eq!(None, Interrupt::highest_priority(0xE0));
+        assert_eq!(Some(Interrupt::VBlank), Interrupt::highest_priority(0x15));
+        assert_eq!(Some(Interrupt::Timer), Interrupt::highest_priority(0x14));
+        assert_eq!(Some(Interrupt::Joypad), Interrupt::highest_priority(0x10));
+    }
+}
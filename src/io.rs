@@ -0,0 +1,224 @@
+// I/O register registry
+// one entry per register the bus implements, with the bits that read back as written.
+// unused bits always read as 1 and read only bits ignore writes, everything else is
+// the last value written
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoRegister {
+    pub addr: u16,
+    pub name: &'static str,
+    // bits that are not connected and read as 1
+    pub unused: u8,
+    // unused bits a CGB connects, their owner masks them per model
+    pub cgb_used: u8,
+    // bits the hardware drives, writes leave them alone
+    pub read_only: u8,
+    // any write clears the whole register instead of storing the value (DIV)
+    pub write_resets: bool,
+    // a write starts or stops something and the register reads back its state instead
+    // (HDMA5, NR52), only the unused bits are fixed
+    pub write_starts: bool,
+    // only there in CGB mode, reads 0xFF on DMG
+    pub cgb_only: bool,
+}
+
+impl IoRegister {
+    const fn new(addr: u16, name: &'static str, unused: u8, read_only: u8) -> Self {
+        Self {
+            addr,
+            name,
+            unused,
+            cgb_used: 0x00,
+            read_only,
+            write_resets: false,
+            write_starts: false,
+            cgb_only: false,
+        }
+    }
+
+    const fn resets(self) -> Self {
+        Self {
+            write_resets: true,
+            ..self
+        }
+    }
+
+    const fn starts(self) -> Self {
+        Self {
+            write_starts: true,
+            ..self
+        }
+    }
+
+    const fn cgb_uses(self, bits: u8) -> Self {
+        Self {
+            cgb_used: bits,
+            ..self
+        }
+    }
+
+    const fn cgb(self) -> Self {
+        Self {
+            cgb_only: true,
+            ..self
+        }
+    }
+
+    // bits that store what the cpu writes
+    pub fn writable(&self) -> u8 {
+        !(self.unused | self.read_only)
+    }
+}
+
+// masks as on a DMG, the CGB only adds the fast serial clock bit to SC
+pub const IO_REGISTERS: &[IoRegister] = &[
+    IoRegister::new(0xFF00, "P1", 0xC0, 0x0F),
+    IoRegister::new(0xFF01, "SB", 0x00, 0x00),
+    IoRegister::new(0xFF02, "SC", 0x7E, 0x00).cgb_uses(0x02),
+    IoRegister::new(0xFF04, "DIV", 0x00, 0x00).resets(),
+    IoRegister::new(0xFF05, "TIMA", 0x00, 0x00),
+    IoRegister::new(0xFF06, "TMA", 0x00, 0x00),
+    IoRegister::new(0xFF07, "TAC", 0xF8, 0x00),
+    IoRegister::new(0xFF0F, "IF", 0xE0, 0x00),
+    IoRegister::new(0xFF10, "NR10", 0x80, 0x00),
+    IoRegister::new(0xFF11, "NR11", 0x3F, 0x00),
+    IoRegister::new(0xFF12, "NR12", 0x00, 0x00),
+    IoRegister::new(0xFF13, "NR13", 0xFF, 0x00),
+    IoRegister::new(0xFF14, "NR14", 0xBF, 0x00),
+    IoRegister::new(0xFF16, "NR21", 0x3F, 0x00),
+    IoRegister::new(0xFF17, "NR22", 0x00, 0x00),
+    IoRegister::new(0xFF18, "NR23", 0xFF, 0x00),
+    IoRegister::new(0xFF19, "NR24", 0xBF, 0x00),
+    IoRegister::new(0xFF1A, "NR30", 0x7F, 0x00),
+    IoRegister::new(0xFF1B, "NR31", 0xFF, 0x00),
+    IoRegister::new(0xFF1C, "NR32", 0x9F, 0x00),
+    IoRegister::new(0xFF1D, "NR33", 0xFF, 0x00),
+    IoRegister::new(0xFF1E, "NR34", 0xBF, 0x00),
+    IoRegister::new(0xFF20, "NR41", 0xFF, 0x00),
+    IoRegister::new(0xFF21, "NR42", 0x00, 0x00),
+    IoRegister::new(0xFF22, "NR43", 0x00, 0x00),
+    IoRegister::new(0xFF23, "NR44", 0xBF, 0x00),
+    IoRegister::new(0xFF24, "NR50", 0x00, 0x00),
+    IoRegister::new(0xFF25, "NR51", 0x00, 0x00),
+    IoRegister::new(0xFF26, "NR52", 0x70, 0x0F).starts(),
+    // wave ram, readable while channel 3 is off
+    IoRegister::new(0xFF30, "WAVE0", 0x00, 0x00),
+    IoRegister::new(0xFF31, "WAVE1", 0x00, 0x00),
+    IoRegister::new(0xFF32, "WAVE2", 0x00, 0x00),
+    IoRegister::new(0xFF33, "WAVE3", 0x00, 0x00),
+    IoRegister::new(0xFF34, "WAVE4", 0x00, 0x00),
+    IoRegister::new(0xFF35, "WAVE5", 0x00, 0x00),
+    IoRegister::new(0xFF36, "WAVE6", 0x00, 0x00),
+    IoRegister::new(0xFF37, "WAVE7", 0x00, 0x00),
+    IoRegister::new(0xFF38, "WAVE8", 0x00, 0x00),
+    IoRegister::new(0xFF39, "WAVE9", 0x00, 0x00),
+    IoRegister::new(0xFF3A, "WAVEA", 0x00, 0x00),
+    IoRegister::new(0xFF3B, "WAVEB", 0x00, 0x00),
+    IoRegister::new(0xFF3C, "WAVEC", 0x00, 0x00),
+    IoRegister::new(0xFF3D, "WAVED", 0x00, 0x00),
+    IoRegister::new(0xFF3E, "WAVEE", 0x00, 0x00),
+    IoRegister::new(0xFF3F, "WAVEF", 0x00, 0x00),
+    IoRegister::new(0xFF40, "LCDC", 0x00, 0x00),
+    IoRegister::new(0xFF41, "STAT", 0x80, 0x07),
+    IoRegister::new(0xFF42, "SCY", 0x00, 0x00),
+    IoRegister::new(0xFF43, "SCX", 0x00, 0x00),
+    IoRegister::new(0xFF44, "LY", 0x00, 0xFF),
+    IoRegister::new(0xFF45, "LYC", 0x00, 0x00),
+    IoRegister::new(0xFF46, "DMA", 0x00, 0x00),
+    IoRegister::new(0xFF47, "BGP", 0x00, 0x00),
+    IoRegister::new(0xFF48, "OBP0", 0x00, 0x00),
+    IoRegister::new(0xFF49, "OBP1", 0x00, 0x00),
+    IoRegister::new(0xFF4A, "WY", 0x00, 0x00),
+    IoRegister::new(0xFF4B, "WX", 0x00, 0x00),
+    IoRegister::new(0xFF4D, "KEY1", 0x7E, 0x80).cgb(),
+    IoRegister::new(0xFF4F, "VBK", 0xFE, 0x00).cgb(),
+    // the DMA addresses are write only
+    IoRegister::new(0xFF51, "HDMA1", 0xFF, 0x00).cgb(),
+    IoRegister::new(0xFF52, "HDMA2", 0xFF, 0x00).cgb(),
+    IoRegister::new(0xFF53, "HDMA3", 0xFF, 0x00).cgb(),
+    IoRegister::new(0xFF54, "HDMA4", 0xFF, 0x00).cgb(),
+    IoRegister::new(0xFF55, "HDMA5", 0x00, 0x00).cgb().starts(),
+    IoRegister::new(0xFF68, "BCPS", 0x40, 0x00).cgb(),
+    IoRegister::new(0xFF69, "BCPD", 0x00, 0x00).cgb(),
+    IoRegister::new(0xFF6A, "OCPS", 0x40, 0x00).cgb(),
+    IoRegister::new(0xFF6B, "OCPD", 0x00, 0x00).cgb(),
+    IoRegister::new(0xFFFF, "IE", 0x00, 0x00),
+];
+
+// bits of 0xFF00-0xFF7F the bus forces to 1 on every model, 0xFF where no register exists
+pub const fn read_masks() -> [u8; 0x80] {
+    let mut masks = [0xFF; 0x80];
+    let mut i = 0;
+    while i < IO_REGISTERS.len() {
+        let register = &IO_REGISTERS[i];
+        if register.addr < 0xFF80 {
+            masks[(register.addr - 0xFF00) as usize] = register.unused & !register.cgb_used;
+        }
+        i += 1;
+    }
+    masks
+}
+
+pub fn io_register(addr: u16) -> Option<&'static IoRegister> {
+    IO_REGISTERS.iter().find(|register| register.addr == addr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{bus::Bus, model::Model};
+
+    fn bus(cgb: bool) -> Bus {
+        let mut rom = vec![0; 0x8000];
+        if cgb {
+            rom[0x143] = 0x80;
+        }
+        Bus::from_rom_bytes(rom)
+    }
+
+    #[test]
+    fn test_io_registers_read_back_through_their_masks() {
+        for register in IO_REGISTERS {
+            for value in 0..=0xFF {
+                // a fresh bus per write, so side effects of earlier values can't leak in
+                let mut bus = bus(register.cgb_only);
+                let before = bus.read_byte(register.addr);
+                bus.write_byte(register.addr, value);
+
+                if register.write_starts {
+                    let read = bus.read_byte(register.addr);
+                    assert_eq!(register.unused, read & register.unused, "{}", register.name);
+                    continue;
+                }
+                let expected = if register.write_resets {
+                    0
+                } else {
+                    register.unused | (before & register.read_only) | (value & register.writable())
+                };
+                assert_eq!(
+                    expected,
+                    bus.read_byte(register.addr),
+                    "{} ({:04X}) after writing {:02X}",
+                    register.name,
+                    register.addr,
+                    value
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_cgb_registers_are_open_bus_on_dmg() {
+        let mut bus = bus(false);
+        assert_eq!(Model::Dmg, bus.model());
+        for register in IO_REGISTERS.iter().filter(|register| register.cgb_only) {
+            bus.write_byte(register.addr, 0x00);
+            assert_eq!(0xFF, bus.read_byte(register.addr), "{}", register.name);
+        }
+        assert_eq!(
+            Some("STAT"),
+            io_register(0xFF41).map(|register| register.name)
+        );
+        assert_eq!(None, io_register(0xFF03));
+    }
+}
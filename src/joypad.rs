@@ -0,0 +1,128 @@
+// joypad, the P1 register at 0xFF00
+// the eight buttons sit in a 2x4 matrix. the game pulls P14 low to read the d-pad or P15
+// low to read the buttons on bits 0-3, a pressed button pulls its line low. a line
+// falling while its row is selected requests the joypad interrupt
+
+use crate::input::Button;
+
+const P14: u8 = 0x10;
+const P15: u8 = 0x20;
+const SELECT_MASK: u8 = P14 | P15;
+
+const BUTTONS: [Button; 8] = [
+    Button::Right,
+    Button::Left,
+    Button::Up,
+    Button::Down,
+    Button::A,
+    Button::B,
+    Button::Select,
+    Button::Start,
+];
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Joypad {
+    // P14/P15 as last written
+    select: u8,
+    // held buttons, the d-pad in the low nibble and A/B/Select/Start in the high one
+    held: u8,
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Self {
+            select: SELECT_MASK,
+            held: 0,
+        }
+    }
+
+    pub fn read_byte(&self) -> u8 {
+        0xC0 | self.select | !self.lines() & 0x0F
+    }
+
+    // returns true when the new selection pulls a line low
+    pub fn write_byte(&mut self, value: u8) -> bool {
+        let lines = self.lines();
+        self.select = value & SELECT_MASK;
+        self.lines() & !lines != 0
+    }
+
+    // replaces the held buttons, returns true when one of them pulls a line low
+    pub fn set_buttons(&mut self, buttons: &[Button]) -> bool {
+        let lines = self.lines();
+        self.held = buttons.iter().fold(0, |held, &button| held | bit(button));
+        self.lines() & !lines != 0
+    }
+
+    pub fn buttons(&self) -> Vec<Button> {
+        BUTTONS
+            .into_iter()
+            .filter(|&button| self.held & bit(button) != 0)
+            .collect()
+    }
+
+    // bits 0-3 of the lines pulled low by held buttons in the selected rows
+    fn lines(&self) -> u8 {
+        let mut lines = 0;
+        if self.select & P14 == 0 {
+            lines |= self.held & 0x0F;
+        }
+        if self.select & P15 == 0 {
+            lines |= self.held >> 4;
+        }
+        lines
+    }
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn bit(button: Button) -> u8 {
+    match button {
+        Button::Right => 0x01,
+        Button::Left => 0x02,
+        Button::Up => 0x04,
+        Button::Down => 0x08,
+        Button::A => 0x10,
+        Button::B => 0x20,
+        Button::Select => 0x40,
+        Button::Start => 0x80,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rows_read_through_select_lines() {
+        let mut joypad = Joypad::new();
+        joypad.set_buttons(&[Button::Up, Button::A, Button::Start]);
+        // nothing selected
+        assert_eq!(0xFF, joypad.read_byte());
+        joypad.write_byte(0x20);
+        assert_eq!(0xEB, joypad.read_byte());
+        joypad.write_byte(0x10);
+        assert_eq!(0xD6, joypad.read_byte());
+        // both rows at once
+        joypad.write_byte(0x00);
+        assert_eq!(0xC2, joypad.read_byte());
+    }
+
+    #[test]
+    fn test_interrupt_on_falling_line() {
+        let mut joypad = Joypad::new();
+        // not selected, no line moves
+        assert!(!joypad.set_buttons(&[Button::A]));
+        assert!(joypad.write_byte(0x10));
+        assert!(!joypad.set_buttons(&[Button::A]));
+        // B pulls line 1 low, A letting go only raises one
+        assert!(joypad.set_buttons(&[Button::B]));
+        assert!(!joypad.set_buttons(&[]));
+        // the d-pad row is not selected
+        assert!(!joypad.set_buttons(&[Button::Left]));
+    }
+}
@@ -0,0 +1,43 @@
+pub mod alu;
+pub mod apu;
+pub mod audio;
+pub mod battery;
+pub mod bus;
+pub mod cartridge;
+pub mod config;
+pub mod console;
+pub mod cpu;
+pub mod crash;
+pub mod debugger;
+pub mod device;
+pub mod disasm;
+pub mod dma;
+pub mod eeprom;
+pub mod gameboy;
+pub mod gamedb;
+pub mod hdma;
+pub mod input;
+pub mod interrupt;
+pub mod io;
+pub mod joypad;
+pub mod link;
+pub mod mbc;
+pub mod memory;
+pub mod model;
+pub mod movie;
+pub mod pacing;
+pub mod palette;
+pub mod pixel;
+pub mod png;
+pub mod ppu;
+pub mod register;
+pub mod rtc;
+pub mod serial;
+pub mod sgb;
+pub mod snapshot;
+pub mod stats;
+pub mod timer;
+pub mod trace;
+#[cfg(feature = "trainer")]
+pub mod trainer;
+pub mod watchdog;
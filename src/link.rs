@@ -0,0 +1,172 @@
+// link cable to another emulator
+// the serial port hands whole bytes to a transport instead of single bits. the side whose
+// game drives the clock sends a transfer with its SB, the other side answers with its own
+// SB and finishes its transfer if its game was waiting on the external clock. when both
+// games start a transfer with their own clock at once, each takes the other's transfer as
+// the answer
+// over TCP every packet is two bytes, a kind and the data
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+const TRANSFER: u8 = 0x01;
+const REPLY: u8 = 0x02;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Packet {
+    // SB of the side driving the clock
+    Transfer(u8),
+    // SB of the other side, in answer to a transfer
+    Reply(u8),
+}
+
+impl Packet {
+    fn to_bytes(self) -> [u8; 2] {
+        match self {
+            Packet::Transfer(data) => [TRANSFER, data],
+            Packet::Reply(data) => [REPLY, data],
+        }
+    }
+
+    fn from_bytes(bytes: [u8; 2]) -> io::Result<Self> {
+        match bytes[0] {
+            TRANSFER => Ok(Packet::Transfer(bytes[1])),
+            REPLY => Ok(Packet::Reply(bytes[1])),
+            kind => Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("unknown link packet {:#04X}", kind),
+            )),
+        }
+    }
+}
+
+// how packets get to the other emulator
+pub trait Transport {
+    fn send(&mut self, packet: Packet) -> io::Result<()>;
+    // the next packet from the peer, waiting at most `timeout` for it
+    fn receive(&mut self, timeout: Duration) -> io::Result<Option<Packet>>;
+}
+
+// which end of the TCP connection this emulator is
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkTarget {
+    Listen(u16),
+    Connect(String),
+}
+
+pub struct TcpLink {
+    stream: TcpStream,
+    // first byte of a packet that arrived on its own
+    partial: Option<u8>,
+}
+
+impl TcpLink {
+    // waits for the other emulator to connect
+    pub fn listen(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(("0.0.0.0", port))?;
+        let (stream, _) = listener.accept()?;
+        Self::new(stream)
+    }
+
+    pub fn connect(addr: &str) -> io::Result<Self> {
+        Self::new(TcpStream::connect(addr)?)
+    }
+
+    pub fn open(target: &LinkTarget) -> io::Result<Self> {
+        match target {
+            LinkTarget::Listen(port) => Self::listen(*port),
+            LinkTarget::Connect(addr) => Self::connect(addr),
+        }
+    }
+
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        // a packet is two bytes, don't let them wait for more
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream,
+            partial: None,
+        })
+    }
+
+    pub fn peer(&self) -> io::Result<String> {
+        self.stream.peer_addr().map(|addr| addr.to_string())
+    }
+}
+
+impl Transport for TcpLink {
+    fn send(&mut self, packet: Packet) -> io::Result<()> {
+        // polls leave the stream non-blocking, a send always goes out whole
+        self.stream.set_nonblocking(false)?;
+        self.stream.write_all(&packet.to_bytes())
+    }
+
+    fn receive(&mut self, timeout: Duration) -> io::Result<Option<Packet>> {
+        // a zero read timeout is an error, not a poll
+        if timeout.is_zero() {
+            self.stream.set_nonblocking(true)?;
+        } else {
+            self.stream.set_nonblocking(false)?;
+            self.stream.set_read_timeout(Some(timeout))?;
+        }
+        let mut bytes = [0; 2];
+        let start = match self.partial {
+            Some(byte) => {
+                bytes[0] = byte;
+                1
+            }
+            None => 0,
+        };
+        let read = match self.stream.read(&mut bytes[start..]) {
+            Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+            Ok(read) => read,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                return Ok(None)
+            }
+            Err(e) => return Err(e),
+        };
+        if start + read < 2 {
+            self.partial = Some(bytes[0]);
+            return Ok(None);
+        }
+        self.partial = None;
+        Packet::from_bytes(bytes).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tcp_link_carries_packets() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let mut client = TcpLink::connect(&addr).unwrap();
+        let mut server = TcpLink::new(listener.accept().unwrap().0).unwrap();
+
+        assert_eq!(None, server.receive(Duration::ZERO).unwrap());
+        client.send(Packet::Transfer(0x42)).unwrap();
+        assert_eq!(
+            Some(Packet::Transfer(0x42)),
+            server.receive(Duration::from_secs(1)).unwrap()
+        );
+        server.send(Packet::Reply(0x99)).unwrap();
+        assert_eq!(
+            Some(Packet::Reply(0x99)),
+            client.receive(Duration::from_secs(1)).unwrap()
+        );
+
+        // half a packet waits for the rest
+        server.stream.write_all(&[TRANSFER]).unwrap();
+        assert_eq!(None, client.receive(Duration::from_millis(100)).unwrap());
+        server.stream.write_all(&[0x07]).unwrap();
+        assert_eq!(
+            Some(Packet::Transfer(0x07)),
+            client.receive(Duration::from_secs(1)).unwrap()
+        );
+
+        drop(server);
+        assert!(client.receive(Duration::from_secs(1)).is_err());
+    }
+}
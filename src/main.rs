@@ -1,23 +1,459 @@
-mod bus;
-mod cartridge;
-mod cpu;
-mod gameboy;
-mod register;
-mod serial;
-mod timer;
+use std::{env, fs, path::Path, str::FromStr, time::Duration};
 
-use std::{env, path::Path};
+#[cfg(feature = "audio")]
+use rustyboy::audio::AudioOutput;
+use rustyboy::{
+    apu::SAMPLE_RATE,
+    audio::LATENCIES,
+    battery::BatterySave,
+    bus::OpenBus,
+    cartridge::{Cartridge, CartridgeError, HEADER_END},
+    config::Config,
+    console::DebugConsole,
+    debugger::{self, AddressRange, Symbols},
+    gameboy::{self, Gameboy},
+    link::{LinkTarget, TcpLink},
+    model::{Model, Region},
+    movie::Movie,
+    pacing::{FramePacer, PresentMode},
+    palette::DmgPalette,
+    ppu::Renderer,
+    stats::{self, RomStats},
+};
 
-use gameboy::Gameboy;
+const USAGE: &str = "Usage: cargo run <ROM> [--refresh-rate <HZ>] [--blend] [--ghosting <0-1>] [--color-correction] [--fifo] [--strict] [--strict-header] [--open-bus ff|last] [--debug-console] [--palette <NAME|COLORS>]\n       [--sample-rate <HZ>] [--audio-latency <MS>] [--audio-device <NAME>] [--audio-sync] [--model dmg|cgb] [--region jp|overseas]\n       [--link listen <PORT>|connect <HOST:PORT>] [--record-movie <FILE> | --play-movie <FILE>]\n       [--diff-state <STATE> [--diff-state <STATE>]] [--symbols <FILE>] [--hexdump <START-END>]\n       [--bench <FRAMES> [--stats <FILE.csv|FILE.json>]] [--screenshot-scale <N>]\n       cargo run rom-info <ROM>";
+
+// value following an option, None after printing why it is missing or invalid
+fn parse_value<T: FromStr<Err = String>>(value: Option<&String>) -> Option<T> {
+    let Some(value) = value else {
+        eprintln!("{}", USAGE);
+        return None;
+    };
+    value.parse().map_err(|e| eprintln!("{}", e)).ok()
+}
+
+// print the ram changes from the first state to the second, or from the freshly started
+// rom to a single state
+#[cfg(feature = "serde")]
+fn diff_states(gameboy: &Gameboy, states: &[String], symbols: Option<&Symbols>) {
+    let load = |path: &String| {
+        fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|state| gameboy.load_state_detached(&state))
+            .map_err(|e| eprintln!("could not load state {}: {}", path, e))
+            .ok()
+    };
+    let loaded: Option<Vec<Gameboy>> = states.iter().map(load).collect();
+    let Some(loaded) = loaded else {
+        return;
+    };
+    let changes = match loaded.as_slice() {
+        [new] => gameboy.diff_ram(new, symbols),
+        [old, new] => old.diff_ram(new, symbols),
+        _ => {
+            eprintln!("{}", USAGE);
+            return;
+        }
+    };
+    for change in changes {
+        println!("{}", change);
+    }
+}
+
+// print what the header of a rom says without starting it. any cartridge type loads, a
+// dump can be inspected whether it runs or not
+fn print_rom_info(path: &str) {
+    let data = match fs::read(path) {
+        Ok(data) if data.len() < HEADER_END => Err(CartridgeError::TooSmall(data.len())),
+        Ok(data) => Ok(data),
+        Err(e) => Err(e.into()),
+    };
+    let mut cartridge = Cartridge::new();
+    match data.and_then(|data| cartridge.load_bytes(data)) {
+        Ok(()) => print!("{}", cartridge),
+        Err(e) => eprintln!("could not load {}: {}", path, e),
+    }
+}
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let args: Vec<String> = env::args().skip(1).collect();
+    let Some(rom) = args.first() else {
+        eprintln!("{}", USAGE);
+        return;
+    };
+    if rom == "rom-info" {
+        match args.get(1) {
+            Some(path) => print_rom_info(path),
+            None => eprintln!("{}", USAGE),
+        }
+        return;
+    }
+
+    let mut refresh_rate = None;
+    let mut mode = PresentMode::Duplicate;
+    let mut ghosting = 0.0;
+    let mut color_correction = false;
+    let mut renderer = Renderer::Scanline;
+    let mut strict = false;
+    let mut strict_header = false;
+    let mut open_bus = OpenBus::default();
+    let mut debug_console = false;
+    let mut palette = DmgPalette::default();
+    let mut model: Option<Model> = None;
+    let mut region: Option<Region> = None;
+    let mut diff_state_files = Vec::new();
+    let mut hexdumps: Vec<AddressRange> = Vec::new();
+    let mut symbols: Option<Symbols> = None;
+    let mut bench_frames: Option<u64> = None;
+    let mut stats_file: Option<String> = None;
+    let mut screenshot_scale = 1;
+    let mut sample_rate = SAMPLE_RATE;
+    let mut audio_latency: Option<u32> = None;
+    let mut audio_device: Option<String> = None;
+    let mut audio_sync = false;
+    let mut link: Option<LinkTarget> = None;
+    let mut record_movie: Option<String> = None;
+    let mut play_movie: Option<String> = None;
+    let mut options = args[1..].iter();
+    while let Some(option) = options.next() {
+        match option.as_str() {
+            "--refresh-rate" => match options.next().and_then(|hz| hz.parse::<f64>().ok()) {
+                Some(hz) if hz > 0.0 => refresh_rate = Some(hz),
+                _ => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--blend" => mode = PresentMode::Blend,
+            "--ghosting" => match options.next().and_then(|w| w.parse::<f64>().ok()) {
+                Some(weight) if (0.0..=1.0).contains(&weight) => ghosting = weight,
+                _ => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--color-correction" => color_correction = true,
+            "--fifo" => renderer = Renderer::Fifo,
+            "--strict" => strict = true,
+            "--strict-header" => strict_header = true,
+            "--open-bus" => match parse_value(options.next()) {
+                Some(parsed) => open_bus = parsed,
+                None => return,
+            },
+            "--debug-console" => debug_console = true,
+            "--palette" => match parse_value(options.next()) {
+                Some(parsed) => palette = parsed,
+                None => return,
+            },
+            "--model" => match parse_value(options.next()) {
+                Some(parsed) => model = Some(parsed),
+                None => return,
+            },
+            "--region" => match parse_value(options.next()) {
+                Some(parsed) => region = Some(parsed),
+                None => return,
+            },
+            "--diff-state" => match options.next() {
+                Some(path) => diff_state_files.push(path.clone()),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--hexdump" => match parse_value(options.next()) {
+                Some(parsed) => hexdumps.push(parsed),
+                None => return,
+            },
+            "--symbols" => match options.next().map(fs::read_to_string) {
+                Some(Ok(file)) => match file.parse() {
+                    Ok(parsed) => symbols = Some(parsed),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        return;
+                    }
+                },
+                Some(Err(e)) => {
+                    eprintln!("could not read symbols: {}", e);
+                    return;
+                }
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--bench" => match options.next().and_then(|frames| frames.parse().ok()) {
+                Some(frames) => bench_frames = Some(frames),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--screenshot-scale" => match options.next().and_then(|n| n.parse().ok()) {
+                Some(n) if n > 0 => screenshot_scale = n,
+                _ => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--sample-rate" => match options.next().and_then(|hz| hz.parse().ok()) {
+                Some(hz) if (8000..=192_000).contains(&hz) => sample_rate = hz,
+                _ => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--audio-latency" => match options.next().and_then(|ms| ms.parse().ok()) {
+                Some(ms) if LATENCIES.contains(&ms) => audio_latency = Some(ms),
+                _ => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--audio-device" => match options.next() {
+                Some(name) => audio_device = Some(name.clone()),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--audio-sync" => audio_sync = true,
+            "--link" => match (options.next().map(String::as_str), options.next()) {
+                (Some("listen"), Some(port)) => match port.parse() {
+                    Ok(port) => link = Some(LinkTarget::Listen(port)),
+                    Err(_) => {
+                        eprintln!("invalid port {}", port);
+                        return;
+                    }
+                },
+                (Some("connect"), Some(addr)) => link = Some(LinkTarget::Connect(addr.clone())),
+                _ => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--record-movie" => match options.next() {
+                Some(path) => record_movie = Some(path.clone()),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--play-movie" => match options.next() {
+                Some(path) => play_movie = Some(path.clone()),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--stats" => match options.next() {
+                Some(path) => stats_file = Some(path.clone()),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            _ => {
+                eprintln!("{}", USAGE);
+                return;
+            }
+        }
+    }
+
+    if record_movie.is_some() && play_movie.is_some() {
+        eprintln!("--record-movie and --play-movie can't be used together");
+        return;
+    }
+    if audio_sync && refresh_rate.is_some() {
+        eprintln!("--audio-sync paces by the audio device, it can't be used with --refresh-rate");
+        return;
+    }
 
-    match args.len() {
-        2 => {
-            let mut gameboy = Gameboy::new(Path::new(&args[1]));
-            gameboy.run();
+    let mut gameboy = match Gameboy::new(Path::new(rom)) {
+        Ok(gameboy) => gameboy,
+        Err(e) => {
+            eprintln!("could not load {}: {}", rom, e);
+            return;
+        }
+    };
+    println!("{}", gameboy.cpu.bus.cartridge());
+    // strict header mode is for checking dumps, anything the boot rom or a checksum
+    // would flag stops it, and so does a controller that isn't emulated
+    match gameboy.cpu.bus.cartridge().validate() {
+        Ok(()) => {}
+        Err(e) if strict_header => {
+            eprintln!("{}: {}", rom, e);
+            return;
+        }
+        Err(e @ CartridgeError::UnsupportedMapper(_)) => {
+            eprintln!("warning: {}, running it as ROM ONLY", e)
+        }
+        Err(e) => eprintln!("warning: {}", e),
+    }
+    if let Some(region) = region {
+        gameboy.cpu.bus.cartridge_mut().set_destination(region);
+    }
+    // after the region, the model decides CGB mode from the final header
+    if let Some(model) = model {
+        gameboy.cpu.set_model(model);
+    }
+    gameboy.cpu.bus.ppu.set_renderer(renderer);
+    gameboy.cpu.bus.strict = strict;
+    gameboy.cpu.bus.open_bus = open_bus;
+    if debug_console {
+        gameboy.cpu.bus.debug_console = Some(DebugConsole::new());
+    }
+    gameboy.cpu.bus.ppu.set_palette(palette);
+    gameboy.cpu.bus.ppu.set_ghosting(ghosting);
+    gameboy.cpu.bus.ppu.set_color_correction(color_correction);
+    gameboy.screenshot_scale = screenshot_scale;
+    gameboy.cpu.bus.apu.set_sample_rate(sample_rate);
+    gameboy.audio_sync = audio_sync;
+    if !diff_state_files.is_empty() {
+        #[cfg(feature = "serde")]
+        diff_states(&gameboy, &diff_state_files, symbols.as_ref());
+        #[cfg(not(feature = "serde"))]
+        {
+            drop(symbols);
+            eprintln!("comparing savestates needs the serde feature");
+        }
+        return;
+    }
+    if !hexdumps.is_empty() {
+        for range in hexdumps {
+            let bytes = gameboy.cpu.bus.dump_range(range.start, range.end);
+            print!("{}", debugger::hexdump(range.start, &bytes));
+        }
+        return;
+    }
+    if let Some(frames) = bench_frames {
+        let results = [RomStats::measure(rom, &mut gameboy, frames)];
+        for result in &results {
+            println!(
+                "{}: {} frames in {:.2}s, {:.1} fps ({:.1}x)",
+                result.rom,
+                result.frames,
+                result.seconds,
+                result.fps(),
+                result.speed()
+            );
+            if let Some(failure) = &result.failure {
+                println!("{}: {}", result.rom, failure);
+            }
+        }
+        if let Some(path) = stats_file {
+            if let Err(e) = stats::write(Path::new(&path), &results) {
+                eprintln!("could not write stats to {}: {}", path, e);
+            }
+        }
+        return;
+    }
+    // the first start without a config walks through the settings
+    let config_path = Config::default_path();
+    let config = match Config::load(&config_path) {
+        Ok(Some(config)) => config,
+        Ok(None) => match Gameboy::run_setup_wizard() {
+            Some(config) => {
+                match config.save(&config_path) {
+                    Ok(()) => eprintln!("config written to {}", config_path.display()),
+                    Err(e) => eprintln!("could not write config: {}", e),
+                }
+                config
+            }
+            None => return,
+        },
+        Err(e) => {
+            eprintln!("could not load config {}: {}", config_path.display(), e);
+            Config::default()
+        }
+    };
+    gameboy.apply_config(&config);
+    gameboy.config_path = Some(config_path);
+    let rom_name = Path::new(rom).file_stem().unwrap_or(rom.as_ref());
+    gameboy.state_path = Some(config.save_dir.join(rom_name));
+    if gameboy.cpu.bus.cartridge().has_battery() {
+        let interval = Duration::from_secs(config.autosave_interval);
+        let battery = BatterySave::for_rom(&config.save_dir, Path::new(rom), interval);
+        match battery.load(gameboy.cpu.bus.cartridge_mut()) {
+            Ok(true) => eprintln!("loaded {}", battery.path().display()),
+            Ok(false) => {}
+            Err(e) => eprintln!("could not load {}: {}", battery.path().display(), e),
+        }
+        gameboy.battery = Some(battery);
+    }
+    if let Some(target) = link {
+        if let LinkTarget::Listen(port) = target {
+            eprintln!("waiting for the other player on port {}", port);
+        }
+        match TcpLink::open(&target) {
+            Ok(link) => {
+                if let Ok(peer) = link.peer() {
+                    eprintln!("linked with {}", peer);
+                }
+                gameboy.cpu.bus.serial.attach_link(Box::new(link));
+            }
+            Err(e) => {
+                eprintln!("could not open the link cable: {}", e);
+                return;
+            }
+        }
+    }
+    // the command line wins over the config
+    let audio_latency = audio_latency.unwrap_or(config.audio_latency);
+    gameboy.cpu.bus.apu.set_latency(audio_latency);
+    #[cfg(feature = "trainer")]
+    match rustyboy::trainer::Trainer::for_rom(Path::new(rom)) {
+        Ok(Some(trainer)) => gameboy.add_frame_hook(trainer.into_frame_hook()),
+        Ok(None) => {}
+        Err(e) => eprintln!("could not load trainer: {}", e),
+    }
+    // kept alive while the game runs, without it the samples stay in the apu
+    #[cfg(feature = "audio")]
+    let _audio = match AudioOutput::open(audio_device.as_deref(), sample_rate, audio_latency) {
+        Ok((output, producer)) => {
+            gameboy.audio = Some(producer);
+            Some(output)
+        }
+        Err(e) => {
+            eprintln!("could not open audio output: {}", e);
+            if audio_device.is_some() {
+                eprintln!("devices: {}", AudioOutput::device_names().join(", "));
+            }
+            None
+        }
+    };
+    #[cfg(not(feature = "audio"))]
+    if audio_device.is_some() {
+        eprintln!("audio output needs the audio feature");
+    }
+    // the battery save is loaded by now, a movie from power on plays against that ram
+    if let Some(path) = &play_movie {
+        let movie = fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|movie| movie.parse::<Movie>())
+            .and_then(|movie| gameboy.play_movie(movie));
+        if let Err(e) = movie {
+            eprintln!("could not play movie {}: {}", path, e);
+            return;
+        }
+    }
+    if record_movie.is_some() {
+        if let Err(e) = gameboy.record_movie() {
+            eprintln!("could not start recording: {}", e);
+            return;
+        }
+    }
+    // Ctrl+C in the terminal ends the run like closing the window, so saves get written
+    if let Err(e) = ctrlc::set_handler(gameboy::interrupt) {
+        eprintln!("could not catch Ctrl+C: {}", e);
+    }
+    match refresh_rate {
+        Some(hz) => gameboy.run_paced(FramePacer::new(hz, mode)),
+        None => gameboy.run(),
+    }
+    if let (Some(path), Some(movie)) = (record_movie, gameboy.stop_movie()) {
+        match fs::write(&path, movie.to_string()) {
+            Ok(()) => eprintln!("{} frames written to {}", movie.len(), path),
+            Err(e) => eprintln!("could not write movie {}: {}", path, e),
         }
-        _ => eprintln!("Usage: cargo run <ROM>"),
     }
 }
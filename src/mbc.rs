@@ -0,0 +1,870 @@
+// memory bank controllers
+// the chip on the cartridge that maps a rom bigger than 32KB and the cartridge ram into
+// the address space. games switch banks by writing to the rom area, which the controller
+// catches instead of the rom. selected by the cartridge type at 0x0147
+
+use crate::eeprom::Eeprom;
+use crate::rtc::Rtc;
+
+pub const ROM_BANK_SIZE: usize = 0x4000;
+
+const RAM_ENABLE: u8 = 0x0A;
+// MBC5 ram bank register bit wired to the motor on rumble cartridges
+const RUMBLE_MOTOR: u8 = 0x08;
+// MBC7 accelerometer reading when level, and how far one g of tilt moves it
+const ACCEL_LEVEL: u16 = 0x81D0;
+const ACCEL_PER_G: f32 = 112.0;
+// what the MBC7 accelerometer reads between erasing and latching
+const ACCEL_ERASED: u16 = 0x8000;
+
+// HuC3 modes, written to 0x0000-0x1FFF, pick what 0xA000-0xBFFF is
+const HUC3_RAM_READ: u8 = 0x0;
+const HUC3_RAM: u8 = 0xA;
+const HUC3_COMMAND: u8 = 0xB;
+const HUC3_RESPONSE: u8 = 0xC;
+const HUC3_SEMAPHORE: u8 = 0xD;
+const HUC3_IR: u8 = 0xE;
+
+// the two 16KB halves of the rom area, the controller picks the bank in each
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RomRegion {
+    // 0x0000-0x3FFF, bank 0 on most controllers
+    Low,
+    // 0x4000-0x7FFF, the switchable bank
+    High,
+}
+
+impl RomRegion {
+    // the region of `addr` in 0x0000-0x7FFF and the address within its bank
+    pub fn split(addr: u16) -> (Self, u16) {
+        let region = if addr < 0x4000 { Self::Low } else { Self::High };
+        (region, addr & 0x3FFF)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Mbc {
+    // 32KB rom, ram if the header has any, nothing to switch
+    None,
+    Mbc1(Mbc1),
+    Mbc3(Mbc3),
+    Mbc5(Mbc5),
+    Mbc7(Mbc7),
+    Huc3(Huc3),
+    WisdomTree(WisdomTree),
+}
+
+impl Mbc {
+    // the controller for a whole rom image, which also finds the ones the cartridge type
+    // doesn't name. None for controllers that aren't emulated
+    pub fn detect(rom: &[u8]) -> Option<Self> {
+        let cartridge_type = rom.get(0x147).copied().unwrap_or(0);
+        // Wisdom Tree games claim to be ROM ONLY, some with the made up type 0xC0
+        let title = rom.get(0x134..0x144).unwrap_or_default();
+        let wisdom_tree = title.windows(6).any(|window| window == b"WISDOM");
+        match cartridge_type {
+            0xC0 => Some(Self::WisdomTree(WisdomTree::new())),
+            0x00 if wisdom_tree && rom.len() > 2 * ROM_BANK_SIZE => {
+                Some(Self::WisdomTree(WisdomTree::new()))
+            }
+            _ => Self::from_header(cartridge_type),
+        }
+    }
+
+    // None for controllers that aren't emulated
+    pub fn from_header(cartridge_type: u8) -> Option<Self> {
+        Some(match cartridge_type {
+            0x00 | 0x08 | 0x09 => Self::None,
+            0x01..=0x03 => Self::Mbc1(Mbc1::new()),
+            0x0F | 0x10 => Self::Mbc3(Mbc3::new(Some(Rtc::new()))),
+            0x11..=0x13 => Self::Mbc3(Mbc3::new(None)),
+            0x19..=0x1B => Self::Mbc5(Mbc5::new(false)),
+            0x1C..=0x1E => Self::Mbc5(Mbc5::new(true)),
+            0x22 => Self::Mbc7(Mbc7::new()),
+            0xFE => Self::Huc3(Huc3::new()),
+            _ => return None,
+        })
+    }
+
+    // a write to 0x0000-0x7FFF
+    pub fn write_byte(&mut self, addr: u16, value: u8) {
+        match self {
+            Self::None => {}
+            Self::Mbc1(mbc) => mbc.write_byte(addr, value),
+            Self::Mbc3(mbc) => mbc.write_byte(addr, value),
+            Self::Mbc5(mbc) => mbc.write_byte(addr, value),
+            Self::Mbc7(mbc) => mbc.write_byte(addr, value),
+            Self::Huc3(mbc) => mbc.write_byte(addr, value),
+            Self::WisdomTree(mbc) => mbc.write_byte(addr),
+        }
+    }
+
+    // rom bank mapped in `region`, before wrapping at the size of the rom
+    pub fn rom_bank(&self, region: RomRegion) -> u16 {
+        match self {
+            Self::None => (region == RomRegion::High) as u16,
+            Self::Mbc1(mbc) => mbc.rom_bank(region),
+            Self::Mbc3(mbc) => mbc.rom_bank(region),
+            Self::Mbc5(mbc) => mbc.rom_bank(region),
+            Self::Mbc7(mbc) => mbc.rom_bank(region),
+            Self::Huc3(mbc) => mbc.rom_bank(region),
+            Self::WisdomTree(mbc) => mbc.rom_bank(region),
+        }
+    }
+
+    // ram bank at 0xA000-0xBFFF, None while the ram is disabled
+    pub fn ram_bank(&self) -> Option<u16> {
+        match self {
+            Self::None => Some(0),
+            Self::Mbc1(mbc) => mbc.ram_bank(),
+            Self::Mbc3(mbc) => mbc.ram_bank(),
+            Self::Mbc5(mbc) => mbc.ram_bank(),
+            // the ram is behind the EEPROM, never mapped directly
+            Self::Mbc7(_) => None,
+            Self::Huc3(mbc) => mbc.ram_bank(),
+            Self::WisdomTree(_) => None,
+        }
+    }
+
+    // ram bank the controller points at, enabled or not. None where a register sits there
+    // instead, like the MBC3 clock or the MBC7 EEPROM
+    pub fn selected_ram_bank(&self) -> Option<u16> {
+        match self {
+            Self::None => Some(0),
+            Self::Mbc1(mbc) => Some(if mbc.mode { mbc.bank2 as u16 } else { 0 }),
+            Self::Mbc3(mbc) => (mbc.ram_bank < 0x08).then_some(mbc.ram_bank as u16),
+            Self::Mbc5(mbc) => Some(mbc.ram_bank as u16),
+            Self::Mbc7(_) => None,
+            Self::Huc3(mbc) => Some(mbc.ram_bank as u16),
+            Self::WisdomTree(_) => None,
+        }
+    }
+
+    // Some when a register is mapped at 0xA000-0xBFFF instead of ram, like the MBC3 clock
+    pub fn read_register(&self, addr: u16) -> Option<u8> {
+        match self {
+            Self::Mbc3(mbc) => mbc.read_clock(),
+            Self::Mbc7(mbc) => mbc.read_register(addr),
+            Self::Huc3(mbc) => mbc.read_register(),
+            _ => None,
+        }
+    }
+
+    // returns false when no register is mapped, the write is for the ram then. `ram` is
+    // the cartridge ram for controllers that reach it through a register
+    pub fn write_register(&mut self, addr: u16, value: u8, ram: &mut [u8]) -> bool {
+        match self {
+            Self::Mbc3(mbc) => mbc.write_clock(value),
+            Self::Mbc7(mbc) => mbc.write_register(addr, value, ram),
+            Self::Huc3(mbc) => mbc.write_register(value),
+            _ => false,
+        }
+    }
+
+    // whether a register write since the last call changed the ram
+    pub fn take_ram_written(&mut self) -> bool {
+        match self {
+            Self::Mbc7(mbc) => mbc.eeprom.take_written(),
+            _ => false,
+        }
+    }
+
+    // tilt of the cartridge in g, x positive to the right and y positive towards the
+    // bottom of the screen. only the MBC7 accelerometer feels it
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        if let Self::Mbc7(mbc) = self {
+            mbc.accel = (accel_reading(x), accel_reading(y));
+        }
+    }
+
+    pub fn rtc(&self) -> Option<&Rtc> {
+        match self {
+            Self::Mbc3(mbc) => mbc.rtc.as_ref(),
+            Self::Huc3(mbc) => Some(&mbc.rtc),
+            _ => None,
+        }
+    }
+
+    pub fn rtc_mut(&mut self) -> Option<&mut Rtc> {
+        match self {
+            Self::Mbc3(mbc) => mbc.rtc.as_mut(),
+            Self::Huc3(mbc) => Some(&mut mbc.rtc),
+            _ => None,
+        }
+    }
+
+    // whether the rumble motor is on, always off on cartridges without one
+    pub fn rumble(&self) -> bool {
+        match self {
+            Self::Mbc5(mbc) => mbc.rumble,
+            _ => false,
+        }
+    }
+
+    // whether the infrared LED is lit, only the HuC3 has one
+    pub fn ir_led(&self) -> bool {
+        match self {
+            Self::Huc3(mbc) => mbc.ir_led,
+            _ => false,
+        }
+    }
+
+    // infrared light from another device reaching the HuC3 sensor
+    pub fn set_ir_light(&mut self, light: bool) {
+        if let Self::Huc3(mbc) = self {
+            mbc.ir_light = light;
+        }
+    }
+}
+
+// up to 2MB rom and 32KB ram. two bank registers, a 5 bit one for the rom bank at 0x4000
+// and a 2 bit one that supplies the upper rom bank bits, or in mode 1 the ram bank and the
+// bank at 0x0000 too
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mbc1 {
+    ram_enabled: bool,
+    // BANK1, never 0
+    bank1: u8,
+    // BANK2
+    bank2: u8,
+    // banking mode, 1 maps BANK2 at 0x0000-0x3FFF and 0xA000-0xBFFF as well
+    mode: bool,
+    // MBC1M multicarts leave the top bit of BANK1 unconnected, BANK2 takes its place
+    #[cfg_attr(feature = "serde", serde(default))]
+    multicart: bool,
+}
+
+impl Mbc1 {
+    pub fn new() -> Self {
+        Self {
+            ram_enabled: false,
+            bank1: 1,
+            bank2: 0,
+            mode: false,
+            multicart: false,
+        }
+    }
+
+    pub fn multicart() -> Self {
+        Self {
+            multicart: true,
+            ..Self::new()
+        }
+    }
+
+    fn write_byte(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == RAM_ENABLE,
+            // only the 5 bits are checked for 0, so 0x20, 0x40 and 0x60 can't be
+            // reached at 0x4000 and map 0x21, 0x41 and 0x61 instead
+            0x2000..=0x3FFF => self.bank1 = (value & 0x1F).max(1),
+            0x4000..=0x5FFF => self.bank2 = value & 0x03,
+            _ => self.mode = value & 0x01 != 0,
+        }
+    }
+
+    fn rom_bank(&self, region: RomRegion) -> u16 {
+        let (shift, bank1) = if self.multicart {
+            (4, self.bank1 & 0x0F)
+        } else {
+            (5, self.bank1)
+        };
+        match region {
+            RomRegion::Low if self.mode => (self.bank2 as u16) << shift,
+            RomRegion::Low => 0,
+            RomRegion::High => (self.bank2 as u16) << shift | bank1 as u16,
+        }
+    }
+
+    fn ram_bank(&self) -> Option<u16> {
+        match (self.ram_enabled, self.mode) {
+            (false, _) => None,
+            (true, false) => Some(0),
+            (true, true) => Some(self.bank2 as u16),
+        }
+    }
+}
+
+impl Default for Mbc1 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// up to 2MB rom and 32KB ram, and on some cartridges a real time clock whose registers
+// are mapped in place of the ram by selecting "banks" 0x08-0x0C
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mbc3 {
+    // enables the clock registers as well
+    ram_enabled: bool,
+    rom_bank: u8,
+    // ram bank, or clock register from 0x08 up
+    ram_bank: u8,
+    // writing 0 and then 1 to 0x6000-0x7FFF latches the clock
+    latch_armed: bool,
+    rtc: Option<Rtc>,
+}
+
+impl Mbc3 {
+    pub fn new(rtc: Option<Rtc>) -> Self {
+        Self {
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            latch_armed: false,
+            rtc,
+        }
+    }
+
+    fn write_byte(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == RAM_ENABLE,
+            0x2000..=0x3FFF => self.rom_bank = (value & 0x7F).max(1),
+            0x4000..=0x5FFF => self.ram_bank = value & 0x0F,
+            _ => {
+                if self.latch_armed && value == 0x01 {
+                    if let Some(rtc) = &mut self.rtc {
+                        rtc.latch();
+                    }
+                }
+                self.latch_armed = value == 0x00;
+            }
+        }
+    }
+
+    fn rom_bank(&self, region: RomRegion) -> u16 {
+        match region {
+            RomRegion::Low => 0,
+            RomRegion::High => self.rom_bank as u16,
+        }
+    }
+
+    fn ram_bank(&self) -> Option<u16> {
+        (self.ram_enabled && self.ram_bank < 0x08).then_some(self.ram_bank as u16)
+    }
+
+    // the selected clock register, reads without a clock chip float
+    fn clock_register(&self) -> Option<u8> {
+        (self.ram_enabled && (0x08..=0x0C).contains(&self.ram_bank)).then_some(self.ram_bank)
+    }
+
+    fn read_clock(&self) -> Option<u8> {
+        let register = self.clock_register()?;
+        self.rtc.as_ref().map(|rtc| rtc.read_byte(register))
+    }
+
+    fn write_clock(&mut self, value: u8) -> bool {
+        let Some(register) = self.clock_register() else {
+            return false;
+        };
+        if let Some(rtc) = &mut self.rtc {
+            rtc.write_byte(register, value);
+        }
+        true
+    }
+}
+
+// up to 8MB rom and 128KB ram, with a 9 bit rom bank number in two registers. unlike
+// MBC1 bank 0 can be mapped at 0x4000. on rumble cartridges bit 3 of the ram bank
+// register drives the motor instead
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mbc5 {
+    ram_enabled: bool,
+    rom_bank: u16,
+    ram_bank: u8,
+    has_rumble: bool,
+    rumble: bool,
+}
+
+impl Mbc5 {
+    pub fn new(has_rumble: bool) -> Self {
+        Self {
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            has_rumble,
+            rumble: false,
+        }
+    }
+
+    fn write_byte(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == RAM_ENABLE,
+            0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | value as u16,
+            0x3000..=0x3FFF => self.rom_bank = (self.rom_bank & 0xFF) | (value as u16 & 1) << 8,
+            0x4000..=0x5FFF if self.has_rumble => {
+                self.ram_bank = value & 0x07;
+                self.rumble = value & RUMBLE_MOTOR != 0;
+            }
+            0x4000..=0x5FFF => self.ram_bank = value & 0x0F,
+            _ => {}
+        }
+    }
+
+    fn rom_bank(&self, region: RomRegion) -> u16 {
+        match region {
+            RomRegion::Low => 0,
+            RomRegion::High => self.rom_bank,
+        }
+    }
+
+    fn ram_bank(&self) -> Option<u16> {
+        self.ram_enabled.then_some(self.ram_bank as u16)
+    }
+}
+
+// up to 2MB rom, a 256 byte EEPROM and a two axis accelerometer in place of the ram, all
+// behind registers at 0xA000-0xAFFF that need two enables: 0x0A at 0x0000 and 0x40 at
+// 0x4000. the game erases the accelerometer latch with 0x55 to 0xA000, latches with 0xAA
+// to 0xA010 and reads X at 0xA020/0xA030 and Y at 0xA040/0xA050
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mbc7 {
+    ram_enabled: bool,
+    registers_enabled: bool,
+    rom_bank: u8,
+    // X and Y as the sensor reads them right now
+    accel: (u16, u16),
+    latched: (u16, u16),
+    // erased and waiting for the latch
+    latch_armed: bool,
+    eeprom: Eeprom,
+}
+
+impl Mbc7 {
+    pub fn new() -> Self {
+        Self {
+            ram_enabled: false,
+            registers_enabled: false,
+            rom_bank: 1,
+            accel: (ACCEL_LEVEL, ACCEL_LEVEL),
+            latched: (ACCEL_ERASED, ACCEL_ERASED),
+            latch_armed: false,
+            eeprom: Eeprom::new(),
+        }
+    }
+
+    fn write_byte(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == RAM_ENABLE,
+            0x2000..=0x3FFF => self.rom_bank = value & 0x7F,
+            0x4000..=0x5FFF => self.registers_enabled = value == 0x40,
+            _ => {}
+        }
+    }
+
+    fn rom_bank(&self, region: RomRegion) -> u16 {
+        match region {
+            RomRegion::Low => 0,
+            RomRegion::High => self.rom_bank as u16,
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.ram_enabled && self.registers_enabled
+    }
+
+    fn read_register(&self, addr: u16) -> Option<u8> {
+        if !self.is_enabled() {
+            return None;
+        }
+        if addr >= 0xB000 {
+            return Some(0xFF);
+        }
+        let [x_low, x_high] = self.latched.0.to_le_bytes();
+        let [y_low, y_high] = self.latched.1.to_le_bytes();
+        Some(match addr >> 4 & 0x0F {
+            0x2 => x_low,
+            0x3 => x_high,
+            0x4 => y_low,
+            0x5 => y_high,
+            // the Z axis, not fitted
+            0x6 => 0x00,
+            0x8 => self.eeprom.read_byte(),
+            _ => 0xFF,
+        })
+    }
+
+    fn write_register(&mut self, addr: u16, value: u8, ram: &mut [u8]) -> bool {
+        if !self.is_enabled() {
+            return false;
+        }
+        match addr >> 4 & 0x0F {
+            0x0 if value == 0x55 => {
+                self.latched = (ACCEL_ERASED, ACCEL_ERASED);
+                self.latch_armed = true;
+            }
+            0x1 if value == 0xAA && self.latch_armed => {
+                self.latched = self.accel;
+                self.latch_armed = false;
+            }
+            0x8 if addr < 0xB000 => self.eeprom.write_byte(value, ram),
+            _ => {}
+        }
+        true
+    }
+}
+
+impl Default for Mbc7 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// up to 2MB rom and 128KB ram, a clock and an infrared port. a mode register picks what
+// 0xA000-0xBFFF is: the ram, the clock's command interface or the IR port. the clock is a
+// microcontroller the game sends 4 bit commands to: it holds 256 nibbles of memory, the
+// game moves the time in and out of the first six with commands 0x60 and 0x61 and reads
+// and writes them one at a time through an address pointer
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Huc3 {
+    mode: u8,
+    rom_bank: u8,
+    ram_bank: u8,
+    rtc: Rtc,
+    // nibbles 0x00-0x02 are the minutes of the day and 0x03-0x05 the days, low first
+    rtc_memory: Vec<u8>,
+    rtc_pointer: u8,
+    // the last command and the nibble it answered with
+    response: u8,
+    ir_led: bool,
+    ir_light: bool,
+}
+
+impl Huc3 {
+    pub fn new() -> Self {
+        Self {
+            mode: HUC3_RAM_READ,
+            rom_bank: 1,
+            ram_bank: 0,
+            rtc: Rtc::new(),
+            rtc_memory: vec![0; 0x100],
+            rtc_pointer: 0,
+            response: 0,
+            ir_led: false,
+            ir_light: false,
+        }
+    }
+
+    fn write_byte(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.mode = value & 0x0F,
+            0x2000..=0x3FFF => self.rom_bank = value & 0x7F,
+            0x4000..=0x5FFF => self.ram_bank = value & 0x03,
+            _ => {}
+        }
+    }
+
+    fn rom_bank(&self, region: RomRegion) -> u16 {
+        match region {
+            RomRegion::Low => 0,
+            RomRegion::High => self.rom_bank as u16,
+        }
+    }
+
+    fn ram_bank(&self) -> Option<u16> {
+        matches!(self.mode, HUC3_RAM_READ | HUC3_RAM).then_some(self.ram_bank as u16)
+    }
+
+    fn read_register(&self) -> Option<u8> {
+        match self.mode {
+            HUC3_RAM_READ | HUC3_RAM => None,
+            HUC3_RESPONSE => Some(0x80 | self.response),
+            // commands run right away, always ready for the next one
+            HUC3_SEMAPHORE => Some(0x01),
+            HUC3_IR => Some(0xC0 | self.ir_light as u8),
+            _ => Some(0xFF),
+        }
+    }
+
+    // the ram is read only in mode 0, so every write outside mode 0xA stops here
+    fn write_register(&mut self, value: u8) -> bool {
+        match self.mode {
+            HUC3_RAM => return false,
+            HUC3_COMMAND => self.command(value >> 4 & 0x07, value & 0x0F),
+            HUC3_IR => self.ir_led = value & 0x01 != 0,
+            _ => {}
+        }
+        true
+    }
+
+    fn command(&mut self, command: u8, argument: u8) {
+        match command {
+            // read a nibble and step the pointer
+            0x1 => {
+                self.response = command << 4 | self.rtc_memory[self.rtc_pointer as usize];
+                self.rtc_pointer = self.rtc_pointer.wrapping_add(1);
+            }
+            // write a nibble and step the pointer
+            0x3 => {
+                self.rtc_memory[self.rtc_pointer as usize] = argument;
+                self.rtc_pointer = self.rtc_pointer.wrapping_add(1);
+            }
+            0x4 => self.rtc_pointer = self.rtc_pointer & 0xF0 | argument,
+            0x5 => self.rtc_pointer = self.rtc_pointer & 0x0F | argument << 4,
+            0x6 => match argument {
+                // copy the time into the memory
+                0x0 => {
+                    self.store_nibbles(0, self.rtc.minutes());
+                    self.store_nibbles(3, self.rtc.days());
+                }
+                // set the clock from the memory
+                0x1 => {
+                    let minutes = self.load_nibbles(0);
+                    let days = self.load_nibbles(3);
+                    self.rtc.set_time(minutes, days);
+                }
+                // status, the clock is running
+                0x2 => self.response = command << 4 | 0x1,
+                // 0xE plays a tone through the cartridge speaker
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    fn store_nibbles(&mut self, start: usize, value: u16) {
+        for i in 0..3 {
+            self.rtc_memory[start + i] = (value >> (i * 4)) as u8 & 0x0F;
+        }
+    }
+
+    fn load_nibbles(&self, start: usize) -> u16 {
+        (0..3).fold(0, |value, i| {
+            value | (self.rtc_memory[start + i] as u16) << (i * 4)
+        })
+    }
+}
+
+impl Default for Huc3 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// unlicensed Wisdom Tree cartridges: a write to 0x0000-0x3FFF switches the whole 32KB
+// rom area to the bank in the low byte of the address, the value doesn't matter
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WisdomTree {
+    bank: u8,
+}
+
+impl WisdomTree {
+    pub fn new() -> Self {
+        Self { bank: 0 }
+    }
+
+    fn write_byte(&mut self, addr: u16) {
+        if addr < 0x4000 {
+            self.bank = addr as u8;
+        }
+    }
+
+    fn rom_bank(&self, region: RomRegion) -> u16 {
+        self.bank as u16 * 2 + (region == RomRegion::High) as u16
+    }
+}
+
+impl Default for WisdomTree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// tilts past 2g are clamped, as far as any game asks for
+fn accel_reading(tilt: f32) -> u16 {
+    (ACCEL_LEVEL as f32 + tilt.clamp(-2.0, 2.0) * ACCEL_PER_G) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mbc1_rom_banks() {
+        let mut mbc = Mbc::from_header(0x01).unwrap();
+        assert_eq!(0, mbc.rom_bank(RomRegion::Low));
+        assert_eq!(1, mbc.rom_bank(RomRegion::High));
+        mbc.write_byte(0x2000, 0x05);
+        assert_eq!(5, mbc.rom_bank(RomRegion::High));
+        // bank 0 can't be selected at 0x4000
+        mbc.write_byte(0x3FFF, 0x00);
+        assert_eq!(1, mbc.rom_bank(RomRegion::High));
+        mbc.write_byte(0x4000, 0x01);
+        assert_eq!(0x21, mbc.rom_bank(RomRegion::High));
+        assert_eq!(0, mbc.rom_bank(RomRegion::Low));
+        // mode 1 moves BANK2 into the lower half too
+        mbc.write_byte(0x6000, 0x01);
+        assert_eq!(0x20, mbc.rom_bank(RomRegion::Low));
+        assert_eq!(0x21, mbc.rom_bank(RomRegion::High));
+    }
+
+    #[test]
+    fn test_mbc1_multicart_banks() {
+        let mut mbc = Mbc::Mbc1(Mbc1::multicart());
+        // the fifth bit still counts for the zero check
+        mbc.write_byte(0x2000, 0x10);
+        assert_eq!(0x00, mbc.rom_bank(RomRegion::High));
+        mbc.write_byte(0x2000, 0x13);
+        mbc.write_byte(0x4000, 0x02);
+        assert_eq!(0x23, mbc.rom_bank(RomRegion::High));
+        mbc.write_byte(0x6000, 0x01);
+        assert_eq!(0x20, mbc.rom_bank(RomRegion::Low));
+    }
+
+    #[test]
+    fn test_mbc1_ram_enable_and_banks() {
+        let mut mbc = Mbc::from_header(0x03).unwrap();
+        assert_eq!(None, mbc.ram_bank());
+        mbc.write_byte(0x0000, 0x0A);
+        assert_eq!(Some(0), mbc.ram_bank());
+        mbc.write_byte(0x4000, 0x02);
+        assert_eq!(Some(0), mbc.ram_bank());
+        mbc.write_byte(0x6000, 0x01);
+        assert_eq!(Some(2), mbc.ram_bank());
+        // only the low nibble counts
+        mbc.write_byte(0x1000, 0xFA);
+        assert_eq!(Some(2), mbc.ram_bank());
+        mbc.write_byte(0x1000, 0x00);
+        assert_eq!(None, mbc.ram_bank());
+        assert_eq!(Some(0), Mbc::from_header(0x00).unwrap().ram_bank());
+        // MBC2 isn't emulated
+        assert_eq!(None, Mbc::from_header(0x05));
+    }
+
+    #[test]
+    fn test_mbc3_maps_clock_registers() {
+        let mut mbc = Mbc::from_header(0x10).unwrap();
+        mbc.write_byte(0x2000, 0x00);
+        assert_eq!(1, mbc.rom_bank(RomRegion::High));
+        mbc.write_byte(0x2000, 0xFF);
+        assert_eq!(0x7F, mbc.rom_bank(RomRegion::High));
+
+        mbc.write_byte(0x0000, 0x0A);
+        mbc.write_byte(0x4000, 0x03);
+        assert_eq!(Some(3), mbc.ram_bank());
+        assert_eq!(None, mbc.read_register(0xA000));
+        // the minutes instead of ram
+        mbc.write_byte(0x4000, 0x09);
+        assert_eq!(None, mbc.ram_bank());
+        assert!(mbc.write_register(0xA000, 42, &mut []));
+        assert_eq!(Some(0xC0), mbc.read_register(0xA000));
+        mbc.write_byte(0x6000, 0x00);
+        mbc.write_byte(0x6000, 0x01);
+        assert_eq!(Some(0xC0 | 42), mbc.read_register(0xA000));
+        assert!(Mbc::from_header(0x13).unwrap().rtc().is_none());
+    }
+
+    #[test]
+    fn test_mbc5_banks_and_rumble() {
+        let mut mbc = Mbc::from_header(0x1B).unwrap();
+        mbc.write_byte(0x2000, 0x00);
+        assert_eq!(0, mbc.rom_bank(RomRegion::High));
+        mbc.write_byte(0x3000, 0x01);
+        mbc.write_byte(0x2000, 0x23);
+        assert_eq!(0x123, mbc.rom_bank(RomRegion::High));
+        assert_eq!(0, mbc.rom_bank(RomRegion::Low));
+        mbc.write_byte(0x0000, 0x0A);
+        mbc.write_byte(0x4000, 0x0F);
+        assert_eq!(Some(0x0F), mbc.ram_bank());
+        assert!(!mbc.rumble());
+
+        // the motor takes bit 3 on rumble cartridges
+        let mut mbc = Mbc::from_header(0x1E).unwrap();
+        mbc.write_byte(0x0000, 0x0A);
+        mbc.write_byte(0x4000, 0x0B);
+        assert_eq!(Some(0x03), mbc.ram_bank());
+        assert!(mbc.rumble());
+        mbc.write_byte(0x4000, 0x03);
+        assert!(!mbc.rumble());
+    }
+
+    #[test]
+    fn test_mbc7_latches_the_accelerometer() {
+        let mut mbc = Mbc::from_header(0x22).unwrap();
+        let mut ram = [0; 256];
+        mbc.write_byte(0x2000, 0x85);
+        assert_eq!(0x05, mbc.rom_bank(RomRegion::High));
+        // both enables are needed
+        mbc.write_byte(0x0000, 0x0A);
+        assert_eq!(None, mbc.read_register(0xA020));
+        assert!(!mbc.write_register(0xA000, 0x55, &mut ram));
+        mbc.write_byte(0x4000, 0x40);
+        assert_eq!(None, mbc.ram_bank());
+
+        mbc.set_tilt(1.0, -0.5);
+        assert!(mbc.write_register(0xA000, 0x55, &mut ram));
+        assert_eq!(Some(0x00), mbc.read_register(0xA020));
+        assert_eq!(Some(0x80), mbc.read_register(0xA030));
+        mbc.write_register(0xA010, 0xAA, &mut ram);
+        assert_eq!(Some(0x40), mbc.read_register(0xA020));
+        assert_eq!(Some(0x82), mbc.read_register(0xA030));
+        assert_eq!(Some(0x98), mbc.read_register(0xA040));
+        assert_eq!(Some(0x81), mbc.read_register(0xA050));
+        // a second latch needs another erase
+        mbc.set_tilt(0.0, 0.0);
+        mbc.write_register(0xA010, 0xAA, &mut ram);
+        assert_eq!(Some(0x40), mbc.read_register(0xA020));
+        assert_eq!(Some(0xFF), mbc.read_register(0xB000));
+        assert!(!mbc.take_ram_written());
+    }
+
+    #[test]
+    fn test_huc3_clock_commands() {
+        let mut mbc = Mbc::from_header(0xFE).unwrap();
+        mbc.write_byte(0x2000, 0x00);
+        assert_eq!(0, mbc.rom_bank(RomRegion::High));
+        mbc.write_byte(0x4000, 0x02);
+        // ram reads in mode 0, writes need mode 0xA
+        assert_eq!(Some(2), mbc.ram_bank());
+        assert!(mbc.write_register(0xA000, 0x12, &mut []));
+        mbc.write_byte(0x0000, 0x0A);
+        assert!(!mbc.write_register(0xA000, 0x12, &mut []));
+
+        // 2 days and 1:30 into the pointer at 0, then set the clock from it
+        mbc.write_byte(0x0000, 0x0B);
+        for command in [0x40, 0x50, 0x3A, 0x35, 0x30, 0x32, 0x30, 0x30, 0x61] {
+            mbc.write_register(0xA000, command, &mut []);
+        }
+        assert_eq!(90, mbc.rtc().unwrap().minutes());
+        mbc.rtc_mut().unwrap().tick(4_194_304 * 60);
+
+        // latch the time and read the minutes back
+        for command in [0x60, 0x40, 0x10, 0x10] {
+            mbc.write_register(0xA000, command, &mut []);
+        }
+        mbc.write_byte(0x0000, 0x0C);
+        assert_eq!(Some(0x90 | 0x05), mbc.read_register(0xA000));
+        mbc.write_byte(0x0000, 0x0D);
+        assert_eq!(Some(0x01), mbc.read_register(0xA000));
+
+        mbc.write_byte(0x0000, 0x0E);
+        mbc.set_ir_light(true);
+        assert_eq!(Some(0xC1), mbc.read_register(0xA000));
+        mbc.write_register(0xA000, 0x01, &mut []);
+        assert!(mbc.ir_led());
+    }
+
+    #[test]
+    fn test_wisdom_tree_switches_32kb() {
+        let mut rom = vec![0; 4 * ROM_BANK_SIZE];
+        rom[0x134..0x140].copy_from_slice(b"WISDOM TREE ");
+        let mut mbc = Mbc::detect(&rom).unwrap();
+        assert_eq!(1, mbc.rom_bank(RomRegion::High));
+        // the address picks the bank, writes above 0x3FFF do nothing
+        mbc.write_byte(0x0001, 0xFF);
+        mbc.write_byte(0x4002, 0x00);
+        assert_eq!(2, mbc.rom_bank(RomRegion::Low));
+        assert_eq!(3, mbc.rom_bank(RomRegion::High));
+        assert_eq!(None, mbc.ram_bank());
+        // a 32KB rom with the same title is plain ROM ONLY
+        assert_eq!(Some(Mbc::None), Mbc::detect(&rom[..2 * ROM_BANK_SIZE]));
+        assert_eq!(None, Mbc::detect(&[0x05; 0x150]));
+    }
+}
@@ -0,0 +1,108 @@
+// what the cpu needs from the memory it runs against
+// Bus is the full machine, FlatRam is a plain 64KB array for tests and embedding the core
+
+use crate::interrupt::Interrupt;
+
+pub trait Memory {
+    fn read(&mut self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, value: u8);
+    // what a read of `addr` would return, without any of its effects. for tracing and
+    // debugging, which must not change what they look at
+    fn peek(&self, addr: u16) -> u8;
+
+    fn read_word(&mut self, addr: u16) -> u16 {
+        (self.read(addr) as u16) | ((self.read(addr.wrapping_add(1)) as u16) << 8)
+    }
+
+    fn write_word(&mut self, addr: u16, value: u16) {
+        self.write(addr, (value & 0xFF) as u8);
+        self.write(addr.wrapping_add(1), (value >> 8) as u8);
+    }
+
+    // called with the address of every instruction before it is fetched
+    fn begin_instruction(&mut self, _pc: u16) {}
+
+    // advance any hardware behind the memory by the machine cycles of the last instruction
+    fn tick(&mut self, _m_cycles: u8) {}
+
+    // the STOP instruction, a CGB switches the cpu speed here when KEY1 asked for it
+    fn stop(&mut self) {}
+
+    // interrupts that are both requested and enabled, as IF/IE bits
+    fn pending_interrupts(&self) -> u8 {
+        0
+    }
+
+    // acknowledge an interrupt the cpu is about to service
+    fn clear_interrupt(&mut self, _interrupt: Interrupt) {}
+
+    // rom bank mapped at `addr`, for tracing and debugging
+    fn rom_bank_at(&self, _addr: u16) -> u16 {
+        0
+    }
+}
+
+// flat 64KB of ram with no hardware behind it
+pub struct FlatRam {
+    pub bytes: Vec<u8>,
+}
+
+impl FlatRam {
+    pub fn new() -> Self {
+        Self {
+            bytes: vec![0; 0x10000],
+        }
+    }
+}
+
+impl Default for FlatRam {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Memory for FlatRam {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.bytes[addr as usize]
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.bytes[addr as usize] = value;
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.bytes[addr as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Cpu;
+
+    #[test]
+    fn test_cpu_runs_against_flat_ram() {
+        let mut ram = FlatRam::new();
+        // LD A,0x12; LD (0xFF0F),A; PUSH AF
+        ram.bytes[0x0100..0x0106].copy_from_slice(&[0x3E, 0x12, 0xEA, 0x0F, 0xFF, 0xF5]);
+        let mut cpu = Cpu::with_memory(ram);
+        for _ in 0..3 {
+            cpu.run_cycle();
+        }
+
+        assert_eq!(0x0106, cpu.pc());
+        // no I/O registers behind flat ram, every address is plain storage
+        assert_eq!(0x12, cpu.bus.bytes[0xFF0F]);
+        assert_eq!(0x12, cpu.bus.bytes[0xFFFD]);
+        assert_eq!(0xB0, cpu.bus.bytes[0xFFFC]);
+    }
+
+    #[test]
+    fn test_word_access_wraps_around() {
+        let mut ram = FlatRam::new();
+        ram.write_word(0xFFFF, 0xBEEF);
+        assert_eq!(0xEF, ram.bytes[0xFFFF]);
+        assert_eq!(0xBE, ram.bytes[0x0000]);
+        assert_eq!(0xBEEF, ram.read_word(0xFFFF));
+    }
+}
@@ -0,0 +1,62 @@
+// hardware revision and market being emulated
+// behaviour that differs between revisions branches on this instead of on the cartridge
+
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Model {
+    // original gameboy
+    #[default]
+    Dmg,
+    // gameboy color
+    Cgb,
+}
+
+impl FromStr for Model {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "dmg" => Ok(Self::Dmg),
+            "cgb" => Ok(Self::Cgb),
+            _ => Err(format!("unknown model: {}", s)),
+        }
+    }
+}
+
+// destination code from the cartridge header (0x014A), some games pick their
+// language or behave differently based on it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    Japan,
+    Overseas,
+}
+
+impl Region {
+    pub fn from_header(code: u8) -> Self {
+        match code {
+            0x00 => Self::Japan,
+            _ => Self::Overseas,
+        }
+    }
+
+    pub fn header_code(&self) -> u8 {
+        match self {
+            Self::Japan => 0x00,
+            Self::Overseas => 0x01,
+        }
+    }
+}
+
+impl FromStr for Region {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "jp" | "japan" => Ok(Self::Japan),
+            "overseas" | "world" => Ok(Self::Overseas),
+            _ => Err(format!("unknown region: {}", s)),
+        }
+    }
+}
@@ -0,0 +1,189 @@
+// input movies
+// the joypad state of every frame, recorded from a starting point and played back into the
+// joypad instead of the player's input. the core has no randomness of its own, the same
+// start and the same input give the same run, down to the frame
+// the start is a savestate when it was recorded with the serde feature, otherwise power on.
+// the file is text:
+//   rustyboy movie
+//   rom 1A2B            global checksum of the rom it was recorded on
+//   state {...}         the savestate it starts from, left out for power on
+//   frames
+//   120 -               120 frames without any button
+//   3 a+start           3 frames holding A and Start
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::input::{Action, Button};
+
+const MAGIC: &str = "rustyboy movie";
+
+// bit order of the per-frame masks
+const BUTTONS: [Button; 8] = [
+    Button::Right,
+    Button::Left,
+    Button::Up,
+    Button::Down,
+    Button::A,
+    Button::B,
+    Button::Select,
+    Button::Start,
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_checksum: u16,
+    // savestate the first frame starts from, None for power on
+    pub start_state: Option<String>,
+    frames: Vec<u8>,
+}
+
+impl Movie {
+    pub fn new(rom_checksum: u16, start_state: Option<String>) -> Self {
+        Self {
+            rom_checksum,
+            start_state,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    // adds a frame holding `buttons`
+    pub fn record(&mut self, buttons: &[Button]) {
+        let mask = BUTTONS
+            .iter()
+            .enumerate()
+            .filter(|(_, button)| buttons.contains(button))
+            .fold(0, |mask, (i, _)| mask | 1 << i);
+        self.frames.push(mask);
+    }
+
+    // the buttons held in frame `index`, None past the end
+    pub fn frame(&self, index: usize) -> Option<Vec<Button>> {
+        self.frames.get(index).map(|&mask| buttons(mask))
+    }
+}
+
+fn buttons(mask: u8) -> Vec<Button> {
+    BUTTONS
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| mask >> i & 1 != 0)
+        .map(|(_, &button)| button)
+        .collect()
+}
+
+fn parse_buttons(s: &str) -> Result<u8, String> {
+    if s == "-" {
+        return Ok(0);
+    }
+    s.split('+').try_fold(0, |mask, name| match name.parse()? {
+        Action::Joypad(button) => {
+            let i = BUTTONS.iter().position(|&b| b == button).unwrap_or(0);
+            Ok(mask | 1 << i)
+        }
+        _ => Err(format!("not a button: {}", name)),
+    })
+}
+
+impl fmt::Display for Movie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", MAGIC)?;
+        writeln!(f, "rom {:04X}", self.rom_checksum)?;
+        if let Some(state) = &self.start_state {
+            writeln!(f, "state {}", state)?;
+        }
+        writeln!(f, "frames")?;
+        // runs of the same input on one line
+        for run in self.frames.chunk_by(|a, b| a == b) {
+            let names: Vec<String> = buttons(run[0])
+                .iter()
+                .map(|&button| Action::Joypad(button).to_string())
+                .collect();
+            if names.is_empty() {
+                writeln!(f, "{} -", run.len())?;
+            } else {
+                writeln!(f, "{} {}", run.len(), names.join("+"))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Movie {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.lines().enumerate();
+        if lines.next().map(|(_, line)| line.trim()) != Some(MAGIC) {
+            return Err("not a rustyboy movie".to_string());
+        }
+        let mut rom_checksum = None;
+        let mut start_state = None;
+        let mut frames = Vec::new();
+        let mut in_frames = false;
+        for (number, line) in lines {
+            let line = line.trim();
+            let invalid = || format!("invalid movie line {}: {}", number + 1, line);
+            if line.is_empty() {
+                continue;
+            }
+            if in_frames {
+                let (count, names) = line.split_once(' ').ok_or_else(invalid)?;
+                let count: usize = count.parse().map_err(|_| invalid())?;
+                let mask =
+                    parse_buttons(names.trim()).map_err(|e| format!("{}: {}", invalid(), e))?;
+                frames.extend(std::iter::repeat_n(mask, count));
+                continue;
+            }
+            match line.split_once(' ') {
+                Some(("rom", checksum)) => {
+                    rom_checksum = Some(u16::from_str_radix(checksum, 16).map_err(|_| invalid())?)
+                }
+                Some(("state", state)) => start_state = Some(state.to_string()),
+                None if line == "frames" => in_frames = true,
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(Self {
+            rom_checksum: rom_checksum.ok_or("movie without a rom checksum")?,
+            start_state,
+            frames,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_movie_text_round_trip() {
+        let mut movie = Movie::new(0xBEEF, Some("{\"state\":1}".to_string()));
+        movie.record(&[]);
+        movie.record(&[]);
+        movie.record(&[Button::Start, Button::A]);
+        movie.record(&[Button::Right]);
+        let text = movie.to_string();
+        assert_eq!(
+            "rustyboy movie\nrom BEEF\nstate {\"state\":1}\nframes\n2 -\n1 a+start\n1 right\n",
+            text
+        );
+        assert_eq!(Ok(movie.clone()), text.parse());
+        assert_eq!(Some(vec![Button::A, Button::Start]), movie.frame(2));
+        assert_eq!(None, movie.frame(4));
+
+        assert!("rom BEEF\nframes".parse::<Movie>().is_err());
+        assert!("rustyboy movie\nframes\n1 -".parse::<Movie>().is_err());
+        assert!("rustyboy movie\nrom 1\nframes\n1 quit"
+            .parse::<Movie>()
+            .is_err());
+    }
+}
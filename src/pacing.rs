@@ -0,0 +1,125 @@
+// frontend frame pacing
+// the gameboy draws ~59.73 frames per second, which never lines up with a host display,
+// so on every host vsync we work out how many emulated frames are due and what to show
+
+use crate::pixel::blend;
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+// 4194304 Hz / 70224 dots per frame
+pub const GAMEBOY_FRAME_RATE: f64 = 4_194_304.0 / 70_224.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PresentMode {
+    // show the newest emulated frame again until the next one is ready
+    Duplicate,
+    // mix the last two emulated frames by how far the vsync falls between them
+    Blend,
+}
+
+pub struct FramePacer {
+    refresh_rate: f64,
+    mode: PresentMode,
+    // emulated time since the newest frame, in frames
+    phase: f64,
+    previous: Vec<u32>,
+    current: Vec<u32>,
+    output: Vec<u32>,
+}
+
+impl FramePacer {
+    pub fn new(refresh_rate: f64, mode: PresentMode) -> Self {
+        Self {
+            refresh_rate,
+            mode,
+            phase: 0.0,
+            previous: vec![0xFFFFFFFF; SCREEN_WIDTH * SCREEN_HEIGHT],
+            current: vec![0xFFFFFFFF; SCREEN_WIDTH * SCREEN_HEIGHT],
+            output: vec![0xFFFFFFFF; SCREEN_WIDTH * SCREEN_HEIGHT],
+        }
+    }
+
+    pub fn refresh_rate(&self) -> f64 {
+        self.refresh_rate
+    }
+
+    // called once per host vsync, returns how many emulated frames to run before presenting
+    pub fn frames_due(&mut self) -> u32 {
+        self.phase += GAMEBOY_FRAME_RATE / self.refresh_rate;
+        let due = self.phase.floor();
+        self.phase -= due;
+        due as u32
+    }
+
+    // frame-ready callback, hand over every completed emulated frame
+    pub fn frame_ready(&mut self, frame: &[u32]) {
+        std::mem::swap(&mut self.previous, &mut self.current);
+        self.current.copy_from_slice(frame);
+    }
+
+    // the image to show at this vsync
+    pub fn present(&mut self) -> &[u32] {
+        match self.mode {
+            PresentMode::Duplicate => &self.current,
+            PresentMode::Blend => {
+                // phase is how far we are past `current` towards the frame after it,
+                // show the previous frame for the part of the vsync it still covers
+                let weight = 1.0 - self.phase;
+                for ((out, &prev), &cur) in self
+                    .output
+                    .iter_mut()
+                    .zip(&self.previous)
+                    .zip(&self.current)
+                {
+                    *out = blend(prev, cur, weight);
+                }
+                &self.output
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_due_matches_emulated_rate() {
+        for refresh_rate in [60.0, 120.0, 144.0, 165.0] {
+            let mut pacer = FramePacer::new(refresh_rate, PresentMode::Duplicate);
+            // one second of vsyncs
+            let frames: u32 = (0..refresh_rate as u32).map(|_| pacer.frames_due()).sum();
+            assert_eq!(
+                GAMEBOY_FRAME_RATE.floor() as u32,
+                frames,
+                "{refresh_rate} Hz"
+            );
+        }
+    }
+
+    #[test]
+    fn test_high_refresh_rate_never_runs_more_than_one_frame() {
+        let mut pacer = FramePacer::new(144.0, PresentMode::Duplicate);
+        assert!((0..1000).all(|_| pacer.frames_due() <= 1));
+    }
+
+    #[test]
+    fn test_duplicate_shows_newest_frame() {
+        let mut pacer = FramePacer::new(120.0, PresentMode::Duplicate);
+        pacer.frame_ready(&vec![0xFF000000; SCREEN_WIDTH * SCREEN_HEIGHT]);
+        assert_eq!(0xFF000000, pacer.present()[0]);
+    }
+
+    #[test]
+    fn test_blend_mixes_last_two_frames() {
+        assert_eq!(0xFF808080, blend(0xFF000000, 0xFFFFFFFF, 0.5));
+        assert_eq!(0xFF000000, blend(0xFF000000, 0xFFFFFFFF, 0.0));
+
+        let mut pacer = FramePacer::new(120.0, PresentMode::Blend);
+        pacer.frame_ready(&vec![0xFF000000; SCREEN_WIDTH * SCREEN_HEIGHT]);
+        // right on the new frame it is shown unmixed
+        assert_eq!(0xFF000000, pacer.present()[0]);
+        pacer.frames_due();
+        // halfway to the next frame part of the white initial frame still shows through
+        assert_ne!(0xFF000000, pacer.present()[0]);
+    }
+}
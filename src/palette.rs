@@ -0,0 +1,113 @@
+// DMG screen colors
+// the DMG has four shades, these are the colors the frontend shows for them.
+// a palette is one of the presets or four custom colors, e.g. from the command line
+
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DmgPalette {
+    // lightest shade first, 0xAARRGGBB like the frame buffer
+    pub colors: [u32; 4],
+}
+
+impl DmgPalette {
+    pub const GRAYSCALE: Self = Self {
+        colors: [0xFFFFFFFF, 0xFFAAAAAA, 0xFF555555, 0xFF000000],
+    };
+    pub const CLASSIC_GREEN: Self = Self {
+        colors: [0xFF9BBC0F, 0xFF8BAC0F, 0xFF306230, 0xFF0F380F],
+    };
+    pub const POCKET: Self = Self {
+        colors: [0xFFC4CFA1, 0xFF8B956D, 0xFF4D533C, 0xFF1F1F1F],
+    };
+    pub const HIGH_CONTRAST: Self = Self {
+        colors: [0xFFFFFFFF, 0xFFC0C0C0, 0xFF404040, 0xFF000000],
+    };
+
+    // names accepted by from_str, in the order cycling goes through them
+    pub const PRESETS: [(&'static str, Self); 4] = [
+        ("gray", Self::GRAYSCALE),
+        ("green", Self::CLASSIC_GREEN),
+        ("pocket", Self::POCKET),
+        ("high-contrast", Self::HIGH_CONTRAST),
+    ];
+
+    // color of shade 0-3 (0 is the lightest)
+    pub fn color(&self, shade: u8) -> u32 {
+        self.colors[shade as usize & 0x3]
+    }
+
+    // the preset after this one, custom palettes go back to the first preset
+    pub fn next_preset(&self) -> Self {
+        let next = Self::PRESETS
+            .iter()
+            .position(|(_, preset)| preset == self)
+            .map_or(0, |i| (i + 1) % Self::PRESETS.len());
+        Self::PRESETS[next].1
+    }
+}
+
+impl Default for DmgPalette {
+    fn default() -> Self {
+        Self::GRAYSCALE
+    }
+}
+
+// a preset name or four RRGGBB hex colors, lightest first: "E0F8D0,88C070,346856,081820"
+impl FromStr for DmgPalette {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some((_, preset)) = Self::PRESETS.iter().find(|(name, _)| *name == s) {
+            return Ok(*preset);
+        }
+
+        let parsed: Vec<u32> = s
+            .split(',')
+            .map(|color| {
+                let color = color.trim().trim_start_matches('#');
+                match u32::from_str_radix(color, 16) {
+                    Ok(rgb) if color.len() == 6 => Ok(0xFF000000 | rgb),
+                    _ => Err(format!("invalid palette: {}", s)),
+                }
+            })
+            .collect::<Result<_, _>>()?;
+        let colors = parsed
+            .try_into()
+            .map_err(|_| format!("a palette needs 4 colors: {}", s))?;
+        Ok(Self { colors })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_presets_and_custom_colors() {
+        assert_eq!(Ok(DmgPalette::POCKET), "pocket".parse());
+        assert_eq!(
+            Ok(DmgPalette {
+                colors: [0xFFE0F8D0, 0xFF88C070, 0xFF346856, 0xFF081820]
+            }),
+            "E0F8D0, #88C070,346856,081820".parse()
+        );
+        assert!("sepia".parse::<DmgPalette>().is_err());
+        assert!("FFFFFF,000000".parse::<DmgPalette>().is_err());
+        assert!("FFFFFF,AAAAAA,555555,00000".parse::<DmgPalette>().is_err());
+    }
+
+    #[test]
+    fn test_cycling_through_presets() {
+        let mut palette = DmgPalette::default();
+        for (_, preset) in DmgPalette::PRESETS.iter().skip(1) {
+            palette = palette.next_preset();
+            assert_eq!(*preset, palette);
+        }
+        assert_eq!(DmgPalette::GRAYSCALE, palette.next_preset());
+
+        let custom: DmgPalette = "010101,020202,030303,040404".parse().unwrap();
+        assert_eq!(DmgPalette::GRAYSCALE, custom.next_preset());
+    }
+}
@@ -0,0 +1,114 @@
+// frame buffer pixel formats
+// the ppu works in 0xAARRGGBB and converts every pixel as it is drawn, so embedders can
+// hand the frame straight to a texture or display. the frame stays a buffer of u32, the
+// byte formats are laid out in memory order and RGB565 uses the low 16 bits
+
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PixelFormat {
+    // 0xAARRGGBB, what the window frontend and screenshots use
+    #[default]
+    Argb32,
+    // bytes R, G, B, A in memory, e.g. a wasm canvas ImageData
+    Rgba8,
+    // bytes B, G, R, A in memory, e.g. an SDL ARGB8888 texture on little endian
+    Bgra8,
+    // 0bRRRRRGGGGGGBBBBB in the low 16 bits, for small LCDs
+    Rgb565,
+}
+
+impl PixelFormat {
+    // `argb` is 0xAARRGGBB
+    pub fn convert(self, argb: u32) -> u32 {
+        let [a, r, g, b] = argb.to_be_bytes();
+        match self {
+            Self::Argb32 => argb,
+            Self::Rgba8 => u32::from_ne_bytes([r, g, b, a]),
+            Self::Bgra8 => u32::from_ne_bytes([b, g, r, a]),
+            Self::Rgb565 => ((r as u32 >> 3) << 11) | ((g as u32 >> 2) << 5) | (b as u32 >> 3),
+        }
+    }
+
+    // back to 0xAARRGGBB, RGB565 loses the low bits of each channel
+    pub fn to_argb(self, pixel: u32) -> u32 {
+        let [r, g, b, a] = match self {
+            Self::Argb32 => return pixel,
+            Self::Rgba8 => pixel.to_ne_bytes(),
+            Self::Bgra8 => {
+                let [b, g, r, a] = pixel.to_ne_bytes();
+                [r, g, b, a]
+            }
+            Self::Rgb565 => {
+                let channel = |value: u32, bits: u32| {
+                    let value = value & ((1 << bits) - 1);
+                    (value << (8 - bits) | value >> (2 * bits - 8)) as u8
+                };
+                [
+                    channel(pixel >> 11, 5),
+                    channel(pixel >> 5, 6),
+                    channel(pixel, 5),
+                    0xFF,
+                ]
+            }
+        };
+        u32::from_be_bytes([a, r, g, b])
+    }
+}
+
+// per channel mix of two 0xAARRGGBB pixels, `weight` is the share of `b`
+pub fn blend(a: u32, b: u32, weight: f64) -> u32 {
+    let mut result = 0;
+    for shift in [0, 8, 16, 24] {
+        let ca = ((a >> shift) & 0xFF) as f64;
+        let cb = ((b >> shift) & 0xFF) as f64;
+        let mixed = (ca + (cb - ca) * weight).round() as u32;
+        result |= mixed << shift;
+    }
+    result
+}
+
+impl FromStr for PixelFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "argb" | "argb32" => Ok(Self::Argb32),
+            "rgba" | "rgba8" => Ok(Self::Rgba8),
+            "bgra" | "bgra8" => Ok(Self::Bgra8),
+            "rgb565" => Ok(Self::Rgb565),
+            _ => Err(format!("unknown pixel format: {}", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert_and_back() {
+        let color = 0xFF336699;
+        assert_eq!(
+            [0x33, 0x66, 0x99, 0xFF],
+            PixelFormat::Rgba8.convert(color).to_ne_bytes()
+        );
+        assert_eq!(
+            [0x99, 0x66, 0x33, 0xFF],
+            PixelFormat::Bgra8.convert(color).to_ne_bytes()
+        );
+        assert_eq!(0x3333, PixelFormat::Rgb565.convert(color));
+        assert_eq!(0xFFFF, PixelFormat::Rgb565.convert(0xFFFFFFFF));
+
+        for format in [PixelFormat::Argb32, PixelFormat::Rgba8, PixelFormat::Bgra8] {
+            assert_eq!(color, format.to_argb(format.convert(color)), "{format:?}");
+        }
+        for color in [0xFFFFFFFF, 0xFF000000, 0xFFFF0000] {
+            let format = PixelFormat::Rgb565;
+            assert_eq!(color, format.to_argb(format.convert(color)));
+        }
+        assert_eq!(Ok(PixelFormat::Rgb565), "RGB565".parse());
+        assert!("yuv".parse::<PixelFormat>().is_err());
+    }
+}
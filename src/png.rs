@@ -0,0 +1,532 @@
+// minimal PNG encoder for screenshots
+// 8 bit RGB, no filtering and uncompressed (stored) deflate blocks. the files are bigger
+// than they need to be, but it is enough for a 160x144 screen and needs no dependencies
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+const COLOR_TYPE_RGB: u8 = 2;
+// most a stored deflate block can hold
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+// `pixels` are 0xAARRGGBB like the frame buffer, alpha is dropped. every pixel becomes a
+// `scale` x `scale` square
+pub fn encode(width: usize, height: usize, pixels: &[u32], scale: usize) -> Vec<u8> {
+    assert_eq!(
+        width * height,
+        pixels.len(),
+        "pixel count does not match size"
+    );
+    let scale = scale.max(1);
+    let (out_width, out_height) = (width * scale, height * scale);
+
+    // every scanline starts with its filter type, 0 for none
+    let mut raw = Vec::with_capacity(out_height * (1 + out_width * 3));
+    for y in 0..out_height {
+        raw.push(0);
+        for x in 0..out_width {
+            let color = pixels[(y / scale) * width + x / scale];
+            raw.extend_from_slice(&color.to_be_bytes()[1..]);
+        }
+    }
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(out_width as u32).to_be_bytes());
+    header.extend_from_slice(&(out_height as u32).to_be_bytes());
+    // bit depth, color type, compression, filter and interlace method
+    header.extend_from_slice(&[8, COLOR_TYPE_RGB, 0, 0, 0]);
+
+    let mut png = SIGNATURE.to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+// pixels of a PNG as 0xAARRGGBB, with its width and height. reads what image editors and
+// other emulators write: 8 bit RGB(A) and gray, 1-8 bit indexed and gray, 16 bit samples
+// cut to 8. interlaced images are not supported
+pub fn decode(png: &[u8]) -> Result<(usize, usize, Vec<u32>), String> {
+    if !png.starts_with(&SIGNATURE) {
+        return Err("not a PNG file".to_string());
+    }
+    let mut header = None;
+    let mut palette = Vec::new();
+    let mut zlib = Vec::new();
+    let mut rest = &png[SIGNATURE.len()..];
+    while rest.len() >= 12 {
+        let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        if rest.len() < 12 + len {
+            break;
+        }
+        let (kind, data) = (&rest[4..8], &rest[8..8 + len]);
+        let crc =
+            u32::from_be_bytes([rest[8 + len], rest[9 + len], rest[10 + len], rest[11 + len]]);
+        if crc32(&rest[4..8 + len]) != crc {
+            return Err(format!(
+                "bad crc in {} chunk",
+                String::from_utf8_lossy(kind)
+            ));
+        }
+        match kind {
+            b"IHDR" if len == 13 => header = Some(data.to_vec()),
+            b"PLTE" => palette = data.to_vec(),
+            b"IDAT" => zlib.extend_from_slice(data),
+            b"IEND" => break,
+            _ => {}
+        }
+        rest = &rest[12 + len..];
+    }
+    let header = header.ok_or("missing IHDR chunk")?;
+    let size = |offset: usize| {
+        u32::from_be_bytes([
+            header[offset],
+            header[offset + 1],
+            header[offset + 2],
+            header[offset + 3],
+        ]) as usize
+    };
+    let (width, height, depth, color_type) = (size(0), size(4), header[8] as usize, header[9]);
+    if header[12] != 0 {
+        return Err("interlaced PNGs are not supported".to_string());
+    }
+    let channels = match (color_type, depth) {
+        (0, 1 | 2 | 4 | 8 | 16) | (3, 1 | 2 | 4 | 8) => 1,
+        (4, 8 | 16) => 2,
+        (2, 8 | 16) => 3,
+        (6, 8 | 16) => 4,
+        _ => {
+            return Err(format!(
+                "unsupported color type {} at depth {}",
+                color_type, depth
+            ))
+        }
+    };
+
+    let raw = inflate(zlib.get(2..).ok_or("missing image data")?)?;
+    let stride = (width * channels * depth).div_ceil(8);
+    // distance to the same channel of the pixel on the left, at least a byte
+    let pixel_bytes = (channels * depth / 8).max(1);
+    if raw.len() < height * (stride + 1) {
+        return Err("image data is too short".to_string());
+    }
+    let mut rows = vec![0; height * stride];
+    for y in 0..height {
+        let filter = raw[y * (stride + 1)];
+        let line = &raw[y * (stride + 1) + 1..(y + 1) * (stride + 1)];
+        for x in 0..stride {
+            let left = if x >= pixel_bytes {
+                rows[y * stride + x - pixel_bytes]
+            } else {
+                0
+            };
+            let up = if y > 0 { rows[(y - 1) * stride + x] } else { 0 };
+            let up_left = if y > 0 && x >= pixel_bytes {
+                rows[(y - 1) * stride + x - pixel_bytes]
+            } else {
+                0
+            };
+            let predicted = match filter {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((left as u16 + up as u16) / 2) as u8,
+                4 => paeth(left, up, up_left),
+                _ => return Err(format!("unknown filter type {}", filter)),
+            };
+            rows[y * stride + x] = line[x].wrapping_add(predicted);
+        }
+    }
+
+    // sample `index` of a row, scaled to 8 bits
+    let sample = |row: &[u8], index: usize| -> u8 {
+        match depth {
+            8 => row[index],
+            16 => row[index * 2],
+            _ => {
+                let bit = index * depth;
+                let value = (row[bit / 8] >> (8 - depth - bit % 8)) & ((1 << depth) - 1) as u8;
+                if color_type == 3 {
+                    value
+                } else {
+                    (value as u32 * 255 / ((1 << depth) - 1)) as u8
+                }
+            }
+        }
+    };
+    let mut pixels = Vec::with_capacity(width * height);
+    for row in rows.chunks(stride.max(1)).take(height) {
+        for x in 0..width {
+            let at = |channel: usize| sample(row, x * channels + channel);
+            let [r, g, b, a] = match color_type {
+                0 => [at(0), at(0), at(0), 0xFF],
+                4 => [at(0), at(0), at(0), at(1)],
+                2 => [at(0), at(1), at(2), 0xFF],
+                6 => [at(0), at(1), at(2), at(3)],
+                _ => {
+                    let index = at(0) as usize * 3;
+                    let color = palette
+                        .get(index..index + 3)
+                        .ok_or("palette index out of range")?;
+                    [color[0], color[1], color[2], 0xFF]
+                }
+            };
+            pixels.push(u32::from_be_bytes([a, r, g, b]));
+        }
+    }
+    Ok((width, height, pixels))
+}
+
+fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+    let estimate = left as i16 + up as i16 - up_left as i16;
+    let (to_left, to_up, to_up_left) = (
+        (estimate - left as i16).abs(),
+        (estimate - up as i16).abs(),
+        (estimate - up_left as i16).abs(),
+    );
+    if to_left <= to_up && to_left <= to_up_left {
+        left
+    } else if to_up <= to_up_left {
+        up
+    } else {
+        up_left
+    }
+}
+
+// deflate decoder for the IDAT stream, stored, fixed and dynamic huffman blocks
+fn inflate(deflate: &[u8]) -> Result<Vec<u8>, String> {
+    const LENGTH_BASE: [u16; 29] = [
+        3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115,
+        131, 163, 195, 227, 258,
+    ];
+    const LENGTH_EXTRA: [u8; 29] = [
+        0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+    ];
+    const DISTANCE_BASE: [u16; 30] = [
+        1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+        2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+    ];
+    const DISTANCE_EXTRA: [u8; 30] = [
+        0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12,
+        13, 13,
+    ];
+    // order the code length code lengths are stored in
+    const CODE_LENGTH_ORDER: [usize; 19] = [
+        16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+    ];
+
+    let mut bits = Bits {
+        data: deflate,
+        pos: 0,
+    };
+    let mut out = Vec::new();
+    loop {
+        let last = bits.read(1)? == 1;
+        match bits.read(2)? {
+            0 => {
+                bits.pos = bits.pos.div_ceil(8) * 8;
+                let len = bits.read(16)? as usize;
+                bits.read(16)?;
+                let start = bits.pos / 8;
+                let block = deflate
+                    .get(start..start + len)
+                    .ok_or("truncated stored block")?;
+                out.extend_from_slice(block);
+                bits.pos += len * 8;
+            }
+            kind @ (1 | 2) => {
+                let (literals, distances) = if kind == 1 {
+                    let mut lengths = [8; 288];
+                    lengths[144..256].fill(9);
+                    lengths[256..280].fill(7);
+                    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+                } else {
+                    let literal_count = bits.read(5)? as usize + 257;
+                    let distance_count = bits.read(5)? as usize + 1;
+                    let code_length_count = bits.read(4)? as usize + 4;
+                    let mut code_lengths = [0; 19];
+                    for &symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+                        code_lengths[symbol] = bits.read(3)? as u8;
+                    }
+                    let code_length_code = Huffman::new(&code_lengths);
+                    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+                    while lengths.len() < literal_count + distance_count {
+                        let (value, repeat) = match bits.decode(&code_length_code)? {
+                            symbol @ 0..=15 => (symbol as u8, 1),
+                            16 => (
+                                *lengths.last().ok_or("repeat without a length")?,
+                                3 + bits.read(2)?,
+                            ),
+                            17 => (0, 3 + bits.read(3)?),
+                            _ => (0, 11 + bits.read(7)?),
+                        };
+                        lengths.extend(std::iter::repeat_n(value, repeat as usize));
+                    }
+                    (
+                        Huffman::new(&lengths[..literal_count]),
+                        Huffman::new(&lengths[literal_count..]),
+                    )
+                };
+                loop {
+                    let symbol = bits.decode(&literals)? as usize;
+                    match symbol {
+                        0..=255 => out.push(symbol as u8),
+                        256 => break,
+                        _ => {
+                            let index = symbol - 257;
+                            let base = *LENGTH_BASE.get(index).ok_or("bad length code")?;
+                            let len = (base + bits.read(LENGTH_EXTRA[index])? as u16) as usize;
+                            let index = bits.decode(&distances)? as usize;
+                            let base = *DISTANCE_BASE.get(index).ok_or("bad distance code")?;
+                            let distance =
+                                (base as u32 + bits.read(DISTANCE_EXTRA[index])?) as usize;
+                            if distance > out.len() {
+                                return Err("distance before the start of the data".to_string());
+                            }
+                            // the match may overlap what it produces, copy byte by byte
+                            for _ in 0..len {
+                                out.push(out[out.len() - distance]);
+                            }
+                        }
+                    }
+                }
+            }
+            _ => return Err("invalid deflate block type".to_string()),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+// deflate bits, least significant first
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Bits<'_> {
+    fn read(&mut self, count: u8) -> Result<u32, String> {
+        let mut value = 0;
+        for i in 0..count {
+            let byte = self
+                .data
+                .get(self.pos / 8)
+                .ok_or("deflate stream ends early")?;
+            value |= ((byte >> (self.pos % 8)) as u32 & 1) << i;
+            self.pos += 1;
+        }
+        Ok(value)
+    }
+
+    // canonical codes are sent most significant bit first, one bit at a time
+    fn decode(&mut self, huffman: &Huffman) -> Result<u16, String> {
+        let (mut code, mut first, mut index) = (0, 0, 0);
+        for &count in &huffman.counts[1..] {
+            code |= self.read(1)? as usize;
+            if code < first + count {
+                return Ok(huffman.symbols[index + code - first]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("invalid huffman code".to_string())
+    }
+}
+
+// canonical huffman code from the bit length of every symbol
+struct Huffman {
+    // number of codes of each length
+    counts: [usize; 16],
+    // symbols ordered by code
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut symbols: Vec<u16> = (0..lengths.len() as u16)
+            .filter(|&symbol| lengths[symbol as usize] != 0)
+            .collect();
+        symbols.sort_by_key(|&symbol| lengths[symbol as usize]);
+        Self { counts, symbols }
+    }
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    // the crc covers the chunk type and data
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    // deflate with a 32K window, no preset dictionary
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        zlib.extend_from_slice(&[1, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none() as u8;
+        let len = block.len() as u16;
+        zlib.push(last);
+        zlib.extend_from_slice(&len.to_le_bytes());
+        zlib.extend_from_slice(&(!len).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(data).to_be_bytes());
+    zlib
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                0xEDB88320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // pixel rows back out of an encoded image, only understands what encode writes
+    fn decode_stored(png: &[u8]) -> (u32, u32, Vec<u8>) {
+        assert_eq!(SIGNATURE, png[..8]);
+        let mut chunks = Vec::new();
+        let mut rest = &png[8..];
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let crc = u32::from_be_bytes(rest[8 + len..12 + len].try_into().unwrap());
+            assert_eq!(crc32(&rest[4..8 + len]), crc);
+            chunks.push((&rest[4..8], &rest[8..8 + len]));
+            rest = &rest[12 + len..];
+        }
+        let header = chunks[0].1;
+        assert_eq!((b"IHDR".as_slice(), 13), (chunks[0].0, header.len()));
+        assert_eq!(b"IEND", chunks[2].0);
+
+        let mut zlib = &chunks[1].1[2..];
+        let mut raw = Vec::new();
+        loop {
+            let last = zlib[0] == 1;
+            let len = u16::from_le_bytes([zlib[1], zlib[2]]) as usize;
+            raw.extend_from_slice(&zlib[5..5 + len]);
+            zlib = &zlib[5 + len..];
+            if last {
+                break;
+            }
+        }
+        assert_eq!(adler32(&raw).to_be_bytes(), zlib);
+        let size =
+            |offset: usize| u32::from_be_bytes(header[offset..offset + 4].try_into().unwrap());
+        (size(0), size(4), raw)
+    }
+
+    #[test]
+    fn test_checksums() {
+        assert_eq!(0xCBF43926, crc32(b"123456789"));
+        assert_eq!(0x091E01DE, adler32(b"123456789"));
+    }
+
+    #[test]
+    fn test_encode_scaled_image() {
+        let pixels = [0xFFFF0000, 0xFF00FF00, 0xFF0000FF, 0xFF123456];
+        let (width, height, raw) = decode_stored(&encode(2, 2, &pixels, 2));
+        assert_eq!((4, 4), (width, height));
+        assert_eq!(4 * (1 + 4 * 3), raw.len());
+        let first_row = [0, 0xFF, 0, 0, 0xFF, 0, 0, 0, 0xFF, 0, 0, 0xFF, 0];
+        assert_eq!(first_row, raw[..13]);
+        assert_eq!(first_row, raw[13..26]);
+        assert_eq!([0x12, 0x34, 0x56], raw[raw.len() - 3..]);
+    }
+
+    // bytes of a hex string, for the fixtures below
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_decode_round_trips_encode() {
+        let pixels = [
+            0xFFFF0000, 0xFF00FF00, 0xFF0000FF, 0xFF123456, 0xFFFFFFFF, 0xFF000000,
+        ];
+        assert_eq!(
+            Ok((3, 2, pixels.to_vec())),
+            decode(&encode(3, 2, &pixels, 1))
+        );
+        let big = vec![0xFFABCDEF; 160 * 144];
+        assert_eq!(
+            Ok((320, 288, vec![0xFFABCDEF; 320 * 288])),
+            decode(&encode(160, 144, &big, 2))
+        );
+        assert!(decode(b"GIF89a").is_err());
+    }
+
+    #[test]
+    fn test_decode_compressed_images() {
+        // written by python's zlib: 8x8 RGB with every filter type, dynamic huffman codes.
+        // pixel (x, y) is (x * 32, y * 32, (x ^ y) * 16)
+        let png = hex(concat!(
+            "89504e470d0a1a0a0000000d49484452000000080000000808020000004b6d29dc00000072494441",
+            "5478da658e0109c3401004274d059c8495f0124e4224bc844888844a58292f211222e125f49bd252",
+            "525886813dd8031091a85236d22c8d7ab04e284417e5c2db2850413beabf3e938ae8119cccafdf5f",
+            "57638538f3f8c8881739d36b75dd1c36cde5b0265a1d5f89fdc2f778ffe713db8f2a0e0f05542500",
+            "00000049454e44ae426082",
+        ));
+        let (width, height, pixels) = decode(&png).unwrap();
+        assert_eq!((8, 8), (width, height));
+        for (i, &pixel) in pixels.iter().enumerate() {
+            let (x, y) = (i as u32 % 8, i as u32 / 8);
+            assert_eq!(
+                0xFF000000 | (x * 32) << 16 | (y * 32) << 8 | ((x ^ y) * 16),
+                pixel,
+                "{x},{y}"
+            );
+        }
+
+        // 5x2, 2 bit indexed with a gray palette, fixed huffman codes
+        let png = hex(concat!(
+            "89504e470d0a1a0a0000000d4948445200000005000000020203000000ed04fece0000000c504c54",
+            "45ffffffaaaaaa55555500000001335b340000000e4944415478da63906660f8e9000002bf01551f",
+            "47696a0000000049454e44ae426082",
+        ));
+        let shades = [0xFFFFFFFF, 0xFFAAAAAA, 0xFF555555, 0xFF000000];
+        let expected = [0, 1, 2, 3, 0, 3, 3, 2, 1, 1].map(|shade| shades[shade]);
+        assert_eq!(Ok((5, 2, expected.to_vec())), decode(&png));
+    }
+
+    #[test]
+    fn test_large_images_span_several_blocks() {
+        let pixels = vec![0xFFABCDEF; 160 * 144];
+        let (width, height, raw) = decode_stored(&encode(160, 144, &pixels, 3));
+        assert_eq!((480, 432), (width, height));
+        assert_eq!(432 * (1 + 480 * 3), raw.len());
+    }
+}
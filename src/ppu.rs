@@ -0,0 +1,1919 @@
+// picture processing unit
+// owns VRAM, OAM and the LCD registers and renders one scanline at a time into the back
+// buffer, at VBlank it is swapped with the front buffer so frontends never see a half drawn
+// frame
+// in CGB mode VRAM has a second bank with per tile attributes and colors come from
+// palette memory instead of the BGP/OBP registers
+
+mod cgb;
+mod fifo;
+mod viewer;
+
+use crate::{
+    interrupt::Interrupt,
+    palette::DmgPalette,
+    pixel::{self, PixelFormat},
+};
+
+use cgb::PaletteRam;
+pub use cgb::{corrected_argb, rgb555_to_argb, PaletteSet, CGB_PALETTE_COUNT};
+use fifo::{PixelFifo, SPRITE_FETCH_DOTS, WINDOW_START_DOTS};
+pub use viewer::{
+    OamEntry, BG_MAP_SIZE, OAM_ENTRIES, TILE_COUNT, TILE_SHEET_HEIGHT, TILE_SHEET_WIDTH,
+    VIEWPORT_COLOR,
+};
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+const VRAM_START: u16 = 0x8000;
+const VRAM_END: u16 = 0x9FFF;
+const OAM_START: u16 = 0xFE00;
+const OAM_END: u16 = 0xFE9F;
+const LCDC: u16 = 0xFF40;
+const STAT: u16 = 0xFF41;
+const SCY: u16 = 0xFF42;
+const SCX: u16 = 0xFF43;
+const LY: u16 = 0xFF44;
+const LYC: u16 = 0xFF45;
+const BGP: u16 = 0xFF47;
+const OBP0: u16 = 0xFF48;
+const OBP1: u16 = 0xFF49;
+const WY: u16 = 0xFF4A;
+const WX: u16 = 0xFF4B;
+const VBK: u16 = 0xFF4F;
+const BCPS: u16 = 0xFF68;
+const BCPD: u16 = 0xFF69;
+const OCPS: u16 = 0xFF6A;
+const OCPD: u16 = 0xFF6B;
+
+const VRAM_BANK_SIZE: usize = 0x2000;
+const VRAM_SIZE: usize = 2 * VRAM_BANK_SIZE;
+const OAM_SIZE: usize = 0xA0;
+
+// one dot is one T-cycle
+const DOTS_PER_LINE: u32 = 456;
+const LINES_PER_FRAME: u8 = 154;
+const OAM_SCAN_DOTS: u32 = 80;
+const PIXEL_TRANSFER_DOTS: u32 = 172;
+// LY changes at the start of a line, the LYC comparator only catches up one M-cycle later
+const LY_COMPARE_DELAY: u32 = 4;
+const LAST_LINE: u8 = LINES_PER_FRAME - 1;
+const SPRITES_PER_LINE: usize = 10;
+const SCREEN_TILES_X: u16 = SCREEN_WIDTH as u16 / 8;
+const WHITE: u32 = 0xFFFFFFFF;
+// the window starts at WX - 7, from 166 on it would start right of the screen
+const WINDOW_X_MAX: u8 = 165;
+
+// LCDC bits
+const LCD_ENABLE: u8 = 1 << 7;
+const WINDOW_TILE_MAP: u8 = 1 << 6;
+const WINDOW_ENABLE: u8 = 1 << 5;
+const BG_TILE_DATA: u8 = 1 << 4;
+const BG_TILE_MAP: u8 = 1 << 3;
+const OBJ_SIZE: u8 = 1 << 2;
+const OBJ_ENABLE: u8 = 1 << 1;
+// on CGB the background can't be turned off, this bit gives it priority over sprites instead
+const BG_ENABLE: u8 = 1;
+
+// STAT bits
+const LYC_INTERRUPT: u8 = 1 << 6;
+const OAM_INTERRUPT: u8 = 1 << 5;
+const VBLANK_INTERRUPT: u8 = 1 << 4;
+const HBLANK_INTERRUPT: u8 = 1 << 3;
+const LYC_EQUAL: u8 = 1 << 2;
+
+// sprite attribute bits
+const BEHIND_BG: u8 = 1 << 7;
+const Y_FLIP: u8 = 1 << 6;
+const X_FLIP: u8 = 1 << 5;
+const OBP1_PALETTE: u8 = 1 << 4;
+// CGB only, background map attributes in VRAM bank 1 share the flip and priority bits
+const TILE_BANK: u8 = 1 << 3;
+const CGB_PALETTE: u8 = 0x7;
+
+// video registers latched at the start of mode 3, WY is already checked at that point
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct LineRegisters {
+    lcdc: u8,
+    scy: u8,
+    scx: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wx: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    PixelTransfer = 3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Renderer {
+    // draws the whole line at once when mode 3 ends, mode 3 always lasts 172 dots
+    #[default]
+    Scanline,
+    // cycle accurate background/sprite FIFO, slower but handles mid-scanline effects
+    Fifo,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Ppu {
+    video_ram: Vec<u8>,
+    oam: Vec<u8>,
+    lcdc: u8,
+    stat: u8,
+    scy: u8,
+    scx: u8,
+    ly: u8,
+    lyc: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
+    // dots elapsed in the current line
+    dots: u32,
+    // back buffer the lines are drawn into, 160x144 pixels in pixel_format
+    pub frame_buffer: Vec<u32>,
+    // requested interrupts as IF bits, collected by the bus
+    pub interrupts: u8,
+    // set when a new frame has been completed, cleared by whoever presents it
+    pub frame_ready: bool,
+    // last complete frame, swapped with frame_buffer at VBlank. saved so a state shows the
+    // picture it was saved on, older states without it start blank
+    #[cfg_attr(feature = "serde", serde(default = "blank_frame"))]
+    front_buffer: Vec<u32>,
+    // state of the shared STAT interrupt line
+    stat_line: bool,
+    // length of the current mode 3 with the scanline renderer
+    pixel_transfer_dots: u32,
+    // the frame right after the LCD is switched on is not drawn
+    skip_frame: bool,
+    // LY has matched WY in this frame, later WY writes don't take the window away
+    window_triggered: bool,
+    // window row to draw next, only advances on lines the window was drawn on
+    window_line: u8,
+    renderer: Renderer,
+    // registers the scanline renderer draws the current line with
+    line_registers: LineRegisters,
+    fifo: PixelFifo,
+    // CGB mode, only for color games on a color gameboy
+    cgb: bool,
+    // VRAM bank the cpu sees at 0x8000-0x9FFF
+    vram_bank: u8,
+    bg_palettes: PaletteRam,
+    obj_palettes: PaletteRam,
+    // colors of the four DMG shades, a frontend setting rather than machine state
+    #[cfg_attr(feature = "serde", serde(skip))]
+    palette: DmgPalette,
+    // layout of the pixels in the frame buffers. a frontend setting, but saved so the
+    // buffers in a state can be read back
+    #[cfg_attr(feature = "serde", serde(default))]
+    pixel_format: PixelFormat,
+    // share of the previous frame kept in every new one, imitates the slow DMG LCD so
+    // sprites flickered at 30Hz look see-through instead of blinking. 0 is off
+    #[cfg_attr(feature = "serde", serde(skip))]
+    ghosting: f64,
+    // show CGB colors the way the CGB LCD does instead of at full saturation
+    #[cfg_attr(feature = "serde", serde(skip))]
+    color_correction: bool,
+}
+
+impl Ppu {
+    pub fn new() -> Self {
+        Self {
+            video_ram: vec![0; VRAM_SIZE],
+            oam: vec![0; OAM_SIZE],
+            lcdc: 0,
+            stat: 0,
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            bgp: 0,
+            obp0: 0,
+            obp1: 0,
+            wy: 0,
+            wx: 0,
+            dots: 0,
+            frame_buffer: blank_frame(),
+            interrupts: 0,
+            frame_ready: false,
+            front_buffer: blank_frame(),
+            stat_line: false,
+            pixel_transfer_dots: PIXEL_TRANSFER_DOTS,
+            skip_frame: false,
+            window_triggered: false,
+            window_line: 0,
+            renderer: Renderer::default(),
+            line_registers: LineRegisters::default(),
+            fifo: PixelFifo::default(),
+            cgb: false,
+            vram_bank: 0,
+            bg_palettes: PaletteRam::new(),
+            obj_palettes: PaletteRam::new(),
+            palette: DmgPalette::default(),
+            pixel_format: PixelFormat::default(),
+            ghosting: 0.0,
+            color_correction: false,
+        }
+    }
+
+    pub fn read_byte(&self, addr: u16) -> u8 {
+        match addr {
+            VRAM_START..=VRAM_END => self.video_ram[self.vram_offset(addr)],
+            OAM_START..=OAM_END => self.oam[(addr - OAM_START) as usize],
+            LCDC => self.lcdc,
+            // bit 7 is not connected
+            STAT => 0x80 | self.stat,
+            SCY => self.scy,
+            SCX => self.scx,
+            LY => self.ly_register(),
+            LYC => self.lyc,
+            BGP => self.bgp,
+            OBP0 => self.obp0,
+            OBP1 => self.obp1,
+            WY => self.wy,
+            WX => self.wx,
+            // the color registers are not there on DMG
+            VBK | BCPS..=OCPD if !self.cgb => 0xFF,
+            VBK => 0xFE | self.vram_bank,
+            BCPS => self.bg_palettes.read_spec(),
+            BCPD => self.bg_palettes.read_data(),
+            OCPS => self.obj_palettes.read_spec(),
+            OCPD => self.obj_palettes.read_data(),
+            // not routed here by the bus, reads as open bus
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write_byte(&mut self, addr: u16, value: u8) {
+        match addr {
+            VRAM_START..=VRAM_END => {
+                let offset = self.vram_offset(addr);
+                self.video_ram[offset] = value;
+            }
+            OAM_START..=OAM_END => self.oam[(addr - OAM_START) as usize] = value,
+            LCDC => {
+                let was_enabled = self.lcdc & LCD_ENABLE != 0;
+                self.lcdc = value;
+                match (was_enabled, value & LCD_ENABLE != 0) {
+                    (true, false) => self.disable_lcd(),
+                    (false, true) => self.enable_lcd(),
+                    _ => {}
+                }
+            }
+            // mode and coincidence bits are read only
+            STAT => {
+                self.stat = (value & 0x78) | (self.stat & 0x07);
+                self.update_stat_line();
+            }
+            SCY => self.scy = value,
+            SCX => self.scx = value,
+            // LY is read only
+            LY => {}
+            LYC => {
+                self.lyc = value;
+                self.compare_lyc();
+                self.update_stat_line();
+            }
+            BGP => self.bgp = value,
+            OBP0 => self.obp0 = value,
+            OBP1 => self.obp1 = value,
+            WY => self.wy = value,
+            WX => self.wx = value,
+            VBK | BCPS..=OCPD if !self.cgb => {}
+            VBK => self.vram_bank = value & 1,
+            BCPS => self.bg_palettes.write_spec(value),
+            BCPD => self.bg_palettes.write_data(value),
+            OCPS => self.obj_palettes.write_spec(value),
+            OCPD => self.obj_palettes.write_data(value),
+            _ => {}
+        }
+    }
+
+    // debugger write, LCDC doesn't switch the LCD on or off and the palette data registers
+    // don't move their index
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        match addr {
+            LCDC => self.lcdc = value,
+            BCPD if self.cgb => self.bg_palettes.poke_data(value),
+            OCPD if self.cgb => self.obj_palettes.poke_data(value),
+            _ => self.write_byte(addr, value),
+        }
+    }
+
+    // the last complete frame, stable while the next one is being drawn
+    pub fn front_buffer(&self) -> &[u32] {
+        &self.front_buffer
+    }
+
+    // same as front_buffer
+    pub fn frame(&self) -> &[u32] {
+        self.front_buffer()
+    }
+
+    // the frame completed since the last poll, if any
+    pub fn poll_frame(&mut self) -> Option<&[u32]> {
+        if !std::mem::take(&mut self.frame_ready) {
+            return None;
+        }
+        Some(&self.front_buffer)
+    }
+
+    // the finished back buffer becomes the front buffer and the old front buffer is drawn
+    // over next, no frame is ever shown half drawn
+    fn complete_frame(&mut self) {
+        std::mem::swap(&mut self.front_buffer, &mut self.frame_buffer);
+        if self.ghosting > 0.0 {
+            // the back buffer still has the previous frame, it is redrawn line by line
+            let format = self.pixel_format;
+            for (new, &old) in self.front_buffer.iter_mut().zip(&self.frame_buffer) {
+                let mixed = pixel::blend(format.to_argb(*new), format.to_argb(old), self.ghosting);
+                *new = format.convert(mixed);
+            }
+        }
+        self.frame_ready = true;
+    }
+
+    // fill the back buffer with what the LCD shows while it is off or skipping a frame
+    fn blank_back_buffer(&mut self) {
+        let blank = if self.cgb {
+            WHITE
+        } else {
+            self.palette.color(0)
+        };
+        self.frame_buffer.fill(self.pixel_format.convert(blank));
+    }
+
+    pub fn palette(&self) -> DmgPalette {
+        self.palette
+    }
+
+    // takes effect from the next line drawn, CGB games bring their own colors
+    pub fn set_palette(&mut self, palette: DmgPalette) {
+        self.palette = palette;
+    }
+
+    pub fn ghosting(&self) -> f64 {
+        self.ghosting
+    }
+
+    // `weight` is the share of the previous frame, from 0 (off) to 1
+    pub fn set_ghosting(&mut self, weight: f64) {
+        self.ghosting = weight.clamp(0.0, 1.0);
+    }
+
+    pub fn color_correction(&self) -> bool {
+        self.color_correction
+    }
+
+    // takes effect from the next line drawn, only changes CGB colors
+    pub fn set_color_correction(&mut self, enabled: bool) {
+        self.color_correction = enabled;
+    }
+
+    pub fn pixel_format(&self) -> PixelFormat {
+        self.pixel_format
+    }
+
+    // converts both frame buffers, debug views stay 0xAARRGGBB
+    pub fn set_pixel_format(&mut self, format: PixelFormat) {
+        let old = self.pixel_format;
+        if old != format {
+            for pixel in self.frame_buffer.iter_mut().chain(&mut self.front_buffer) {
+                *pixel = format.convert(old.to_argb(*pixel));
+            }
+        }
+        self.pixel_format = format;
+    }
+
+    pub fn is_cgb_mode(&self) -> bool {
+        self.cgb
+    }
+
+    // switched on by the bus when a color game runs on a color gameboy
+    pub fn set_cgb_mode(&mut self, cgb: bool) {
+        self.cgb = cgb;
+        if !cgb {
+            self.vram_bank = 0;
+        }
+    }
+
+    // index into video_ram of a cpu access to `addr` through the selected bank
+    fn vram_offset(&self, addr: u16) -> usize {
+        self.vram_bank as usize * VRAM_BANK_SIZE + (addr - VRAM_START) as usize
+    }
+
+    pub fn renderer(&self) -> Renderer {
+        self.renderer
+    }
+
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.renderer = renderer;
+    }
+
+    // the ppu holds VRAM during pixel transfer and OAM from the scan on, cpu reads of them
+    // see 0xFF then
+    pub fn is_locked(&self, addr: u16) -> bool {
+        match addr {
+            VRAM_START..=VRAM_END => self.mode() == Mode::PixelTransfer,
+            OAM_START..=OAM_END => matches!(self.mode(), Mode::OamScan | Mode::PixelTransfer),
+            _ => false,
+        }
+    }
+
+    // the row of 8 OAM bytes the scan reads in this machine cycle, None outside mode 2
+    pub fn oam_scan_row(&self) -> Option<usize> {
+        (self.mode() == Mode::OamScan).then_some(self.dots as usize / 4)
+    }
+
+    // the DMG OAM bug: a cpu access to 0xFE00-0xFEFF during the scan garbles the row being
+    // read. its first word is mixed with the row above, the other three are copied from
+    // it. the first row is never hit
+    pub fn corrupt_oam(&mut self, write: bool) {
+        let Some(row) = self
+            .oam_scan_row()
+            .filter(|row| (1..OAM_SIZE / 8).contains(row))
+        else {
+            return;
+        };
+        let word = |oam: &[u8], row: usize, index: usize| {
+            u16::from_le_bytes([oam[row * 8 + index * 2], oam[row * 8 + index * 2 + 1]])
+        };
+        let a = word(&self.oam, row, 0);
+        let b = word(&self.oam, row - 1, 0);
+        let c = word(&self.oam, row - 1, 2);
+        let first = if write {
+            ((a ^ c) & (b ^ c)) ^ c
+        } else {
+            b | (a & c)
+        };
+        self.oam[row * 8..row * 8 + 2].copy_from_slice(&first.to_le_bytes());
+        self.oam
+            .copy_within((row - 1) * 8 + 2..row * 8, row * 8 + 2);
+    }
+
+    pub fn mode(&self) -> Mode {
+        match self.stat & 0x3 {
+            0 => Mode::HBlank,
+            1 => Mode::VBlank,
+            2 => Mode::OamScan,
+            _ => Mode::PixelTransfer,
+        }
+    }
+
+    // advance the ppu by `cycles` dots (T-cycles)
+    pub fn update_ly(&mut self, cycles: u32) {
+        if self.lcdc & LCD_ENABLE == 0 {
+            return;
+        }
+
+        for _ in 0..cycles {
+            self.dots += 1;
+            if self.dots == DOTS_PER_LINE {
+                self.dots = 0;
+                self.ly = (self.ly + 1) % LINES_PER_FRAME;
+            }
+            self.compare_lyc();
+
+            let mode = self.next_mode();
+            if mode != self.mode() {
+                self.enter_mode(mode);
+            }
+            self.update_stat_line();
+            if self.renderer == Renderer::Fifo && self.mode() == Mode::PixelTransfer {
+                self.fifo_step();
+            }
+        }
+    }
+
+    fn next_mode(&self) -> Mode {
+        if self.ly as usize >= SCREEN_HEIGHT {
+            return Mode::VBlank;
+        }
+        // the first line after turning the LCD on has no OAM scan, STAT reads mode 0
+        if self.dots < OAM_SCAN_DOTS && self.skip_frame && self.ly == 0 {
+            return Mode::HBlank;
+        }
+        if self.dots < OAM_SCAN_DOTS {
+            return Mode::OamScan;
+        }
+        match self.renderer {
+            Renderer::Scanline if self.dots < OAM_SCAN_DOTS + self.pixel_transfer_dots => {
+                Mode::PixelTransfer
+            }
+            Renderer::Scanline => Mode::HBlank,
+            // mode 3 lasts until the FIFO has pushed the whole line
+            Renderer::Fifo => match self.mode() {
+                Mode::OamScan => Mode::PixelTransfer,
+                Mode::PixelTransfer if self.fifo.is_done() => Mode::HBlank,
+                mode => mode,
+            },
+        }
+    }
+
+    fn enter_mode(&mut self, mode: Mode) {
+        self.stat = (self.stat & !0x3) | mode as u8;
+        match mode {
+            Mode::HBlank => {
+                let window_drawn = match self.renderer {
+                    Renderer::Scanline => self.window_on_line(),
+                    Renderer::Fifo => self.fifo.in_window(),
+                };
+                if self.renderer == Renderer::Scanline && !self.skip_frame {
+                    self.render_latched_scanline();
+                }
+                if window_drawn {
+                    self.window_line = self.window_line.wrapping_add(1);
+                }
+            }
+            Mode::VBlank => {
+                // nothing was drawn into the back buffer, it still holds an older frame
+                if std::mem::take(&mut self.skip_frame) {
+                    self.blank_back_buffer();
+                }
+                self.reset_window();
+                self.complete_frame();
+                self.request_interrupt(Interrupt::VBlank);
+            }
+            Mode::OamScan => {}
+            Mode::PixelTransfer => {
+                if self.ly == self.wy {
+                    self.window_triggered = true;
+                }
+                match self.renderer {
+                    Renderer::Scanline => {
+                        self.pixel_transfer_dots = self.pixel_transfer_length();
+                        self.line_registers = self.live_line_registers();
+                    }
+                    Renderer::Fifo => self.fifo_start_line(),
+                }
+            }
+        }
+    }
+
+    // the screen goes blank and the ppu stops at the start of line 0
+    fn disable_lcd(&mut self) {
+        self.ly = 0;
+        self.dots = 0;
+        self.stat &= !0x3;
+        self.compare_lyc();
+        // no STAT interrupts while the LCD is off
+        self.stat_line = false;
+        self.reset_window();
+        self.blank_back_buffer();
+        self.complete_frame();
+    }
+
+    // timing restarts from line 0, the first frame is not shown
+    fn enable_lcd(&mut self) {
+        self.ly = 0;
+        self.dots = 0;
+        self.skip_frame = true;
+        self.reset_window();
+        self.compare_lyc();
+        self.update_stat_line();
+    }
+
+    fn reset_window(&mut self) {
+        self.window_triggered = false;
+        self.window_line = 0;
+    }
+
+    // the window is drawn on this line once LY has matched WY in the frame
+    fn window_on_line(&self) -> bool {
+        self.lcdc & WINDOW_ENABLE != 0
+            && (self.window_triggered || self.ly == self.wy)
+            && self.wx <= WINDOW_X_MAX
+    }
+
+    // mode 3 length for the scanline renderer, with the same penalties the FIFO pays:
+    // fine scroll pixels are fetched and dropped, every sprite stalls the fetcher and
+    // starting the window restarts the background fetch. HBlank shrinks to match
+    fn pixel_transfer_length(&self) -> u32 {
+        let mut dots = PIXEL_TRANSFER_DOTS;
+        // a window starting at the left edge drops its own 7 - WX pixels instead
+        dots += if self.window_on_line() && self.wx <= 7 {
+            (7 - self.wx) as u32
+        } else {
+            (self.scx % 8) as u32
+        };
+        if self.lcdc & OBJ_ENABLE != 0 {
+            // sprites right of the screen are never reached
+            let sprites = self
+                .sprites_on_line()
+                .into_iter()
+                .filter(|sprite| (self.oam[sprite * 4 + 1] as usize) < SCREEN_WIDTH + 8)
+                .count() as u32;
+            dots += sprites * SPRITE_FETCH_DOTS as u32;
+        }
+        if self.window_on_line() {
+            dots += WINDOW_START_DOTS;
+        }
+        dots
+    }
+
+    // what the cpu reads from LY. line 153 only shows as 153 for its first M-cycle, the
+    // counter already reads 0 for the rest of it
+    fn ly_register(&self) -> u8 {
+        if self.ly == LAST_LINE && self.dots >= LY_COMPARE_DELAY {
+            0
+        } else {
+            self.ly
+        }
+    }
+
+    // the line the LYC comparator sees, None while it matches nothing. every new line
+    // starts with one M-cycle where the flag is clear, line 153 compares 153 for one
+    // M-cycle and then 0 from its third M-cycle on, so LYC=0 fires during line 153
+    fn ly_compare(&self) -> Option<u8> {
+        match (self.ly, self.dots / LY_COMPARE_DELAY) {
+            (0, _) => Some(0),
+            (_, 0) => None,
+            (LAST_LINE, 1) => Some(LAST_LINE),
+            (LAST_LINE, 2) => None,
+            (LAST_LINE, _) => Some(0),
+            (ly, _) => Some(ly),
+        }
+    }
+
+    fn compare_lyc(&mut self) {
+        if self.ly_compare() == Some(self.lyc) {
+            self.stat |= LYC_EQUAL;
+        } else {
+            self.stat &= !LYC_EQUAL;
+        }
+    }
+
+    // all STAT sources share one interrupt line, the interrupt is only requested when
+    // the OR of the enabled conditions goes from low to high. a condition starting while
+    // another one still holds the line high does not fire again
+    fn update_stat_line(&mut self) {
+        if self.lcdc & LCD_ENABLE == 0 {
+            return;
+        }
+        let mode_source = match self.mode() {
+            Mode::HBlank => HBLANK_INTERRUPT,
+            Mode::VBlank => VBLANK_INTERRUPT,
+            Mode::OamScan => OAM_INTERRUPT,
+            Mode::PixelTransfer => 0,
+        };
+        let line = self.stat & mode_source != 0
+            || (self.stat & LYC_INTERRUPT != 0 && self.stat & LYC_EQUAL != 0);
+        if line && !self.stat_line {
+            self.request_interrupt(Interrupt::LcdStat);
+        }
+        self.stat_line = line;
+    }
+
+    fn request_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupts |= interrupt.bit();
+    }
+
+    // color index 0-3 of pixel (x, y) in the tile at `tile_addr` in VRAM bank `bank`
+    fn tile_pixel(&self, bank: u8, tile_addr: u16, x: u8, y: u8) -> u8 {
+        let addr =
+            bank as usize * VRAM_BANK_SIZE + (tile_addr - VRAM_START) as usize + y as usize * 2;
+        let low = self.video_ram[addr];
+        let high = self.video_ram[addr + 1];
+        let bit = 7 - x;
+        ((high >> bit) & 1) << 1 | ((low >> bit) & 1)
+    }
+
+    // color indices of row y of the tile at `tile_addr`, left to right
+    fn tile_row(&self, bank: u8, tile_addr: u16, y: u8) -> [u8; 8] {
+        let addr =
+            bank as usize * VRAM_BANK_SIZE + (tile_addr - VRAM_START) as usize + y as usize * 2;
+        let low = self.video_ram[addr];
+        let high = self.video_ram[addr + 1];
+        std::array::from_fn(|x| ((high >> (7 - x)) & 1) << 1 | ((low >> (7 - x)) & 1))
+    }
+
+    // address of a background/window tile, honouring the signed 0x8800 addressing mode
+    fn bg_tile_addr(&self, tile_number: u8) -> u16 {
+        if self.lcdc & BG_TILE_DATA != 0 {
+            VRAM_START + tile_number as u16 * 16
+        } else {
+            (0x9000_i32 + tile_number as i8 as i32 * 16) as u16
+        }
+    }
+
+    // tile data of the first 256 background tiles on screen, row by row from the top
+    // left of the map. this is what the SGB copies out of VRAM for its data transfers
+    pub fn screen_tile_data(&self) -> Vec<u8> {
+        let map = if self.lcdc & BG_TILE_MAP != 0 {
+            0x9C00
+        } else {
+            0x9800
+        };
+        (0..256u16)
+            .flat_map(|i| {
+                let map_addr = map + (i / SCREEN_TILES_X) * 32 + i % SCREEN_TILES_X;
+                let tile = self.video_ram[(map_addr - VRAM_START) as usize];
+                let start = (self.bg_tile_addr(tile) - VRAM_START) as usize;
+                self.video_ram[start..start + 16].iter().copied()
+            })
+            .collect()
+    }
+
+    // color index and CGB attributes of the background or window at screen position x
+    // on the current line
+    fn bg_pixel(&self, x: u8) -> (u8, u8) {
+        // with WX < 7 the first 7 - WX window pixels are clipped
+        let window_x = self.wx as i16 - 7;
+        let in_window = self.window_on_line() && x as i16 >= window_x;
+
+        let (map_x, map_y, map_base) = if in_window {
+            let map = if self.lcdc & WINDOW_TILE_MAP != 0 {
+                0x9C00
+            } else {
+                0x9800
+            };
+            ((x as i16 - window_x) as u8, self.window_line, map)
+        } else {
+            let map = if self.lcdc & BG_TILE_MAP != 0 {
+                0x9C00
+            } else {
+                0x9800
+            };
+            (
+                x.wrapping_add(self.scx),
+                self.ly.wrapping_add(self.scy),
+                map,
+            )
+        };
+
+        let map_addr = map_base + (map_y as u16 / 8) * 32 + map_x as u16 / 8;
+        let (tile_number, attributes) = self.map_entry(map_addr);
+        let index = self.bg_tile_pixel(tile_number, attributes, map_x % 8, map_y % 8);
+        (index, attributes)
+    }
+
+    // tile number and, in CGB mode, the attributes from bank 1 of a tile map entry
+    fn map_entry(&self, map_addr: u16) -> (u8, u8) {
+        let offset = (map_addr - VRAM_START) as usize;
+        let attributes = if self.cgb {
+            self.video_ram[VRAM_BANK_SIZE + offset]
+        } else {
+            0
+        };
+        (self.video_ram[offset], attributes)
+    }
+
+    // pixel of a background/window tile with the CGB bank and flip attributes applied
+    fn bg_tile_pixel(&self, tile_number: u8, attributes: u8, x: u8, y: u8) -> u8 {
+        let x = if attributes & X_FLIP != 0 { 7 - x } else { x };
+        let y = if attributes & Y_FLIP != 0 { 7 - y } else { y };
+        let bank = (attributes & TILE_BANK != 0) as u8;
+        self.tile_pixel(bank, self.bg_tile_addr(tile_number), x, y)
+    }
+
+    // indices into OAM of the (at most 10) sprites that cover the current line
+    fn sprites_on_line(&self) -> Vec<usize> {
+        let height = if self.lcdc & OBJ_SIZE != 0 { 16 } else { 8 };
+        (0..40)
+            .filter(|sprite| {
+                let y = self.oam[sprite * 4] as i16 - 16;
+                (y..y + height).contains(&(self.ly as i16))
+            })
+            .take(SPRITES_PER_LINE)
+            .collect()
+    }
+
+    // color index and attributes of the sprite pixel drawn at each x of the line, if any.
+    // every sprite row is decoded once, from the highest priority sprite down, and only
+    // fills the pixels nothing above it covered
+    fn sprite_line(&self, sprites: &[usize]) -> [Option<(u8, u8)>; SCREEN_WIDTH] {
+        let height = if self.lcdc & OBJ_SIZE != 0 { 16 } else { 8 };
+        let mut line = [None; SCREEN_WIDTH];
+        let mut sprites = sprites.to_vec();
+        // lower x wins, ties go to the earlier OAM entry. CGB only goes by OAM order
+        if !self.cgb {
+            sprites.sort_by_key(|&sprite| self.oam[sprite * 4 + 1]);
+        }
+
+        for sprite in sprites {
+            let entry = &self.oam[sprite * 4..sprite * 4 + 4];
+            let attributes = entry[3];
+            let mut row = (self.ly as i16 - (entry[0] as i16 - 16)) as u8;
+            if attributes & Y_FLIP != 0 {
+                row = height as u8 - 1 - row;
+            }
+            let mut tile = entry[2];
+            if height == 16 {
+                tile &= 0xFE;
+            }
+            let tile_addr = VRAM_START + tile as u16 * 16 + (row as u16 / 8) * 16;
+            let bank = (self.cgb && attributes & TILE_BANK != 0) as u8;
+            let mut pixels = self.tile_row(bank, tile_addr, row % 8);
+            if attributes & X_FLIP != 0 {
+                pixels.reverse();
+            }
+
+            let sprite_x = entry[1] as i16 - 8;
+            for (column, index) in pixels.into_iter().enumerate() {
+                let x = sprite_x + column as i16;
+                // color 0 is transparent
+                if index == 0 || !(0..SCREEN_WIDTH as i16).contains(&x) {
+                    continue;
+                }
+                line[x as usize].get_or_insert((index, attributes));
+            }
+        }
+        line
+    }
+
+    // color of a pixel from its background and (winning) sprite parts, in the output
+    // pixel format
+    fn mix_pixel(&self, bg_index: u8, bg_attributes: u8, obj: Option<(u8, u8)>) -> u32 {
+        let color = match obj {
+            Some((index, attributes)) if !self.bg_over_obj(bg_index, bg_attributes, attributes) => {
+                self.obj_color(attributes, index)
+            }
+            _ => self.bg_color(bg_attributes, bg_index),
+        };
+        self.pixel_format.convert(color)
+    }
+
+    // background colors 1-3 cover sprites flagged to sit behind it. on CGB the tile can
+    // claim priority too, unless LCDC bit 0 is clear and sprites always win
+    fn bg_over_obj(&self, bg_index: u8, bg_attributes: u8, obj_attributes: u8) -> bool {
+        if bg_index == 0 {
+            return false;
+        }
+        if self.cgb {
+            self.lcdc & BG_ENABLE != 0 && (bg_attributes | obj_attributes) & BEHIND_BG != 0
+        } else {
+            obj_attributes & BEHIND_BG != 0
+        }
+    }
+
+    fn bg_color(&self, attributes: u8, index: u8) -> u32 {
+        if self.cgb {
+            self.cgb_color(self.bg_palettes.rgb555(attributes & CGB_PALETTE, index))
+        } else {
+            self.apply_palette(self.bgp, index)
+        }
+    }
+
+    fn obj_color(&self, attributes: u8, index: u8) -> u32 {
+        if self.cgb {
+            self.cgb_color(self.obj_palettes.rgb555(attributes & CGB_PALETTE, index))
+        } else if attributes & OBP1_PALETTE != 0 {
+            self.apply_palette(self.obp1, index)
+        } else {
+            self.apply_palette(self.obp0, index)
+        }
+    }
+
+    fn cgb_color(&self, rgb555: u16) -> u32 {
+        if self.color_correction {
+            corrected_argb(rgb555)
+        } else {
+            rgb555_to_argb(rgb555)
+        }
+    }
+
+    pub fn render_scanline(&mut self) {
+        let sprites = if self.lcdc & OBJ_ENABLE != 0 {
+            self.sprite_line(&self.sprites_on_line())
+        } else {
+            [None; SCREEN_WIDTH]
+        };
+
+        for x in 0..SCREEN_WIDTH as u8 {
+            let (bg_index, bg_attributes) = if self.cgb || self.lcdc & BG_ENABLE != 0 {
+                self.bg_pixel(x)
+            } else {
+                (0, 0)
+            };
+            let color = self.mix_pixel(bg_index, bg_attributes, sprites[x as usize]);
+            self.frame_buffer[self.ly as usize * SCREEN_WIDTH + x as usize] = color;
+        }
+    }
+
+    // the scanline renderer draws a whole line at once when mode 3 ends, so it uses the
+    // registers as they were when the line started. writes during mode 3 show up on the
+    // next line instead of changing the line already being drawn
+    fn render_latched_scanline(&mut self) {
+        let live = self.live_line_registers();
+        self.set_line_registers(self.line_registers);
+        self.render_scanline();
+        self.set_line_registers(live);
+    }
+
+    fn live_line_registers(&self) -> LineRegisters {
+        LineRegisters {
+            lcdc: self.lcdc,
+            scy: self.scy,
+            scx: self.scx,
+            bgp: self.bgp,
+            obp0: self.obp0,
+            obp1: self.obp1,
+            wx: self.wx,
+        }
+    }
+
+    fn set_line_registers(&mut self, registers: LineRegisters) {
+        self.lcdc = registers.lcdc;
+        self.scy = registers.scy;
+        self.scx = registers.scx;
+        self.bgp = registers.bgp;
+        self.obp0 = registers.obp0;
+        self.obp1 = registers.obp1;
+        self.wx = registers.wx;
+    }
+
+    // map a color index through a palette register to one of the four DMG shades
+    pub fn apply_palette(&self, palette: u8, index: u8) -> u32 {
+        self.palette.color((palette >> (index * 2)) & 0x3)
+    }
+}
+
+fn blank_frame() -> Vec<u32> {
+    vec![WHITE; SCREEN_WIDTH * SCREEN_HEIGHT]
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIGHT: u32 = 0xFFAAAAAA;
+    const DARK: u32 = 0xFF555555;
+    const BLACK: u32 = 0xFF000000;
+
+    fn enabled_ppu() -> Ppu {
+        let mut ppu = Ppu::new();
+        ppu.write_byte(LCDC, LCD_ENABLE | BG_TILE_DATA | BG_ENABLE);
+        ppu.write_byte(BGP, 0xE4);
+        ppu.write_byte(OBP0, 0xE4);
+        // as if the LCD had been on for a while
+        ppu.skip_frame = false;
+        ppu
+    }
+
+    #[test]
+    fn test_foreign_addresses_are_open_bus() {
+        let mut ppu = enabled_ppu();
+        ppu.write_byte(0xFF4C, 0x00);
+        assert_eq!(0xFF, ppu.read_byte(0xFF4C));
+        assert_eq!(0xFF, ppu.read_byte(0xC000));
+    }
+
+    // tile where every row has the same low/high bit planes
+    fn fill_tile(ppu: &mut Ppu, tile: u16, low: u8, high: u8) {
+        for row in 0..8 {
+            ppu.write_byte(VRAM_START + tile * 16 + row * 2, low);
+            ppu.write_byte(VRAM_START + tile * 16 + row * 2 + 1, high);
+        }
+    }
+
+    #[test]
+    fn test_mode_timing_within_a_line() {
+        let mut ppu = enabled_ppu();
+        ppu.update_ly(1);
+        assert_eq!(Mode::OamScan, ppu.mode());
+        ppu.update_ly(OAM_SCAN_DOTS - 1);
+        assert_eq!(Mode::PixelTransfer, ppu.mode());
+        ppu.update_ly(PIXEL_TRANSFER_DOTS);
+        assert_eq!(Mode::HBlank, ppu.mode());
+        ppu.update_ly(DOTS_PER_LINE - OAM_SCAN_DOTS - PIXEL_TRANSFER_DOTS);
+        assert_eq!(1, ppu.read_byte(LY));
+        assert_eq!(Mode::OamScan, ppu.mode());
+    }
+
+    #[test]
+    fn test_vblank_interrupt_and_frame_wraparound() {
+        let mut ppu = enabled_ppu();
+        ppu.update_ly(DOTS_PER_LINE * 144);
+        assert_eq!(144, ppu.read_byte(LY));
+        assert_eq!(Mode::VBlank, ppu.mode());
+        assert_eq!(Interrupt::VBlank.bit(), ppu.interrupts);
+        assert!(ppu.frame_ready);
+
+        ppu.update_ly(DOTS_PER_LINE * 10);
+        assert_eq!(0, ppu.read_byte(LY));
+        assert_eq!(Mode::OamScan, ppu.mode());
+    }
+
+    #[test]
+    fn test_lyc_coincidence() {
+        let mut ppu = enabled_ppu();
+        ppu.write_byte(STAT, LYC_INTERRUPT);
+        ppu.write_byte(LYC, 2);
+        assert_eq!(0, ppu.read_byte(STAT) & LYC_EQUAL);
+        ppu.update_ly(DOTS_PER_LINE * 2);
+        // LY already reads 2, the comparator lags one M-cycle behind
+        assert_eq!(2, ppu.read_byte(LY));
+        assert_eq!(0, ppu.read_byte(STAT) & LYC_EQUAL);
+        ppu.update_ly(LY_COMPARE_DELAY);
+        assert_eq!(LYC_EQUAL, ppu.read_byte(STAT) & LYC_EQUAL);
+        assert_eq!(Interrupt::LcdStat.bit(), ppu.interrupts);
+    }
+
+    // LY and the LYC flag around the frame wrap, the timing mooneye's ly_lyc tests check
+    #[test]
+    fn test_line_153_reads_as_line_0() {
+        let mut ppu = enabled_ppu();
+        ppu.update_ly(DOTS_PER_LINE * LAST_LINE as u32);
+        assert_eq!(LAST_LINE, ppu.read_byte(LY));
+        ppu.update_ly(LY_COMPARE_DELAY - 1);
+        assert_eq!(LAST_LINE, ppu.read_byte(LY));
+        ppu.update_ly(1);
+        assert_eq!(0, ppu.read_byte(LY));
+        assert_eq!(Mode::VBlank, ppu.mode());
+        // still line 0 when the frame really wraps
+        ppu.update_ly(DOTS_PER_LINE - LY_COMPARE_DELAY);
+        assert_eq!(0, ppu.read_byte(LY));
+        assert_eq!(Mode::OamScan, ppu.mode());
+    }
+
+    #[test]
+    fn test_lyc_timing_on_line_153() {
+        // the flag for each dot of the start of line 153
+        let flags = |lyc: u8| {
+            let mut ppu = enabled_ppu();
+            ppu.write_byte(LYC, lyc);
+            ppu.update_ly(DOTS_PER_LINE * LAST_LINE as u32);
+            (0..16)
+                .map(|_| {
+                    let equal = ppu.read_byte(STAT) & LYC_EQUAL != 0;
+                    ppu.update_ly(1);
+                    equal
+                })
+                .collect::<Vec<_>>()
+        };
+        let dots = |range: std::ops::Range<usize>| {
+            (0..16).map(|dot| range.contains(&dot)).collect::<Vec<_>>()
+        };
+        assert_eq!(dots(4..8), flags(LAST_LINE));
+        assert_eq!(dots(12..16), flags(0));
+
+        // an LYC=0 interrupt comes during line 153 and the flag stays set through line 0
+        let mut ppu = enabled_ppu();
+        ppu.write_byte(LYC, 0);
+        ppu.write_byte(STAT, LYC_INTERRUPT);
+        ppu.update_ly(DOTS_PER_LINE * LAST_LINE as u32 + 11);
+        ppu.interrupts = 0;
+        ppu.update_ly(1);
+        assert_eq!(Interrupt::LcdStat.bit(), ppu.interrupts);
+        ppu.interrupts = 0;
+        ppu.update_ly(DOTS_PER_LINE);
+        assert_eq!(0, ppu.interrupts);
+        assert_eq!(LYC_EQUAL, ppu.read_byte(STAT) & LYC_EQUAL);
+    }
+
+    // number of STAT interrupts requested over `dots`
+    fn count_stat_interrupts(ppu: &mut Ppu, dots: u32) -> u32 {
+        let mut count = 0;
+        for _ in 0..dots {
+            ppu.update_ly(1);
+            if ppu.interrupts & Interrupt::LcdStat.bit() != 0 {
+                count += 1;
+            }
+            ppu.interrupts = 0;
+        }
+        count
+    }
+
+    #[test]
+    fn test_stat_line_only_fires_on_rising_edge() {
+        // HBlank runs straight into the next line's OAM scan, the line never drops
+        let mut ppu = enabled_ppu();
+        ppu.write_byte(STAT, HBLANK_INTERRUPT | OAM_INTERRUPT);
+        ppu.update_ly(OAM_SCAN_DOTS);
+        ppu.interrupts = 0;
+        assert_eq!(10, count_stat_interrupts(&mut ppu, DOTS_PER_LINE * 10));
+
+        // LYC holds the line high through the whole line, modes can't fire on top of it
+        let mut ppu = enabled_ppu();
+        ppu.write_byte(LYC, 1);
+        ppu.write_byte(STAT, HBLANK_INTERRUPT | LYC_INTERRUPT);
+        ppu.update_ly(DOTS_PER_LINE);
+        ppu.interrupts = 0;
+        assert_eq!(1, count_stat_interrupts(&mut ppu, DOTS_PER_LINE));
+
+        // every vblank line raises the mode 1 condition, but only the first one fires
+        let mut ppu = enabled_ppu();
+        ppu.write_byte(STAT, VBLANK_INTERRUPT);
+        assert_eq!(
+            1,
+            count_stat_interrupts(&mut ppu, DOTS_PER_LINE * LINES_PER_FRAME as u32)
+        );
+    }
+
+    #[test]
+    fn test_enabling_a_true_condition_fires() {
+        let mut ppu = enabled_ppu();
+        ppu.update_ly(1);
+        assert_eq!(Mode::OamScan, ppu.mode());
+        ppu.write_byte(STAT, OAM_INTERRUPT);
+        assert_eq!(Interrupt::LcdStat.bit(), ppu.interrupts);
+    }
+
+    #[test]
+    fn test_disabling_lcd_blanks_and_resets() {
+        let mut ppu = enabled_ppu();
+        fill_tile(&mut ppu, 0, 0xFF, 0xFF);
+        ppu.write_byte(STAT, HBLANK_INTERRUPT);
+        ppu.update_ly(DOTS_PER_LINE * 3 + 100);
+        assert_eq!(BLACK, ppu.frame_buffer[0]);
+
+        ppu.interrupts = 0;
+        ppu.write_byte(LCDC, BG_TILE_DATA | BG_ENABLE);
+        assert_eq!(0, ppu.read_byte(LY));
+        assert_eq!(Mode::HBlank, ppu.mode());
+        assert!(ppu.front_buffer().iter().all(|&pixel| pixel == WHITE));
+        ppu.update_ly(DOTS_PER_LINE * 3);
+        assert_eq!(0, ppu.read_byte(LY));
+        assert_eq!(0, ppu.interrupts);
+    }
+
+    #[test]
+    fn test_enabling_lcd_skips_first_frame() {
+        let mut ppu = Ppu::new();
+        fill_tile(&mut ppu, 0, 0xFF, 0xFF);
+        ppu.write_byte(BGP, 0xE4);
+        ppu.write_byte(LCDC, LCD_ENABLE | BG_TILE_DATA | BG_ENABLE);
+
+        // line 0 starts without an OAM scan
+        ppu.update_ly(OAM_SCAN_DOTS - 1);
+        assert_eq!(Mode::HBlank, ppu.mode());
+        ppu.update_ly(1);
+        assert_eq!(Mode::PixelTransfer, ppu.mode());
+
+        ppu.update_ly(DOTS_PER_LINE * 144);
+        assert_eq!(Mode::VBlank, ppu.mode());
+        assert!(ppu.front_buffer().iter().all(|&pixel| pixel == WHITE));
+
+        // the next frame is drawn, with the normal OAM scan
+        ppu.update_ly(DOTS_PER_LINE * 10 - OAM_SCAN_DOTS);
+        assert_eq!(0, ppu.read_byte(LY));
+        assert_eq!(Mode::OamScan, ppu.mode());
+        ppu.update_ly(DOTS_PER_LINE);
+        assert_eq!(BLACK, ppu.frame_buffer[0]);
+    }
+
+    #[test]
+    fn test_completed_frame_is_stable_while_drawing() {
+        let mut ppu = enabled_ppu();
+        fill_tile(&mut ppu, 0, 0xFF, 0xFF);
+        assert_eq!(None, ppu.poll_frame());
+        ppu.update_ly(DOTS_PER_LINE * SCREEN_HEIGHT as u32);
+        assert!(ppu
+            .poll_frame()
+            .unwrap()
+            .iter()
+            .all(|&pixel| pixel == BLACK));
+        assert_eq!(None, ppu.poll_frame());
+
+        // halfway into the next frame the old one is still what gets shown
+        fill_tile(&mut ppu, 0, 0x00, 0x00);
+        ppu.update_ly(DOTS_PER_LINE * (LINES_PER_FRAME as u32 - SCREEN_HEIGHT as u32 + 72));
+        assert_eq!(WHITE, ppu.frame_buffer[0]);
+        assert_eq!(BLACK, ppu.frame()[0]);
+        assert_eq!(None, ppu.poll_frame());
+    }
+
+    #[test]
+    fn test_front_and_back_buffer_swap_at_vblank() {
+        let mut ppu = enabled_ppu();
+        fill_tile(&mut ppu, 0, 0xFF, 0xFF);
+        let front = ppu.front_buffer().as_ptr();
+        ppu.update_ly(DOTS_PER_LINE * SCREEN_HEIGHT as u32);
+        // swapped, not copied
+        assert_eq!(front, ppu.frame_buffer.as_ptr());
+        assert_eq!(BLACK, ppu.front_buffer()[0]);
+
+        // a skipped frame after turning the LCD back on shows blank, not the stale buffer
+        ppu.write_byte(LCDC, 0);
+        ppu.frame_buffer.fill(BLACK);
+        ppu.write_byte(LCDC, LCD_ENABLE | BG_TILE_DATA | BG_ENABLE);
+        ppu.update_ly(DOTS_PER_LINE * SCREEN_HEIGHT as u32);
+        assert!(ppu.front_buffer().iter().all(|&pixel| pixel == WHITE));
+    }
+
+    #[test]
+    fn test_ghosting_mixes_in_previous_frame() {
+        let mut ppu = enabled_ppu();
+        ppu.set_ghosting(0.5);
+        fill_tile(&mut ppu, 0, 0xFF, 0xFF);
+        let frame = DOTS_PER_LINE * LINES_PER_FRAME as u32;
+        ppu.update_ly(frame);
+        // half of the white power on frame is left
+        assert_eq!(0xFF808080, ppu.front_buffer()[0]);
+        ppu.update_ly(frame);
+        assert_eq!(0xFF404040, ppu.front_buffer()[0]);
+
+        ppu.set_ghosting(0.0);
+        ppu.update_ly(frame);
+        assert_eq!(BLACK, ppu.front_buffer()[0]);
+        ppu.set_ghosting(2.0);
+        assert_eq!(1.0, ppu.ghosting());
+    }
+
+    #[test]
+    fn test_disabled_lcd_does_not_advance() {
+        let mut ppu = Ppu::new();
+        ppu.update_ly(DOTS_PER_LINE * 3);
+        assert_eq!(0, ppu.read_byte(LY));
+    }
+
+    #[test]
+    fn test_background_scanline() {
+        let mut ppu = enabled_ppu();
+        // tile 1: left half color 3, right half color 1
+        fill_tile(&mut ppu, 1, 0xFF, 0xF0);
+        ppu.write_byte(0x9800, 1);
+        ppu.render_scanline();
+        assert_eq!(BLACK, ppu.frame_buffer[0]);
+        assert_eq!(BLACK, ppu.frame_buffer[3]);
+        assert_eq!(LIGHT, ppu.frame_buffer[4]);
+        assert_eq!(LIGHT, ppu.frame_buffer[7]);
+        // the rest of the map points at empty tile 0
+        assert_eq!(WHITE, ppu.frame_buffer[8]);
+
+        ppu.write_byte(SCX, 4);
+        ppu.render_scanline();
+        assert_eq!(LIGHT, ppu.frame_buffer[0]);
+        assert_eq!(WHITE, ppu.frame_buffer[4]);
+    }
+
+    #[test]
+    fn test_signed_tile_addressing() {
+        let mut ppu = enabled_ppu();
+        ppu.write_byte(LCDC, LCD_ENABLE | BG_ENABLE);
+        // tile 0x80 in 0x8800 mode lives at 0x8800
+        fill_tile(&mut ppu, 0x80, 0x00, 0xFF);
+        ppu.write_byte(0x9800, 0x80);
+        ppu.render_scanline();
+        assert_eq!(DARK, ppu.frame_buffer[0]);
+    }
+
+    #[test]
+    fn test_sprite_over_background() {
+        let mut ppu = enabled_ppu();
+        ppu.write_byte(LCDC, LCD_ENABLE | BG_TILE_DATA | BG_ENABLE | OBJ_ENABLE);
+        fill_tile(&mut ppu, 2, 0xFF, 0xFF);
+        // sprite at screen (4, 0)
+        ppu.write_byte(OAM_START, 16);
+        ppu.write_byte(OAM_START + 1, 12);
+        ppu.write_byte(OAM_START + 2, 2);
+        ppu.render_scanline();
+        assert_eq!(WHITE, ppu.frame_buffer[3]);
+        assert_eq!(BLACK, ppu.frame_buffer[4]);
+        assert_eq!(BLACK, ppu.frame_buffer[11]);
+        assert_eq!(WHITE, ppu.frame_buffer[12]);
+
+        // behind a non-zero background the sprite is hidden
+        fill_tile(&mut ppu, 0, 0xFF, 0x00);
+        ppu.write_byte(OAM_START + 3, BEHIND_BG);
+        ppu.render_scanline();
+        assert_eq!(LIGHT, ppu.frame_buffer[4]);
+    }
+
+    #[test]
+    fn test_dmg_sprite_priority() {
+        let mut ppu = enabled_ppu();
+        ppu.write_byte(LCDC, LCD_ENABLE | BG_TILE_DATA | BG_ENABLE | OBJ_ENABLE);
+        // tile 2 is only solid in its left half, tile 3 everywhere
+        fill_tile(&mut ppu, 2, 0xF0, 0xF0);
+        fill_tile(&mut ppu, 3, 0xFF, 0x00);
+        // sprite 0 at x 8 with tile 3, sprite 1 left of it at x 4 with tile 2,
+        // sprite 2 at x 4 too but later in OAM
+        for (sprite, x, tile) in [(0, 16, 3), (1, 12, 2), (2, 12, 3)] {
+            ppu.write_byte(OAM_START + sprite * 4, 16);
+            ppu.write_byte(OAM_START + sprite * 4 + 1, x);
+            ppu.write_byte(OAM_START + sprite * 4 + 2, tile);
+        }
+        ppu.render_scanline();
+        // the lower x wins over the earlier OAM entry
+        assert_eq!(BLACK, ppu.frame_buffer[4]);
+        assert_eq!(BLACK, ppu.frame_buffer[7]);
+        // where it is transparent the tie at x 4 goes to sprite 2 over sprite 0
+        assert_eq!(LIGHT, ppu.frame_buffer[8]);
+        assert_eq!(LIGHT, ppu.frame_buffer[11]);
+        assert_eq!(LIGHT, ppu.frame_buffer[12]);
+        assert_eq!(WHITE, ppu.frame_buffer[16]);
+    }
+
+    #[test]
+    fn test_dmg_palette_colors() {
+        let mut ppu = enabled_ppu();
+        fill_tile(&mut ppu, 0, 0xFF, 0x00);
+        ppu.set_palette(DmgPalette::CLASSIC_GREEN);
+        ppu.render_scanline();
+        assert_eq!(0xFF8BAC0F, ppu.frame_buffer[0]);
+
+        // switching at runtime shows from the next line on
+        ppu.set_palette(DmgPalette::POCKET);
+        assert_eq!(0xFF8BAC0F, ppu.frame_buffer[0]);
+        ppu.render_scanline();
+        assert_eq!(0xFF8B956D, ppu.frame_buffer[0]);
+
+        // the LCD turned off shows the lightest shade
+        ppu.write_byte(LCDC, 0);
+        assert_eq!(0xFFC4CFA1, ppu.front_buffer()[0]);
+    }
+
+    #[test]
+    fn test_pixel_formats() {
+        let mut ppu = enabled_ppu();
+        fill_tile(&mut ppu, 0, 0xFF, 0x00);
+        ppu.set_pixel_format(PixelFormat::Rgb565);
+        ppu.render_scanline();
+        assert_eq!(PixelFormat::Rgb565.convert(LIGHT), ppu.frame_buffer[0]);
+        assert!(ppu.frame_buffer[..SCREEN_WIDTH]
+            .iter()
+            .all(|&pixel| pixel <= 0xFFFF));
+
+        ppu.set_pixel_format(PixelFormat::Rgba8);
+        let [r, g, b, a] = ppu.frame_buffer[0].to_ne_bytes();
+        assert_eq!(
+            PixelFormat::Rgb565.to_argb(PixelFormat::Rgb565.convert(LIGHT)),
+            u32::from_be_bytes([a, r, g, b])
+        );
+        ppu.write_byte(LCDC, 0);
+        assert_eq!([0xFF; 4], ppu.front_buffer()[0].to_ne_bytes());
+    }
+
+    #[test]
+    fn test_dump_tiles() {
+        let mut ppu = enabled_ppu();
+        // tile 383 has a dark pixel in its top right corner
+        ppu.write_byte(0x97F0, 0x00);
+        ppu.write_byte(0x97F1, 0x01);
+        ppu.write_byte(VRAM_START, 0x80);
+        ppu.set_cgb_mode(true);
+        ppu.write_byte(VBK, 1);
+        fill_tile(&mut ppu, 0, 0xFF, 0xFF);
+
+        let tiles = ppu.dump_tiles(0);
+        assert_eq!(TILE_COUNT, tiles.len());
+        assert_eq!(1, tiles[0][0]);
+        assert_eq!(0, tiles[0][1]);
+        assert_eq!(2, tiles[383][7]);
+        assert!(ppu.dump_tiles(1)[0].iter().all(|&index| index == 3));
+
+        ppu.set_cgb_mode(false);
+        let sheet = ppu.tile_sheet(0);
+        assert_eq!(TILE_SHEET_WIDTH * TILE_SHEET_HEIGHT, sheet.len());
+        assert_eq!(LIGHT, sheet[0]);
+        assert_eq!(
+            DARK,
+            sheet[(TILE_SHEET_HEIGHT - 8) * TILE_SHEET_WIDTH + TILE_SHEET_WIDTH - 1]
+        );
+    }
+
+    #[test]
+    fn test_render_bg_map_with_viewport() {
+        let mut ppu = enabled_ppu();
+        fill_tile(&mut ppu, 1, 0xFF, 0xFF);
+        // bottom right tile of the low map, top left of the high map
+        ppu.write_byte(0x9BFF, 1);
+        ppu.write_byte(0x9C00, 1);
+
+        let map = ppu.render_bg_map(false);
+        assert_eq!(BG_MAP_SIZE * BG_MAP_SIZE, map.len());
+        assert_eq!(WHITE, map[0]);
+        assert_eq!(BLACK, map[BG_MAP_SIZE * BG_MAP_SIZE - 1]);
+        assert_eq!(BLACK, ppu.render_bg_map(true)[0]);
+
+        // a viewport scrolled past the right and bottom edge wraps to the top left
+        ppu.scx = 200;
+        ppu.scy = 250;
+        let mut map = ppu.render_bg_map(false);
+        ppu.draw_viewport(&mut map);
+        assert_eq!(VIEWPORT_COLOR, map[250 * BG_MAP_SIZE + 200]);
+        let (right, bottom) = (
+            (200 + SCREEN_WIDTH - 1) % 256,
+            (250 + SCREEN_HEIGHT - 1) % 256,
+        );
+        assert_eq!(VIEWPORT_COLOR, map[bottom * BG_MAP_SIZE + right]);
+        assert_eq!(WHITE, map[10 * BG_MAP_SIZE + 10]);
+        assert_eq!(BLACK, map[255 * BG_MAP_SIZE + 255 - 1]);
+    }
+
+    #[test]
+    fn test_oam_entries_and_sprite_thumbnails() {
+        let mut ppu = enabled_ppu();
+        ppu.obp1 = 0b1110_0100;
+        // tile 2: top left pixel is color 3, everything else transparent
+        fill_tile(&mut ppu, 2, 0x00, 0x00);
+        ppu.write_byte(VRAM_START + 2 * 16, 0x80);
+        ppu.write_byte(VRAM_START + 2 * 16 + 1, 0x80);
+        let oam = OAM_START + 39 * 4;
+        for (offset, value) in [16, 8, 2, X_FLIP | OBP1_PALETTE].into_iter().enumerate() {
+            ppu.write_byte(oam + offset as u16, value);
+        }
+
+        let entries = ppu.oam_entries();
+        assert_eq!(OAM_ENTRIES, entries.len());
+        let sprite = entries[39];
+        assert_eq!(
+            (39, 8, 16, 2),
+            (sprite.index, sprite.x, sprite.y, sprite.tile)
+        );
+        assert!(sprite.x_flip && sprite.obp1 && !sprite.y_flip && !sprite.behind_bg);
+        assert!(sprite.on_screen(8));
+        assert!(!entries[0].on_screen(8));
+
+        let thumbnail = ppu.sprite_thumbnail(&sprite);
+        assert_eq!(64, thumbnail.len());
+        // flipped into the top right corner
+        assert_eq!(BLACK, thumbnail[7]);
+        assert_eq!(0, thumbnail[0]);
+
+        ppu.write_byte(LCDC, ppu.lcdc | OBJ_SIZE);
+        assert_eq!(128, ppu.sprite_thumbnail(&sprite).len());
+    }
+
+    #[test]
+    fn test_edit_cgb_palettes() {
+        let mut ppu = cgb_ppu(Renderer::Scanline);
+        write_color(&mut ppu, BCPS, 1, 2, 0x001F);
+        let set = ppu.cgb_palettes();
+        assert_eq!(0x001F, set.bg[1][2]);
+        assert_eq!(RED, ppu.cgb_swatches(false)[1][2]);
+
+        ppu.set_cgb_color(true, 3, 1, 0x03E0);
+        assert_eq!(GREEN, ppu.cgb_swatches(true)[3][1]);
+        // the game sees the edit through the data register
+        ppu.write_byte(OCPS, 3 * 8 + 2);
+        assert_eq!(0xE0, ppu.read_byte(OCPD));
+
+        ppu.set_cgb_palettes(&set);
+        assert_eq!(set, ppu.cgb_palettes());
+    }
+
+    #[test]
+    fn test_window_covers_background() {
+        let mut ppu = enabled_ppu();
+        ppu.write_byte(
+            LCDC,
+            LCD_ENABLE | BG_TILE_DATA | BG_ENABLE | WINDOW_ENABLE | WINDOW_TILE_MAP,
+        );
+        fill_tile(&mut ppu, 3, 0xFF, 0xFF);
+        for tile in 0..32 {
+            ppu.write_byte(0x9C00 + tile, 3);
+        }
+        ppu.write_byte(WX, 7 + 80);
+        ppu.render_scanline();
+        assert_eq!(WHITE, ppu.frame_buffer[79]);
+        assert_eq!(BLACK, ppu.frame_buffer[80]);
+    }
+
+    const RED: u32 = 0xFFFF0000;
+    const GREEN: u32 = 0xFF00FF00;
+    const BLUE: u32 = 0xFF0000FF;
+
+    fn cgb_ppu(renderer: Renderer) -> Ppu {
+        let mut ppu = enabled_ppu();
+        ppu.set_cgb_mode(true);
+        ppu.set_renderer(renderer);
+        ppu
+    }
+
+    // set one color through the spec/data register pair starting at `spec`
+    fn write_color(ppu: &mut Ppu, spec: u16, palette: u8, index: u8, rgb555: u16) {
+        ppu.write_byte(spec, palette * 8 + index * 2);
+        ppu.write_byte(spec + 1, rgb555 as u8);
+        ppu.write_byte(spec, palette * 8 + index * 2 + 1);
+        ppu.write_byte(spec + 1, (rgb555 >> 8) as u8);
+    }
+
+    #[test]
+    fn test_cgb_registers() {
+        // not there on DMG
+        let mut ppu = enabled_ppu();
+        ppu.write_byte(BCPS, 0x85);
+        assert_eq!(0xFF, ppu.read_byte(BCPS));
+        assert_eq!(0xFF, ppu.read_byte(VBK));
+
+        let mut ppu = cgb_ppu(Renderer::Scanline);
+        ppu.write_byte(VRAM_START, 0x11);
+        ppu.write_byte(VBK, 1);
+        assert_eq!(0xFF, ppu.read_byte(VBK));
+        assert_eq!(0x00, ppu.read_byte(VRAM_START));
+        ppu.write_byte(VRAM_START, 0x22);
+        ppu.write_byte(VBK, 0);
+        assert_eq!(0xFE, ppu.read_byte(VBK));
+        assert_eq!(0x11, ppu.read_byte(VRAM_START));
+
+        // data writes step through palette memory with auto increment
+        ppu.write_byte(OCPS, 0x80 | 0x3E);
+        for value in [0x1F, 0x00, 0xE0] {
+            ppu.write_byte(OCPD, value);
+        }
+        assert_eq!(0xC1, ppu.read_byte(OCPS));
+        ppu.write_byte(OCPS, 0x3E);
+        assert_eq!(0x1F, ppu.read_byte(OCPD));
+        ppu.write_byte(OCPS, 0x00);
+        assert_eq!(0xE0, ppu.read_byte(OCPD));
+        assert_eq!(RED, ppu.obj_palettes.color(7, 3));
+        assert_eq!(WHITE, ppu.bg_palettes.color(0, 0));
+    }
+
+    #[test]
+    fn test_cgb_tile_attributes() {
+        for renderer in [Renderer::Scanline, Renderer::Fifo] {
+            let mut ppu = cgb_ppu(renderer);
+            // tile 1 is blank in bank 0, in bank 1 only its first column is set
+            ppu.write_byte(VBK, 1);
+            fill_tile(&mut ppu, 1, 0x80, 0x80);
+            ppu.write_byte(0x9800, TILE_BANK | X_FLIP | 1);
+            ppu.write_byte(0x9801, TILE_BANK | 2);
+            ppu.write_byte(VBK, 0);
+            ppu.write_byte(0x9800, 1);
+            ppu.write_byte(0x9801, 1);
+            write_color(&mut ppu, BCPS, 1, 3, 0x001F);
+            write_color(&mut ppu, BCPS, 2, 3, 0x7C00);
+
+            ppu.update_ly(DOTS_PER_LINE);
+            assert_eq!(WHITE, ppu.frame_buffer[0], "{renderer:?}");
+            assert_eq!(RED, ppu.frame_buffer[7], "{renderer:?}");
+            assert_eq!(BLUE, ppu.frame_buffer[8], "{renderer:?}");
+            assert_eq!(WHITE, ppu.frame_buffer[9], "{renderer:?}");
+        }
+    }
+
+    #[test]
+    fn test_cgb_color_correction() {
+        let mut ppu = cgb_ppu(Renderer::Scanline);
+        fill_tile(&mut ppu, 0, 0xFF, 0xFF);
+        write_color(&mut ppu, BCPS, 0, 3, 0x001F);
+        ppu.render_scanline();
+        assert_eq!(RED, ppu.frame_buffer[0]);
+
+        ppu.set_color_correction(true);
+        ppu.render_scanline();
+        assert_eq!(corrected_argb(0x001F), ppu.frame_buffer[0]);
+        assert_ne!(RED, ppu.frame_buffer[0]);
+
+        // DMG colors come from the DMG palette and stay as they are
+        let mut ppu = enabled_ppu();
+        ppu.set_color_correction(true);
+        fill_tile(&mut ppu, 0, 0xFF, 0xFF);
+        ppu.render_scanline();
+        assert_eq!(BLACK, ppu.frame_buffer[0]);
+    }
+
+    #[test]
+    fn test_cgb_sprite_priority() {
+        for renderer in [Renderer::Scanline, Renderer::Fifo] {
+            let scene = |lcdc: u8, bg_attributes: u8| {
+                let mut ppu = cgb_ppu(renderer);
+                ppu.write_byte(LCDC, LCD_ENABLE | BG_TILE_DATA | OBJ_ENABLE | lcdc);
+                fill_tile(&mut ppu, 0, 0xFF, 0x00);
+                fill_tile(&mut ppu, 2, 0xFF, 0xFF);
+                ppu.write_byte(VBK, 1);
+                ppu.write_byte(0x9801, bg_attributes);
+                ppu.write_byte(VBK, 0);
+                // sprite 0 at screen x 12, sprite 1 left of it at 8
+                for (sprite, x, palette) in [(0, 20, 2), (1, 16, 3)] {
+                    ppu.write_byte(OAM_START + sprite * 4, 16);
+                    ppu.write_byte(OAM_START + sprite * 4 + 1, x);
+                    ppu.write_byte(OAM_START + sprite * 4 + 2, 2);
+                    ppu.write_byte(OAM_START + sprite * 4 + 3, palette);
+                }
+                write_color(&mut ppu, OCPS, 2, 3, 0x03E0);
+                write_color(&mut ppu, OCPS, 3, 3, 0x7C00);
+                write_color(&mut ppu, BCPS, 0, 1, 0x001F);
+                ppu.update_ly(DOTS_PER_LINE);
+                ppu
+            };
+
+            // the lower OAM index wins regardless of x
+            let ppu = scene(BG_ENABLE, 0);
+            assert_eq!(BLUE, ppu.frame_buffer[8], "{renderer:?}");
+            assert_eq!(GREEN, ppu.frame_buffer[12], "{renderer:?}");
+            assert_eq!(RED, ppu.frame_buffer[0], "{renderer:?}");
+
+            // a tile with the priority attribute covers sprites
+            let ppu = scene(BG_ENABLE, BEHIND_BG);
+            assert_eq!(RED, ppu.frame_buffer[12], "{renderer:?}");
+
+            // unless LCDC bit 0 hands sprites the master priority
+            let ppu = scene(0, BEHIND_BG);
+            assert_eq!(GREEN, ppu.frame_buffer[12], "{renderer:?}");
+            assert_eq!(RED, ppu.frame_buffer[0], "{renderer:?}");
+        }
+    }
+
+    // a frame with scrolled background, window and overlapping/flipped sprites
+    fn busy_scene(renderer: Renderer) -> Ppu {
+        let mut ppu = enabled_ppu();
+        ppu.set_renderer(renderer);
+        ppu.write_byte(
+            LCDC,
+            LCD_ENABLE | BG_TILE_DATA | BG_ENABLE | OBJ_ENABLE | WINDOW_ENABLE | WINDOW_TILE_MAP,
+        );
+        ppu.write_byte(OBP1, 0x1B);
+        fill_tile(&mut ppu, 1, 0xF0, 0x3C);
+        fill_tile(&mut ppu, 2, 0x81, 0xFF);
+        fill_tile(&mut ppu, 3, 0x0F, 0x55);
+        for tile in 0..0x400 {
+            ppu.write_byte(0x9800 + tile, (tile % 3) as u8 + 1);
+            ppu.write_byte(0x9C00 + tile, (tile % 2) as u8 * 2);
+        }
+        ppu.write_byte(SCX, 3);
+        ppu.write_byte(SCY, 5);
+        ppu.write_byte(WX, 7 + 100);
+        ppu.write_byte(WY, 60);
+        let sprites = [
+            (20, 3, 3, 0),
+            (24, 6, 1, X_FLIP),
+            (30, 50, 2, OBP1_PALETTE | Y_FLIP),
+            (30, 54, 3, BEHIND_BG),
+            (90, 150, 1, 0),
+            (100, 110, 3, X_FLIP | OBP1_PALETTE),
+        ];
+        for (i, (y, x, tile, attributes)) in sprites.into_iter().enumerate() {
+            let addr = OAM_START + i as u16 * 4;
+            ppu.write_byte(addr, y);
+            ppu.write_byte(addr + 1, x);
+            ppu.write_byte(addr + 2, tile);
+            ppu.write_byte(addr + 3, attributes);
+        }
+        ppu.update_ly(DOTS_PER_LINE * LINES_PER_FRAME as u32);
+        ppu
+    }
+
+    // dots spent in mode 3 on the first line
+    fn pixel_transfer_length(ppu: &mut Ppu) -> u32 {
+        ppu.update_ly(OAM_SCAN_DOTS);
+        let mut dots = 0;
+        while ppu.mode() == Mode::PixelTransfer {
+            ppu.update_ly(1);
+            dots += 1;
+        }
+        dots
+    }
+
+    #[test]
+    fn test_fifo_matches_scanline_for_static_frames() {
+        let scanline = busy_scene(Renderer::Scanline);
+        let fifo = busy_scene(Renderer::Fifo);
+        assert!(scanline.frame_buffer == fifo.frame_buffer);
+    }
+
+    #[test]
+    fn test_fifo_mode_3_length() {
+        let mut ppu = enabled_ppu();
+        ppu.set_renderer(Renderer::Fifo);
+        assert_eq!(PIXEL_TRANSFER_DOTS, pixel_transfer_length(&mut ppu));
+
+        // fine scrolling throws away pixels
+        let mut ppu = enabled_ppu();
+        ppu.set_renderer(Renderer::Fifo);
+        ppu.write_byte(SCX, 5);
+        assert_eq!(PIXEL_TRANSFER_DOTS + 5, pixel_transfer_length(&mut ppu));
+
+        // every sprite stalls the FIFO
+        let mut ppu = enabled_ppu();
+        ppu.set_renderer(Renderer::Fifo);
+        ppu.write_byte(LCDC, LCD_ENABLE | BG_TILE_DATA | BG_ENABLE | OBJ_ENABLE);
+        for i in 0..2 {
+            ppu.write_byte(OAM_START + i * 4, 16);
+            ppu.write_byte(OAM_START + i * 4 + 1, 40 + i as u8 * 20);
+        }
+        assert_eq!(
+            PIXEL_TRANSFER_DOTS + 2 * SPRITE_FETCH_DOTS as u32,
+            pixel_transfer_length(&mut ppu)
+        );
+    }
+
+    #[test]
+    fn test_mode_3_stretches_with_scroll_sprites_and_window() {
+        let setups: [&dyn Fn(&mut Ppu); 5] = [
+            &|_| {},
+            &|ppu| ppu.write_byte(SCX, 7),
+            &|ppu| {
+                ppu.write_byte(LCDC, LCD_ENABLE | BG_TILE_DATA | BG_ENABLE | OBJ_ENABLE);
+                for (i, x) in [0, 30, 90, 200].into_iter().enumerate() {
+                    ppu.write_byte(OAM_START + i as u16 * 4, 16);
+                    ppu.write_byte(OAM_START + i as u16 * 4 + 1, x);
+                }
+            },
+            &|ppu| {
+                ppu.write_byte(LCDC, LCD_ENABLE | BG_TILE_DATA | BG_ENABLE | WINDOW_ENABLE);
+                ppu.write_byte(WX, 7 + 40);
+            },
+            &|ppu| {
+                ppu.write_byte(LCDC, LCD_ENABLE | BG_TILE_DATA | BG_ENABLE | WINDOW_ENABLE);
+                ppu.write_byte(WX, 200);
+            },
+        ];
+        let mut lengths = Vec::new();
+        for setup in setups {
+            let mut scanline = enabled_ppu();
+            setup(&mut scanline);
+            let mut fifo = enabled_ppu();
+            fifo.set_renderer(Renderer::Fifo);
+            setup(&mut fifo);
+
+            let length = pixel_transfer_length(&mut scanline);
+            assert_eq!(pixel_transfer_length(&mut fifo), length);
+            lengths.push(length);
+            // HBlank gives the time back, the line is still 456 dots
+            scanline.update_ly(DOTS_PER_LINE - OAM_SCAN_DOTS - length - 1);
+            assert_eq!(Mode::HBlank, scanline.mode());
+            scanline.update_ly(1);
+            assert_eq!(1, scanline.read_byte(LY));
+        }
+        assert_eq!(
+            vec![
+                PIXEL_TRANSFER_DOTS,
+                PIXEL_TRANSFER_DOTS + 7,
+                PIXEL_TRANSFER_DOTS + 3 * SPRITE_FETCH_DOTS as u32,
+                PIXEL_TRANSFER_DOTS + WINDOW_START_DOTS,
+                PIXEL_TRANSFER_DOTS,
+            ],
+            lengths
+        );
+    }
+
+    // black window rows 0-7 and 16-23, dark rows 8-15, over a light background
+    fn window_scene(renderer: Renderer) -> Ppu {
+        let mut ppu = enabled_ppu();
+        ppu.set_renderer(renderer);
+        ppu.write_byte(
+            LCDC,
+            LCD_ENABLE | BG_TILE_DATA | BG_ENABLE | WINDOW_ENABLE | WINDOW_TILE_MAP,
+        );
+        fill_tile(&mut ppu, 1, 0xFF, 0xFF);
+        fill_tile(&mut ppu, 2, 0xFF, 0x00);
+        fill_tile(&mut ppu, 3, 0x00, 0xFF);
+        for tile in 0..0x400 {
+            ppu.write_byte(0x9800 + tile, 2);
+            let row = tile / 32;
+            ppu.write_byte(0x9C00 + tile, if row % 2 == 0 { 1 } else { 3 });
+        }
+        ppu
+    }
+
+    #[test]
+    fn test_window_left_edge_clipping() {
+        for renderer in [Renderer::Scanline, Renderer::Fifo] {
+            let mut ppu = window_scene(renderer);
+            // window column 0 is dark, the rest black
+            fill_tile(&mut ppu, 4, 0x7F, 0xFF);
+            ppu.write_byte(0x9C00, 4);
+            ppu.write_byte(WX, 0);
+            let length = pixel_transfer_length(&mut ppu);
+            ppu.update_ly(DOTS_PER_LINE);
+            assert_eq!(BLACK, ppu.frame_buffer[0], "{renderer:?}");
+            assert_eq!(PIXEL_TRANSFER_DOTS + WINDOW_START_DOTS + 7, length);
+
+            let mut ppu = window_scene(renderer);
+            fill_tile(&mut ppu, 4, 0x7F, 0xFF);
+            ppu.write_byte(0x9C00, 4);
+            ppu.write_byte(WX, 6);
+            ppu.update_ly(DOTS_PER_LINE);
+            assert_eq!(BLACK, ppu.frame_buffer[0], "{renderer:?}");
+            ppu.write_byte(WX, 7);
+            ppu.update_ly(DOTS_PER_LINE);
+            assert_eq!(DARK, ppu.frame_buffer[SCREEN_WIDTH], "{renderer:?}");
+        }
+    }
+
+    #[test]
+    fn test_window_at_wx_166_never_starts() {
+        for renderer in [Renderer::Scanline, Renderer::Fifo] {
+            let mut ppu = window_scene(renderer);
+            ppu.write_byte(WX, 166);
+            assert_eq!(PIXEL_TRANSFER_DOTS, pixel_transfer_length(&mut ppu));
+            ppu.update_ly(DOTS_PER_LINE);
+            assert_eq!(LIGHT, ppu.frame_buffer[SCREEN_WIDTH - 1], "{renderer:?}");
+
+            // one column further left the window shows up
+            ppu.write_byte(WX, 165);
+            ppu.update_ly(DOTS_PER_LINE * 2);
+            assert_eq!(
+                BLACK,
+                ppu.frame_buffer[SCREEN_WIDTH * 3 - 1],
+                "{renderer:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_window_line_counter_skips_hidden_lines() {
+        for renderer in [Renderer::Scanline, Renderer::Fifo] {
+            let mut ppu = window_scene(renderer);
+            ppu.write_byte(WX, 7);
+            ppu.update_ly(DOTS_PER_LINE * 8);
+            // hide the window for lines 8-15
+            ppu.write_byte(
+                LCDC,
+                LCD_ENABLE | BG_TILE_DATA | BG_ENABLE | WINDOW_TILE_MAP,
+            );
+            ppu.update_ly(DOTS_PER_LINE * 8);
+            ppu.write_byte(
+                LCDC,
+                LCD_ENABLE | BG_TILE_DATA | BG_ENABLE | WINDOW_ENABLE | WINDOW_TILE_MAP,
+            );
+            ppu.update_ly(DOTS_PER_LINE);
+
+            assert_eq!(BLACK, ppu.frame_buffer[7 * SCREEN_WIDTH], "{renderer:?}");
+            assert_eq!(LIGHT, ppu.frame_buffer[8 * SCREEN_WIDTH], "{renderer:?}");
+            // line 16 continues with window row 8
+            assert_eq!(DARK, ppu.frame_buffer[16 * SCREEN_WIDTH], "{renderer:?}");
+        }
+    }
+
+    #[test]
+    fn test_window_waits_for_ly_to_match_wy() {
+        for renderer in [Renderer::Scanline, Renderer::Fifo] {
+            let mut ppu = window_scene(renderer);
+            ppu.write_byte(WX, 7);
+            ppu.write_byte(WY, 100);
+            ppu.update_ly(DOTS_PER_LINE * 50);
+            // LY is already past the new WY, the window stays off for this frame
+            ppu.write_byte(WY, 30);
+            ppu.update_ly(DOTS_PER_LINE * (LINES_PER_FRAME as u32 - 50));
+            let frame = ppu.front_buffer();
+            assert_eq!(LIGHT, frame[60 * SCREEN_WIDTH], "{renderer:?}");
+            assert_eq!(LIGHT, frame[120 * SCREEN_WIDTH], "{renderer:?}");
+
+            // next frame it starts on line 30 with its first row
+            ppu.update_ly(DOTS_PER_LINE * 31);
+            assert_eq!(LIGHT, ppu.frame_buffer[29 * SCREEN_WIDTH], "{renderer:?}");
+            assert_eq!(BLACK, ppu.frame_buffer[30 * SCREEN_WIDTH], "{renderer:?}");
+        }
+    }
+
+    #[test]
+    fn test_raster_effects_between_lines() {
+        for renderer in [Renderer::Scanline, Renderer::Fifo] {
+            let mut ppu = enabled_ppu();
+            ppu.set_renderer(renderer);
+            fill_tile(&mut ppu, 1, 0xFF, 0xFF);
+            for column in (0..32).step_by(2) {
+                ppu.write_byte(0x9800 + column, 1);
+            }
+            // like a game's HBlank handler: new scroll and palette for each line
+            let scripted = [(0, 0xE4), (8, 0xE4), (0, 0x1B), (8, 0x1B)];
+            for (line, &(scx, bgp)) in scripted.iter().enumerate() {
+                // still in HBlank of the previous line (or VBlank before line 0)
+                ppu.write_byte(SCX, scx);
+                ppu.write_byte(BGP, bgp);
+                ppu.update_ly(OAM_SCAN_DOTS + 20);
+                // too late for this line
+                ppu.write_byte(SCX, 4);
+                ppu.write_byte(BGP, 0x00);
+                ppu.update_ly(DOTS_PER_LINE - OAM_SCAN_DOTS - 20);
+                assert_eq!(line as u8 + 1, ppu.read_byte(LY));
+            }
+
+            let first_pixels: Vec<u32> = (0..4)
+                .map(|line| ppu.frame_buffer[line * SCREEN_WIDTH])
+                .collect();
+            assert_eq!(
+                vec![BLACK, WHITE, WHITE, BLACK],
+                first_pixels,
+                "{renderer:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_fifo_sees_mid_scanline_scroll() {
+        for renderer in [Renderer::Scanline, Renderer::Fifo] {
+            let mut ppu = enabled_ppu();
+            ppu.set_renderer(renderer);
+            fill_tile(&mut ppu, 1, 0xFF, 0xFF);
+            // black and white tile columns
+            for column in (0..32).step_by(2) {
+                ppu.write_byte(0x9800 + column, 1);
+            }
+            ppu.update_ly(OAM_SCAN_DOTS + PIXEL_TRANSFER_DOTS / 2);
+            ppu.write_byte(SCX, 8);
+            ppu.update_ly(DOTS_PER_LINE);
+
+            assert_eq!(BLACK, ppu.frame_buffer[0], "{renderer:?}");
+            // the fifo scrolls the columns past the write, the scanline renderer only
+            // picks the write up on the next line
+            let expected = match renderer {
+                Renderer::Scanline => BLACK,
+                Renderer::Fifo => WHITE,
+            };
+            assert_eq!(expected, ppu.frame_buffer[144], "{renderer:?}");
+            assert_eq!(WHITE, ppu.frame_buffer[SCREEN_WIDTH], "{renderer:?}");
+        }
+    }
+}
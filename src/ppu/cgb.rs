@@ -0,0 +1,260 @@
+// gameboy color palette memory
+// 8 palettes of 4 colors for the background and 8 more for sprites, each color is a
+// little endian RGB555 word. the game picks a byte through the spec register (BCPS/OCPS)
+// and reads or writes it through the data register (BCPD/OCPD)
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+const PALETTE_RAM_SIZE: usize = 64;
+pub const CGB_PALETTE_COUNT: usize = 8;
+const AUTO_INCREMENT: u8 = 1 << 7;
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PaletteRam {
+    data: Vec<u8>,
+    // byte selected by the spec register
+    index: u8,
+    // step to the next byte after every data write
+    auto_increment: bool,
+}
+
+impl PaletteRam {
+    pub fn new() -> Self {
+        Self {
+            // every color starts out white
+            data: vec![0xFF; PALETTE_RAM_SIZE],
+            index: 0,
+            auto_increment: false,
+        }
+    }
+
+    pub fn read_spec(&self) -> u8 {
+        let increment = if self.auto_increment {
+            AUTO_INCREMENT
+        } else {
+            0
+        };
+        increment | 0x40 | self.index
+    }
+
+    pub fn write_spec(&mut self, value: u8) {
+        self.index = value & 0x3F;
+        self.auto_increment = value & AUTO_INCREMENT != 0;
+    }
+
+    pub fn read_data(&self) -> u8 {
+        self.data[self.index as usize]
+    }
+
+    pub fn write_data(&mut self, value: u8) {
+        self.data[self.index as usize] = value;
+        if self.auto_increment {
+            self.index = (self.index + 1) & 0x3F;
+        }
+    }
+
+    // writes the data register without moving the index
+    pub fn poke_data(&mut self, value: u8) {
+        self.data[self.index as usize] = value;
+    }
+
+    // color `index` of `palette` as an RGB555 word, the unused top bit cleared
+    pub fn rgb555(&self, palette: u8, index: u8) -> u16 {
+        let offset = Self::offset(palette, index);
+        u16::from_le_bytes([self.data[offset], self.data[offset + 1]]) & 0x7FFF
+    }
+
+    pub fn set_rgb555(&mut self, palette: u8, index: u8, value: u16) {
+        let offset = Self::offset(palette, index);
+        self.data[offset..offset + 2].copy_from_slice(&(value & 0x7FFF).to_le_bytes());
+    }
+
+    fn offset(palette: u8, index: u8) -> usize {
+        (palette as usize & 0x7) * 8 + (index as usize & 0x3) * 2
+    }
+
+    // color `index` of `palette` as 0xAARRGGBB
+    pub fn color(&self, palette: u8, index: u8) -> u32 {
+        rgb555_to_argb(self.rgb555(palette, index))
+    }
+}
+
+// how the CGB LCD shows an RGB555 color: darker, less saturated, the channels bleed into
+// each other. mixes in linear light, the matrix is pokefan531's gbc-color shader
+pub fn corrected_argb(rgb555: u16) -> u32 {
+    static TABLE: OnceLock<Vec<u32>> = OnceLock::new();
+    let table = TABLE.get_or_init(|| (0..0x8000).map(correct).collect());
+    table[rgb555 as usize & 0x7FFF]
+}
+
+const LCD_GAMMA: f64 = 2.2;
+const LCD_LUMINANCE: f64 = 0.94;
+// output channel from the red, green and blue input channels
+const LCD_MIX: [[f64; 3]; 3] = [
+    [0.82, 0.24, -0.06],
+    [0.125, 0.665, 0.21],
+    [0.195, 0.075, 0.73],
+];
+
+fn correct(rgb555: u16) -> u32 {
+    let input = [0, 5, 10].map(|shift| {
+        let value = ((rgb555 >> shift) & 0x1F) as f64 / 31.0;
+        value.powf(LCD_GAMMA) * LCD_LUMINANCE
+    });
+    let [r, g, b] = LCD_MIX.map(|weights| {
+        let linear: f64 = weights.iter().zip(input).map(|(w, c)| w * c).sum();
+        (linear.clamp(0.0, 1.0).powf(1.0 / LCD_GAMMA) * 255.0).round() as u32
+    });
+    0xFF000000 | r << 16 | g << 8 | b
+}
+
+// 0bBBBBBGGGGGRRRRR as 0xAARRGGBB
+pub fn rgb555_to_argb(rgb555: u16) -> u32 {
+    // stretch 5 bit channels to 8 bits so 0x1F maps to 0xFF
+    let channel = |shift: u16| {
+        let value = ((rgb555 >> shift) & 0x1F) as u32;
+        value << 3 | value >> 2
+    };
+    0xFF000000 | channel(0) << 16 | channel(5) << 8 | channel(10)
+}
+
+impl Default for PaletteRam {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// every CGB palette as RGB555 words, to export and import for experiments.
+// the text form is one palette per line, "bg0 7FFF 56B5 294A 0000" up to "obj7 ..."
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PaletteSet {
+    pub bg: [[u16; 4]; CGB_PALETTE_COUNT],
+    pub obj: [[u16; 4]; CGB_PALETTE_COUNT],
+}
+
+impl PaletteSet {
+    pub fn from_ram(bg: &PaletteRam, obj: &PaletteRam) -> Self {
+        let read = |ram: &PaletteRam| {
+            std::array::from_fn(|palette| {
+                std::array::from_fn(|index| ram.rgb555(palette as u8, index as u8))
+            })
+        };
+        Self {
+            bg: read(bg),
+            obj: read(obj),
+        }
+    }
+
+    pub fn write_to(&self, bg: &mut PaletteRam, obj: &mut PaletteRam) {
+        for (palettes, ram) in [(&self.bg, bg), (&self.obj, obj)] {
+            for (palette, colors) in palettes.iter().enumerate() {
+                for (index, &color) in colors.iter().enumerate() {
+                    ram.set_rgb555(palette as u8, index as u8, color);
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for PaletteSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, palettes) in [("bg", &self.bg), ("obj", &self.obj)] {
+            for (palette, colors) in palettes.iter().enumerate() {
+                write!(f, "{}{}", name, palette)?;
+                for color in colors {
+                    write!(f, " {:04X}", color)?;
+                }
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// palettes missing from the text stay white
+impl FromStr for PaletteSet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut set = Self {
+            bg: [[0x7FFF; 4]; CGB_PALETTE_COUNT],
+            obj: [[0x7FFF; 4]; CGB_PALETTE_COUNT],
+        };
+        for (number, line) in s.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = || format!("invalid palette on line {}: {}", number + 1, line);
+            let mut fields = line.split_whitespace();
+            let name = fields.next().ok_or_else(invalid)?;
+            let (palettes, palette) = if let Some(palette) = name.strip_prefix("bg") {
+                (&mut set.bg, palette)
+            } else if let Some(palette) = name.strip_prefix("obj") {
+                (&mut set.obj, palette)
+            } else {
+                return Err(invalid());
+            };
+            let palette: usize = palette.parse().map_err(|_| invalid())?;
+            let colors: Vec<u16> = fields
+                .map(|color| u16::from_str_radix(color, 16).map_err(|_| invalid()))
+                .collect::<Result<_, _>>()?;
+            if palette >= CGB_PALETTE_COUNT
+                || colors.len() != 4
+                || colors.iter().any(|&c| c > 0x7FFF)
+            {
+                return Err(invalid());
+            }
+            palettes[palette].copy_from_slice(&colors);
+        }
+        Ok(set)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_color_correction() {
+        // black stays black, white gets a little dimmer
+        assert_eq!(0xFF000000, corrected_argb(0x0000));
+        let [_, r, g, b] = corrected_argb(0x7FFF).to_be_bytes();
+        assert!((240..255).contains(&r) && r == g && g == b, "{r} {g} {b}");
+        // pure red loses saturation to the other channels
+        let [_, r, g, b] = corrected_argb(0x001F).to_be_bytes();
+        assert!(r < 0xFF && g > 0 && b > 0, "{r} {g} {b}");
+    }
+
+    #[test]
+    fn test_palette_set_text_round_trip() {
+        let mut bg = PaletteRam::new();
+        let mut obj = PaletteRam::new();
+        bg.set_rgb555(0, 1, 0x001F);
+        obj.set_rgb555(7, 3, 0x7C00);
+        let set = PaletteSet::from_ram(&bg, &obj);
+        assert_eq!(0x001F, set.bg[0][1]);
+        assert_eq!(0x7C00, set.obj[7][3]);
+
+        let text = set.to_string();
+        assert!(text.starts_with("bg0 7FFF 001F 7FFF 7FFF\n"), "{text}");
+        assert_eq!(Ok(set), text.parse());
+
+        let (mut bg, mut obj) = (PaletteRam::new(), PaletteRam::new());
+        "obj2 0000 1111 2222 3333"
+            .parse::<PaletteSet>()
+            .unwrap()
+            .write_to(&mut bg, &mut obj);
+        assert_eq!(0x3333, obj.rgb555(2, 3));
+        assert_eq!(0x7FFF, bg.rgb555(2, 3));
+
+        assert!("bg8 0000 0000 0000 0000".parse::<PaletteSet>().is_err());
+        assert!("bg0 0000 0000 0000".parse::<PaletteSet>().is_err());
+        assert!("bg0 8000 0000 0000 0000".parse::<PaletteSet>().is_err());
+        assert!("x 0000 0000 0000 0000".parse::<PaletteSet>().is_err());
+        assert!("é 0000 0000 0000 0000".parse::<PaletteSet>().is_err());
+        assert!("bgé 0000 0000 0000 0000".parse::<PaletteSet>().is_err());
+    }
+}
@@ -0,0 +1,262 @@
+// pixel FIFO renderer
+// models mode 3 dot by dot: a fetcher feeds 8 background/window pixels at a time into a
+// FIFO that shifts one pixel to the LCD per dot, sprites are mixed into a second FIFO.
+// registers are read while the line is being drawn, so mid-scanline SCX/WX/palette
+// writes show up where the hardware would show them, and mode 3 length varies with
+// SCX, the window and the number of sprites on the line
+
+use std::collections::VecDeque;
+
+use super::{
+    Ppu, BG_ENABLE, BG_TILE_MAP, OBJ_ENABLE, OBJ_SIZE, SCREEN_WIDTH, TILE_BANK, VRAM_START,
+    WINDOW_TILE_MAP, X_FLIP, Y_FLIP,
+};
+
+// dots before the first pixel reaches the LCD, the first tile fetch is thrown away
+const STARTUP_DOTS: u8 = 12;
+// tile number, low byte and high byte each take two dots
+const FETCH_DOTS: u8 = 6;
+pub(super) const SPRITE_FETCH_DOTS: u8 = 6;
+// the background fetch starts over when the window begins
+pub(super) const WINDOW_START_DOTS: u32 = 6;
+
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct BgPixel {
+    index: u8,
+    // CGB map attributes of the tile the pixel came from
+    attributes: u8,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct ObjPixel {
+    index: u8,
+    attributes: u8,
+    // OAM index, decides overlaps on CGB
+    sprite: u8,
+}
+
+#[derive(Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PixelFifo {
+    bg: VecDeque<BgPixel>,
+    obj: VecDeque<ObjPixel>,
+    // dots spent on the current fetch
+    fetcher_dots: u8,
+    // tile column the fetcher works on, relative to the start of the line or window
+    fetcher_x: u8,
+    tile_number: u8,
+    tile_attributes: u8,
+    fetched: [BgPixel; 8],
+    // pixels pushed to the LCD on this line
+    lcd_x: u8,
+    // pixels still to drop for fine SCX scrolling
+    discard: u8,
+    startup: u8,
+    stall: u8,
+    in_window: bool,
+    // OAM indices of sprites on this line not yet fetched
+    sprites: Vec<usize>,
+}
+
+impl PixelFifo {
+    pub fn is_done(&self) -> bool {
+        self.lcd_x as usize == SCREEN_WIDTH
+    }
+
+    pub fn in_window(&self) -> bool {
+        self.in_window
+    }
+}
+
+impl Ppu {
+    pub(super) fn fifo_start_line(&mut self) {
+        let sprites = if self.lcdc & OBJ_ENABLE != 0 {
+            self.sprites_on_line()
+        } else {
+            Vec::new()
+        };
+        self.fifo = PixelFifo {
+            discard: self.scx % 8,
+            startup: STARTUP_DOTS,
+            sprites,
+            ..PixelFifo::default()
+        };
+    }
+
+    // one dot of mode 3
+    pub(super) fn fifo_step(&mut self) {
+        if self.fifo.stall > 0 {
+            self.fifo.stall -= 1;
+            return;
+        }
+
+        self.fetcher_step();
+        if self.fifo.startup > 0 {
+            self.fifo.startup -= 1;
+            return;
+        }
+
+        if !self.fifo.in_window && self.window_starts_here() {
+            self.fifo.in_window = true;
+            self.fifo.bg.clear();
+            self.fifo.fetcher_x = 0;
+            self.fifo.fetcher_dots = 0;
+            // a window left of the screen edge is clipped, it also ends any fine scrolling
+            self.fifo.discard = 7u8.saturating_sub(self.wx);
+            // the window fetch starts on this dot
+            self.fetcher_step();
+            return;
+        }
+
+        if self.fifo.discard == 0 && self.fetch_sprites() {
+            return;
+        }
+
+        let Some(bg) = self.fifo.bg.pop_front() else {
+            return;
+        };
+        if self.fifo.discard > 0 {
+            self.fifo.discard -= 1;
+            return;
+        }
+
+        let bg_index = if self.cgb || self.lcdc & BG_ENABLE != 0 {
+            bg.index
+        } else {
+            0
+        };
+        let obj = self.fifo.obj.pop_front().unwrap_or_default();
+        let obj = (obj.index != 0).then_some((obj.index, obj.attributes));
+        let color = self.mix_pixel(bg_index, bg.attributes, obj);
+        if !self.skip_frame {
+            self.frame_buffer[self.ly as usize * SCREEN_WIDTH + self.fifo.lcd_x as usize] = color;
+        }
+        self.fifo.lcd_x += 1;
+    }
+
+    fn window_starts_here(&self) -> bool {
+        self.window_on_line() && self.fifo.lcd_x as i16 >= self.wx as i16 - 7
+    }
+
+    fn fetcher_step(&mut self) {
+        if self.fifo.fetcher_dots < FETCH_DOTS {
+            self.fifo.fetcher_dots += 1;
+            match self.fifo.fetcher_dots {
+                2 => (self.fifo.tile_number, self.fifo.tile_attributes) = self.fetch_map_entry(),
+                FETCH_DOTS => self.fifo.fetched = self.fetch_tile_row(),
+                _ => {}
+            }
+            return;
+        }
+
+        // the row is only pushed once the FIFO has drained
+        if self.fifo.bg.is_empty() {
+            self.fifo.bg.extend(self.fifo.fetched);
+            self.fifo.fetcher_x = self.fifo.fetcher_x.wrapping_add(1);
+            self.fifo.fetcher_dots = 0;
+        }
+    }
+
+    fn fetch_map_entry(&self) -> (u8, u8) {
+        let (map_base, column, row) = if self.fifo.in_window {
+            let map: u16 = if self.lcdc & WINDOW_TILE_MAP != 0 {
+                0x9C00
+            } else {
+                0x9800
+            };
+            (map, self.fifo.fetcher_x, self.window_line)
+        } else {
+            let map: u16 = if self.lcdc & BG_TILE_MAP != 0 {
+                0x9C00
+            } else {
+                0x9800
+            };
+            (
+                map,
+                (self.scx / 8).wrapping_add(self.fifo.fetcher_x),
+                self.ly.wrapping_add(self.scy),
+            )
+        };
+        let map_addr = map_base + (row as u16 / 8) * 32 + (column as u16 % 32);
+        self.map_entry(map_addr)
+    }
+
+    fn fetch_tile_row(&self) -> [BgPixel; 8] {
+        let row = if self.fifo.in_window {
+            self.window_line
+        } else {
+            self.ly.wrapping_add(self.scy)
+        };
+        let (tile_number, attributes) = (self.fifo.tile_number, self.fifo.tile_attributes);
+        std::array::from_fn(|x| BgPixel {
+            index: self.bg_tile_pixel(tile_number, attributes, x as u8, row % 8),
+            attributes,
+        })
+    }
+
+    // fetch every sprite that starts at the current LCD position, true if the FIFO stalls
+    fn fetch_sprites(&mut self) -> bool {
+        let lcd_x = self.fifo.lcd_x as i16;
+        let Some(position) = self
+            .fifo
+            .sprites
+            .iter()
+            .position(|&sprite| self.oam[sprite * 4 + 1] as i16 - 8 <= lcd_x)
+        else {
+            return false;
+        };
+        let sprite = self.fifo.sprites.remove(position);
+        self.merge_sprite(sprite);
+        self.fifo.stall = SPRITE_FETCH_DOTS - 1;
+        true
+    }
+
+    fn merge_sprite(&mut self, sprite: usize) {
+        let height: u8 = if self.lcdc & OBJ_SIZE != 0 { 16 } else { 8 };
+        let entry = [
+            self.oam[sprite * 4],
+            self.oam[sprite * 4 + 1],
+            self.oam[sprite * 4 + 2],
+            self.oam[sprite * 4 + 3],
+        ];
+        let attributes = entry[3];
+
+        let mut row = (self.ly as i16 - (entry[0] as i16 - 16)) as u8;
+        if attributes & Y_FLIP != 0 {
+            row = height - 1 - row;
+        }
+        let mut tile = entry[2];
+        if height == 16 {
+            tile &= 0xFE;
+        }
+        let tile_addr = VRAM_START + tile as u16 * 16 + (row as u16 / 8) * 16;
+        let bank = (self.cgb && attributes & TILE_BANK != 0) as u8;
+
+        // pixels left of the LCD position were already shifted out
+        let first_column = (self.fifo.lcd_x as i16 - (entry[1] as i16 - 8)) as usize;
+        for (slot, column) in (first_column..8).enumerate() {
+            let column = if attributes & X_FLIP != 0 {
+                7 - column
+            } else {
+                column
+            };
+            let pixel = ObjPixel {
+                index: self.tile_pixel(bank, tile_addr, column as u8, row % 8),
+                attributes,
+                sprite: sprite as u8,
+            };
+            // sprites fetched earlier win and only transparent slots are filled,
+            // on CGB a lower OAM index takes the pixel over
+            let cgb_wins = |existing: &ObjPixel| {
+                self.cgb && pixel.index != 0 && pixel.sprite < existing.sprite
+            };
+            match self.fifo.obj.get_mut(slot) {
+                Some(existing) if existing.index == 0 || cgb_wins(existing) => *existing = pixel,
+                Some(_) => {}
+                None => self.fifo.obj.push_back(pixel),
+            }
+        }
+    }
+}
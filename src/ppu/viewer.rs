@@ -0,0 +1,189 @@
+// debug views of VRAM for a debugger UI or the command line
+// nothing here changes ppu state, everything is decoded from VRAM as it is right now
+
+use super::{
+    cgb::{PaletteSet, CGB_PALETTE_COUNT},
+    Ppu, BEHIND_BG, CGB_PALETTE, OBJ_SIZE, OBP1_PALETTE, SCREEN_HEIGHT, SCREEN_WIDTH, TILE_BANK,
+    VRAM_START, X_FLIP, Y_FLIP,
+};
+
+pub const TILE_COUNT: usize = 384;
+// the tile sheet lays the tiles out 16 per row
+pub const TILE_SHEET_WIDTH: usize = 16 * 8;
+pub const TILE_SHEET_HEIGHT: usize = TILE_COUNT / 16 * 8;
+// a tile map is 32x32 tiles
+pub const BG_MAP_SIZE: usize = 256;
+// outline drawn by draw_viewport
+pub const VIEWPORT_COLOR: u32 = 0xFFFF0000;
+pub const OAM_ENTRIES: usize = 40;
+
+// one OAM entry with its attribute byte decoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OamEntry {
+    pub index: u8,
+    // raw position, the sprite's top left pixel is at (x - 8, y - 16) on screen
+    pub x: u8,
+    pub y: u8,
+    pub tile: u8,
+    pub attributes: u8,
+    pub behind_bg: bool,
+    pub y_flip: bool,
+    pub x_flip: bool,
+    // DMG palette, OBP1 instead of OBP0
+    pub obp1: bool,
+    // CGB only
+    pub bank: u8,
+    pub cgb_palette: u8,
+}
+
+impl OamEntry {
+    // visible somewhere on a screen line, with `height` 8 or 16
+    pub fn on_screen(&self, height: u8) -> bool {
+        self.y > 16 - height && self.y < SCREEN_HEIGHT as u8 + 16 && self.x > 0 && self.x < 168
+    }
+}
+
+impl Ppu {
+    // the 384 tiles at 0x8000-0x97FF of VRAM `bank`, each as 64 color indices row by row
+    pub fn dump_tiles(&self, bank: u8) -> Vec<[u8; 64]> {
+        (0..TILE_COUNT)
+            .map(|tile| {
+                let tile_addr = VRAM_START + tile as u16 * 16;
+                std::array::from_fn(|pixel| {
+                    self.tile_pixel(bank & 1, tile_addr, (pixel % 8) as u8, (pixel / 8) as u8)
+                })
+            })
+            .collect()
+    }
+
+    // every tile of `bank` in one TILE_SHEET_WIDTH x TILE_SHEET_HEIGHT image, colored with
+    // the background palette (BGP, or CGB palette 0)
+    pub fn tile_sheet(&self, bank: u8) -> Vec<u32> {
+        let tiles = self.dump_tiles(bank);
+        let mut sheet = vec![0; TILE_SHEET_WIDTH * TILE_SHEET_HEIGHT];
+        for (tile, pixels) in tiles.iter().enumerate() {
+            let (left, top) = (tile % 16 * 8, tile / 16 * 8);
+            for (pixel, &index) in pixels.iter().enumerate() {
+                let (x, y) = (left + pixel % 8, top + pixel / 8);
+                sheet[y * TILE_SHEET_WIDTH + x] = self.bg_color(0, index);
+            }
+        }
+        sheet
+    }
+
+    // the whole BG_MAP_SIZE x BG_MAP_SIZE background of the 0x9800 map, or 0x9C00 with
+    // `high_map`, using the current tile data area and palettes
+    pub fn render_bg_map(&self, high_map: bool) -> Vec<u32> {
+        let map_base: u16 = if high_map { 0x9C00 } else { 0x9800 };
+        let mut map = vec![0; BG_MAP_SIZE * BG_MAP_SIZE];
+        for (pixel, color) in map.iter_mut().enumerate() {
+            let (x, y) = (pixel % BG_MAP_SIZE, pixel / BG_MAP_SIZE);
+            let map_addr = map_base + (y / 8 * 32 + x / 8) as u16;
+            let (tile_number, attributes) = self.map_entry(map_addr);
+            let index = self.bg_tile_pixel(tile_number, attributes, x as u8 % 8, y as u8 % 8);
+            *color = self.bg_color(attributes, index);
+        }
+        map
+    }
+
+    // outline the part of a rendered map the screen shows at the current SCX/SCY,
+    // wrapping around the edges like the hardware does
+    pub fn draw_viewport(&self, map: &mut [u32]) {
+        let mut plot = |x: usize, y: usize| {
+            let (x, y) = (
+                (self.scx as usize + x) % BG_MAP_SIZE,
+                (self.scy as usize + y) % BG_MAP_SIZE,
+            );
+            map[y * BG_MAP_SIZE + x] = VIEWPORT_COLOR;
+        };
+        for x in 0..SCREEN_WIDTH {
+            plot(x, 0);
+            plot(x, SCREEN_HEIGHT - 1);
+        }
+        for y in 0..SCREEN_HEIGHT {
+            plot(0, y);
+            plot(SCREEN_WIDTH - 1, y);
+        }
+    }
+
+    // all 40 sprites as they are in OAM right now
+    pub fn oam_entries(&self) -> Vec<OamEntry> {
+        self.oam
+            .chunks(4)
+            .take(OAM_ENTRIES)
+            .enumerate()
+            .map(|(index, entry)| {
+                let attributes = entry[3];
+                OamEntry {
+                    index: index as u8,
+                    y: entry[0],
+                    x: entry[1],
+                    tile: entry[2],
+                    attributes,
+                    behind_bg: attributes & BEHIND_BG != 0,
+                    y_flip: attributes & Y_FLIP != 0,
+                    x_flip: attributes & X_FLIP != 0,
+                    obp1: attributes & OBP1_PALETTE != 0,
+                    bank: (attributes & TILE_BANK != 0) as u8,
+                    cgb_palette: attributes & CGB_PALETTE,
+                }
+            })
+            .collect()
+    }
+
+    // 8 pixel wide image of a sprite as it would be drawn with the current sprite size and
+    // palettes, 8 or 16 rows. transparent pixels are 0
+    pub fn sprite_thumbnail(&self, entry: &OamEntry) -> Vec<u32> {
+        let height: u8 = if self.lcdc & OBJ_SIZE != 0 { 16 } else { 8 };
+        let tile = if height == 16 {
+            entry.tile & 0xFE
+        } else {
+            entry.tile
+        };
+        let bank = if self.cgb { entry.bank } else { 0 };
+        (0..height)
+            .flat_map(|y| (0..8u8).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let x = if entry.x_flip { 7 - x } else { x };
+                let y = if entry.y_flip { height - 1 - y } else { y };
+                let tile_addr = VRAM_START + tile as u16 * 16 + (y as u16 / 8) * 16;
+                match self.tile_pixel(bank, tile_addr, x, y % 8) {
+                    0 => 0,
+                    index => self.obj_color(entry.attributes, index),
+                }
+            })
+            .collect()
+    }
+
+    // every CGB palette as it is in palette memory right now
+    pub fn cgb_palettes(&self) -> PaletteSet {
+        PaletteSet::from_ram(&self.bg_palettes, &self.obj_palettes)
+    }
+
+    // replace palette memory, e.g. with an imported set
+    pub fn set_cgb_palettes(&mut self, set: &PaletteSet) {
+        set.write_to(&mut self.bg_palettes, &mut self.obj_palettes);
+    }
+
+    // change one color while the game runs, it shows from the next pixel drawn with it
+    pub fn set_cgb_color(&mut self, obj: bool, palette: u8, index: u8, rgb555: u16) {
+        let palettes = if obj {
+            &mut self.obj_palettes
+        } else {
+            &mut self.bg_palettes
+        };
+        palettes.set_rgb555(palette, index, rgb555);
+    }
+
+    // the 8 background or sprite palettes as 0xAARRGGBB swatches
+    pub fn cgb_swatches(&self, obj: bool) -> [[u32; 4]; CGB_PALETTE_COUNT] {
+        let palettes = if obj {
+            &self.obj_palettes
+        } else {
+            &self.bg_palettes
+        };
+        std::array::from_fn(|palette| {
+            std::array::from_fn(|index| palettes.color(palette as u8, index as u8))
+        })
+    }
+}
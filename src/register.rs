@@ -1,3 +1,5 @@
+use crate::model::Model;
+
 const MAX_FLAG_VALUE: u8 = 0x00F0;
 
 #[derive(Clone, Copy)]
@@ -8,6 +10,8 @@ pub enum Flags {
     Carry = 1 << 4,     // set if last operation produced result over 255 or under 0
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Register {
     // 8-bit registers
     pub a: u8,
@@ -41,6 +45,24 @@ impl Register {
         }
     }
 
+    // state the boot rom of `model` leaves behind, CGB games look at A to detect color
+    pub fn for_model(model: Model) -> Self {
+        match model {
+            Model::Dmg => Self::new(),
+            Model::Cgb => Self {
+                a: 0x11,
+                f: 0x80,
+                b: 0x00,
+                c: 0x00,
+                d: 0xFF,
+                e: 0x56,
+                h: 0x00,
+                l: 0x0D,
+                ..Self::new()
+            },
+        }
+    }
+
     pub fn get_bc(&self) -> u16 {
         (self.b as u16) << 8 | self.c as u16
     }
@@ -0,0 +1,264 @@
+// MBC3 real time clock
+// seconds, minutes, hours and a 9 bit day counter, counted by a 32768 Hz crystal on the
+// cartridge and kept running by the battery. the game latches the counters into a copy
+// it can read in peace and writes the live ones to set the clock. the HuC3 counts the same
+// time as minutes of the day and days, and keeps it here as well
+//
+// battery saves carry the clock after the ram the way BGB and VBA-M store it, so saves
+// move between emulators: the live and the latched registers as 5 little endian u32 each,
+// then the unix time of the save as a u64 (48 bytes) or a u32 (44 bytes, older VBA-M)
+
+pub const FOOTER_LEN: usize = 48;
+pub const SHORT_FOOTER_LEN: usize = 44;
+
+// what the dots handed to tick add up to in one second
+const DOTS_PER_SECOND: u32 = 4_194_304;
+
+const SECONDS: usize = 0;
+const MINUTES: usize = 1;
+const HOURS: usize = 2;
+const DAY_LOW: usize = 3;
+const DAY_HIGH: usize = 4;
+
+// DH bits
+const DAY_BIT_8: u8 = 0x01;
+const HALT: u8 = 0x40;
+const DAY_CARRY: u8 = 0x80;
+
+// bits of S, M, H, DL and DH that exist
+const REGISTER_MASKS: [u8; 5] = [0x3F, 0x3F, 0x1F, 0xFF, DAY_CARRY | HALT | DAY_BIT_8];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rtc {
+    // S, M, H, DL, DH
+    live: [u8; 5],
+    latched: [u8; 5],
+    // dots into the current second
+    dots: u32,
+}
+
+impl Rtc {
+    pub fn new() -> Self {
+        Self {
+            live: [0; 5],
+            latched: [0; 5],
+            dots: 0,
+        }
+    }
+
+    // `register` is the value written to 0x4000-0x5FFF to select it, 0x08-0x0C
+    pub fn read_byte(&self, register: u8) -> u8 {
+        let index = (register - 0x08) as usize;
+        self.latched[index] | !REGISTER_MASKS[index]
+    }
+
+    pub fn write_byte(&mut self, register: u8, value: u8) {
+        let index = (register - 0x08) as usize;
+        self.live[index] = value & REGISTER_MASKS[index];
+        // setting the seconds restarts the second
+        if index == SECONDS {
+            self.dots = 0;
+        }
+    }
+
+    pub fn latch(&mut self) {
+        self.latched = self.live;
+    }
+
+    // minutes into the current day
+    pub fn minutes(&self) -> u16 {
+        self.live[HOURS] as u16 * 60 + self.live[MINUTES] as u16
+    }
+
+    // the day counter with the carry as a tenth bit
+    pub fn days(&self) -> u16 {
+        self.day() | ((self.live[DAY_HIGH] & DAY_CARRY) as u16) << 2
+    }
+
+    // sets the clock to the start of a minute, minutes past a day move on to the next one
+    pub fn set_time(&mut self, minutes: u16, days: u16) {
+        self.dots = 0;
+        self.live[SECONDS] = 0;
+        self.live[MINUTES] = (minutes % 60) as u8;
+        self.live[HOURS] = (minutes / 60 % 24) as u8;
+        self.live[DAY_HIGH] &= HALT;
+        self.set_day(days as u64 + minutes as u64 / 1440);
+    }
+
+    pub fn tick(&mut self, dots: u32) {
+        if self.is_halted() {
+            return;
+        }
+        self.dots += dots;
+        while self.dots >= DOTS_PER_SECOND {
+            self.dots -= DOTS_PER_SECOND;
+            self.advance_second();
+        }
+    }
+
+    // moves the clock on by the time the emulator was closed
+    pub fn advance(&mut self, mut seconds: u64) {
+        if self.is_halted() {
+            return;
+        }
+        // counters set out of range count up to their mask and wrap without a carry,
+        // step those a second at a time until they are back in range
+        while seconds > 0 && !self.is_in_range() {
+            self.advance_second();
+            seconds -= 1;
+        }
+        let total = self.live[SECONDS] as u64
+            + self.live[MINUTES] as u64 * 60
+            + self.live[HOURS] as u64 * 3600
+            + self.day() as u64 * 86400
+            + seconds;
+        let day = total / 86400;
+        self.live[SECONDS] = (total % 60) as u8;
+        self.live[MINUTES] = (total / 60 % 60) as u8;
+        self.live[HOURS] = (total / 3600 % 24) as u8;
+        self.set_day(day);
+    }
+
+    // the save file footer, `now` in seconds since the unix epoch
+    pub fn to_footer(&self, now: u64) -> [u8; FOOTER_LEN] {
+        let mut footer = [0; FOOTER_LEN];
+        for (i, &value) in self.live.iter().chain(&self.latched).enumerate() {
+            footer[i * 4] = value;
+        }
+        footer[40..].copy_from_slice(&now.to_le_bytes());
+        footer
+    }
+
+    // restores the clock from a save footer and runs it forward to `now`. false if
+    // `footer` has neither of the two lengths
+    pub fn load_footer(&mut self, footer: &[u8], now: u64) -> bool {
+        let saved = match footer.len() {
+            FOOTER_LEN => u64::from_le_bytes(footer[40..48].try_into().unwrap()),
+            SHORT_FOOTER_LEN => u32::from_le_bytes(footer[40..44].try_into().unwrap()) as u64,
+            _ => return false,
+        };
+        for i in 0..5 {
+            self.live[i] = footer[i * 4] & REGISTER_MASKS[i];
+            self.latched[i] = footer[20 + i * 4] & REGISTER_MASKS[i];
+        }
+        self.dots = 0;
+        self.advance(now.saturating_sub(saved));
+        true
+    }
+
+    fn is_halted(&self) -> bool {
+        self.live[DAY_HIGH] & HALT != 0
+    }
+
+    fn is_in_range(&self) -> bool {
+        self.live[SECONDS] < 60 && self.live[MINUTES] < 60 && self.live[HOURS] < 24
+    }
+
+    fn day(&self) -> u16 {
+        ((self.live[DAY_HIGH] & DAY_BIT_8) as u16) << 8 | self.live[DAY_LOW] as u16
+    }
+
+    // days past 511 wrap and set the carry, which stays until the game clears it
+    fn set_day(&mut self, day: u64) {
+        let carry = if day > 0x1FF { DAY_CARRY } else { 0 };
+        self.live[DAY_LOW] = day as u8;
+        self.live[DAY_HIGH] =
+            (self.live[DAY_HIGH] & (DAY_CARRY | HALT)) | carry | (day >> 8) as u8 & DAY_BIT_8;
+    }
+
+    fn advance_second(&mut self) {
+        let counters = [(SECONDS, 60), (MINUTES, 60), (HOURS, 24)];
+        for (index, limit) in counters {
+            let value = (self.live[index] + 1) & REGISTER_MASKS[index];
+            if value != limit {
+                self.live[index] = value;
+                return;
+            }
+            self.live[index] = 0;
+        }
+        self.set_day(self.day() as u64 + 1);
+    }
+}
+
+impl Default for Rtc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clock_counts_and_latches() {
+        let mut rtc = Rtc::new();
+        rtc.write_byte(0x08, 59);
+        rtc.write_byte(0x09, 59);
+        rtc.write_byte(0x0A, 23);
+        rtc.write_byte(0x0B, 0xFF);
+        rtc.write_byte(0x0C, 0x01);
+        rtc.tick(DOTS_PER_SECOND - 1);
+        rtc.latch();
+        assert_eq!(0xC0 | 59, rtc.read_byte(0x08));
+        rtc.tick(1);
+        // the latched copy holds still
+        assert_eq!(0xC0 | 59, rtc.read_byte(0x08));
+        rtc.latch();
+        assert_eq!(0xC0, rtc.read_byte(0x08));
+        assert_eq!(0xE0, rtc.read_byte(0x0A));
+        // day 511 rolled over to 0 with the carry set
+        assert_eq!(0x00, rtc.read_byte(0x0B));
+        assert_eq!(0xBE | DAY_CARRY, rtc.read_byte(0x0C));
+
+        // nothing moves while halted
+        rtc.write_byte(0x0C, HALT);
+        rtc.tick(DOTS_PER_SECOND * 3);
+        rtc.latch();
+        assert_eq!(0xC0, rtc.read_byte(0x08));
+    }
+
+    #[test]
+    fn test_minutes_and_days() {
+        let mut rtc = Rtc::new();
+        rtc.set_time(1439, 0x1FF);
+        assert_eq!(1439, rtc.minutes());
+        assert_eq!(0x1FF, rtc.days());
+        rtc.tick(DOTS_PER_SECOND * 60);
+        assert_eq!(0, rtc.minutes());
+        assert_eq!(0x200, rtc.days());
+        rtc.set_time(1440 + 61, 3);
+        assert_eq!(61, rtc.minutes());
+        assert_eq!(4, rtc.days());
+    }
+
+    #[test]
+    fn test_footer_runs_the_clock_to_now() {
+        let mut rtc = Rtc::new();
+        rtc.write_byte(0x08, 30);
+        rtc.write_byte(0x0A, 22);
+        rtc.latch();
+        let footer = rtc.to_footer(1_000_000);
+        assert_eq!(30, footer[0]);
+        assert_eq!(22, footer[28]);
+
+        // two hours and a minute later the next day
+        let mut loaded = Rtc::new();
+        assert!(loaded.load_footer(&footer, 1_000_000 + 7260));
+        loaded.latch();
+        assert_eq!(30, loaded.read_byte(0x08) & 0x3F);
+        assert_eq!(1, loaded.read_byte(0x09) & 0x3F);
+        assert_eq!(0, loaded.read_byte(0x0A) & 0x1F);
+        assert_eq!(1, loaded.read_byte(0x0B));
+
+        // 44 byte footers carry a 32 bit time
+        let mut short = footer[..SHORT_FOOTER_LEN].to_vec();
+        short[40..44].copy_from_slice(&999_990u32.to_le_bytes());
+        let mut loaded = Rtc::new();
+        assert!(loaded.load_footer(&short, 1_000_000));
+        loaded.latch();
+        assert_eq!(40, loaded.read_byte(0x08) & 0x3F);
+        assert!(!loaded.load_footer(&footer[..10], 0));
+    }
+}
@@ -1,6 +1,47 @@
+// serial port (link cable)
+// a transfer shifts SB out one bit at a time, MSB first, while shifting the peer's bits in
+// with nothing connected every incoming bit is 1. with a link cable (see link.rs) the
+// peer's byte replaces SB when the transfer completes
+
+use std::io;
+use std::time::Duration;
+
+use crate::link::{Packet, Transport};
+
+// bit periods in machine cycles
+// the serial clock is derived from the same divider as the cpu, so in CGB double speed
+// mode it runs twice as fast in real time but the period in cpu cycles stays the same
+const BIT_PERIOD: u32 = 128; // 8192 Hz
+const FAST_BIT_PERIOD: u32 = 4; // 262144 Hz, CGB only
+
+// how long a transfer waits for the peer to answer before it reads 0xFF
+const LINK_TIMEOUT: Duration = Duration::from_millis(100);
+
+const TRANSFER_START: u8 = 0x80;
+const FAST_CLOCK: u8 = 0x02;
+const INTERNAL_CLOCK: u8 = 0x01;
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Serial {
     pub data: u8, // TODO: make private when done testing
     pub control: u8,
+    // SC bit 1 only selects the fast clock on CGB, DMG ignores it
+    fast_clock_available: bool,
+    bits_left: u8,
+    clock: u32,
+    // set when a transfer completes, the bus turns it into the serial interrupt
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub interrupt: bool,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    link: Option<Box<dyn Transport>>,
+    // machine cycles since the link was last checked for packets
+    #[cfg_attr(feature = "serde", serde(skip))]
+    poll_clock: u32,
+    // a transfer went to the peer and its byte hasn't come back yet
+    #[cfg_attr(feature = "serde", serde(skip))]
+    awaiting_reply: bool,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    peer_data: Option<u8>,
 }
 
 impl Serial {
@@ -8,14 +49,37 @@ impl Serial {
         Self {
             data: 0,
             control: 0,
+            fast_clock_available: false,
+            bits_left: 0,
+            clock: 0,
+            interrupt: false,
+            link: None,
+            poll_clock: 0,
+            awaiting_reply: false,
+            peer_data: None,
         }
     }
 
+    pub fn attach_link(&mut self, link: Box<dyn Transport>) {
+        self.link = Some(link);
+    }
+
+    // moves the link over to a bus loaded from a savestate
+    pub fn take_link(&mut self) -> Option<Box<dyn Transport>> {
+        self.awaiting_reply = false;
+        self.link.take()
+    }
+
+    pub fn set_fast_clock_available(&mut self, available: bool) {
+        self.fast_clock_available = available;
+    }
+
     pub fn read_byte(&self, addr: u16) -> u8 {
         match addr {
             0xFF01 => self.data,
-            0xFF02 => self.control,
-            _ => panic!("Serial read error at address: {}", addr),
+            0xFF02 => self.control | self.unused_control_bits(),
+            // not routed here by the bus, reads as open bus
+            _ => 0xFF,
         }
     }
 
@@ -24,13 +88,138 @@ impl Serial {
             0xFF01 => self.data = value,
             0xFF02 => {
                 self.control = value;
-                if value == 0x81 {
-                    self.data = value;
-                    println!("Serial data stuff!!!!");
+                // with the external clock we wait for a peer that never clocks us
+                if value & (TRANSFER_START | INTERNAL_CLOCK) == TRANSFER_START | INTERNAL_CLOCK {
+                    self.bits_left = 8;
+                    self.clock = 0;
+                    self.peer_data = None;
+                    self.awaiting_reply = self.send(Packet::Transfer(self.data));
+                } else {
+                    self.bits_left = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // debugger write, setting the start bit of SC doesn't start a transfer
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        match addr {
+            0xFF01 => self.data = value,
+            0xFF02 => self.control = value,
+            _ => {}
+        }
+    }
+
+    // SC bits that are not connected and read as 1
+    fn unused_control_bits(&self) -> u8 {
+        if self.fast_clock_available {
+            0x7C
+        } else {
+            0x7E
+        }
+    }
+
+    // machine cycles it takes to shift one bit with the current clock selection
+    pub fn bit_period(&self) -> u32 {
+        if self.fast_clock_available && self.control & FAST_CLOCK != 0 {
+            FAST_BIT_PERIOD
+        } else {
+            BIT_PERIOD
+        }
+    }
+
+    pub fn transfer_in_progress(&self) -> bool {
+        self.bits_left > 0
+    }
+
+    pub fn update(&mut self, opcode_cycles: u8) {
+        if self.link.is_some() {
+            self.poll_clock += opcode_cycles as u32;
+            if self.poll_clock >= BIT_PERIOD {
+                self.poll_clock = 0;
+                while self.poll_link(Duration::ZERO) {}
+            }
+        }
+        if self.bits_left == 0 {
+            return;
+        }
+
+        self.clock += opcode_cycles as u32;
+        while self.bits_left > 0 && self.clock >= self.bit_period() {
+            self.clock -= self.bit_period();
+            self.data = self.data << 1 | 1;
+            self.bits_left -= 1;
+            if self.bits_left == 0 {
+                if self.link.is_some() {
+                    self.data = self.receive_peer_data();
+                }
+                self.control &= !TRANSFER_START;
+                self.interrupt = true;
+            }
+        }
+    }
+
+    // the peer's byte for the transfer that just completed, 0xFF if it doesn't answer
+    fn receive_peer_data(&mut self) -> u8 {
+        while self.awaiting_reply && self.poll_link(LINK_TIMEOUT) {}
+        self.awaiting_reply = false;
+        self.peer_data.take().unwrap_or(0xFF)
+    }
+
+    // handles the next packet from the peer, false when none came within `timeout`
+    fn poll_link(&mut self, timeout: Duration) -> bool {
+        let Some(link) = &mut self.link else {
+            return false;
+        };
+        let packet = match link.receive(timeout) {
+            Ok(Some(packet)) => packet,
+            Ok(None) => return false,
+            Err(e) => {
+                self.disconnect(e);
+                return false;
+            }
+        };
+        match packet {
+            // both games drive the clock, the transfers answer each other
+            Packet::Transfer(data) | Packet::Reply(data) if self.awaiting_reply => {
+                self.peer_data = Some(data);
+                self.awaiting_reply = false;
+            }
+            Packet::Transfer(data) => {
+                self.send(Packet::Reply(self.data));
+                // the peer's clock shifts a transfer waiting on the external clock
+                if self.control & (TRANSFER_START | INTERNAL_CLOCK) == TRANSFER_START {
+                    self.data = data;
+                    self.control &= !TRANSFER_START;
+                    self.interrupt = true;
                 }
             }
-            _ => panic!("Serial write error at address: {}", addr),
+            // an answer that came too late
+            Packet::Reply(_) => {}
         }
+        true
+    }
+
+    // false without a link or when sending failed
+    fn send(&mut self, packet: Packet) -> bool {
+        let Some(link) = &mut self.link else {
+            return false;
+        };
+        match link.send(packet) {
+            Ok(()) => true,
+            Err(e) => {
+                self.disconnect(e);
+                false
+            }
+        }
+    }
+
+    // carries on as if the cable was pulled
+    fn disconnect(&mut self, e: io::Error) {
+        eprintln!("link cable disconnected: {}", e);
+        self.link = None;
+        self.awaiting_reply = false;
     }
 }
 
@@ -39,3 +228,167 @@ impl Default for Serial {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+
+    use super::*;
+
+    // run until the transfer completes and return how many cycles it took
+    fn transfer_cycles(serial: &mut Serial) -> u32 {
+        let mut cycles = 0;
+        while serial.transfer_in_progress() {
+            serial.update(1);
+            cycles += 1;
+        }
+        cycles
+    }
+
+    #[test]
+    fn test_foreign_addresses_are_open_bus() {
+        let mut serial = Serial::new();
+        serial.write_byte(0xFF03, 0x81);
+        assert!(!serial.transfer_in_progress());
+        assert_eq!(0xFF, serial.read_byte(0xC000));
+    }
+
+    #[test]
+    fn test_transfer_shifts_in_ones_at_8192_hz() {
+        let mut serial = Serial::new();
+        serial.write_byte(0xFF01, 0x00);
+        serial.write_byte(0xFF02, 0x81);
+        serial.update(BIT_PERIOD as u8);
+        assert_eq!(0x01, serial.read_byte(0xFF01));
+        assert_eq!(0xFF, serial.read_byte(0xFF02));
+        assert!(!serial.interrupt);
+        assert_eq!(7 * BIT_PERIOD, transfer_cycles(&mut serial));
+        assert!(serial.interrupt);
+        assert_eq!(0xFF, serial.read_byte(0xFF01));
+        assert_eq!(0x7F, serial.read_byte(0xFF02));
+    }
+
+    #[test]
+    fn test_fast_clock_on_cgb() {
+        let mut serial = Serial::new();
+        serial.set_fast_clock_available(true);
+        serial.write_byte(0xFF02, 0x83);
+        assert_eq!(8 * FAST_BIT_PERIOD, transfer_cycles(&mut serial));
+
+        serial.write_byte(0xFF02, 0x81);
+        assert_eq!(8 * BIT_PERIOD, transfer_cycles(&mut serial));
+    }
+
+    #[test]
+    fn test_fast_clock_bit_is_ignored_on_dmg() {
+        let mut serial = Serial::new();
+        serial.write_byte(0xFF02, 0x83);
+        assert_eq!(8 * BIT_PERIOD, transfer_cycles(&mut serial));
+    }
+
+    #[test]
+    fn test_external_clock_waits_for_peer() {
+        let mut serial = Serial::new();
+        serial.write_byte(0xFF01, 0x42);
+        serial.write_byte(0xFF02, 0x80);
+        assert!(!serial.transfer_in_progress());
+        serial.update(255);
+        assert_eq!(0x42, serial.read_byte(0xFF01));
+        assert_eq!(0xFE, serial.read_byte(0xFF02));
+    }
+
+    // one end of an in-memory cable
+    struct Cable {
+        outgoing: Rc<RefCell<VecDeque<Packet>>>,
+        incoming: Rc<RefCell<VecDeque<Packet>>>,
+    }
+
+    impl Transport for Cable {
+        fn send(&mut self, packet: Packet) -> io::Result<()> {
+            self.outgoing.borrow_mut().push_back(packet);
+            Ok(())
+        }
+
+        fn receive(&mut self, _: Duration) -> io::Result<Option<Packet>> {
+            Ok(self.incoming.borrow_mut().pop_front())
+        }
+    }
+
+    fn linked() -> (Serial, Serial) {
+        let a = Rc::new(RefCell::new(VecDeque::new()));
+        let b = Rc::new(RefCell::new(VecDeque::new()));
+        let mut first = Serial::new();
+        first.attach_link(Box::new(Cable {
+            outgoing: a.clone(),
+            incoming: b.clone(),
+        }));
+        let mut second = Serial::new();
+        second.attach_link(Box::new(Cable {
+            outgoing: b,
+            incoming: a,
+        }));
+        (first, second)
+    }
+
+    fn run_both(first: &mut Serial, second: &mut Serial) {
+        for _ in 0..8 * BIT_PERIOD {
+            first.update(1);
+            second.update(1);
+        }
+    }
+
+    #[test]
+    fn test_link_exchanges_bytes_with_external_clock_peer() {
+        let (mut master, mut slave) = linked();
+        slave.write_byte(0xFF01, 0x99);
+        slave.write_byte(0xFF02, 0x80);
+        master.write_byte(0xFF01, 0x42);
+        master.write_byte(0xFF02, 0x81);
+        run_both(&mut master, &mut slave);
+        assert!(!master.transfer_in_progress());
+        assert_eq!(0x99, master.read_byte(0xFF01));
+        assert!(master.interrupt);
+        assert_eq!(0x42, slave.read_byte(0xFF01));
+        assert_eq!(0x7E, slave.read_byte(0xFF02));
+        assert!(slave.interrupt);
+    }
+
+    #[test]
+    fn test_link_with_both_sides_driving_the_clock() {
+        let (mut first, mut second) = linked();
+        first.write_byte(0xFF01, 0x12);
+        second.write_byte(0xFF01, 0x34);
+        first.write_byte(0xFF02, 0x81);
+        second.write_byte(0xFF02, 0x81);
+        run_both(&mut first, &mut second);
+        assert_eq!(0x34, first.read_byte(0xFF01));
+        assert_eq!(0x12, second.read_byte(0xFF01));
+        assert!(first.interrupt && second.interrupt);
+    }
+
+    #[test]
+    fn test_link_reads_ff_when_the_peer_is_silent() {
+        let (mut master, _peer) = linked();
+        master.write_byte(0xFF01, 0x42);
+        master.write_byte(0xFF02, 0x81);
+        assert_eq!(8 * BIT_PERIOD, transfer_cycles(&mut master));
+        assert_eq!(0xFF, master.read_byte(0xFF01));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_fixture_last_bit_of_transfer() {
+        let mut serial: Serial =
+            serde_json::from_str(include_str!("../tests/fixtures/serial_last_bit.json")).unwrap();
+        assert!(serial.transfer_in_progress());
+        serial.update(1);
+        assert_eq!(0x7F, serial.read_byte(0xFF01));
+        assert!(serial.transfer_in_progress());
+        serial.update(1);
+        assert!(!serial.transfer_in_progress());
+        assert_eq!(0xFF, serial.read_byte(0xFF01));
+        assert_eq!(0x7F, serial.read_byte(0xFF02));
+    }
+}
@@ -0,0 +1,574 @@
+// Super Game Boy
+// SGB games talk to the SNES by pulsing the joypad select lines: both low starts a
+// packet, P14 low sends a 0 and P15 low a 1, with both lines high between bits. a packet
+// is 16 bytes sent LSB first and ends with a 0 stop bit. the commands handled here color
+// the game screen, frame it with a 256x224 border and ask for more joypads, the rest are
+// ignored
+
+use crate::palette::DmgPalette;
+use crate::ppu::{rgb555_to_argb, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+pub const SGB_WIDTH: usize = 256;
+pub const SGB_HEIGHT: usize = 224;
+// top left corner of the game screen inside the border
+const SCREEN_LEFT: usize = 48;
+const SCREEN_TOP: usize = 40;
+
+const PACKET_LEN: usize = 16;
+// select line bits in P1
+const P14: u8 = 1 << 4;
+const P15: u8 = 1 << 5;
+const SELECT_MASK: u8 = P14 | P15;
+
+// the game screen is colored in 8x8 cells
+const CELLS_X: usize = SCREEN_WIDTH / 8;
+const CELLS_Y: usize = SCREEN_HEIGHT / 8;
+// VRAM transfers copy 4KB
+const TRANSFER_LEN: usize = 0x1000;
+// 256 4bpp tiles
+const BORDER_TILES_LEN: usize = 2 * TRANSFER_LEN;
+// PCT_TRN: a 32x32 map of 16 bit entries, then the four border palettes of 16 colors
+const BORDER_MAP_WIDTH: usize = 32;
+const BORDER_PALETTES: usize = 0x800;
+const BORDER_DATA_LEN: usize = BORDER_PALETTES + 4 * 16 * 2;
+const BLACK: u32 = 0xFF000000;
+
+// command codes, the top 5 bits of the first byte
+const PAL01: u8 = 0x00;
+const PAL23: u8 = 0x01;
+const PAL03: u8 = 0x02;
+const PAL12: u8 = 0x03;
+const ATTR_BLK: u8 = 0x04;
+const PAL_SET: u8 = 0x0A;
+const PAL_TRN: u8 = 0x0B;
+const MLT_REQ: u8 = 0x11;
+const CHR_TRN: u8 = 0x13;
+const PCT_TRN: u8 = 0x14;
+const MASK_EN: u8 = 0x17;
+
+// data a command wants copied out of VRAM, see Ppu::screen_tile_data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transfer {
+    // 512 palettes for PAL_SET
+    Palettes,
+    // half of the border tiles, the upper half when `high`
+    BorderTiles { high: bool },
+    BorderMap,
+}
+
+// what MASK_EN puts over the game screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Mask {
+    #[default]
+    Off,
+    // keep showing the last frame
+    Freeze,
+    Black,
+    // color 0 of the first palette
+    Color0,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Sgb {
+    // select lines of the last P1 write
+    select: u8,
+    // next bit of the packet being received, None between packets
+    bit: Option<usize>,
+    packet: [u8; PACKET_LEN],
+    // packets of a command that spans several
+    command: Vec<u8>,
+    players: u8,
+    player: u8,
+    // four palettes of RGB555 colors, color 0 is shared by all of them
+    palettes: [[u16; 4]; 4],
+    // false until the game sets a palette, the screen keeps the DMG colors until then
+    colorized: bool,
+    // palette of each 8x8 cell of the game screen
+    attributes: Vec<u8>,
+    // from PAL_TRN, four colors each
+    system_palettes: Vec<u16>,
+    border_tiles: Vec<u8>,
+    // from PCT_TRN, empty while there is no border
+    border: Vec<u8>,
+    mask: Mask,
+    frozen: Vec<u32>,
+}
+
+impl Sgb {
+    pub fn new() -> Self {
+        Self {
+            select: SELECT_MASK,
+            bit: None,
+            packet: [0; PACKET_LEN],
+            command: Vec::new(),
+            players: 1,
+            player: 0,
+            palettes: [[0; 4]; 4],
+            colorized: false,
+            attributes: vec![0; CELLS_X * CELLS_Y],
+            system_palettes: vec![0; 512 * 4],
+            border_tiles: vec![0; BORDER_TILES_LEN],
+            border: Vec::new(),
+            mask: Mask::Off,
+            frozen: Vec::new(),
+        }
+    }
+
+    // P1 write, returns the VRAM transfer a finished command asks for
+    pub fn write_joypad(&mut self, value: u8) -> Option<Transfer> {
+        let select = value & SELECT_MASK;
+        let previous = std::mem::replace(&mut self.select, select);
+        match select {
+            0 => {
+                self.bit = Some(0);
+                self.packet = [0; PACKET_LEN];
+                None
+            }
+            SELECT_MASK => {
+                // with several joypads, P15 going back high moves on to the next one
+                if self.bit.is_none() && self.players > 1 && previous & P15 == 0 {
+                    self.player = (self.player + 1) % self.players;
+                }
+                None
+            }
+            // a bit is only sent by leaving the both high state
+            _ if previous != SELECT_MASK => None,
+            _ => {
+                let bit = self.bit?;
+                let one = select & P15 == 0;
+                if bit == PACKET_LEN * 8 {
+                    self.bit = None;
+                    // a packet without its 0 stop bit is dropped
+                    return if one { None } else { self.packet_received() };
+                }
+                if one {
+                    self.packet[bit / 8] |= 1 << (bit % 8);
+                }
+                self.bit = Some(bit + 1);
+                None
+            }
+        }
+    }
+
+    // P1 read of the joypad number with both select lines high, 0xF for the first one.
+    // None when the game only asked for one joypad
+    pub fn joypad_id(&self) -> Option<u8> {
+        (self.players > 1 && self.select == SELECT_MASK).then(|| 0xF0 | (0x0F - self.player))
+    }
+
+    // `data` is Ppu::screen_tile_data at the time of the command
+    pub fn finish_transfer(&mut self, transfer: Transfer, data: &[u8]) {
+        let data = &data[..TRANSFER_LEN.min(data.len())];
+        match transfer {
+            Transfer::Palettes => {
+                for (color, bytes) in self.system_palettes.iter_mut().zip(data.chunks(2)) {
+                    *color = word(bytes, 0);
+                }
+            }
+            Transfer::BorderTiles { high } => {
+                let start = if high { TRANSFER_LEN } else { 0 };
+                self.border_tiles[start..start + data.len()].copy_from_slice(data);
+            }
+            Transfer::BorderMap => self.border = data[..BORDER_DATA_LEN.min(data.len())].to_vec(),
+        }
+    }
+
+    pub fn has_border(&self) -> bool {
+        !self.border.is_empty()
+    }
+
+    // the SGB_WIDTH x SGB_HEIGHT picture for `screen`, a 0xAARRGGBB frame drawn with
+    // `palette`. the frame's colors are matched back to the DMG shades to color them
+    pub fn compose(&mut self, screen: &[u32], palette: &DmgPalette) -> Vec<u32> {
+        let backdrop = if self.colorized {
+            rgb555_to_argb(self.palettes[0][0])
+        } else {
+            palette.color(0)
+        };
+        let mut output = vec![backdrop; SGB_WIDTH * SGB_HEIGHT];
+        if self.has_border() {
+            self.draw_border(&mut output);
+        }
+
+        if self.mask == Mask::Freeze && self.frozen.is_empty() {
+            self.frozen = screen.to_vec();
+        }
+        let screen = match self.mask {
+            Mask::Freeze => &self.frozen,
+            _ => screen,
+        };
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                let color = screen[y * SCREEN_WIDTH + x];
+                let attribute = self.attributes[(y / 8) * CELLS_X + x / 8];
+                output[(SCREEN_TOP + y) * SGB_WIDTH + SCREEN_LEFT + x] = match self.mask {
+                    Mask::Black => BLACK,
+                    Mask::Color0 => backdrop,
+                    _ if self.colorized => {
+                        let shade = nearest_shade(palette, color);
+                        rgb555_to_argb(self.palettes[attribute as usize][shade as usize])
+                    }
+                    _ => color,
+                };
+            }
+        }
+        output
+    }
+
+    fn draw_border(&self, output: &mut [u32]) {
+        for y in 0..SGB_HEIGHT {
+            for x in 0..SGB_WIDTH {
+                let entry = word(&self.border, ((y / 8) * BORDER_MAP_WIDTH + x / 8) * 2);
+                let tile = (entry & 0xFF) as usize * 32;
+                let palette = (entry >> 10) as usize & 0x3;
+                let column = if entry & 0x4000 != 0 {
+                    x % 8
+                } else {
+                    7 - x % 8
+                };
+                let row = if entry & 0x8000 != 0 {
+                    7 - y % 8
+                } else {
+                    y % 8
+                };
+                // four bitplanes, planes 0 and 1 of every row come first, then 2 and 3
+                let color = [0, 1, 16, 17]
+                    .iter()
+                    .enumerate()
+                    .map(|(plane, offset)| {
+                        let bits = self.border_tiles[tile + row * 2 + offset];
+                        ((bits >> column) & 1) << plane
+                    })
+                    .sum::<u8>() as usize;
+                // color 0 is transparent
+                if color != 0 {
+                    let rgb555 = word(&self.border, BORDER_PALETTES + (palette * 16 + color) * 2);
+                    output[y * SGB_WIDTH + x] = rgb555_to_argb(rgb555);
+                }
+            }
+        }
+    }
+
+    fn packet_received(&mut self) -> Option<Transfer> {
+        self.command.extend_from_slice(&self.packet);
+        let packets = (self.command[0] & 0x7).max(1) as usize;
+        if self.command.len() < packets * PACKET_LEN {
+            return None;
+        }
+        let command = std::mem::take(&mut self.command);
+        self.run_command(&command)
+    }
+
+    fn run_command(&mut self, data: &[u8]) -> Option<Transfer> {
+        match data[0] >> 3 {
+            PAL01 => self.set_palette_pair(0, 1, data),
+            PAL23 => self.set_palette_pair(2, 3, data),
+            PAL03 => self.set_palette_pair(0, 3, data),
+            PAL12 => self.set_palette_pair(1, 2, data),
+            ATTR_BLK => self.attribute_blocks(data),
+            PAL_SET => self.set_system_palettes(data),
+            PAL_TRN => return Some(Transfer::Palettes),
+            MLT_REQ => {
+                self.players = match data[1] & 0x3 {
+                    1 => 2,
+                    3 => 4,
+                    _ => 1,
+                };
+                self.player = 0;
+            }
+            CHR_TRN => {
+                return Some(Transfer::BorderTiles {
+                    high: data[1] & 1 != 0,
+                })
+            }
+            PCT_TRN => return Some(Transfer::BorderMap),
+            MASK_EN => {
+                self.mask = match data[1] & 0x3 {
+                    1 => Mask::Freeze,
+                    2 => Mask::Black,
+                    3 => Mask::Color0,
+                    _ => Mask::Off,
+                };
+                self.frozen.clear();
+            }
+            _ => {}
+        }
+        None
+    }
+
+    // color 0 for every palette, then colors 1-3 of `first` and of `second`
+    fn set_palette_pair(&mut self, first: usize, second: usize, data: &[u8]) {
+        let color0 = word(data, 1);
+        for palette in &mut self.palettes {
+            palette[0] = color0;
+        }
+        for index in 1..4 {
+            self.palettes[first][index] = word(data, 1 + index * 2);
+            self.palettes[second][index] = word(data, 7 + index * 2);
+        }
+        self.colorized = true;
+    }
+
+    // four of the PAL_TRN palettes, bit 6 of the flags also lifts the mask
+    fn set_system_palettes(&mut self, data: &[u8]) {
+        for (palette, id) in self.palettes.iter_mut().zip((1..9).step_by(2)) {
+            let start = (word(data, id) & 0x1FF) as usize * 4;
+            palette.copy_from_slice(&self.system_palettes[start..start + 4]);
+        }
+        let color0 = self.palettes[0][0];
+        for palette in &mut self.palettes {
+            palette[0] = color0;
+        }
+        if data[9] & 0x40 != 0 {
+            self.mask = Mask::Off;
+        }
+        self.colorized = true;
+    }
+
+    // rectangles of cells given by their corners, each with a palette for the cells
+    // inside, on the edge and outside of it
+    fn attribute_blocks(&mut self, data: &[u8]) {
+        let count = (data[1] & 0x1F) as usize;
+        for block in data[2..].chunks_exact(6).take(count) {
+            let control = block[0] & 0x7;
+            let inside = block[1] & 0x3;
+            let outside = (block[1] >> 4) & 0x3;
+            // with only the inside or only the outside changing, the edge goes along
+            let edge = match control {
+                1 => Some(inside),
+                4 => Some(outside),
+                _ if control & 2 != 0 => Some((block[1] >> 2) & 0x3),
+                _ => None,
+            };
+            let (x1, y1, x2, y2) = (
+                (block[2] & 0x1F) as usize,
+                (block[3] & 0x1F) as usize,
+                (block[4] & 0x1F) as usize,
+                (block[5] & 0x1F) as usize,
+            );
+            for y in 0..CELLS_Y {
+                for x in 0..CELLS_X {
+                    let within = (x1..=x2).contains(&x) && (y1..=y2).contains(&y);
+                    let on_edge = within && (x == x1 || x == x2 || y == y1 || y == y2);
+                    let palette = if on_edge {
+                        edge
+                    } else if within {
+                        (control & 1 != 0).then_some(inside)
+                    } else {
+                        (control & 4 != 0).then_some(outside)
+                    };
+                    if let Some(palette) = palette {
+                        self.attributes[y * CELLS_X + x] = palette;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Default for Sgb {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// little endian RGB555 color at `offset`
+fn word(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]]) & 0x7FFF
+}
+
+// DMG shade of `palette` closest to `color`, the frame may be blended by ghosting
+fn nearest_shade(palette: &DmgPalette, color: u32) -> u8 {
+    let distance = |shade: u8| {
+        let [_, r, g, b] = palette.color(shade).to_be_bytes();
+        let [_, r2, g2, b2] = color.to_be_bytes();
+        r.abs_diff(r2) as u32 + g.abs_diff(g2) as u32 + b.abs_diff(b2) as u32
+    };
+    (0..4).min_by_key(|&shade| distance(shade)).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+
+    const RED: u16 = 0x001F;
+    const GREEN: u16 = 0x03E0;
+    const BLUE: u16 = 0x7C00;
+
+    // bit bang one packet per 16 bytes of `data`, returns the last transfer requested
+    fn send(sgb: &mut Sgb, data: &[u8]) -> Option<Transfer> {
+        let mut transfer = None;
+        for packet in data.chunks(PACKET_LEN) {
+            sgb.write_joypad(0x00);
+            sgb.write_joypad(0x30);
+            for bit in 0..PACKET_LEN * 8 {
+                let one = packet
+                    .get(bit / 8)
+                    .is_some_and(|byte| byte >> (bit % 8) & 1 != 0);
+                sgb.write_joypad(if one { 0x10 } else { 0x20 });
+                sgb.write_joypad(0x30);
+            }
+            // stop bit
+            transfer = sgb.write_joypad(0x20);
+            sgb.write_joypad(0x30);
+        }
+        transfer
+    }
+
+    fn command(code: u8, packets: u8, args: &[u8]) -> Vec<u8> {
+        let mut data = vec![0; packets as usize * PACKET_LEN];
+        data[0] = code << 3 | packets;
+        data[1..1 + args.len()].copy_from_slice(args);
+        data
+    }
+
+    fn colors(colors: &[u16]) -> Vec<u8> {
+        colors
+            .iter()
+            .flat_map(|color| color.to_le_bytes())
+            .collect()
+    }
+
+    fn screen(shade: u8) -> Vec<u32> {
+        vec![DmgPalette::GRAYSCALE.color(shade); SCREEN_WIDTH * SCREEN_HEIGHT]
+    }
+
+    fn at(output: &[u32], x: usize, y: usize) -> u32 {
+        output[y * SGB_WIDTH + x]
+    }
+
+    #[test]
+    fn test_palettes_and_attribute_blocks() {
+        let mut sgb = Sgb::new();
+        let gray = DmgPalette::GRAYSCALE;
+        // nothing set yet, the DMG colors go through
+        let output = sgb.compose(&screen(3), &gray);
+        assert_eq!(gray.color(3), at(&output, SCREEN_LEFT, SCREEN_TOP));
+        assert_eq!(gray.color(0), at(&output, 0, 0));
+
+        // palette 0 shade 3 red, palette 1 shade 3 blue
+        let pal01 = colors(&[0x7FFF, 0, 0, RED, 0, 0, BLUE]);
+        assert_eq!(None, send(&mut sgb, &command(PAL01, 1, &pal01)));
+        // the top left 2x2 cells are palette 1
+        let block = [1, 0b00_01_01, 0, 0, 1, 1];
+        send(
+            &mut sgb,
+            &command(ATTR_BLK, 1, &[&[1], &block[..]].concat()),
+        );
+
+        let output = sgb.compose(&screen(3), &gray);
+        assert_eq!(0xFFFFFFFF, at(&output, 0, 0));
+        assert_eq!(0xFF0000FF, at(&output, SCREEN_LEFT, SCREEN_TOP));
+        assert_eq!(0xFF0000FF, at(&output, SCREEN_LEFT + 15, SCREEN_TOP + 15));
+        assert_eq!(0xFFFF0000, at(&output, SCREEN_LEFT + 16, SCREEN_TOP));
+
+        send(&mut sgb, &command(MASK_EN, 1, &[2]));
+        let output = sgb.compose(&screen(3), &gray);
+        assert_eq!(BLACK, at(&output, SCREEN_LEFT, SCREEN_TOP));
+    }
+
+    #[test]
+    fn test_attribute_block_edges() {
+        let mut sgb = Sgb::new();
+        // inside 1 only, the edge follows the inside
+        let block = [1, 0b00_00_01, 2, 2, 4, 4];
+        send(
+            &mut sgb,
+            &command(ATTR_BLK, 1, &[&[1], &block[..]].concat()),
+        );
+        assert_eq!(1, sgb.attributes[2 * CELLS_X + 2]);
+        assert_eq!(1, sgb.attributes[3 * CELLS_X + 3]);
+        assert_eq!(0, sgb.attributes[5 * CELLS_X + 5]);
+
+        // edge 2 and outside 3, the inside is left alone
+        let block = [6, 0b11_10_00, 2, 2, 4, 4];
+        send(
+            &mut sgb,
+            &command(ATTR_BLK, 1, &[&[1], &block[..]].concat()),
+        );
+        assert_eq!(2, sgb.attributes[2 * CELLS_X + 4]);
+        assert_eq!(1, sgb.attributes[3 * CELLS_X + 3]);
+        assert_eq!(3, sgb.attributes[0]);
+    }
+
+    #[test]
+    fn test_multiplayer_request() {
+        let mut sgb = Sgb::new();
+        assert_eq!(None, sgb.joypad_id());
+        send(&mut sgb, &command(MLT_REQ, 1, &[1]));
+        assert_eq!(Some(0xFF), sgb.joypad_id());
+        // the game reads the buttons and lets go of P15
+        sgb.write_joypad(0x10);
+        sgb.write_joypad(0x30);
+        assert_eq!(Some(0xFE), sgb.joypad_id());
+        sgb.write_joypad(0x10);
+        sgb.write_joypad(0x30);
+        assert_eq!(Some(0xFF), sgb.joypad_id());
+    }
+
+    #[test]
+    fn test_border_transfers() {
+        let mut sgb = Sgb::new();
+        assert_eq!(
+            Some(Transfer::BorderTiles { high: false }),
+            send(&mut sgb, &command(CHR_TRN, 1, &[0]))
+        );
+        // tile 1 is color 1 in its left column, color 0 elsewhere
+        let mut tiles = vec![0; TRANSFER_LEN];
+        for row in 0..8 {
+            tiles[32 + row * 2] = 0x80;
+        }
+        sgb.finish_transfer(Transfer::BorderTiles { high: false }, &tiles);
+
+        assert_eq!(
+            Some(Transfer::BorderMap),
+            send(&mut sgb, &command(PCT_TRN, 1, &[]))
+        );
+        // tile 1 with border palette 4 at the top left, x flipped next to it
+        let mut map = vec![0; TRANSFER_LEN];
+        map[0..2].copy_from_slice(&(1u16 | 4 << 10).to_le_bytes());
+        map[2..4].copy_from_slice(&(1u16 | 4 << 10 | 0x4000).to_le_bytes());
+        map[BORDER_PALETTES + 2..BORDER_PALETTES + 4].copy_from_slice(&GREEN.to_le_bytes());
+        assert!(!sgb.has_border());
+        sgb.finish_transfer(Transfer::BorderMap, &map);
+        assert!(sgb.has_border());
+
+        let gray = DmgPalette::GRAYSCALE;
+        let output = sgb.compose(&screen(2), &gray);
+        assert_eq!(0xFF00FF00, at(&output, 0, 0));
+        // transparent shows the backdrop
+        assert_eq!(gray.color(0), at(&output, 1, 0));
+        assert_eq!(0xFF00FF00, at(&output, 15, 7));
+        assert_eq!(gray.color(2), at(&output, SCREEN_LEFT, SCREEN_TOP));
+    }
+
+    #[test]
+    fn test_bus_sends_joypad_writes_to_sgb() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x146] = 0x03;
+        rom[0x14B] = 0x33;
+        let mut bus = Bus::from_rom_bytes(rom);
+        assert!(bus.sgb.is_some());
+        assert!(Bus::from_rom_bytes(vec![0; 0x8000]).sgb.is_none());
+
+        // background tile 0 at the top left of the map has a known first byte
+        bus.write_byte(0x8000, 0xA5);
+        let data = command(CHR_TRN, 1, &[0]);
+        for byte in std::iter::once(0x00).chain((0..PACKET_LEN * 8).map(|bit| {
+            if data[bit / 8] >> (bit % 8) & 1 != 0 {
+                0x10
+            } else {
+                0x20
+            }
+        })) {
+            bus.write_byte(0xFF00, byte);
+            bus.write_byte(0xFF00, 0x30);
+        }
+        bus.write_byte(0xFF00, 0x20);
+        bus.write_byte(0xFF00, 0x30);
+        assert_eq!(0xA5, bus.sgb.as_ref().unwrap().border_tiles[0]);
+    }
+}
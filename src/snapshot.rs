@@ -0,0 +1,138 @@
+// read-only machine state for UI panels and overlays
+// the emulation thread publishes a snapshot at every frame boundary, readers on other
+// threads always see one complete frame's state and never lock the core
+
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+
+use crate::{
+    gameboy::{FrameHook, Gameboy},
+    ppu::{SCREEN_HEIGHT, SCREEN_WIDTH},
+    register::Register,
+};
+
+const IO_START: u16 = 0xFF00;
+const IO_END: u16 = 0xFF7F;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MachineSnapshot {
+    pub registers: Register,
+    // 0xFF00-0xFF7F as the cpu would read them
+    pub io: Vec<u8>,
+    pub interrupt_enable: u8,
+    pub frame: Vec<u32>,
+    // rom bank mapped at 0x4000-0x7FFF
+    pub rom_bank: u16,
+}
+
+impl MachineSnapshot {
+    pub fn capture(gameboy: &Gameboy) -> Self {
+        let bus = &gameboy.cpu.bus;
+        Self {
+            registers: gameboy.cpu.registers(),
+            io: (IO_START..=IO_END).map(|addr| bus.peek(addr)).collect(),
+            interrupt_enable: bus.interrupt_enable(),
+            frame: bus.ppu.frame().to_vec(),
+            rom_bank: bus.rom_bank_at(0x4000),
+        }
+    }
+
+    // value of the I/O register at `addr`
+    pub fn io_register(&self, addr: u16) -> u8 {
+        self.io[(addr - IO_START) as usize]
+    }
+}
+
+impl Default for MachineSnapshot {
+    fn default() -> Self {
+        Self {
+            registers: Register::new(),
+            io: vec![0; (IO_END - IO_START) as usize + 1],
+            interrupt_enable: 0,
+            frame: vec![0xFFFFFFFF; SCREEN_WIDTH * SCREEN_HEIGHT],
+            rom_bank: 1,
+        }
+    }
+}
+
+// handed to the UI, cheap to clone and share between threads
+#[derive(Clone)]
+pub struct SnapshotReader {
+    current: Arc<ArcSwap<MachineSnapshot>>,
+}
+
+impl SnapshotReader {
+    // the latest published snapshot, it stays valid while the core moves on
+    pub fn load(&self) -> Arc<MachineSnapshot> {
+        self.current.load_full()
+    }
+}
+
+// owned by the emulation thread
+pub struct SnapshotPublisher {
+    current: Arc<ArcSwap<MachineSnapshot>>,
+}
+
+impl SnapshotPublisher {
+    pub fn new() -> (Self, SnapshotReader) {
+        let current = Arc::new(ArcSwap::from_pointee(MachineSnapshot::default()));
+        let reader = SnapshotReader {
+            current: Arc::clone(&current),
+        };
+        (Self { current }, reader)
+    }
+
+    pub fn publish(&self, snapshot: MachineSnapshot) {
+        self.current.store(Arc::new(snapshot));
+    }
+
+    // frame hook that publishes a snapshot at the end of every frame
+    pub fn into_frame_hook(self) -> FrameHook {
+        Box::new(move |gameboy: &mut Gameboy| self.publish(MachineSnapshot::capture(gameboy)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_snapshots_are_published_at_frame_boundaries() {
+        // INC A; JP 0x0100
+        let mut rom = vec![0; 0x8000];
+        rom[0x0100..0x0104].copy_from_slice(&[0x3C, 0xC3, 0x00, 0x01]);
+        let mut gameboy = Gameboy::from_rom_bytes(rom);
+        let (publisher, reader) = SnapshotPublisher::new();
+        gameboy.add_frame_hook(publisher.into_frame_hook());
+
+        assert_eq!(MachineSnapshot::default(), *reader.load());
+        gameboy.run_frame();
+        let snapshot = reader.load();
+        assert_eq!(gameboy.cpu.registers(), snapshot.registers);
+        assert_eq!(144, snapshot.io_register(0xFF44));
+        assert_eq!(0x91, snapshot.io_register(0xFF40));
+        assert_eq!(1, snapshot.rom_bank);
+
+        // mid-frame progress is not visible until the frame ends
+        gameboy.run_for_cycles(100);
+        assert_eq!(snapshot.registers, reader.load().registers);
+    }
+
+    #[test]
+    fn test_reader_on_another_thread() {
+        let (publisher, reader) = SnapshotPublisher::new();
+        let handle = thread::spawn(move || {
+            let mut seen = 0;
+            while seen != 0x42 {
+                seen = reader.load().interrupt_enable;
+            }
+        });
+        publisher.publish(MachineSnapshot {
+            interrupt_enable: 0x42,
+            ..MachineSnapshot::default()
+        });
+        handle.join().unwrap();
+    }
+}
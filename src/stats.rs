@@ -0,0 +1,160 @@
+// emulation speed statistics
+// results of headless runs in a form other tools can read, to track performance and
+// compatibility across versions. csv for spreadsheets, json with the serde feature
+// fps is emulation speed
+
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::gameboy::{Gameboy, StopReason};
+use crate::pacing::GAMEBOY_FRAME_RATE;
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct RomStats {
+    pub rom: String,
+    pub frames: u64,
+    // machine cycles
+    pub cycles: u64,
+    // wall clock time the run took
+    pub seconds: f64,
+    // why the rom stopped early, None when it ran all frames
+    pub failure: Option<String>,
+}
+
+impl RomStats {
+    // run `frames` frames as fast as possible and time them
+    pub fn measure(rom: &str, gameboy: &mut Gameboy, frames: u64) -> Self {
+        let mut stats = Self {
+            rom: rom.to_string(),
+            frames: 0,
+            cycles: 0,
+            seconds: 0.0,
+            failure: None,
+        };
+        let start = Instant::now();
+        while stats.frames < frames {
+            let summary = gameboy.run_frame();
+            stats.cycles += summary.cycles;
+            stats.frames += 1;
+            stats.failure = match summary.reason {
+                StopReason::Halted => Some("halted with interrupts disabled".to_string()),
+                StopReason::Stuck(stuck) => Some(stuck.to_string()),
+                _ => None,
+            };
+            if stats.failure.is_some() {
+                break;
+            }
+        }
+        stats.seconds = start.elapsed().as_secs_f64();
+        stats
+    }
+
+    pub fn fps(&self) -> f64 {
+        if self.seconds > 0.0 {
+            self.frames as f64 / self.seconds
+        } else {
+            0.0
+        }
+    }
+
+    // multiple of real hardware speed
+    pub fn speed(&self) -> f64 {
+        self.fps() / GAMEBOY_FRAME_RATE
+    }
+}
+
+// one line per rom after a header line
+pub fn to_csv(stats: &[RomStats]) -> String {
+    // quote fields that could contain the separator, doubling embedded quotes
+    let quote = |field: &str| {
+        if field.contains([',', '"', '\n']) {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field.to_string()
+        }
+    };
+    let mut csv = String::from("rom,frames,cycles,seconds,fps,failure\n");
+    for rom in stats {
+        let _ = writeln!(
+            csv,
+            "{},{},{},{:.3},{:.1},{}",
+            quote(&rom.rom),
+            rom.frames,
+            rom.cycles,
+            rom.seconds,
+            rom.fps(),
+            quote(rom.failure.as_deref().unwrap_or(""))
+        );
+    }
+    csv
+}
+
+#[cfg(feature = "serde")]
+pub fn to_json(stats: &[RomStats]) -> Result<String, String> {
+    serde_json::to_string_pretty(stats).map_err(|e| e.to_string())
+}
+
+// json for a .json path, csv otherwise
+pub fn write(path: &Path, stats: &[RomStats]) -> io::Result<()> {
+    let contents = match path.extension().and_then(|extension| extension.to_str()) {
+        #[cfg(feature = "serde")]
+        Some("json") => {
+            to_json(stats).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+        }
+        #[cfg(not(feature = "serde"))]
+        Some("json") => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "json stats need the serde feature",
+            ))
+        }
+        _ => to_csv(stats),
+    };
+    fs::write(path, contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_measure_and_export_csv() {
+        // JR -2
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x102].copy_from_slice(&[0x18, 0xFE]);
+        let mut gameboy = Gameboy::from_rom_bytes(rom);
+        gameboy.watchdog = None;
+        let stats = RomStats::measure("loop, \"test\".gb", &mut gameboy, 3);
+        assert_eq!(3, stats.frames);
+        assert!(stats.cycles > 0);
+        assert_eq!(None, stats.failure);
+
+        let csv = to_csv(&[stats]);
+        let mut lines = csv.lines();
+        assert_eq!(Some("rom,frames,cycles,seconds,fps,failure"), lines.next());
+        let row = lines.next().unwrap();
+        assert!(row.starts_with("\"loop, \"\"test\"\".gb\",3,"), "{row}");
+        assert!(row.ends_with(','), "{row}");
+        assert_eq!(None, lines.next());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_export_json() {
+        let stats = RomStats {
+            rom: "a.gb".to_string(),
+            frames: 60,
+            cycles: 1_053_360,
+            seconds: 0.5,
+            failure: Some("halted".to_string()),
+        };
+        let json: serde_json::Value = serde_json::from_str(&to_json(&[stats]).unwrap()).unwrap();
+        assert_eq!("a.gb", json[0]["rom"]);
+        assert_eq!(60, json[0]["frames"]);
+        assert_eq!("halted", json[0]["failure"]);
+    }
+}
@@ -1,116 +1,193 @@
 // built-in timer in the gameboy
 
-// TIMA timer updates at a configurable rate, depends on frequency set in TAC register
-// when TIMA overflows an interrupt is issued and TIMA is reset to TMA's value
-// should only increment timer if timer is enabled in TAC register
+// a 16 bit system counter runs at the cpu clock, DIV is its upper byte. TIMA counts the
+// falling edges of one of its bits, picked by TAC. when TIMA overflows it reads 0 for one
+// machine cycle, then TMA is loaded into it and the interrupt is requested.
+// TAC bit 2 enables TIMA, its edge detector sees the selected bit ANDed with the enable
 // NOTE: we are doing machine cycles and not clock cycles
 
-// TODO: check if timer bit is active or not in tac
+const TAC_ENABLE: u8 = 0x04;
 
-const MAX_M_CYCLES_FOR_OPCODE: u8 = 4;
-
-pub struct Clock {
-    primary: u32,
-    div: u32,
-    tima: u32,
-    instr_cycles: u32,
+// what the debugger shows of the timer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerDebugState {
+    // the whole system counter, DIV is the upper 8 bits
+    pub counter: u16,
+    pub tima: u8,
+    pub tma: u8,
+    pub tac: u8,
+    pub enabled: bool,
+    // TIMA increments per second
+    pub frequency: u32,
+    // bit of the counter TIMA counts the falling edges of
+    pub selected_bit: u8,
+    pub overflow_pending: bool,
+    // machine cycles until the next increment, None while disabled
+    pub next_increment: Option<u16>,
 }
 
+// clock cycles per machine cycle
+const COUNTER_STEP: u16 = 4;
+// clock cycles per second
+const CPU_FREQUENCY: u32 = 4_194_304;
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct Timer {
-    // divider register
-    // used to update sweep(channel 1), fade in/out
-    div: u8,
+    // system counter in clock cycles, DIV is the upper 8 bits
+    counter: u16,
     // timer counter
-    // updates at a specific rate, 16384 Hz
-    // cpu is 4.12 Mhz => 4194304 Hz / 16384 Hz = 256 clock cycles
-    // in machine cycles: 262144 Hz / 16384 Hz = 16 machines cycles
     tima: u8,
     // timer modulo
     tma: u8,
     // timer control
     tac: u8,
+    // TIMA overflowed in the last machine cycle, TMA is loaded in the next one
+    overflow_pending: bool,
+    // TMA is being loaded this machine cycle: TIMA writes are lost and TMA writes go
+    // through to TIMA as well
+    reloading: bool,
     // enable interrupt
     pub interrupt: bool,
-
-    speed: u8,
-    pub clock: Clock,
 }
 
 impl Timer {
     pub fn new() -> Self {
         Self {
-            div: 0,
+            counter: 0,
             tima: 0,
             tma: 0,
             tac: 0,
+            overflow_pending: false,
+            reloading: false,
             interrupt: false,
+        }
+    }
 
-            speed: 0,
-            clock: Clock {
-                primary: 0,
-                div: 0,
-                tima: 0,
-                instr_cycles: 0,
-            },
+    pub fn update(&mut self, m_cycles: u8) {
+        for _ in 0..m_cycles {
+            self.step();
         }
     }
 
-    pub fn update(&mut self, opcode_cycles: u8) {
-        // check if 4 m-cycles have occured
-        // since no opcode takes more than 4 m-cycles
-        self.clock.instr_cycles += opcode_cycles as u32;
-        if self.clock.instr_cycles >= MAX_M_CYCLES_FOR_OPCODE as u32 {
-            self.clock.primary += 1;
-            self.clock.div += 1;
-            self.clock.instr_cycles -= MAX_M_CYCLES_FOR_OPCODE as u32;
-            if self.clock.div == 0x10 {
-                self.div = self.div.wrapping_add(1);
-                self.clock.div = 0;
-            }
+    // one machine cycle
+    fn step(&mut self) {
+        self.reloading = false;
+        if std::mem::take(&mut self.overflow_pending) {
+            self.tima = self.tma;
+            self.interrupt = true;
+            self.reloading = true;
         }
+        self.set_counter(self.counter.wrapping_add(COUNTER_STEP));
+    }
 
-        self.get_clock_speed();
-
-        // increment timer(tima) by 1 when primary clock surpasses timer speed
-        if self.clock.primary >= self.speed as u32 {
-            self.clock.primary = 0;
-            self.tima += 1;
-            if self.clock.tima > 0xFF {
-                println!("INTERRUPT NOOOOOOOOW");
-                self.tima = self.tma;
-                self.clock.tima -= 0xFF;
-                self.interrupt = true;
-            }
+    // TIMA increments when the selected counter bit goes from high to low, whatever the
+    // reason for the change
+    fn set_counter(&mut self, counter: u16) {
+        let old = self.timer_signal();
+        self.counter = counter;
+        self.check_falling_edge(old);
+    }
+
+    // disabling the timer or selecting another bit can be a falling edge too
+    fn set_tac(&mut self, value: u8) {
+        let old = self.timer_signal();
+        self.tac = value & 0x7;
+        self.check_falling_edge(old);
+    }
+
+    fn check_falling_edge(&mut self, old: bool) {
+        if old && !self.timer_signal() {
+            self.increment_tima();
         }
     }
 
+    fn timer_signal(&self) -> bool {
+        self.tac & TAC_ENABLE != 0 && self.counter & self.selected_bit() != 0
+    }
+
+    fn increment_tima(&mut self) {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        self.tima = tima;
+        self.overflow_pending = overflow;
+    }
+
     pub fn read_byte(&self, addr: u16) -> u8 {
         match addr {
-            0xFF04 => self.div,
+            0xFF04 => (self.counter >> 8) as u8,
             0xFF05 => self.tima,
             0xFF06 => self.tma,
-            0xFF07 => self.speed,
-            _ => panic!("timer.read_byte() went wrong at: {}", addr),
+            // only the low 3 bits exist
+            0xFF07 => 0xF8 | self.tac,
+            // not routed here by the bus, reads as open bus
+            _ => 0xFF,
         }
     }
 
     pub fn write_byte(&mut self, addr: u16, value: u8) {
         match addr {
-            0xFF04 => self.div = 0x00,
+            // resets the whole counter, TIMA sees a falling edge if the selected bit was set
+            0xFF04 => self.set_counter(0),
+            // a write during the reload loses to TMA, one before it cancels the reload and
+            // the interrupt
+            0xFF05 if !self.reloading => {
+                self.tima = value;
+                self.overflow_pending = false;
+            }
+            0xFF05 => {}
+            0xFF06 => {
+                self.tma = value;
+                if self.reloading {
+                    self.tima = value;
+                }
+            }
+            0xFF07 => self.set_tac(value),
+            _ => {}
+        }
+    }
+
+    // debugger write straight into the registers, DIV sets the upper byte of the counter.
+    // nothing sees a falling edge and a pending reload is left as it is
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        match addr {
+            0xFF04 => self.counter = (self.counter & 0x00FF) | (value as u16) << 8,
             0xFF05 => self.tima = value,
             0xFF06 => self.tma = value,
             0xFF07 => self.tac = value & 0x7,
-            _ => panic!("timer.write_byte() went wrong at: {}", addr),
+            _ => {}
+        }
+    }
+
+    pub fn debug_state(&self) -> TimerDebugState {
+        let bit = self.selected_bit();
+        let enabled = self.tac & TAC_ENABLE != 0;
+        // the bit falls whenever the counter passes a multiple of twice its value
+        let period = bit << 1;
+        TimerDebugState {
+            counter: self.counter,
+            tima: self.tima,
+            tma: self.tma,
+            tac: self.tac,
+            enabled,
+            frequency: CPU_FREQUENCY / period as u32,
+            selected_bit: bit.trailing_zeros() as u8,
+            overflow_pending: self.overflow_pending,
+            next_increment: enabled
+                .then(|| (period - self.counter % period).div_ceil(COUNTER_STEP)),
         }
     }
 
-    fn get_clock_speed(&mut self) {
-        self.speed = match self.tac & 0x3 {
-            0x00 => 0x40,
-            0x01 => 0x1,
-            0x02 => 0x4,
-            0x03 => 0x10,
-            _ => panic!("not valid tac speeds"),
+    // bit of the system counter whose falling edge clocks TIMA
+    fn selected_bit(&self) -> u16 {
+        match self.tac & 0x3 {
+            // 4096 Hz
+            0x00 => 1 << 9,
+            // 262144 Hz
+            0x01 => 1 << 3,
+            // 65536 Hz
+            0x02 => 1 << 5,
+            // 16384 Hz
+            _ => 1 << 7,
         }
     }
 }
@@ -120,3 +197,179 @@ impl Default for Timer {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMA: u16 = 0xFF05;
+    const TMA: u16 = 0xFF06;
+    const TAC: u16 = 0xFF07;
+
+    // a timer at 262144 Hz with TIMA one increment away from overflowing
+    fn about_to_overflow() -> Timer {
+        let mut timer = Timer::new();
+        timer.write_byte(TAC, 0x05);
+        timer.write_byte(TMA, 0x80);
+        timer.write_byte(TIMA, 0xFF);
+        // the edge comes every 4 machine cycles
+        timer.update(3);
+        assert_eq!(0xFF, timer.read_byte(TIMA));
+        timer
+    }
+
+    #[test]
+    fn test_overflow_reloads_a_cycle_later() {
+        let mut timer = about_to_overflow();
+        timer.update(1);
+        assert_eq!(0x00, timer.read_byte(TIMA));
+        assert!(!timer.interrupt);
+        timer.update(1);
+        assert_eq!(0x80, timer.read_byte(TIMA));
+        assert!(timer.interrupt);
+    }
+
+    #[test]
+    fn test_tima_write_before_the_reload_cancels_it() {
+        let mut timer = about_to_overflow();
+        timer.update(1);
+        timer.write_byte(TIMA, 0x42);
+        timer.update(1);
+        assert_eq!(0x42, timer.read_byte(TIMA));
+        assert!(!timer.interrupt);
+    }
+
+    #[test]
+    fn test_writes_during_the_reload() {
+        let mut timer = about_to_overflow();
+        timer.update(2);
+        // TIMA keeps the reloaded value, a new TMA is taken right away
+        timer.write_byte(TIMA, 0x42);
+        assert_eq!(0x80, timer.read_byte(TIMA));
+        timer.write_byte(TMA, 0x90);
+        assert_eq!(0x90, timer.read_byte(TIMA));
+        // after the reload cycle TMA writes stay in TMA
+        timer.update(1);
+        timer.write_byte(TMA, 0xA0);
+        assert_eq!(0x90, timer.read_byte(TIMA));
+    }
+
+    #[test]
+    fn test_div_write_glitches_tima() {
+        const DIV: u16 = 0xFF04;
+        let mut timer = Timer::new();
+        // 262144 Hz, the selected bit is set for the last 2 of every 4 machine cycles
+        timer.write_byte(TAC, 0x05);
+        timer.update(2);
+        timer.write_byte(DIV, 0x42);
+        assert_eq!(1, timer.read_byte(TIMA));
+        assert_eq!(0, timer.read_byte(DIV));
+        // with the bit clear the reset is not an edge
+        timer.update(1);
+        timer.write_byte(DIV, 0);
+        assert_eq!(1, timer.read_byte(TIMA));
+
+        // resetting DIV faster than the timer ticks keeps counting, but only while the
+        // bit goes high in between
+        for _ in 0..10 {
+            timer.update(2);
+            timer.write_byte(DIV, 0);
+        }
+        assert_eq!(11, timer.read_byte(TIMA));
+        for _ in 0..10 {
+            timer.update(1);
+            timer.write_byte(DIV, 0);
+        }
+        assert_eq!(11, timer.read_byte(TIMA));
+
+        // 4096 Hz only ever ticks from the reset once the counter reaches bit 9
+        timer.write_byte(TAC, 0x04);
+        for _ in 0..10 {
+            timer.update(0x7F);
+            timer.write_byte(DIV, 0);
+        }
+        assert_eq!(11, timer.read_byte(TIMA));
+        timer.update(0x80);
+        timer.write_byte(DIV, 0);
+        assert_eq!(12, timer.read_byte(TIMA));
+    }
+
+    #[test]
+    fn test_tac_frequencies() {
+        // machine cycles per TIMA increment at 4096, 262144, 65536 and 16384 Hz
+        for (select, period) in [(0x00, 256), (0x01, 4), (0x02, 16), (0x03, 64)] {
+            let mut timer = Timer::new();
+            timer.write_byte(TAC, TAC_ENABLE | select);
+            assert_eq!(0xFC | select, timer.read_byte(TAC));
+            for _ in 0..period - 1 {
+                timer.update(1);
+            }
+            assert_eq!(0, timer.read_byte(TIMA));
+            timer.update(1);
+            assert_eq!(1, timer.read_byte(TIMA));
+            for _ in 0..3 * period {
+                timer.update(1);
+            }
+            assert_eq!(4, timer.read_byte(TIMA));
+        }
+    }
+
+    #[test]
+    fn test_tac_enable_gates_tima() {
+        let mut timer = Timer::new();
+        timer.write_byte(TAC, 0x01);
+        assert_eq!(0xF9, timer.read_byte(TAC));
+        timer.update(200);
+        assert_eq!(0, timer.read_byte(TIMA));
+
+        // disabling while the selected bit is set is a falling edge
+        timer.write_byte(TAC, TAC_ENABLE | 0x01);
+        timer.write_byte(0xFF04, 0);
+        timer.update(2);
+        timer.write_byte(TAC, 0x01);
+        assert_eq!(1, timer.read_byte(TIMA));
+        // with the bit clear it is not
+        timer.write_byte(0xFF04, 0);
+        timer.write_byte(TAC, TAC_ENABLE | 0x01);
+        timer.update(1);
+        timer.write_byte(TAC, 0x01);
+        assert_eq!(1, timer.read_byte(TIMA));
+        // nor is a DIV reset while disabled
+        timer.update(1);
+        timer.write_byte(0xFF04, 0);
+        assert_eq!(1, timer.read_byte(TIMA));
+    }
+
+    #[test]
+    fn test_debug_state() {
+        let mut timer = Timer::new();
+        timer.write_byte(TAC, 0x02);
+        let state = timer.debug_state();
+        assert_eq!(65536, state.frequency);
+        assert_eq!(5, state.selected_bit);
+        assert_eq!(None, state.next_increment);
+
+        timer.write_byte(TAC, TAC_ENABLE | 0x02);
+        timer.update(5);
+        let state = timer.debug_state();
+        assert_eq!(20, state.counter);
+        assert_eq!(Some(11), state.next_increment);
+        timer.update(11);
+        assert_eq!(1, timer.read_byte(TIMA));
+        assert_eq!(Some(16), timer.debug_state().next_increment);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_keeps_pending_overflow() {
+        let mut timer = about_to_overflow();
+        timer.update(1);
+        let json = serde_json::to_string(&timer).unwrap();
+        let mut restored: Timer = serde_json::from_str(&json).unwrap();
+        assert_eq!(timer.debug_state(), restored.debug_state());
+        assert!(restored.debug_state().overflow_pending);
+        restored.update(1);
+        assert_eq!(0x80, restored.read_byte(TIMA));
+        assert!(restored.interrupt);
+    }
+}
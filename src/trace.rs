@@ -0,0 +1,102 @@
+// instruction tracing for external tools
+// records are produced right before an instruction executes, so tracers never
+// need to parse the debug output printed by the cpu
+
+use std::collections::VecDeque;
+use std::sync::mpsc::{self, Receiver, TrySendError};
+
+use crate::register::Register;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub pc: u16,
+    // rom bank mapped at pc, 0 outside of the cartridge rom
+    pub bank: u16,
+    // opcode followed by its operands
+    pub bytes: Vec<u8>,
+    pub mnemonic: String,
+    // register state before the instruction executes
+    pub registers: Register,
+}
+
+pub type Tracer = Box<dyn FnMut(&TraceRecord)>;
+
+pub const HISTORY_LEN: usize = 100;
+
+// the last instructions executed, cheap enough to always keep around
+// entries are only disassembled when someone asks for them, e.g. a crash report
+#[derive(Default)]
+pub struct TraceHistory {
+    // rom bank and registers right before each instruction
+    entries: VecDeque<(u16, Register)>,
+}
+
+impl TraceHistory {
+    pub fn push(&mut self, bank: u16, registers: Register) {
+        if self.entries.len() == HISTORY_LEN {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((bank, registers));
+    }
+
+    // oldest first
+    pub fn iter(&self) -> impl Iterator<Item = &(u16, Register)> {
+        self.entries.iter()
+    }
+}
+
+// tracer that forwards records into a bounded channel
+// records are dropped instead of blocking the core when the receiver falls behind
+pub fn channel_tracer(capacity: usize) -> (Tracer, Receiver<TraceRecord>) {
+    let (sender, receiver) = mpsc::sync_channel(capacity);
+    let mut connected = true;
+    let tracer = Box::new(move |record: &TraceRecord| {
+        if connected {
+            if let Err(TrySendError::Disconnected(_)) = sender.try_send(record.clone()) {
+                connected = false;
+            }
+        }
+    });
+    (tracer, receiver)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Cpu;
+
+    #[test]
+    fn test_channel_tracer_yields_executed_instructions() {
+        // LD BC,0xBEEF; INC B; JP 0x0100
+        let mut rom = vec![0; 0x8000];
+        rom[0x0100..0x0107].copy_from_slice(&[0x01, 0xEF, 0xBE, 0x04, 0xC3, 0x00, 0x01]);
+        let mut cpu = Cpu::from_rom_bytes(rom);
+        let (tracer, records) = channel_tracer(8);
+        cpu.set_tracer(tracer);
+        for _ in 0..3 {
+            cpu.run_cycle();
+        }
+
+        let records: Vec<TraceRecord> = records.try_iter().collect();
+        assert_eq!(3, records.len());
+        assert_eq!(0x0100, records[0].pc);
+        assert_eq!(vec![0x01, 0xEF, 0xBE], records[0].bytes);
+        assert_eq!("LD BC,$BEEF", records[0].mnemonic);
+        assert_eq!(0x0013, records[0].registers.get_bc());
+        assert_eq!("INC B", records[1].mnemonic);
+        assert_eq!(0xBEEF, records[1].registers.get_bc());
+        assert_eq!("JP $0100", records[2].mnemonic);
+        assert_eq!(0, records[2].bank);
+    }
+
+    #[test]
+    fn test_channel_tracer_drops_records_when_full() {
+        let mut cpu = Cpu::from_rom_bytes(vec![0; 0x8000]);
+        let (tracer, records) = channel_tracer(2);
+        cpu.set_tracer(tracer);
+        for _ in 0..5 {
+            cpu.run_cycle();
+        }
+        assert_eq!(2, records.try_iter().count());
+    }
+}
@@ -0,0 +1,122 @@
+// trainers, built on frame hooks and poke
+// a per-game config next to the rom (game.gb => game.trainer) lists values to freeze,
+// one per line as "NAME ADDR=VALUE" in hex, e.g. "lives C0A5=03". # starts a comment
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::gameboy::{FrameHook, Gameboy};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Freeze {
+    pub name: String,
+    pub addr: u16,
+    pub value: u8,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Trainer {
+    pub freezes: Vec<Freeze>,
+}
+
+impl Trainer {
+    // config file that belongs to `rom_file`
+    pub fn config_path(rom_file: &Path) -> PathBuf {
+        rom_file.with_extension("trainer")
+    }
+
+    // trainer for `rom_file`, None when the game has no config
+    pub fn for_rom(rom_file: &Path) -> io::Result<Option<Self>> {
+        let path = Self::config_path(rom_file);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let config = fs::read_to_string(&path)?;
+        config
+            .parse()
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    // frame hook that writes every frozen value back after each frame
+    pub fn into_frame_hook(self) -> FrameHook {
+        Box::new(move |gameboy: &mut Gameboy| {
+            for freeze in &self.freezes {
+                gameboy.write_range(freeze.addr, &[freeze.value]);
+            }
+        })
+    }
+}
+
+impl FromStr for Trainer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut freezes = Vec::new();
+        for (number, line) in s.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = || format!("invalid trainer entry on line {}: {}", number + 1, line);
+            let (name, assignment) = line.split_once(char::is_whitespace).ok_or_else(invalid)?;
+            let (addr, value) = assignment.trim().split_once('=').ok_or_else(invalid)?;
+            freezes.push(Freeze {
+                name: name.to_string(),
+                addr: u16::from_str_radix(addr.trim(), 16).map_err(|_| invalid())?,
+                value: u8::from_str_radix(value.trim(), 16).map_err(|_| invalid())?,
+            });
+        }
+        Ok(Self { freezes })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let trainer: Trainer = "# infinite everything\nlives C0A5=03\n\nhealth D000 = FF # full"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            vec![
+                Freeze {
+                    name: "lives".to_string(),
+                    addr: 0xC0A5,
+                    value: 0x03
+                },
+                Freeze {
+                    name: "health".to_string(),
+                    addr: 0xD000,
+                    value: 0xFF
+                },
+            ],
+            trainer.freezes
+        );
+        assert!("lives".parse::<Trainer>().is_err());
+        assert!("lives C0A5=300".parse::<Trainer>().is_err());
+        assert_eq!(
+            Path::new("roms/tetris.trainer"),
+            Trainer::config_path(Path::new("roms/tetris.gb"))
+        );
+    }
+
+    #[test]
+    fn test_infinite_lives() {
+        // LD HL,0xC0A5; DEC (HL); JP 0x0103, the game loses a life on every loop
+        let mut rom = vec![0; 0x8000];
+        rom[0x0100..0x0107].copy_from_slice(&[0x21, 0xA5, 0xC0, 0x35, 0xC3, 0x03, 0x01]);
+        let mut gameboy = Gameboy::from_rom_bytes(rom);
+        let trainer: Trainer = "lives C0A5=03".parse().unwrap();
+        gameboy.add_frame_hook(trainer.into_frame_hook());
+
+        for _ in 0..3 {
+            gameboy.run_frame();
+            assert_eq!(vec![0x03], gameboy.read_range(0xC0A5, 1));
+        }
+    }
+}
@@ -0,0 +1,140 @@
+// runaway loop detection
+// a missing opcode or a broken interrupt usually leaves the game spinning on a few
+// instructions forever, which looks exactly like a hang. the watchdog notices the cpu
+// running the same handful of addresses without touching any IO register and reports them
+
+use std::fmt;
+
+// about a second of emulated time, in machine cycles
+pub const DEFAULT_THRESHOLD: u64 = 1_048_576;
+// distinct instruction addresses a loop may span
+const MAX_LOOP_LEN: usize = 16;
+
+// a loop the cpu seems to be stuck in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StuckLoop {
+    pub bank: u16,
+    // lowest and highest instruction address seen in the loop
+    pub start: u16,
+    pub end: u16,
+    // machine cycles spent in it without IO
+    pub cycles: u64,
+}
+
+impl fmt::Display for StuckLoop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "pc stuck in {:02X}:{:04X}-{:04X} for {} cycles with no IO",
+            self.bank, self.start, self.end, self.cycles
+        )
+    }
+}
+
+pub struct Watchdog {
+    threshold: u64,
+    // distinct pcs since the last reset
+    addresses: Vec<u16>,
+    bank: u16,
+    io_accesses: u64,
+    cycles: u64,
+    // a loop is only reported once until the cpu leaves it
+    fired: bool,
+}
+
+impl Watchdog {
+    // report loops that run for `threshold` machine cycles
+    pub fn new(threshold: u64) -> Self {
+        Self {
+            threshold,
+            addresses: Vec::with_capacity(MAX_LOOP_LEN),
+            bank: 0,
+            io_accesses: 0,
+            cycles: 0,
+            fired: false,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.addresses.clear();
+        self.cycles = 0;
+        self.fired = false;
+    }
+
+    // feed every executed instruction, `io_accesses` is the bus counter after it ran
+    pub fn observe(
+        &mut self,
+        bank: u16,
+        pc: u16,
+        io_accesses: u64,
+        cycles: u64,
+    ) -> Option<StuckLoop> {
+        let known = self.addresses.contains(&pc);
+        if io_accesses != self.io_accesses
+            || bank != self.bank
+            || (!known && self.addresses.len() == MAX_LOOP_LEN)
+        {
+            self.reset();
+            self.io_accesses = io_accesses;
+            self.bank = bank;
+        }
+        if !self.addresses.contains(&pc) {
+            self.addresses.push(pc);
+        }
+
+        self.cycles += cycles;
+        if self.fired || self.cycles < self.threshold {
+            return None;
+        }
+        self.fired = true;
+        Some(StuckLoop {
+            bank: self.bank,
+            start: self.addresses.iter().copied().min().unwrap_or(pc),
+            end: self.addresses.iter().copied().max().unwrap_or(pc),
+            cycles: self.cycles,
+        })
+    }
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Self::new(DEFAULT_THRESHOLD)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reports_tight_loop_once() {
+        let mut watchdog = Watchdog::new(100);
+        let mut reports = Vec::new();
+        for _ in 0..30 {
+            for pc in [0x0150, 0x0152, 0x0153] {
+                reports.extend(watchdog.observe(1, pc, 0, 4));
+            }
+        }
+        assert_eq!(
+            vec![StuckLoop {
+                bank: 1,
+                start: 0x0150,
+                end: 0x0153,
+                cycles: 100
+            }],
+            reports
+        );
+
+        // IO activity restarts the count
+        assert_eq!(None, watchdog.observe(1, 0x0150, 1, 4));
+        assert_eq!(4, watchdog.cycles);
+    }
+
+    #[test]
+    fn test_long_code_paths_are_not_loops() {
+        let mut watchdog = Watchdog::new(100);
+        for pc in 0..1000 {
+            assert_eq!(None, watchdog.observe(0, pc, 0, 4));
+        }
+    }
+}
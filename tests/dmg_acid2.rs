@@ -0,0 +1,94 @@
+// dmg-acid2 screenshot regression test
+// runs Matt Currie's dmg-acid2 (https://github.com/mattcurrie/dmg-acid2) headless and
+// compares the screen to its reference image. the rom and image are not part of the repo,
+// put them at tests/roms/dmg-acid2.gb and tests/roms/dmg-acid2.png (the repo's
+// img/reference-dmg.png) or point DMG_ACID2_ROM and DMG_ACID2_REFERENCE at them. without
+// them the test passes after saying it was skipped.
+// on a mismatch the screen and a diff with the wrong pixels in red are written to
+// target/dmg-acid2
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rustyboy::{
+    gameboy::Gameboy,
+    palette::DmgPalette,
+    png,
+    ppu::{SCREEN_HEIGHT, SCREEN_WIDTH},
+};
+
+// the test is drawn after a few frames, give it a second
+const FRAMES: u32 = 60;
+const MISMATCH: u32 = 0xFFFF0000;
+
+fn fixture(variable: &str, name: &str) -> PathBuf {
+    env::var_os(variable).map(PathBuf::from).unwrap_or_else(|| {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/roms")
+            .join(name)
+    })
+}
+
+#[test]
+fn dmg_acid2_matches_reference() {
+    let rom_path = fixture("DMG_ACID2_ROM", "dmg-acid2.gb");
+    let reference_path = fixture("DMG_ACID2_REFERENCE", "dmg-acid2.png");
+    let (Ok(rom), Ok(reference)) = (fs::read(&rom_path), fs::read(&reference_path)) else {
+        eprintln!(
+            "skipped: needs {} and {}",
+            rom_path.display(),
+            reference_path.display()
+        );
+        return;
+    };
+    let (width, height, expected) = png::decode(&reference).expect("reference image");
+    assert_eq!((SCREEN_WIDTH, SCREEN_HEIGHT), (width, height));
+
+    let mut gameboy = Gameboy::from_rom_bytes(rom);
+    // the rom ends in a loop the watchdog would stop
+    gameboy.watchdog = None;
+    // the reference is drawn with these shades
+    gameboy.cpu.bus.ppu.set_palette(DmgPalette::GRAYSCALE);
+    for _ in 0..FRAMES {
+        gameboy.run_frame();
+    }
+    let screen = gameboy.cpu.bus.ppu.frame();
+
+    // alpha does not matter
+    let wrong: Vec<usize> = (0..screen.len())
+        .filter(|&i| screen[i] & 0xFFFFFF != expected[i] & 0xFFFFFF)
+        .collect();
+    if wrong.is_empty() {
+        return;
+    }
+
+    let out = Path::new(env!("CARGO_MANIFEST_DIR")).join("target/dmg-acid2");
+    fs::create_dir_all(&out).unwrap();
+    // the expected image faded, with the wrong pixels on top
+    let mut diff: Vec<u32> = expected
+        .iter()
+        .map(|&pixel| 0xFF000000 | (pixel & 0xFEFEFE) >> 1 | 0x808080)
+        .collect();
+    for &i in &wrong {
+        diff[i] = MISMATCH;
+    }
+    fs::write(
+        out.join("screen.png"),
+        png::encode(SCREEN_WIDTH, SCREEN_HEIGHT, screen, 1),
+    )
+    .unwrap();
+    fs::write(
+        out.join("diff.png"),
+        png::encode(SCREEN_WIDTH, SCREEN_HEIGHT, &diff, 1),
+    )
+    .unwrap();
+    let first = wrong[0];
+    panic!(
+        "{} pixels differ from the reference, the first at ({}, {}). see {}",
+        wrong.len(),
+        first % SCREEN_WIDTH,
+        first / SCREEN_WIDTH,
+        out.display()
+    );
+}
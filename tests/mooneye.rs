@@ -0,0 +1,96 @@
+// mooneye-gb acceptance tests
+// runs the acceptance roms of Joonas Javanainen's mooneye test suite
+// (https://github.com/Gekkio/mooneye-test-suite) headless. a test finishes with LD B,B and
+// the fibonacci numbers 3, 5, 8, 13, 21, 34 in B, C, D, E, H and L when it passed. the roms
+// are not part of the repo, put the built suite's acceptance directory at
+// tests/roms/mooneye/acceptance or point MOONEYE_ACCEPTANCE at it.
+// ignored by default since most of them don't pass yet, run it with
+// `cargo test --test mooneye -- --ignored --nocapture` for the scoreboard. the core prints
+// nothing while it loads or runs a rom, the output is one line per rom and the total
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rustyboy::gameboy::Gameboy;
+
+// LD B,B, the suite's software breakpoint
+const BREAKPOINT: u8 = 0x40;
+const PASSED: [u8; 6] = [3, 5, 8, 13, 21, 34];
+// the slowest tests take a few seconds, give them 20
+const MAX_CYCLES: u64 = 20 * 1_048_576;
+
+fn acceptance_dir() -> PathBuf {
+    env::var_os("MOONEYE_ACCEPTANCE")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/roms/mooneye/acceptance")
+        })
+}
+
+// every .gb below `dir`, sorted so the report is stable
+fn find_roms(dir: &Path, roms: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for path in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+        if path.is_dir() {
+            find_roms(&path, roms);
+        } else if path.extension().is_some_and(|extension| extension == "gb") {
+            roms.push(path);
+        }
+    }
+    roms.sort();
+}
+
+// None when the test passed, otherwise what went wrong
+fn run(rom: Vec<u8>) -> Option<String> {
+    let mut gameboy = Gameboy::from_rom_bytes(rom);
+    // the tests end in a loop the watchdog would stop
+    gameboy.watchdog = None;
+    let summary = gameboy.run_until(MAX_CYCLES, |gb| {
+        gb.read_range(gb.cpu.pc(), 1)[0] == BREAKPOINT
+    });
+    let registers = gameboy.cpu.registers();
+    let signature = [
+        registers.b,
+        registers.c,
+        registers.d,
+        registers.e,
+        registers.h,
+        registers.l,
+    ];
+    if gameboy.read_range(registers.pc, 1)[0] != BREAKPOINT {
+        Some(format!("did not finish: {:?}", summary.reason))
+    } else if signature != PASSED {
+        Some(format!("failed with {:02X?}", signature))
+    } else {
+        None
+    }
+}
+
+#[test]
+#[ignore]
+fn mooneye_acceptance() {
+    let dir = acceptance_dir();
+    let mut roms = Vec::new();
+    find_roms(&dir, &mut roms);
+    if roms.is_empty() {
+        eprintln!("skipped: no roms in {}", dir.display());
+        return;
+    }
+
+    let mut failed = 0;
+    for path in &roms {
+        let name = path.strip_prefix(&dir).unwrap_or(path).display();
+        match run(fs::read(path).unwrap()) {
+            None => println!("PASS {name}"),
+            Some(reason) => {
+                failed += 1;
+                println!("FAIL {name}: {reason}");
+            }
+        }
+    }
+    println!("{} of {} passed", roms.len() - failed, roms.len());
+    assert_eq!(0, failed, "{failed} of {} roms failed", roms.len());
+}